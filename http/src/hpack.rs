@@ -0,0 +1,489 @@
+// http/hpack.rs
+use std::collections::{HashMap, VecDeque};
+use std::sync::OnceLock;
+
+// Taille de table dynamique annoncée par défaut (SETTINGS_HEADER_TABLE_SIZE)
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+// Table statique de la RFC 7541 (annexe A), indexée à partir de 1
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// Codes de Huffman (code, longueur en bits) des octets 0 à 255 puis de EOS,
+// RFC 7541 annexe B
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13), (0x7fffd8, 23), (0xfffffe2, 28), (0xfffffe3, 28), (0xfffffe4, 28), (0xfffffe5, 28),
+    (0xfffffe6, 28), (0xfffffe7, 28), (0xfffffe8, 28), (0xffffea, 24), (0x3ffffffc, 30), (0xfffffe9, 28),
+    (0xfffffea, 28), (0x3ffffffd, 30), (0xfffffeb, 28), (0xfffffec, 28), (0xfffffed, 28), (0xfffffee, 28),
+    (0xfffffef, 28), (0xffffff0, 28), (0xffffff1, 28), (0xffffff2, 28), (0x3ffffffe, 30), (0xffffff3, 28),
+    (0xffffff4, 28), (0xffffff5, 28), (0xffffff6, 28), (0xffffff7, 28), (0xffffff8, 28), (0xffffff9, 28),
+    (0xffffffa, 28), (0xffffffb, 28), (0x14, 6), (0x3f8, 10), (0x3f9, 10), (0xffa, 12),
+    (0x1ff9, 13), (0x15, 6), (0xf8, 8), (0x7fa, 11), (0x3fa, 10), (0x3fb, 10),
+    (0xf9, 8), (0x7fb, 11), (0xfa, 8), (0x16, 6), (0x17, 6), (0x18, 6),
+    (0x0, 5), (0x1, 5), (0x2, 5), (0x19, 6), (0x1a, 6), (0x1b, 6),
+    (0x1c, 6), (0x1d, 6), (0x1e, 6), (0x1f, 6), (0x5c, 7), (0xfb, 8),
+    (0x7ffc, 15), (0x20, 6), (0xffb, 12), (0x3fc, 10), (0x1ffa, 13), (0x21, 6),
+    (0x5d, 7), (0x5e, 7), (0x5f, 7), (0x60, 7), (0x61, 7), (0x62, 7),
+    (0x63, 7), (0x64, 7), (0x65, 7), (0x66, 7), (0x67, 7), (0x68, 7),
+    (0x69, 7), (0x6a, 7), (0x6b, 7), (0x6c, 7), (0x6d, 7), (0x6e, 7),
+    (0x6f, 7), (0x70, 7), (0x71, 7), (0x72, 7), (0xfc, 8), (0x73, 7),
+    (0xfd, 8), (0x1ffb, 13), (0x7fff0, 19), (0x1ffc, 13), (0x3ffc, 14), (0x22, 6),
+    (0x7ffd, 15), (0x3, 5), (0x23, 6), (0x4, 5), (0x24, 6), (0x5, 5),
+    (0x25, 6), (0x26, 6), (0x27, 6), (0x6, 5), (0x74, 7), (0x75, 7),
+    (0x28, 6), (0x29, 6), (0x2a, 6), (0x7, 5), (0x2b, 6), (0x76, 7),
+    (0x2c, 6), (0x8, 5), (0x9, 5), (0x2d, 6), (0x77, 7), (0x78, 7),
+    (0x79, 7), (0x7a, 7), (0x7b, 7), (0x7ffe, 15), (0x7fc, 11), (0x3ffd, 14),
+    (0x1ffd, 13), (0xffffffc, 28), (0xfffe6, 20), (0x3fffd2, 22), (0xfffe7, 20), (0xfffe8, 20),
+    (0x3fffd3, 22), (0x3fffd4, 22), (0x3fffd5, 22), (0x7fffd9, 23), (0x3fffd6, 22), (0x7fffda, 23),
+    (0x7fffdb, 23), (0x7fffdc, 23), (0x7fffdd, 23), (0x7fffde, 23), (0xffffeb, 24), (0x7fffdf, 23),
+    (0xffffec, 24), (0xffffed, 24), (0x3fffd7, 22), (0x7fffe0, 23), (0xffffee, 24), (0x7fffe1, 23),
+    (0x7fffe2, 23), (0x7fffe3, 23), (0x7fffe4, 23), (0x1fffdc, 21), (0x3fffd8, 22), (0x7fffe5, 23),
+    (0x3fffd9, 22), (0x7fffe6, 23), (0x7fffe7, 23), (0xffffef, 24), (0x3fffda, 22), (0x1fffdd, 21),
+    (0xfffe9, 20), (0x3fffdb, 22), (0x3fffdc, 22), (0x7fffe8, 23), (0x7fffe9, 23), (0x1fffde, 21),
+    (0x7fffea, 23), (0x3fffdd, 22), (0x3fffde, 22), (0xfffff0, 24), (0x1fffdf, 21), (0x3fffdf, 22),
+    (0x7fffeb, 23), (0x7fffec, 23), (0x1fffe0, 21), (0x1fffe1, 21), (0x3fffe0, 22), (0x1fffe2, 21),
+    (0x7fffed, 23), (0x3fffe1, 22), (0x7fffee, 23), (0x7fffef, 23), (0xfffea, 20), (0x3fffe2, 22),
+    (0x3fffe3, 22), (0x3fffe4, 22), (0x7ffff0, 23), (0x3fffe5, 22), (0x3fffe6, 22), (0x7ffff1, 23),
+    (0x3ffffe0, 26), (0x3ffffe1, 26), (0xfffeb, 20), (0x7fff1, 19), (0x3fffe7, 22), (0x7ffff2, 23),
+    (0x3fffe8, 22), (0x1ffffec, 25), (0x3ffffe2, 26), (0x3ffffe3, 26), (0x3ffffe4, 26), (0x7ffffde, 27),
+    (0x7ffffdf, 27), (0x3ffffe5, 26), (0xfffff1, 24), (0x1ffffed, 25), (0x7fff2, 19), (0x1fffe3, 21),
+    (0x3ffffe6, 26), (0x7ffffe0, 27), (0x7ffffe1, 27), (0x3ffffe7, 26), (0x7ffffe2, 27), (0xfffff2, 24),
+    (0x1fffe4, 21), (0x1fffe5, 21), (0x3ffffe8, 26), (0x3ffffe9, 26), (0xffffffd, 28), (0x7ffffe3, 27),
+    (0x7ffffe4, 27), (0x7ffffe5, 27), (0xfffec, 20), (0xfffff3, 24), (0xfffed, 20), (0x1fffe6, 21),
+    (0x3fffe9, 22), (0x1fffe7, 21), (0x1fffe8, 21), (0x7ffff3, 23), (0x3fffea, 22), (0x3fffeb, 22),
+    (0x1ffffee, 25), (0x1ffffef, 25), (0xfffff4, 24), (0xfffff5, 24), (0x3ffffea, 26), (0x7ffff4, 23),
+    (0x3ffffeb, 26), (0x7ffffe6, 27), (0x3ffffec, 26), (0x3ffffed, 26), (0x7ffffe7, 27), (0x7ffffe8, 27),
+    (0x7ffffe9, 27), (0x7ffffea, 27), (0x7ffffeb, 27), (0xffffffe, 28), (0x7ffffec, 27), (0x7ffffed, 27),
+    (0x7ffffee, 27), (0x7ffffef, 27), (0x7fffff0, 27), (0x3ffffee, 26), (0x3fffffff, 30),
+];
+
+const EOS: u16 = 256;
+
+// Erreur de décompression: la connexion doit être fermée (COMPRESSION_ERROR)
+#[derive(Debug, Clone, PartialEq)]
+pub struct HpackError(pub &'static str);
+
+// Symbole de chaque code, par longueur puis par valeur
+fn huffman_symbols() -> &'static HashMap<(u8, u32), u16> {
+    static SYMBOLS: OnceLock<HashMap<(u8, u32), u16>> = OnceLock::new();
+    SYMBOLS.get_or_init(|| {
+        HUFFMAN_CODES
+            .iter()
+            .enumerate()
+            .map(|(symbol, (code, len))| ((*len, *code), symbol as u16))
+            .collect()
+    })
+}
+
+pub fn huffman_decode(input: &[u8]) -> Result<Vec<u8>, HpackError> {
+    let symbols = huffman_symbols();
+    let mut output = Vec::with_capacity(input.len() * 8 / 5);
+    let (mut code, mut len) = (0u32, 0u8);
+    for byte in input {
+        for shift in (0..8).rev() {
+            code = (code << 1) | ((*byte as u32 >> shift) & 1);
+            len += 1;
+            match symbols.get(&(len, code)) {
+                Some(&EOS) => return Err(HpackError("EOS in Huffman string")),
+                Some(symbol) => {
+                    output.push(*symbol as u8);
+                    code = 0;
+                    len = 0;
+                }
+                None if len >= 30 => return Err(HpackError("invalid Huffman code")),
+                None => {}
+            }
+        }
+    }
+    // Le bourrage est le début de EOS: moins de 8 bits, tous à 1
+    if len >= 8 || code != (1 << len) - 1 {
+        return Err(HpackError("invalid Huffman padding"));
+    }
+    Ok(output)
+}
+
+pub fn huffman_encode(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let (mut bits, mut len) = (0u64, 0u32);
+    for byte in input {
+        let (code, code_len) = HUFFMAN_CODES[*byte as usize];
+        bits = (bits << code_len) | code as u64;
+        len += code_len as u32;
+        while len >= 8 {
+            len -= 8;
+            output.push((bits >> len) as u8);
+        }
+    }
+    if len > 0 {
+        let padding = 8 - len;
+        output.push(((bits << padding) | ((1 << padding) - 1)) as u8);
+    }
+    output
+}
+
+// Entier sur un préfixe de `prefix` bits (RFC 7541, 5.1); retourne la valeur
+// et le nombre d'octets lus
+fn decode_integer(input: &[u8], prefix: u8) -> Result<(usize, usize), HpackError> {
+    let max = (1usize << prefix) - 1;
+    let first = *input.first().ok_or(HpackError("truncated integer"))? as usize & max;
+    if first < max {
+        return Ok((first, 1));
+    }
+    let mut value = max;
+    for (i, byte) in input.iter().enumerate().skip(1) {
+        let shift = 7 * (i - 1);
+        if shift > 28 {
+            return Err(HpackError("integer overflow"));
+        }
+        value += (*byte as usize & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(HpackError("truncated integer"))
+}
+
+fn encode_integer(output: &mut Vec<u8>, value: usize, prefix: u8, flags: u8) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        output.push(flags | value as u8);
+        return;
+    }
+    output.push(flags | max as u8);
+    let mut rest = value - max;
+    while rest >= 0x80 {
+        output.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    output.push(rest as u8);
+}
+
+fn decode_string(input: &[u8]) -> Result<(Vec<u8>, usize), HpackError> {
+    let huffman = input.first().is_some_and(|byte| byte & 0x80 != 0);
+    let (len, read) = decode_integer(input, 7)?;
+    let raw = input
+        .get(read..read + len)
+        .ok_or(HpackError("truncated string"))?;
+    let value = if huffman { huffman_decode(raw)? } else { raw.to_vec() };
+    Ok((value, read + len))
+}
+
+// Chaîne codée avec Huffman lorsque c'est plus court
+fn encode_string(output: &mut Vec<u8>, value: &[u8]) {
+    let compressed = huffman_encode(value);
+    if compressed.len() < value.len() {
+        encode_integer(output, compressed.len(), 7, 0x80);
+        output.extend_from_slice(&compressed);
+    } else {
+        encode_integer(output, value.len(), 7, 0);
+        output.extend_from_slice(value);
+    }
+}
+
+// Table dynamique: les entrées récentes en tête, taille comptée selon la
+// RFC 7541 (nom + valeur + 32 octets)
+#[derive(Debug)]
+struct DynamicTable {
+    entries: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    fn new(max_size: usize) -> Self {
+        DynamicTable {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<(&str, &str)> {
+        if index == 0 {
+            return None;
+        }
+        if index <= STATIC_TABLE.len() {
+            return Some(STATIC_TABLE[index - 1]);
+        }
+        self.entries
+            .get(index - STATIC_TABLE.len() - 1)
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = name.len() + value.len() + 32;
+        self.size += size;
+        self.entries.push_front((name, value));
+        self.evict();
+    }
+
+    fn resize(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            match self.entries.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + 32,
+                None => break,
+            }
+        }
+    }
+}
+
+// Décompresse les blocs d'en-têtes reçus sur une connexion
+#[derive(Debug)]
+pub struct Decoder {
+    table: DynamicTable,
+    limit: usize, // taille maximale que l'encodeur distant peut choisir
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder {
+            table: DynamicTable::new(DEFAULT_TABLE_SIZE),
+            limit: DEFAULT_TABLE_SIZE,
+        }
+    }
+}
+
+impl Decoder {
+    // Décode un bloc complet (HEADERS et CONTINUATION réunis)
+    pub fn decode(&mut self, mut block: &[u8]) -> Result<Vec<(String, String)>, HpackError> {
+        let mut headers = Vec::new();
+        let mut allow_resize = true;
+        while let Some(&first) = block.first() {
+            let read = if first & 0x80 != 0 {
+                // Champ indexé
+                let (index, read) = decode_integer(block, 7)?;
+                let (name, value) = self.table.get(index).ok_or(HpackError("invalid index"))?;
+                headers.push((name.to_string(), value.to_string()));
+                read
+            } else if first & 0xe0 == 0x20 {
+                // Mise à jour de la taille, uniquement en début de bloc
+                let (size, read) = decode_integer(block, 5)?;
+                if !allow_resize || size > self.limit {
+                    return Err(HpackError("invalid table size update"));
+                }
+                self.table.resize(size);
+                read
+            } else {
+                // Littéral avec indexation (01), sans indexation (0000) ou
+                // jamais indexé (0001)
+                let indexing = first & 0x40 != 0;
+                let prefix = if indexing { 6 } else { 4 };
+                let (index, mut read) = decode_integer(block, prefix)?;
+                let name = if index == 0 {
+                    let (name, len) = decode_string(&block[read..])?;
+                    read += len;
+                    String::from_utf8(name).map_err(|_| HpackError("invalid header name"))?
+                } else {
+                    self.table.get(index).ok_or(HpackError("invalid index"))?.0.to_string()
+                };
+                let (value, len) = decode_string(&block[read..])?;
+                read += len;
+                let value = String::from_utf8_lossy(&value).into_owned();
+                if indexing {
+                    self.table.insert(name.clone(), value.clone());
+                }
+                headers.push((name, value));
+                read
+            };
+            if first & 0xe0 != 0x20 {
+                allow_resize = false;
+            }
+            block = &block[read..];
+        }
+        Ok(headers)
+    }
+}
+
+// Compresse les en-têtes des réponses. Sans table dynamique: les champs de la
+// table statique sont indexés, les autres envoyés comme littéraux non indexés.
+#[derive(Debug, Default)]
+pub struct Encoder;
+
+impl Encoder {
+    pub fn encode<'a>(&self, headers: impl IntoIterator<Item = (&'a str, &'a str)>) -> Vec<u8> {
+        let mut output = Vec::new();
+        for (name, value) in headers {
+            let exact = STATIC_TABLE.iter().position(|entry| *entry == (name, value));
+            if let Some(index) = exact {
+                encode_integer(&mut output, index + 1, 7, 0x80);
+                continue;
+            }
+            match STATIC_TABLE.iter().position(|(known, _)| *known == name) {
+                Some(index) => encode_integer(&mut output, index + 1, 4, 0),
+                None => {
+                    output.push(0);
+                    encode_string(&mut output, name.as_bytes());
+                }
+            }
+            encode_string(&mut output, value.as_bytes());
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        let text: String = text.split_whitespace().collect();
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn pairs(headers: &[(String, String)]) -> Vec<(&str, &str)> {
+        headers.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect()
+    }
+
+    #[test]
+    fn test_integers() {
+        // RFC 7541, C.1
+        let mut output = Vec::new();
+        encode_integer(&mut output, 10, 5, 0);
+        encode_integer(&mut output, 1337, 5, 0);
+        encode_integer(&mut output, 42, 8, 0);
+        assert_eq!(output, [0x0a, 0x1f, 0x9a, 0x0a, 0x2a]);
+        assert_eq!(decode_integer(&[0x1f, 0x9a, 0x0a], 5), Ok((1337, 3)));
+        assert!(decode_integer(&[0x1f, 0x9a], 5).is_err());
+        assert!(decode_integer(&[0x1f, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01], 5).is_err());
+    }
+
+    #[test]
+    fn test_huffman() {
+        let encoded = huffman_encode(b"www.example.com");
+        assert_eq!(encoded, hex("f1e3 c2e5 f23a 6ba0 ab90 f4ff"));
+        assert_eq!(huffman_decode(&encoded).unwrap(), b"www.example.com");
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(huffman_decode(&huffman_encode(&all)).unwrap(), all);
+        // Bourrage à 0 ou de 8 bits et plus
+        assert!(huffman_decode(&[0x00]).is_err());
+        assert!(huffman_decode(&[0xff]).is_err());
+        assert!(huffman_decode(&[0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn test_decode_requests_with_huffman() {
+        // RFC 7541, C.4: trois requêtes sur la même connexion
+        let mut decoder = Decoder::default();
+        let first = decoder.decode(&hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff")).unwrap();
+        assert_eq!(
+            pairs(&first),
+            [(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com")]
+        );
+        let second = decoder.decode(&hex("8286 84be 5886 a8eb 1064 9cbf")).unwrap();
+        assert_eq!(second.last().unwrap(), &("cache-control".to_string(), "no-cache".to_string()));
+        assert_eq!(second[3].1, "www.example.com");
+        let third = decoder
+            .decode(&hex("8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf"))
+            .unwrap();
+        assert_eq!(
+            pairs(&third),
+            [
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ]
+        );
+        assert_eq!(decoder.table.size, 164);
+    }
+
+    #[test]
+    fn test_table_size_and_errors() {
+        let mut decoder = Decoder::default();
+        // Mise à jour à 0: la table est vidée, puis une entrée est ajoutée
+        // et évincée aussitôt
+        let block = hex("20 400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572");
+        let headers = decoder.decode(&block).unwrap();
+        assert_eq!(pairs(&headers), [("custom-key", "custom-header")]);
+        assert!(decoder.table.entries.is_empty());
+        assert!(decoder.decode(&hex("82 20")).is_err());
+        assert!(decoder.decode(&hex("3f e2 1f")).is_err());
+        assert!(decoder.decode(&hex("be")).is_err());
+        assert!(decoder.decode(&hex("80")).is_err());
+        assert!(decoder.decode(&hex("04 85 ab")).is_err());
+    }
+
+    #[test]
+    fn test_encoder_round_trip() {
+        let headers = [
+            (":status", "200"),
+            (":status", "201"),
+            ("content-type", "text/html"),
+            ("x-powered-by", "localhost"),
+        ];
+        let block = Encoder.encode(headers);
+        assert_eq!(block[0], 0x88);
+        let decoded = Decoder::default().decode(&block).unwrap();
+        assert_eq!(pairs(&decoded), headers);
+    }
+}
@@ -0,0 +1,420 @@
+// http/http2.rs
+
+// Préface envoyée par le client avant sa première trame (RFC 9113, 3.4)
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+pub const FRAME_HEADER_LEN: usize = 9;
+pub const DEFAULT_WINDOW_SIZE: u32 = 65_535;
+pub const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
+const MAX_FRAME_SIZE_LIMIT: u32 = (1 << 24) - 1;
+
+// Types de trames
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// Drapeaux
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+// Codes d'erreur de RST_STREAM et GOAWAY
+pub const NO_ERROR: u32 = 0x0;
+pub const PROTOCOL_ERROR: u32 = 0x1;
+pub const INTERNAL_ERROR: u32 = 0x2;
+pub const FLOW_CONTROL_ERROR: u32 = 0x3;
+pub const STREAM_CLOSED: u32 = 0x5;
+pub const FRAME_SIZE_ERROR: u32 = 0x6;
+pub const REFUSED_STREAM: u32 = 0x7;
+pub const CANCEL: u32 = 0x8;
+pub const COMPRESSION_ERROR: u32 = 0x9;
+pub const HTTP_1_1_REQUIRED: u32 = 0xd;
+
+// Paramètres de SETTINGS
+pub const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+// Erreur de connexion: GOAWAY avec `code`, puis fermeture
+#[derive(Debug, Clone, PartialEq)]
+pub struct H2Error {
+    pub code: u32,
+    pub reason: &'static str,
+}
+
+impl H2Error {
+    pub fn protocol(reason: &'static str) -> Self {
+        H2Error { code: PROTOCOL_ERROR, reason }
+    }
+
+    fn frame_size(reason: &'static str) -> Self {
+        H2Error { code: FRAME_SIZE_ERROR, reason }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Data {
+        stream: u32,
+        payload: Vec<u8>,
+        end_stream: bool,
+        flow_len: u32, // longueur comptée par le contrôle de flux, bourrage compris
+    },
+    Headers {
+        stream: u32,
+        block: Vec<u8>,
+        end_stream: bool,
+        end_headers: bool,
+    },
+    Priority {
+        stream: u32,
+    },
+    RstStream {
+        stream: u32,
+        code: u32,
+    },
+    Settings {
+        ack: bool,
+        params: Vec<(u16, u32)>,
+    },
+    Ping {
+        ack: bool,
+        data: [u8; 8],
+    },
+    GoAway {
+        last_stream: u32,
+        code: u32,
+    },
+    WindowUpdate {
+        stream: u32,
+        increment: u32,
+    },
+    Continuation {
+        stream: u32,
+        block: Vec<u8>,
+        end_headers: bool,
+    },
+    // Type inconnu: ignoré, sauf au milieu d'un bloc d'en-têtes
+    Unknown {
+        stream: u32,
+    },
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+// Retire le bourrage d'une trame DATA ou HEADERS
+fn unpad(payload: &[u8], flags: u8) -> Result<&[u8], H2Error> {
+    if flags & FLAG_PADDED == 0 {
+        return Ok(payload);
+    }
+    let padding = *payload.first().ok_or(H2Error::frame_size("missing pad length"))? as usize;
+    if padding >= payload.len() {
+        return Err(H2Error::protocol("padding exceeds payload"));
+    }
+    Ok(&payload[1..payload.len() - padding])
+}
+
+pub fn parse_settings(payload: &[u8]) -> Result<Vec<(u16, u32)>, H2Error> {
+    if !payload.len().is_multiple_of(6) {
+        return Err(H2Error::frame_size("invalid SETTINGS length"));
+    }
+    Ok(payload
+        .chunks(6)
+        .map(|param| (u16::from_be_bytes([param[0], param[1]]), read_u32(&param[2..])))
+        .collect())
+}
+
+impl Frame {
+    // Lit une trame complète au début de `buf`; retourne la trame et le
+    // nombre d'octets consommés, ou None s'il faut plus de données
+    pub fn parse(buf: &[u8], max_frame_size: u32) -> Result<Option<(Frame, usize)>, H2Error> {
+        if buf.len() < FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]);
+        if len > max_frame_size {
+            return Err(H2Error::frame_size("frame larger than SETTINGS_MAX_FRAME_SIZE"));
+        }
+        let total = FRAME_HEADER_LEN + len as usize;
+        if buf.len() < total {
+            return Ok(None);
+        }
+        let (kind, flags) = (buf[3], buf[4]);
+        let stream = read_u32(&buf[5..9]) & MAX_WINDOW_SIZE;
+        let payload = &buf[FRAME_HEADER_LEN..total];
+        let on_stream = |frame: Frame| match stream {
+            0 => Err(H2Error::protocol("frame requires a stream")),
+            _ => Ok(frame),
+        };
+        let on_connection = |frame: Frame| match stream {
+            0 => Ok(frame),
+            _ => Err(H2Error::protocol("frame must be sent on stream 0")),
+        };
+        let frame = match kind {
+            DATA => on_stream(Frame::Data {
+                stream,
+                payload: unpad(payload, flags)?.to_vec(),
+                end_stream: flags & FLAG_END_STREAM != 0,
+                flow_len: len,
+            })?,
+            HEADERS => {
+                let mut block = unpad(payload, flags)?;
+                if flags & FLAG_PRIORITY != 0 {
+                    block = block.get(5..).ok_or(H2Error::frame_size("truncated priority"))?;
+                }
+                on_stream(Frame::Headers {
+                    stream,
+                    block: block.to_vec(),
+                    end_stream: flags & FLAG_END_STREAM != 0,
+                    end_headers: flags & FLAG_END_HEADERS != 0,
+                })?
+            }
+            PRIORITY if len != 5 => return Err(H2Error::frame_size("invalid PRIORITY length")),
+            PRIORITY => on_stream(Frame::Priority { stream })?,
+            RST_STREAM if len != 4 => return Err(H2Error::frame_size("invalid RST_STREAM length")),
+            RST_STREAM => on_stream(Frame::RstStream { stream, code: read_u32(payload) })?,
+            SETTINGS => {
+                let ack = flags & FLAG_ACK != 0;
+                if ack && len != 0 {
+                    return Err(H2Error::frame_size("SETTINGS ack with payload"));
+                }
+                on_connection(Frame::Settings { ack, params: parse_settings(payload)? })?
+            }
+            PUSH_PROMISE => return Err(H2Error::protocol("clients cannot push")),
+            PING if len != 8 => return Err(H2Error::frame_size("invalid PING length")),
+            PING => {
+                let mut data = [0; 8];
+                data.copy_from_slice(payload);
+                on_connection(Frame::Ping { ack: flags & FLAG_ACK != 0, data })?
+            }
+            GOAWAY if len < 8 => return Err(H2Error::frame_size("invalid GOAWAY length")),
+            GOAWAY => on_connection(Frame::GoAway {
+                last_stream: read_u32(payload) & MAX_WINDOW_SIZE,
+                code: read_u32(&payload[4..]),
+            })?,
+            WINDOW_UPDATE if len != 4 => return Err(H2Error::frame_size("invalid WINDOW_UPDATE length")),
+            WINDOW_UPDATE => Frame::WindowUpdate {
+                stream,
+                increment: read_u32(payload) & MAX_WINDOW_SIZE,
+            },
+            CONTINUATION => on_stream(Frame::Continuation {
+                stream,
+                block: payload.to_vec(),
+                end_headers: flags & FLAG_END_HEADERS != 0,
+            })?,
+            _ => Frame::Unknown { stream },
+        };
+        Ok(Some((frame, total)))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let (kind, flags, stream, payload) = match self {
+            Frame::Data { stream, payload, end_stream, .. } => {
+                (DATA, flag(*end_stream, FLAG_END_STREAM), *stream, payload.clone())
+            }
+            Frame::Headers { stream, block, end_stream, end_headers } => (
+                HEADERS,
+                flag(*end_stream, FLAG_END_STREAM) | flag(*end_headers, FLAG_END_HEADERS),
+                *stream,
+                block.clone(),
+            ),
+            Frame::Priority { stream } => (PRIORITY, 0, *stream, vec![0; 5]),
+            Frame::RstStream { stream, code } => (RST_STREAM, 0, *stream, code.to_be_bytes().to_vec()),
+            Frame::Settings { ack, params } => {
+                let payload = params
+                    .iter()
+                    .flat_map(|(id, value)| id.to_be_bytes().into_iter().chain(value.to_be_bytes()))
+                    .collect();
+                (SETTINGS, flag(*ack, FLAG_ACK), 0, payload)
+            }
+            Frame::Ping { ack, data } => (PING, flag(*ack, FLAG_ACK), 0, data.to_vec()),
+            Frame::GoAway { last_stream, code } => {
+                let mut payload = last_stream.to_be_bytes().to_vec();
+                payload.extend_from_slice(&code.to_be_bytes());
+                (GOAWAY, 0, 0, payload)
+            }
+            Frame::WindowUpdate { stream, increment } => {
+                (WINDOW_UPDATE, 0, *stream, increment.to_be_bytes().to_vec())
+            }
+            Frame::Continuation { stream, block, end_headers } => {
+                (CONTINUATION, flag(*end_headers, FLAG_END_HEADERS), *stream, block.clone())
+            }
+            Frame::Unknown { stream } => (0xff, 0, *stream, Vec::new()),
+        };
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend_from_slice(&[kind, flags]);
+        frame.extend_from_slice(&stream.to_be_bytes());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+}
+
+fn flag(set: bool, value: u8) -> u8 {
+    if set {
+        value
+    } else {
+        0
+    }
+}
+
+// Bloc d'en-têtes découpé en HEADERS puis CONTINUATION selon la taille
+// maximale des trames du client
+pub fn encode_headers(stream: u32, block: &[u8], end_stream: bool, max_frame_size: u32) -> Vec<u8> {
+    let mut chunks = block.chunks(max_frame_size as usize).peekable();
+    let first = chunks.next().unwrap_or_default();
+    let mut output = Frame::Headers {
+        stream,
+        block: first.to_vec(),
+        end_stream,
+        end_headers: chunks.peek().is_none(),
+    }
+    .encode();
+    while let Some(chunk) = chunks.next() {
+        let frame = Frame::Continuation {
+            stream,
+            block: chunk.to_vec(),
+            end_headers: chunks.peek().is_none(),
+        };
+        output.extend(frame.encode());
+    }
+    output
+}
+
+// Paramètres annoncés par le client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub header_table_size: u32,
+    pub initial_window_size: u32,
+    pub max_frame_size: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            header_table_size: 4096,
+            initial_window_size: DEFAULT_WINDOW_SIZE,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+}
+
+impl Settings {
+    // Applique les paramètres reçus; les identifiants inconnus sont ignorés
+    pub fn apply(&mut self, params: &[(u16, u32)]) -> Result<(), H2Error> {
+        for (id, value) in params {
+            match *id {
+                SETTINGS_HEADER_TABLE_SIZE => self.header_table_size = *value,
+                SETTINGS_ENABLE_PUSH if *value > 1 => {
+                    return Err(H2Error::protocol("invalid SETTINGS_ENABLE_PUSH"))
+                }
+                SETTINGS_INITIAL_WINDOW_SIZE if *value > MAX_WINDOW_SIZE => {
+                    return Err(H2Error {
+                        code: FLOW_CONTROL_ERROR,
+                        reason: "invalid SETTINGS_INITIAL_WINDOW_SIZE",
+                    })
+                }
+                SETTINGS_INITIAL_WINDOW_SIZE => self.initial_window_size = *value,
+                SETTINGS_MAX_FRAME_SIZE if !(DEFAULT_MAX_FRAME_SIZE..=MAX_FRAME_SIZE_LIMIT).contains(value) => {
+                    return Err(H2Error::protocol("invalid SETTINGS_MAX_FRAME_SIZE"))
+                }
+                SETTINGS_MAX_FRAME_SIZE => self.max_frame_size = *value,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_round_trip() {
+        let frames = [
+            Frame::Data { stream: 1, payload: b"hello".to_vec(), end_stream: true, flow_len: 5 },
+            Frame::RstStream { stream: 3, code: CANCEL },
+            Frame::Settings { ack: false, params: vec![(SETTINGS_MAX_CONCURRENT_STREAMS, 100)] },
+            Frame::Settings { ack: true, params: Vec::new() },
+            Frame::Ping { ack: true, data: *b"12345678" },
+            Frame::GoAway { last_stream: 7, code: NO_ERROR },
+            Frame::WindowUpdate { stream: 0, increment: 1024 },
+        ];
+        for frame in frames {
+            let bytes = frame.encode();
+            assert_eq!(Frame::parse(&bytes, DEFAULT_MAX_FRAME_SIZE), Ok(Some((frame, bytes.len()))));
+            // Trame incomplète
+            assert_eq!(Frame::parse(&bytes[..bytes.len() - 1], DEFAULT_MAX_FRAME_SIZE), Ok(None));
+        }
+    }
+
+    #[test]
+    fn test_padding_and_priority() {
+        // HEADERS avec PADDED et PRIORITY: 2 octets de bourrage
+        let mut bytes = vec![0, 0, 10, HEADERS, FLAG_PADDED | FLAG_PRIORITY | FLAG_END_HEADERS, 0, 0, 0, 1];
+        bytes.extend_from_slice(&[2, 0, 0, 0, 0, 16, 0x82, 0x84, 0, 0]);
+        let (frame, _) = Frame::parse(&bytes, DEFAULT_MAX_FRAME_SIZE).unwrap().unwrap();
+        assert_eq!(
+            frame,
+            Frame::Headers { stream: 1, block: vec![0x82, 0x84], end_stream: false, end_headers: true }
+        );
+        // Le bourrage compte dans le contrôle de flux
+        let bytes = [0, 0, 4, DATA, FLAG_PADDED, 0, 0, 0, 1, 2, b'a', 0, 0];
+        let (frame, _) = Frame::parse(&bytes, DEFAULT_MAX_FRAME_SIZE).unwrap().unwrap();
+        assert_eq!(frame, Frame::Data { stream: 1, payload: b"a".to_vec(), end_stream: false, flow_len: 4 });
+        let bytes = [0, 0, 2, DATA, FLAG_PADDED, 0, 0, 0, 1, 2, b'a'];
+        assert_eq!(Frame::parse(&bytes, DEFAULT_MAX_FRAME_SIZE).unwrap_err().code, PROTOCOL_ERROR);
+    }
+
+    #[test]
+    fn test_invalid_frames() {
+        let parse = |bytes: &[u8]| Frame::parse(bytes, DEFAULT_MAX_FRAME_SIZE).unwrap_err().code;
+        // Taille au-delà de SETTINGS_MAX_FRAME_SIZE
+        assert_eq!(parse(&[0, 0x40, 1, DATA, 0, 0, 0, 0, 1]), FRAME_SIZE_ERROR);
+        assert_eq!(parse(&[0, 0, 0, DATA, 0, 0, 0, 0, 0]), PROTOCOL_ERROR);
+        assert_eq!(parse(&[0, 0, 0, SETTINGS, 0, 0, 0, 0, 1]), PROTOCOL_ERROR);
+        assert_eq!(parse(&[0, 0, 5, SETTINGS, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), FRAME_SIZE_ERROR);
+        assert_eq!(parse(&[0, 0, 4, PING, 0, 0, 0, 0, 0, 0, 0, 0, 0]), FRAME_SIZE_ERROR);
+        assert_eq!(parse(&[0, 0, 0, PUSH_PROMISE, 0, 0, 0, 0, 1]), PROTOCOL_ERROR);
+        let unknown = [0, 0, 1, 0x42, 0, 0, 0, 0, 1, 0];
+        assert_eq!(Frame::parse(&unknown, DEFAULT_MAX_FRAME_SIZE), Ok(Some((Frame::Unknown { stream: 1 }, 10))));
+    }
+
+    #[test]
+    fn test_headers_are_split() {
+        let block = vec![0x82; 40];
+        let bytes = encode_headers(1, &block, true, 16);
+        let (first, used) = Frame::parse(&bytes, 16).unwrap().unwrap();
+        assert_eq!(first, Frame::Headers { stream: 1, block: vec![0x82; 16], end_stream: true, end_headers: false });
+        let (second, more) = Frame::parse(&bytes[used..], 16).unwrap().unwrap();
+        assert!(matches!(second, Frame::Continuation { end_headers: false, .. }));
+        let (last, _) = Frame::parse(&bytes[used + more..], 16).unwrap().unwrap();
+        assert_eq!(last, Frame::Continuation { stream: 1, block: vec![0x82; 8], end_headers: true });
+    }
+
+    #[test]
+    fn test_settings() {
+        let mut settings = Settings::default();
+        settings.apply(&[(SETTINGS_INITIAL_WINDOW_SIZE, 1 << 20), (SETTINGS_MAX_FRAME_SIZE, 1 << 15), (0x99, 1)]).unwrap();
+        assert_eq!((settings.initial_window_size, settings.max_frame_size), (1 << 20, 1 << 15));
+        assert_eq!(settings.apply(&[(SETTINGS_INITIAL_WINDOW_SIZE, 1 << 31)]).unwrap_err().code, FLOW_CONTROL_ERROR);
+        assert_eq!(settings.apply(&[(SETTINGS_MAX_FRAME_SIZE, 100)]).unwrap_err().code, PROTOCOL_ERROR);
+        assert_eq!(settings.apply(&[(SETTINGS_ENABLE_PUSH, 2)]).unwrap_err().code, PROTOCOL_ERROR);
+        assert!(parse_settings(&[0, 1, 0]).is_err());
+    }
+}
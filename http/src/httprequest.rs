@@ -1,3 +1,4 @@
+use crate::multipart::Multipart;
 use std::collections::HashMap;
 #[derive(Debug, PartialEq)]
 pub enum Resource {
@@ -11,6 +12,11 @@ pub struct HttpRequest {
     pub resource: Resource,
     pub headers: HashMap<String, String>,
     pub msg_body: String,
+    pub multipart: Option<Multipart>, // corps multipart/form-data analysé pendant la lecture
+    pub form: Option<HashMap<String, Vec<String>>>, // corps application/x-www-form-urlencoded
+    pub json: Option<serde_json::Value>,            // corps application/json
+    pub params: HashMap<String, String>,            // paramètres capturés par la route
+    pub secure: bool,                               // reçue sur une connexion TLS
 }
 
 impl From<String> for HttpRequest {
@@ -29,10 +35,11 @@ impl From<String> for HttpRequest {
             // If the line read is request line, call function
             // process_req_line();
             if line.contains("HTTP") {
-                let (method, resource, version) = process_req_line(line);
-                parsed_method = method;
-                parsed_version = version;
-                parsed_resource = resource;
+                if let Ok((method, resource, version)) = process_req_line(line) {
+                    parsed_method = method;
+                    parsed_version = version;
+                    parsed_resource = resource;
+                }
                 // If the line read is header line, call function
                 // process_header_line();
             } else if line.contains(":") {
@@ -50,6 +57,11 @@ impl From<String> for HttpRequest {
             resource: parsed_resource,
             headers: parsed_headers,
             msg_body: parsed_msg_body.to_string(),
+            multipart: None,
+            form: None,
+            json: None,
+            params: HashMap::new(),
+            secure: false,
         }
     }
 }
@@ -68,28 +80,77 @@ impl HttpRequest {
             resource,
             headers,
             msg_body,
+            multipart: None,
+            form: None,
+            json: None,
+            params: HashMap::new(),
+            secure: false,
         }
     }
+
+    pub fn with_multipart(mut self, multipart: Multipart) -> Self {
+        self.multipart = Some(multipart);
+        self
+    }
+
+    // Type de média du corps, sans paramètres ni casse: "application/json"
+    pub fn content_type(&self) -> Option<String> {
+        self.headers.get("Content-Type").map(|value| {
+            value
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase()
+        })
+    }
+
+    // Décode les corps urlencoded et JSON selon l'en-tête Content-Type
+    pub fn decode_body(&mut self, body: &[u8]) -> Result<(), String> {
+        match self.content_type().as_deref() {
+            Some("application/x-www-form-urlencoded") => {
+                let body = std::str::from_utf8(body).map_err(|e| e.to_string())?;
+                self.form = Some(parse_urlencoded(body));
+            }
+            Some(media) if media == "application/json" || media.ends_with("+json") => {
+                let value = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+                self.json = Some(value);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Première valeur d'un champ de formulaire urlencoded
+    pub fn form_value(&self, name: &str) -> Option<&str> {
+        self.form
+            .as_ref()?
+            .get(name)?
+            .first()
+            .map(String::as_str)
+    }
 }
 
-pub fn process_req_line(s: &str) -> (Method, Resource, Version) {
+// Une ligne de requête a exactement trois parties: "GET /index.html HTTP/1.1"
+pub fn process_req_line(s: &str) -> Result<(Method, Resource, Version), &'static str> {
     // Parse the request line into individual chunks split by whitespaces.
     let mut words = s.split_whitespace();
-    // Extract the HTTP method from first part of the request line
-    let method = words.next().expect("faild to take next");
-    // Extract the resource (URI/URL) from second part of the request line
-    let resource = words.next().expect("faild to take next");
-    // Extract the HTTP version from third part of the request line
-    let version = words.next().expect("faild to take next");
-    (
+    // Extract the HTTP method, the resource (URI/URL) and the HTTP version
+    let (Some(method), Some(resource), Some(version), None) =
+        (words.next(), words.next(), words.next(), words.next())
+    else {
+        return Err("malformed request line");
+    };
+    Ok((
         method.into(),
         Resource::Path(resource.to_string()),
         version.into(),
-    )
+    ))
 }
 pub fn process_header_line(s: &str) -> (String, String) {
-    // Parse the header line into words split by separator (':')
-    let mut header_items = s.split(":");
+    // Parse the header line into words split by the first separator (':'),
+    // values such as `Host: localhost:8080` keep their own colons
+    let mut header_items = s.splitn(2, ":");
     let mut key = String::from("");
     let mut value = String::from("");
     // Extract the key part of the header
@@ -103,11 +164,60 @@ pub fn process_header_line(s: &str) -> (String, String) {
     (key, value)
 }
 
+// a=1&b=x+y&a=2 -> {a: [1, 2], b: ["x y"]}
+pub fn parse_urlencoded(s: &str) -> HashMap<String, Vec<String>> {
+    let mut fields: HashMap<String, Vec<String>> = HashMap::new();
+    for pair in s.trim().split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        fields
+            .entry(percent_decode(key))
+            .or_default()
+            .push(percent_decode(value));
+    }
+    fields
+}
+
+// Décodage des formulaires: "+" représente une espace
+pub fn percent_decode(s: &str) -> String {
+    decode(s, true)
+}
+
+// Décodage d'un segment de chemin, où "+" reste un "+"
+pub fn percent_decode_path(s: &str) -> String {
+    decode(s, false)
+}
+
+fn decode(s: &str, plus_as_space: bool) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_as_space => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len()
+                && bytes[i + 1].is_ascii_hexdigit()
+                && bytes[i + 2].is_ascii_hexdigit() =>
+            {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("00");
+                decoded.push(u8::from_str_radix(hex, 16).unwrap_or(0));
+                i += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Method {
     Get,
     Post,
     Delete,
+    Put,
+    Patch,
+    Head,
+    Options,
     Uninitialized,
 }
 
@@ -117,6 +227,10 @@ impl From<&str> for Method {
             "GET" => Method::Get,
             "POST" => Method::Post,
             "DELETE" => Method::Delete,
+            "PUT" => Method::Put,
+            "PATCH" => Method::Patch,
+            "HEAD" => Method::Head,
+            "OPTIONS" => Method::Options,
             _ => Method::Uninitialized,
         }
     }
@@ -124,6 +238,7 @@ impl From<&str> for Method {
 
 #[derive(Debug, PartialEq)]
 pub enum Version {
+    V1_0,
     V1_1,
     V2_0,
     Uninitialized,
@@ -132,6 +247,7 @@ pub enum Version {
 impl From<&str> for Version {
     fn from(s: &str) -> Version {
         match s {
+            "HTTP/1.0" => Version::V1_0,
             "HTTP/1.1" => Version::V1_1,
             "HTTP/2.0" => Version::V2_0,
             _ => Version::Uninitialized,
@@ -139,6 +255,15 @@ impl From<&str> for Version {
     }
 }
 
+// Le client attend "100 Continue" avant d'envoyer le corps; l'en-tête est
+// ignoré en HTTP/1.0
+pub fn expects_continue(version: &Version, headers: &HashMap<String, String>) -> bool {
+    *version == Version::V1_1
+        && headers.iter().any(|(key, value)| {
+            key.trim().eq_ignore_ascii_case("Expect") && value.trim().eq_ignore_ascii_case("100-continue")
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -146,10 +271,74 @@ mod tests {
     fn test_method_into() {
         let m: Method = "GET".into();
         assert_eq!(m, Method::Get);
+        assert_eq!(Method::from("PATCH"), Method::Patch);
+        assert_eq!(Method::from("OPTIONS"), Method::Options);
+        assert_eq!(Method::from("BREW"), Method::Uninitialized);
+    }
+    #[test]
+    fn test_process_req_line() {
+        let (method, resource, version) = process_req_line("GET /index.html HTTP/1.1\r\n").unwrap();
+        assert_eq!(method, Method::Get);
+        assert_eq!(resource, Resource::Path("/index.html".to_string()));
+        assert_eq!(version, Version::V1_1);
+        assert!(process_req_line("X HTTP\r\n").is_err());
+        assert!(process_req_line("GET / HTTP/1.1 extra").is_err());
     }
     #[test]
     fn test_version_into() {
         let m: Version = "HTTP/1.1".into();
         assert_eq!(m, Version::V1_1);
+        assert_eq!(Version::from("HTTP/1.0"), Version::V1_0);
+        assert_eq!(Version::from("HTTP/3.0"), Version::Uninitialized);
+    }
+    #[test]
+    fn test_expects_continue() {
+        let mut headers = HashMap::new();
+        headers.insert("expect".to_string(), " 100-Continue\r\n".to_string());
+        assert!(expects_continue(&Version::V1_1, &headers));
+        assert!(!expects_continue(&Version::V1_0, &headers));
+        headers.insert("expect".to_string(), " 200-ok\r\n".to_string());
+        assert!(!expects_continue(&Version::V1_1, &headers));
+    }
+    #[test]
+    fn test_parse_urlencoded() {
+        let form = parse_urlencoded("name=Ada+Lovelace&tag=a&tag=b%26c&empty=&flag");
+        assert_eq!(form["name"], vec!["Ada Lovelace"]);
+        assert_eq!(form["tag"], vec!["a", "b&c"]);
+        assert_eq!(form["empty"], vec![""]);
+        assert_eq!(form["flag"], vec![""]);
+        assert_eq!(percent_decode("%C3%A9t%C3%A9%2"), "été%2");
+        assert_eq!(percent_decode_path("c++/a%20b+c"), "c++/a b+c");
+    }
+    #[test]
+    fn test_decode_body() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "Content-Type".to_string(),
+            " application/json; charset=utf-8\r\n".to_string(),
+        );
+        let mut req = HttpRequest::new(
+            Method::Post,
+            Version::V1_1,
+            Resource::Path("/api/".to_string()),
+            headers,
+            String::new(),
+        );
+        req.decode_body(br#"{"order_id": 3}"#).unwrap();
+        assert_eq!(req.json.as_ref().unwrap()["order_id"], 3);
+        assert!(req.decode_body(b"{not json").is_err());
+
+        req.headers.insert(
+            "Content-Type".to_string(),
+            " application/x-www-form-urlencoded".to_string(),
+        );
+        req.decode_body(b"csrf_token=abc&x=1").unwrap();
+        assert_eq!(req.form_value("csrf_token"), Some("abc"));
+    }
+    #[test]
+    fn test_header_value_with_colon() {
+        let (key, value) = process_header_line("Origin: http://localhost:8080");
+        assert_eq!(key, "Origin");
+        assert_eq!(value, " http://localhost:8080");
     }
 }
@@ -2,12 +2,16 @@
 use std::collections::HashMap;
 use std::io::{Result, Write};
 
+// Réponse intermédiaire à une requête portant "Expect: 100-continue"
+pub const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct HttpResponse<'a> {
     pub version: &'a str,
     pub status_code: String,
     pub status_text: &'a str,
     pub headers: Option<HashMap<&'a str, &'a str>>,
+    pub owned_headers: Vec<(&'a str, String)>, // valeurs construites pour cette réponse (Location...)
     pub body: Option<String>,
 }
 
@@ -18,6 +22,7 @@ impl<'a> Default for HttpResponse<'a> {
             status_code: "200".into(),
             status_text: "OK".into(),
             headers: None,
+            owned_headers: Vec::new(),
             body: None,
         }
     }
@@ -57,6 +62,24 @@ impl<'a> HttpResponse<'a> {
         Ok(())
     }
 
+    // En-tête dont la valeur appartient à la réponse; remplace une valeur
+    // déjà présente sous le même nom
+    pub fn set_header(&mut self, name: &'a str, value: String) {
+        if let Some(headers) = self.headers.as_mut() {
+            headers.remove(name);
+        }
+        self.owned_headers.retain(|(existing, _)| *existing != name);
+        self.owned_headers.push((name, value));
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.owned_headers
+            .iter()
+            .find(|(existing, _)| *existing == name)
+            .map(|(_, value)| value.as_str())
+            .or_else(|| self.headers.as_ref()?.get(name).copied())
+    }
+
     pub fn get_version(&self) -> &str {
         self.version
     }
@@ -72,6 +95,9 @@ impl<'a> HttpResponse<'a> {
         for (k, v) in map.iter() {
             header_string = format!("{}{}:{}\r\n", header_string, k, v);
         }
+        for (k, v) in &self.owned_headers {
+            header_string = format!("{}{}:{}\r\n", header_string, k, v);
+        }
         header_string
     }
     pub fn get_body(&self) -> &str {
@@ -100,6 +126,22 @@ impl<'a> From<HttpResponse<'a>> for String {
 pub fn get_status_code_text_n_message(code: &str) -> (&str, &str, &str) {
     match code {
         "200" => ("200", "OK", "The request was successful."),
+        "201" => ("201", "Created", "The request succeeded and a new resource was created."),
+        "301" => ("301", "Moved Permanently", "The resource has been moved permanently."),
+        "302" => ("302", "Found", "The resource has been moved temporarily."),
+        "303" => ("303", "See Other", "The response can be found at another location."),
+        "307" =>
+            (
+                "307",
+                "Temporary Redirect",
+                "The resource has been moved temporarily, repeat the request there.",
+            ),
+        "308" =>
+            (
+                "308",
+                "Permanent Redirect",
+                "The resource has been moved permanently, repeat the request there.",
+            ),
         "400" =>
             (
                 "400",
@@ -116,12 +158,45 @@ pub fn get_status_code_text_n_message(code: &str) -> (&str, &str, &str) {
                 "The method specified in the request is not allowed for the resource identified by the request URI.",
             ),
         "408" => ("408", "Request Timeout", "The request timed out due to a timeout"),
+        "409" =>
+            (
+                "409",
+                "Conflict",
+                "The request conflicts with the current state of the target resource.",
+            ),
+        "415" =>
+            (
+                "415",
+                "Unsupported Media Type",
+                "The request body is in a format not supported by the resource.",
+            ),
         "413" =>
             (
                 "413",
                 "Payload Too Large",
                 "The server is unwilling to process the request because its payload is too large.",
             ),
+        "426" =>
+            (
+                "426",
+                "Upgrade Required",
+                "The server refuses to perform the request using the current protocol.",
+            ),
+        "501" =>
+            (
+                "501",
+                "Not Implemented",
+                "The server does not support the functionality required to fulfill the request.",
+            ),
+        "502" =>
+            ("502", "Bad Gateway", "The server received an invalid response from the upstream server."),
+        "504" => ("504", "Gateway Timeout", "The upstream server did not respond in time."),
+        "505" =>
+            (
+                "505",
+                "HTTP Version Not Supported",
+                "The server does not support the HTTP version used in the request.",
+            ),
         _ => ("500", "Internal Server Error", "An unexpected error occurred"),
     }
 }
@@ -147,6 +222,7 @@ mod tests {
                 h.insert("Content-Type", "text/html");
                 Some(h)
             },
+            owned_headers: Vec::new(),
             body: Some("Item was shipped on 21st Dec 2020".into()),
         };
         assert_eq!(response_actual, response_expected);
@@ -170,6 +246,7 @@ mod tests {
                 h.insert("Content-Type", "text/html");
                 Some(h)
             },
+            owned_headers: Vec::new(),
             body: Some("Item was shipped on 21st Dec 2020".into()),
         };
         assert_eq!(response_actual, response_expected);
@@ -186,6 +263,7 @@ mod tests {
                 h.insert("Content-Type", "text/html");
                 Some(h)
             },
+            owned_headers: Vec::new(),
             body: Some("Item was shipped on 21st Dec 2020".into()),
         };
         let http_string: String = response_expected.into();
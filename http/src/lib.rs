@@ -1,3 +1,7 @@
 // http/lib.rs
+pub mod hpack;
+pub mod http2;
 pub mod httprequest;
 pub mod httpresponse;
+pub mod multipart;
+pub mod websocket;
@@ -0,0 +1,552 @@
+// http/multipart.rs
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Taille maximale de la section d'en-têtes d'une partie
+const MAX_PART_HEADERS: usize = 8 * 1024;
+
+static SPOOL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Extrait le paramètre boundary d'un en-tête Content-Type multipart
+pub fn boundary(content_type: &str) -> Option<String> {
+    content_type
+        .split(';')
+        .map(str::trim)
+        .find_map(|param| param.strip_prefix("boundary="))
+        .map(|b| b.trim().trim_matches('"').to_string())
+        .filter(|b| !b.is_empty())
+}
+
+#[derive(Debug)]
+pub enum PartData {
+    Bytes(Vec<u8>),
+    File { path: PathBuf, size: usize },
+}
+
+#[derive(Debug)]
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub headers: HashMap<String, String>,
+    pub data: PartData,
+}
+
+impl Part {
+    // Recherche d'un en-tête de la partie sans tenir compte de la casse
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    pub fn size(&self) -> usize {
+        match &self.data {
+            PartData::Bytes(bytes) => bytes.len(),
+            PartData::File { size, .. } => *size,
+        }
+    }
+
+    // Contenu en mémoire d'un champ (None pour un fichier stocké sur disque)
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match &self.data {
+            PartData::Bytes(bytes) => Some(bytes),
+            PartData::File { .. } => None,
+        }
+    }
+
+    // Enregistre le contenu de la partie à l'emplacement donné
+    pub fn persist(&self, dest: &Path) -> io::Result<()> {
+        match &self.data {
+            PartData::Bytes(bytes) => fs::write(dest, bytes),
+            PartData::File { path, .. } => {
+                if fs::rename(path, dest).is_err() {
+                    // rename échoue entre deux systèmes de fichiers
+                    fs::copy(path, dest)?;
+                    fs::remove_file(path)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Multipart {
+    pub parts: Vec<Part>,
+}
+
+impl Multipart {
+    pub fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &Part> {
+        self.parts.iter().filter(|part| part.is_file())
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = &Part> {
+        self.parts.iter().filter(|part| !part.is_file())
+    }
+
+    // Première valeur d'un champ texte
+    pub fn field(&self, name: &str) -> Option<&[u8]> {
+        self.fields().find(|part| part.name == name)?.bytes()
+    }
+
+    // Toutes les valeurs d'un champ répété
+    pub fn field_values(&self, name: &str) -> Vec<&[u8]> {
+        self.fields()
+            .filter(|part| part.name == name)
+            .filter_map(|part| part.bytes())
+            .collect()
+    }
+}
+
+impl Drop for Multipart {
+    // Supprime les fichiers temporaires qui n'ont pas été déplacés
+    fn drop(&mut self) {
+        for part in &self.parts {
+            if let PartData::File { path, .. } = &part.data {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    Preamble,
+    AfterBoundary,
+    Headers,
+    Body,
+    Done,
+}
+
+enum Sink {
+    Memory(Vec<u8>),
+    Disk { file: File, path: PathBuf, size: usize },
+}
+
+struct CurrentPart {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    headers: HashMap<String, String>,
+    sink: Sink,
+}
+
+// Analyseur multipart/form-data incrémental: les octets sont fournis au fur et
+// à mesure de leur lecture sur la socket, les fichiers sont écrits sur disque
+pub struct MultipartParser {
+    delimiter: Vec<u8>, // "\r\n--boundary"
+    state: State,
+    buffer: Vec<u8>,
+    current: Option<CurrentPart>,
+    spool_dir: Option<PathBuf>,
+    max_file_size: Option<usize>,
+    result: Multipart,
+}
+
+impl MultipartParser {
+    // Sans répertoire d'attente, les fichiers sont conservés en mémoire
+    pub fn new(boundary: &str, spool_dir: Option<&Path>) -> Self {
+        MultipartParser {
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            state: State::Preamble,
+            buffer: Vec::new(),
+            current: None,
+            spool_dir: spool_dir.map(Path::to_path_buf),
+            max_file_size: None,
+            result: Multipart::default(),
+        }
+    }
+
+    // Taille maximale de chaque fichier: l'analyse s'arrête avec une erreur
+    // FileTooLarge dès qu'elle est dépassée
+    pub fn with_max_file_size(mut self, max_file_size: Option<usize>) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.buffer.extend_from_slice(chunk);
+        loop {
+            let progressed = match self.state {
+                State::Preamble => self.parse_preamble(),
+                State::AfterBoundary => self.parse_after_boundary()?,
+                State::Headers => self.parse_headers()?,
+                State::Body => self.parse_body()?,
+                State::Done => {
+                    // L'épilogue est ignoré
+                    self.buffer.clear();
+                    false
+                }
+            };
+            if !progressed {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<Multipart> {
+        if self.state != State::Done {
+            return Err(invalid("unexpected end of multipart body"));
+        }
+        Ok(std::mem::take(&mut self.result))
+    }
+
+    fn parse_preamble(&mut self) -> bool {
+        // Le premier délimiteur peut apparaître sans CRLF initial
+        let first = &self.delimiter[2..];
+        match find_bytes(&self.buffer, first) {
+            Some(pos) => {
+                self.buffer.drain(..pos + first.len());
+                self.state = State::AfterBoundary;
+                true
+            }
+            None => {
+                let keep = first.len().saturating_sub(1).min(self.buffer.len());
+                self.buffer.drain(..self.buffer.len() - keep);
+                false
+            }
+        }
+    }
+
+    fn parse_after_boundary(&mut self) -> io::Result<bool> {
+        // Espaces de remplissage tolérés après le délimiteur
+        let padding = self
+            .buffer
+            .iter()
+            .take_while(|b| **b == b' ' || **b == b'\t')
+            .count();
+        if self.buffer.len() < padding + 2 {
+            return Ok(false);
+        }
+        let marker = &self.buffer[padding..padding + 2];
+        if marker == b"--" {
+            self.state = State::Done;
+        } else if marker == b"\r\n" {
+            self.state = State::Headers;
+        } else {
+            return Err(invalid("malformed multipart boundary"));
+        }
+        self.buffer.drain(..padding + 2);
+        Ok(true)
+    }
+
+    fn parse_headers(&mut self) -> io::Result<bool> {
+        // Une section d'en-têtes vide commence directement par CRLF
+        let end = if self.buffer.starts_with(b"\r\n") {
+            Some(0)
+        } else {
+            find_bytes(&self.buffer, b"\r\n\r\n")
+        };
+        let end = match end {
+            Some(end) => end,
+            None => {
+                if self.buffer.len() > MAX_PART_HEADERS {
+                    return Err(invalid("multipart part headers too large"));
+                }
+                return Ok(false);
+            }
+        };
+        let raw = String::from_utf8_lossy(&self.buffer[..end]).into_owned();
+        let consumed = if end == 0 { 2 } else { end + 4 };
+        self.buffer.drain(..consumed);
+
+        let mut headers = HashMap::new();
+        for line in raw.split("\r\n").filter(|line| !line.is_empty()) {
+            if let Some((key, value)) = line.split_once(':') {
+                headers.insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+        let disposition = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Content-Disposition"))
+            .map(|(_, v)| v.clone())
+            .ok_or_else(|| invalid("Content-Disposition not found"))?;
+        let params = disposition_params(&disposition);
+        let name = params.get("name").cloned().unwrap_or_default();
+        let filename = params.get("filename").cloned();
+        let content_type = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Content-Type"))
+            .map(|(_, v)| v.clone());
+
+        let sink = match (&filename, &self.spool_dir) {
+            (Some(_), Some(dir)) => {
+                let path = dir.join(format!(
+                    "upload-{}-{}.part",
+                    std::process::id(),
+                    SPOOL_COUNTER.fetch_add(1, Ordering::Relaxed)
+                ));
+                let file = File::create(&path)?;
+                Sink::Disk { file, path, size: 0 }
+            }
+            _ => Sink::Memory(Vec::new()),
+        };
+        self.current = Some(CurrentPart {
+            name,
+            filename,
+            content_type,
+            headers,
+            sink,
+        });
+        self.state = State::Body;
+        Ok(true)
+    }
+
+    fn parse_body(&mut self) -> io::Result<bool> {
+        match find_bytes(&self.buffer, &self.delimiter) {
+            Some(pos) => {
+                let data: Vec<u8> = self.buffer.drain(..pos + self.delimiter.len()).collect();
+                self.write_current(&data[..pos])?;
+                self.close_current()?;
+                self.state = State::AfterBoundary;
+                Ok(true)
+            }
+            None => {
+                // Garde la fin du tampon, qui peut contenir un début de délimiteur
+                let keep = (self.delimiter.len() - 1).min(self.buffer.len());
+                let data: Vec<u8> = self.buffer.drain(..self.buffer.len() - keep).collect();
+                self.write_current(&data)?;
+                Ok(false)
+            }
+        }
+    }
+
+    fn write_current(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let Some(part) = self.current.as_mut() else {
+            return Ok(());
+        };
+        let written = match &part.sink {
+            Sink::Memory(bytes) => bytes.len(),
+            Sink::Disk { size, .. } => *size,
+        };
+        if part.filename.is_some() && self.max_file_size.is_some_and(|max| written + data.len() > max) {
+            return Err(io::Error::new(io::ErrorKind::FileTooLarge, "multipart file too large"));
+        }
+        match &mut part.sink {
+            Sink::Memory(bytes) => bytes.extend_from_slice(data),
+            Sink::Disk { file, size, .. } => {
+                file.write_all(data)?;
+                *size += data.len();
+            }
+        }
+        Ok(())
+    }
+
+    fn close_current(&mut self) -> io::Result<()> {
+        if let Some(part) = self.current.take() {
+            let data = match part.sink {
+                Sink::Memory(bytes) => PartData::Bytes(bytes),
+                Sink::Disk { mut file, path, size } => {
+                    file.flush()?;
+                    PartData::File { path, size }
+                }
+            };
+            self.result.parts.push(Part {
+                name: part.name,
+                filename: part.filename,
+                content_type: part.content_type,
+                headers: part.headers,
+                data,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MultipartParser {
+    // Un envoi interrompu ne doit pas laisser de fichier temporaire
+    fn drop(&mut self) {
+        if let Some(CurrentPart {
+            sink: Sink::Disk { path, .. },
+            ..
+        }) = &self.current
+        {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// Analyse un corps multipart déjà entièrement en mémoire
+pub fn parse(body: &[u8], boundary: &str) -> io::Result<Multipart> {
+    let mut parser = MultipartParser::new(boundary, None);
+    parser.feed(body)?;
+    parser.finish()
+}
+
+// form-data; name="a"; filename="b.txt" -> {name: a, filename: b.txt}
+fn disposition_params(value: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = value;
+    while let Some(pos) = rest.find(';') {
+        rest = rest[pos + 1..].trim_start();
+        let (key, after) = match rest.split_once('=') {
+            Some(kv) => kv,
+            None => break,
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let (val, remaining) = if let Some(quoted) = after.strip_prefix('"') {
+            // Valeur entre guillemets, avec échappements \" éventuels
+            let mut val = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some((_, next)) = chars.next() {
+                            val.push(next);
+                        }
+                    }
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    _ => val.push(c),
+                }
+            }
+            (val, &quoted[end.min(quoted.len())..])
+        } else {
+            let end = after.find(';').unwrap_or(after.len());
+            (after[..end].trim().to_string(), &after[end..])
+        };
+        params.insert(key, val);
+        rest = remaining;
+    }
+    params
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "----WebKitFormBoundary7MA4YWxkTrZu0gW";
+
+    fn body() -> Vec<u8> {
+        format!(
+            "preamble\r\n--{b}\r\nContent-Disposition: form-data; name=\"tag\"\r\n\r\none\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"tag\"\r\n\r\ntwo\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\nHello\r\nWorld\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"b.bin\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n\x00\x01--\r\n\
+             --{b}--\r\nepilogue",
+            b = BOUNDARY
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_boundary() {
+        assert_eq!(
+            boundary(" multipart/form-data; boundary=\"abc\"; charset=utf-8\r\n"),
+            Some("abc".to_string())
+        );
+        assert_eq!(boundary("multipart/form-data"), None);
+    }
+
+    #[test]
+    fn test_parse_multiple_files_and_fields() {
+        let multipart = parse(&body(), BOUNDARY).expect("valid multipart body");
+        assert_eq!(multipart.parts.len(), 4);
+        assert_eq!(multipart.field_values("tag"), vec![&b"one"[..], &b"two"[..]]);
+
+        let files: Vec<&Part> = multipart.files().collect();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].filename.as_deref(), Some("a.txt"));
+        assert_eq!(files[0].content_type.as_deref(), Some("text/plain"));
+        assert_eq!(files[0].bytes(), Some(&b"Hello\r\nWorld"[..]));
+        assert_eq!(files[1].bytes(), Some(&b"\x00\x01--"[..]));
+        assert_eq!(files[1].header("content-type"), Some("application/octet-stream"));
+    }
+
+    #[test]
+    fn test_streaming_byte_by_byte_to_disk() {
+        let dir = std::env::temp_dir();
+        let mut parser = MultipartParser::new(BOUNDARY, Some(&dir));
+        for byte in body() {
+            parser.feed(&[byte]).expect("valid chunk");
+        }
+        let multipart = parser.finish().expect("complete body");
+        let file = multipart.files().next().expect("file part");
+        let path = match &file.data {
+            PartData::File { path, size } => {
+                assert_eq!(*size, 12);
+                path.clone()
+            }
+            PartData::Bytes(_) => panic!("file should be spooled to disk"),
+        };
+        assert_eq!(fs::read(&path).unwrap(), b"Hello\r\nWorld");
+
+        let dest = dir.join("multipart_test_a.txt");
+        file.persist(&dest).expect("persist upload");
+        assert_eq!(fs::read(&dest).unwrap(), b"Hello\r\nWorld");
+        drop(multipart);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_max_file_size_aborts_while_streaming() {
+        let dir = std::env::temp_dir();
+        let mut parser = MultipartParser::new(BOUNDARY, Some(&dir)).with_max_file_size(Some(4));
+        let body = body();
+        let error = body
+            .chunks(3)
+            .find_map(|chunk| parser.feed(chunk).err())
+            .expect("file exceeds the limit");
+        assert_eq!(error.kind(), io::ErrorKind::FileTooLarge);
+        let spooled = match &parser.current {
+            Some(CurrentPart { sink: Sink::Disk { path, size, .. }, .. }) => {
+                assert!(*size <= 4);
+                path.clone()
+            }
+            _ => panic!("file should be spooled to disk"),
+        };
+        drop(parser);
+        assert!(!spooled.exists());
+
+        let mut parser = MultipartParser::new(BOUNDARY, None).with_max_file_size(Some(12));
+        parser.feed(&body).expect("files within the limit");
+        assert_eq!(parser.finish().unwrap().files().count(), 2);
+    }
+
+    #[test]
+    fn test_truncated_body() {
+        let body = body();
+        let mut parser = MultipartParser::new(BOUNDARY, None);
+        parser.feed(&body[..body.len() / 2]).unwrap();
+        assert!(parser.finish().is_err());
+    }
+
+    #[test]
+    fn test_disposition_params() {
+        let params = disposition_params("form-data; name=\"up\"; filename=\"a \\\"b\\\";c.txt\"");
+        assert_eq!(params.get("name").map(String::as_str), Some("up"));
+        assert_eq!(params.get("filename").map(String::as_str), Some("a \"b\";c.txt"));
+    }
+}
@@ -0,0 +1,452 @@
+// http/websocket.rs
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+
+// Constante de la RFC 6455 concaténée à la clé du client
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Une trame de contrôle ne peut pas être fragmentée ni dépasser 125 octets
+pub const MAX_CONTROL_PAYLOAD: usize = 125;
+
+// Codes de fermeture utilisés par le serveur
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+pub const CLOSE_POLICY: u16 = 1008;
+pub const CLOSE_TOO_BIG: u16 = 1009;
+
+// Erreur de protocole: la connexion est fermée avec `code`
+#[derive(Debug, Clone, PartialEq)]
+pub struct WsError {
+    pub code: u16,
+    pub reason: &'static str,
+}
+
+impl WsError {
+    fn protocol(reason: &'static str) -> Self {
+        WsError { code: CLOSE_PROTOCOL_ERROR, reason }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HandshakeError {
+    // Version autre que 13: réponse 426 avec Sec-WebSocket-Version
+    UnsupportedVersion,
+    Invalid(&'static str),
+}
+
+// Valeur de Sec-WebSocket-Accept pour la clé envoyée par le client
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(GUID.as_bytes());
+    STANDARD.encode(hasher.finalize())
+}
+
+// Recherche d'un en-tête sans tenir compte de la casse; les valeurs du
+// parseur conservent les espaces de tête
+pub fn header<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.trim())
+}
+
+fn has_token(value: Option<&str>, token: &str) -> bool {
+    value.is_some_and(|value| value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+}
+
+// La requête demande-t-elle un passage en WebSocket ?
+pub fn is_upgrade(headers: &HashMap<String, String>) -> bool {
+    has_token(header(headers, "Upgrade"), "websocket")
+}
+
+// Vérifie la poignée de main d'ouverture et retourne Sec-WebSocket-Accept
+pub fn handshake(headers: &HashMap<String, String>) -> Result<String, HandshakeError> {
+    if !is_upgrade(headers) {
+        return Err(HandshakeError::Invalid("missing Upgrade: websocket"));
+    }
+    if !has_token(header(headers, "Connection"), "upgrade") {
+        return Err(HandshakeError::Invalid("missing Connection: Upgrade"));
+    }
+    if header(headers, "Sec-WebSocket-Version") != Some("13") {
+        return Err(HandshakeError::UnsupportedVersion);
+    }
+    let key = header(headers, "Sec-WebSocket-Key").ok_or(HandshakeError::Invalid("missing Sec-WebSocket-Key"))?;
+    match STANDARD.decode(key) {
+        Ok(nonce) if nonce.len() == 16 => Ok(accept_key(key)),
+        _ => Err(HandshakeError::Invalid("invalid Sec-WebSocket-Key")),
+    }
+}
+
+// Réponse 101 envoyée telle quelle: un statut 1xx n'a ni corps ni
+// Content-Length
+pub fn handshake_response(accept: &str) -> String {
+    format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(value: u8) -> Option<Opcode> {
+        match value {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    pub fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(opcode: Opcode, payload: Vec<u8>) -> Self {
+        Frame { fin: true, opcode, payload }
+    }
+
+    // La raison est tronquée pour rester dans une trame de contrôle
+    pub fn close(code: u16, reason: &str) -> Self {
+        let mut payload = code.to_be_bytes().to_vec();
+        let mut end = reason.len().min(MAX_CONTROL_PAYLOAD - 2);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        Frame::new(Opcode::Close, payload)
+    }
+
+    // Décode la trame au début de `buf`: None tant qu'elle n'est pas complète,
+    // sinon la trame et le nombre d'octets consommés. Le client doit masquer
+    // ses trames et le serveur jamais (`masked` attendu selon le côté lu).
+    pub fn parse(buf: &[u8], masked: bool, max_payload: usize) -> Result<Option<(Frame, usize)>, WsError> {
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+        if buf[0] & 0x70 != 0 {
+            return Err(WsError::protocol("reserved bits set"));
+        }
+        let fin = buf[0] & 0x80 != 0;
+        let opcode = Opcode::from_u8(buf[0] & 0x0F).ok_or(WsError::protocol("unknown opcode"))?;
+        if buf[1] & 0x80 != 0 && !masked {
+            return Err(WsError::protocol("unexpected masked frame"));
+        }
+        if buf[1] & 0x80 == 0 && masked {
+            return Err(WsError::protocol("unmasked client frame"));
+        }
+        let (len, mut offset) = match buf[1] & 0x7F {
+            126 if buf.len() < 4 => return Ok(None),
+            126 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+            127 if buf.len() < 10 => return Ok(None),
+            127 => (u64::from_be_bytes(buf[2..10].try_into().unwrap_or_default()), 10),
+            len => (len as u64, 2),
+        };
+        if opcode.is_control() && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+            return Err(WsError::protocol("invalid control frame"));
+        }
+        if len > max_payload as u64 {
+            return Err(WsError { code: CLOSE_TOO_BIG, reason: "frame too big" });
+        }
+        let len = len as usize;
+        let mask = if masked {
+            if buf.len() < offset + 4 {
+                return Ok(None);
+            }
+            offset += 4;
+            Some([buf[offset - 4], buf[offset - 3], buf[offset - 2], buf[offset - 1]])
+        } else {
+            None
+        };
+        if buf.len() < offset + len {
+            return Ok(None);
+        }
+        let mut payload = buf[offset..offset + len].to_vec();
+        if let Some(mask) = mask {
+            apply_mask(&mut payload, mask);
+        }
+        Ok(Some((Frame { fin, opcode, payload }, offset + len)))
+    }
+
+    // Encode la trame; `mask` est obligatoire pour une trame envoyée par un
+    // client (le serveur relayant vers un amont)
+    pub fn encode(&self, mask: Option<[u8; 4]>) -> Vec<u8> {
+        let len = self.payload.len();
+        let mut out = Vec::with_capacity(len + 14);
+        out.push(if self.fin { 0x80 } else { 0 } | self.opcode.as_u8());
+        let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+        match len {
+            0..=125 => out.push(mask_bit | len as u8),
+            126..=0xFFFF => {
+                out.push(mask_bit | 126);
+                out.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            _ => {
+                out.push(mask_bit | 127);
+                out.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        match mask {
+            Some(mask) => {
+                out.extend_from_slice(&mask);
+                let start = out.len();
+                out.extend_from_slice(&self.payload);
+                apply_mask(&mut out[start..], mask);
+            }
+            None => out.extend_from_slice(&self.payload),
+        }
+        out
+    }
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+// Code et raison d'une trame de fermeture; les codes réservés ou inconnus
+// sont une erreur de protocole
+pub fn close_reason(payload: &[u8]) -> Result<Option<(u16, String)>, WsError> {
+    match payload.len() {
+        0 => return Ok(None),
+        1 => return Err(WsError::protocol("invalid close payload")),
+        _ => {}
+    }
+    let code = u16::from_be_bytes([payload[0], payload[1]]);
+    let valid = matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999);
+    if !valid {
+        return Err(WsError::protocol("invalid close code"));
+    }
+    let reason = String::from_utf8(payload[2..].to_vec())
+        .map_err(|_| WsError { code: CLOSE_INVALID_DATA, reason: "invalid close reason" })?;
+    Ok(Some((code, reason)))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<(u16, String)>),
+}
+
+impl Message {
+    pub fn into_frame(self) -> Frame {
+        match self {
+            Message::Text(text) => Frame::new(Opcode::Text, text.into_bytes()),
+            Message::Binary(data) => Frame::new(Opcode::Binary, data),
+            Message::Ping(data) => Frame::new(Opcode::Ping, data),
+            Message::Pong(data) => Frame::new(Opcode::Pong, data),
+            Message::Close(Some((code, reason))) => Frame::close(code, &reason),
+            Message::Close(None) => Frame::new(Opcode::Close, Vec::new()),
+        }
+    }
+}
+
+// Reconstitue les messages fragmentés. Les trames de contrôle peuvent
+// s'intercaler entre les fragments d'un message.
+#[derive(Debug)]
+pub struct Assembler {
+    max_message: usize,
+    partial: Option<(Opcode, Vec<u8>)>,
+    size: usize,
+}
+
+impl Assembler {
+    pub fn new(max_message: usize) -> Self {
+        Assembler { max_message, partial: None, size: 0 }
+    }
+
+    // Vérifie l'enchaînement des fragments et la taille cumulée du message
+    // sans conserver les données (relais vers un amont)
+    pub fn check(&mut self, frame: &Frame) -> Result<(), WsError> {
+        if frame.opcode.is_control() {
+            return Ok(());
+        }
+        let continuing = self.size > 0 || self.partial.is_some();
+        match (frame.opcode, continuing) {
+            (Opcode::Continuation, false) => return Err(WsError::protocol("unexpected continuation frame")),
+            (Opcode::Text | Opcode::Binary, true) => return Err(WsError::protocol("expected continuation frame")),
+            _ => {}
+        }
+        let size = self.size + frame.payload.len();
+        if size > self.max_message {
+            return Err(WsError { code: CLOSE_TOO_BIG, reason: "message too big" });
+        }
+        // Taille comptée au moins à 1 pour reconnaître un premier fragment vide
+        self.size = if frame.fin { 0 } else { size.max(1) };
+        Ok(())
+    }
+
+    // Ajoute une trame; retourne le message lorsqu'il est complet
+    pub fn push(&mut self, frame: Frame) -> Result<Option<Message>, WsError> {
+        self.check(&frame)?;
+        match frame.opcode {
+            Opcode::Close => return close_reason(&frame.payload).map(|reason| Some(Message::Close(reason))),
+            Opcode::Ping => return Ok(Some(Message::Ping(frame.payload))),
+            Opcode::Pong => return Ok(Some(Message::Pong(frame.payload))),
+            _ => {}
+        }
+        let (opcode, data) = match self.partial.take() {
+            Some((opcode, mut data)) => {
+                data.extend_from_slice(&frame.payload);
+                (opcode, data)
+            }
+            None => (frame.opcode, frame.payload),
+        };
+        if !frame.fin {
+            self.partial = Some((opcode, data));
+            return Ok(None);
+        }
+        if opcode == Opcode::Binary {
+            return Ok(Some(Message::Binary(data)));
+        }
+        String::from_utf8(data)
+            .map(|text| Some(Message::Text(text)))
+            .map_err(|_| WsError { code: CLOSE_INVALID_DATA, reason: "invalid UTF-8 text" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake_headers() -> HashMap<String, String> {
+        let mut headers = HashMap::new();
+        headers.insert("Upgrade".to_string(), " websocket".to_string());
+        headers.insert("Connection".to_string(), " keep-alive, Upgrade\r\n".to_string());
+        headers.insert("Sec-WebSocket-Key".to_string(), " dGhlIHNhbXBsZSBub25jZQ==".to_string());
+        headers.insert("Sec-WebSocket-Version".to_string(), " 13".to_string());
+        headers
+    }
+
+    #[test]
+    fn test_handshake() {
+        let mut headers = handshake_headers();
+        assert_eq!(handshake(&headers), Ok("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".to_string()));
+
+        headers.insert("Sec-WebSocket-Version".to_string(), " 8".to_string());
+        assert_eq!(handshake(&headers), Err(HandshakeError::UnsupportedVersion));
+
+        let mut headers = handshake_headers();
+        headers.insert("Sec-WebSocket-Key".to_string(), " c2hvcnQ=".to_string());
+        assert!(matches!(handshake(&headers), Err(HandshakeError::Invalid(_))));
+
+        let mut headers = handshake_headers();
+        headers.insert("Connection".to_string(), " keep-alive".to_string());
+        assert!(matches!(handshake(&headers), Err(HandshakeError::Invalid(_))));
+        headers.remove("Upgrade");
+        assert!(!is_upgrade(&headers));
+    }
+
+    #[test]
+    fn test_parse_masked_frame() {
+        // Exemple de la RFC 6455: "Hello" masqué
+        let bytes = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let (frame, used) = Frame::parse(&bytes, true, 1024).unwrap().unwrap();
+        assert_eq!(frame, Frame::new(Opcode::Text, b"Hello".to_vec()));
+        assert_eq!(used, bytes.len());
+        assert_eq!(frame.encode(Some([0x37, 0xfa, 0x21, 0x3d])), bytes);
+        // Trame incomplète
+        assert_eq!(Frame::parse(&bytes[..6], true, 1024), Ok(None));
+        // Le serveur n'accepte que des trames masquées et n'en reçoit pas de l'amont
+        assert_eq!(Frame::parse(&[0x81, 0x05, b'H'], true, 1024).unwrap_err().code, CLOSE_PROTOCOL_ERROR);
+        assert_eq!(Frame::parse(&bytes, false, 1024).unwrap_err().code, CLOSE_PROTOCOL_ERROR);
+    }
+
+    #[test]
+    fn test_extended_lengths() {
+        for len in [126, 70_000] {
+            let frame = Frame::new(Opcode::Binary, vec![7; len]);
+            let bytes = frame.encode(None);
+            assert_eq!(Frame::parse(&bytes, false, 100_000), Ok(Some((frame.clone(), bytes.len()))));
+            assert_eq!(Frame::parse(&bytes[..bytes.len() - 1], false, 100_000), Ok(None));
+            assert_eq!(Frame::parse(&bytes, false, 100).unwrap_err().code, CLOSE_TOO_BIG);
+        }
+    }
+
+    #[test]
+    fn test_invalid_frames() {
+        // Bits réservés, opcode inconnu, trame de contrôle fragmentée ou trop longue
+        for bytes in [vec![0xC1, 0x00], vec![0x83, 0x00], vec![0x09, 0x00], vec![0x89, 126, 0, 126]] {
+            assert_eq!(Frame::parse(&bytes, false, 1024).unwrap_err().code, CLOSE_PROTOCOL_ERROR);
+        }
+    }
+
+    #[test]
+    fn test_fragmented_message() {
+        let mut assembler = Assembler::new(10);
+        let first = Frame { fin: false, opcode: Opcode::Text, payload: b"Hel".to_vec() };
+        let last = Frame { fin: true, opcode: Opcode::Continuation, payload: b"lo".to_vec() };
+        assert_eq!(assembler.push(first.clone()), Ok(None));
+        assert_eq!(
+            assembler.push(Frame::new(Opcode::Ping, b"p".to_vec())),
+            Ok(Some(Message::Ping(b"p".to_vec())))
+        );
+        assert_eq!(assembler.push(Frame::new(Opcode::Text, b"x".to_vec())).unwrap_err().code, CLOSE_PROTOCOL_ERROR);
+
+        let mut assembler = Assembler::new(10);
+        assembler.push(first.clone()).unwrap();
+        assert_eq!(assembler.push(last.clone()), Ok(Some(Message::Text("Hello".to_string()))));
+        assert_eq!(assembler.push(last).unwrap_err().code, CLOSE_PROTOCOL_ERROR);
+
+        // Taille cumulée limitée
+        let mut assembler = Assembler::new(4);
+        assembler.push(first.clone()).unwrap();
+        let more = Frame { fin: false, opcode: Opcode::Continuation, payload: b"lo".to_vec() };
+        assert_eq!(assembler.push(more).unwrap_err().code, CLOSE_TOO_BIG);
+
+        let mut assembler = Assembler::new(10);
+        assert_eq!(
+            assembler.push(Frame::new(Opcode::Text, vec![0xff, 0xfe])).unwrap_err().code,
+            CLOSE_INVALID_DATA
+        );
+    }
+
+    #[test]
+    fn test_close_frames() {
+        let frame = Frame::close(CLOSE_NORMAL, "bye");
+        assert_eq!(close_reason(&frame.payload), Ok(Some((1000, "bye".to_string()))));
+        assert_eq!(close_reason(&[]), Ok(None));
+        assert!(close_reason(&[0x03]).is_err());
+        assert!(close_reason(&1005u16.to_be_bytes()).is_err());
+        assert_eq!(Frame::close(CLOSE_NORMAL, &"é".repeat(100)).payload.len(), 124);
+    }
+}
@@ -0,0 +1,320 @@
+// auth.rs
+use crate::config::{constant_time_eq, ConfigServer, Route};
+use crate::handler::PageErrorHandler;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use http::{httprequest::HttpRequest, httpresponse::HttpResponse};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::fs;
+
+const DEFAULT_REALM: &str = "localhost";
+
+// Authentification HTTP d'une route (Basic et/ou Bearer)
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct RouteAuth {
+    pub realm: Option<String>,
+    pub basic: Option<String>,  // fichier au format htpasswd (user:hash)
+    pub bearer: Option<String>, // fichier de jetons, un par ligne
+    #[serde(skip)]
+    pub(crate) users: Vec<(String, String)>, // entrées du fichier htpasswd, lues par `load`
+    #[serde(skip)]
+    pub(crate) tokens: Vec<String>,
+}
+
+impl RouteAuth {
+    // Lit les fichiers d'identifiants une fois, au chargement de la
+    // configuration; ils sont donc relus avec elle sur SIGHUP
+    pub fn load(&mut self) {
+        self.users = self
+            .basic
+            .as_deref()
+            .map(|htpasswd| {
+                entries(htpasswd)
+                    .iter()
+                    .filter_map(|line| line.split_once(':'))
+                    .map(|(user, hash)| (user.to_string(), hash.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.tokens = self.bearer.as_deref().map(entries).unwrap_or_default();
+    }
+
+    // Vérifie que les fichiers d'identifiants sont lisibles et que chaque
+    // ligne est exploitable: user:hash pour Basic, un jeton sans espace pour Bearer
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let files = [("basic", &self.basic), ("bearer", &self.bearer)];
+        for (scheme, path) in files.into_iter().filter_map(|(scheme, path)| Some((scheme, path.as_deref()?))) {
+            let lines = match numbered_entries(path) {
+                Ok(lines) => lines,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    problems.push(format!("auth {} file `{}` not found", scheme, path));
+                    continue;
+                }
+                Err(e) => {
+                    problems.push(format!("auth {} file `{}` cannot be read: {}", scheme, path, e));
+                    continue;
+                }
+            };
+            for (number, line) in lines {
+                let valid = match scheme {
+                    "basic" => line.split_once(':').is_some_and(|(user, hash)| !user.is_empty() && !hash.is_empty()),
+                    _ => !line.contains(char::is_whitespace),
+                };
+                if !valid {
+                    let expected = if scheme == "basic" { "user:hash" } else { "a single token" };
+                    problems.push(format!("auth {} file `{}` line {}: expected {}", scheme, path, number, expected));
+                }
+            }
+        }
+        problems
+    }
+
+    fn realm(&self) -> &str {
+        self.realm.as_deref().unwrap_or(DEFAULT_REALM)
+    }
+
+    // Construit la valeur de l'en-tête WWW-Authenticate pour les schémas configurés
+    fn challenge(&self, bearer_error: Option<&str>) -> String {
+        let mut challenges = Vec::new();
+        if self.basic.is_some() {
+            challenges.push(format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm()));
+        }
+        if self.bearer.is_some() {
+            match bearer_error {
+                Some(error) => challenges.push(format!(
+                    "Bearer realm=\"{}\", error=\"{}\"",
+                    self.realm(),
+                    error
+                )),
+                None => challenges.push(format!("Bearer realm=\"{}\"", self.realm())),
+            }
+        }
+        challenges.join(", ")
+    }
+}
+
+// Vérifie l'en-tête Authorization d'une requête pour la route donnée.
+// Retourne Ok(None) si la route n'est pas protégée, Ok(Some(user)) si les
+// identifiants sont valides et Err(401) avec le challenge sinon.
+pub fn authorize<'a>(
+    req: &HttpRequest,
+    route: &Route,
+    config: &ConfigServer,
+) -> Result<Option<String>, Box<HttpResponse<'a>>> {
+    let auth = match &route.auth {
+        Some(auth) if auth.basic.is_some() || auth.bearer.is_some() => auth,
+        _ => return Ok(None),
+    };
+
+    let header = match req.headers.get("Authorization") {
+        Some(h) => h.trim(),
+        None => return Err(unauthorized(auth, config, None)),
+    };
+    let (scheme, credentials) = header.split_once(' ').unwrap_or((header, ""));
+    let credentials = credentials.trim();
+
+    if scheme.eq_ignore_ascii_case("Basic") {
+        if let Some(user) = auth.basic.as_ref().and_then(|_| check_basic(credentials, &auth.users)) {
+            return Ok(Some(user));
+        }
+        return Err(unauthorized(auth, config, None));
+    }
+    if scheme.eq_ignore_ascii_case("Bearer") && auth.bearer.is_some() {
+        if check_bearer(credentials, &auth.tokens) {
+            return Ok(Some(String::from("bearer")));
+        }
+        return Err(unauthorized(auth, config, Some("invalid_token")));
+    }
+    Err(unauthorized(auth, config, None))
+}
+
+fn unauthorized<'a>(
+    auth: &RouteAuth,
+    config: &ConfigServer,
+    bearer_error: Option<&str>,
+) -> Box<HttpResponse<'a>> {
+    let mut response = PageErrorHandler::error_response(config, "401");
+    response.set_header("WWW-Authenticate", auth.challenge(bearer_error));
+    Box::new(response)
+}
+
+// Lignes utiles d'un fichier d'identifiants, sans commentaires ni lignes vides
+fn entries(path: &str) -> Vec<String> {
+    match numbered_entries(path) {
+        Ok(lines) => lines.into_iter().map(|(_, line)| line).collect(),
+        Err(e) => {
+            eprintln!("Failed to read credentials {}: {}", path, e);
+            Vec::new()
+        }
+    }
+}
+
+// Lignes utiles avec leur numéro dans le fichier, pour situer les erreurs
+fn numbered_entries(path: &str) -> std::io::Result<Vec<(usize, String)>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| (number, line.to_string()))
+        .collect())
+}
+
+// Décode "user:password" et le compare aux entrées htpasswd
+fn check_basic(credentials: &str, users: &[(String, String)]) -> Option<String> {
+    let decoded = STANDARD.decode(credentials).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+
+    users
+        .iter()
+        .find(|(name, _)| name == user)
+        .filter(|(_, hash)| verify_password(password, hash))
+        .map(|(name, _)| name.clone())
+}
+
+// Formats supportés: {SHA} (htpasswd -s), {SHA256} en hexadécimal et texte brut
+fn verify_password(password: &str, hash: &str) -> bool {
+    if let Some(expected) = hash.strip_prefix("{SHA}") {
+        let digest = STANDARD.encode(Sha1::digest(password.as_bytes()));
+        return constant_time_eq(digest.as_bytes(), expected.as_bytes());
+    }
+    if let Some(expected) = hash.strip_prefix("{SHA256}") {
+        let digest = hex::encode(Sha256::digest(password.as_bytes()));
+        return constant_time_eq(digest.as_bytes(), expected.to_ascii_lowercase().as_bytes());
+    }
+    if hash.starts_with('$') {
+        eprintln!("Unsupported htpasswd hash format (use {{SHA}} or {{SHA256}})");
+        return false;
+    }
+    constant_time_eq(password.as_bytes(), hash.as_bytes())
+}
+
+fn check_bearer(token: &str, tokens: &[String]) -> bool {
+    !token.is_empty() && tokens.iter().any(|line| constant_time_eq(line.as_bytes(), token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use http::httprequest::{Method, Resource, Version};
+    use std::collections::HashMap;
+
+    fn setup_config(mut auth: RouteAuth) -> ConfigServer {
+        auth.load();
+        ConfigServer {
+            root: String::from("/public"),
+            ports: vec![8080],
+            client_body_limit: 1024,
+            routes: vec![Route {
+                alias: "/ci/".to_string(),
+                pages: vec!["index.html".to_string()],
+                default_page: "index.html".to_string(),
+                auth: Some(auth),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn request(authorization: Option<&str>) -> HttpRequest {
+        let mut headers = HashMap::new();
+        if let Some(value) = authorization {
+            headers.insert("Authorization".to_string(), format!(" {}", value));
+        }
+        HttpRequest::new(
+            Method::Get,
+            Version::V1_1,
+            Resource::Path("/ci/".to_string()),
+            headers,
+            String::new(),
+        )
+    }
+
+    fn write_file(name: &str, contents: &str) -> String {
+        let path = temp_dir("auth").join(name);
+        fs::write(&path, contents).expect("failed to write test file");
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        let htpasswd = write_file(
+            "auth_test.htpasswd",
+            "# comment\nalice:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\nbob:secret\n",
+        );
+        let config = setup_config(RouteAuth {
+            realm: Some("CI".to_string()),
+            basic: Some(htpasswd),
+            bearer: None,
+            ..Default::default()
+        });
+        let route = &config.routes[0];
+
+        // alice:password
+        let req = request(Some("Basic YWxpY2U6cGFzc3dvcmQ="));
+        assert_eq!(authorize(&req, route, &config).ok(), Some(Some("alice".to_string())));
+        // bob:secret
+        let req = request(Some("Basic Ym9iOnNlY3JldA=="));
+        assert_eq!(authorize(&req, route, &config).ok(), Some(Some("bob".to_string())));
+        // bob:wrong
+        let req = request(Some("Basic Ym9iOndyb25n"));
+        let resp = authorize(&req, route, &config).unwrap_err();
+        assert_eq!(resp.get_status_code(), "401");
+        assert_eq!(
+            resp.header("WWW-Authenticate"),
+            Some("Basic realm=\"CI\", charset=\"UTF-8\"")
+        );
+    }
+
+    #[test]
+    fn test_bearer_auth() {
+        let tokens = write_file("auth_test.tokens", "token-1\n\ntoken-2\n");
+        let config = setup_config(RouteAuth {
+            realm: None,
+            basic: None,
+            bearer: Some(tokens),
+            ..Default::default()
+        });
+        let route = &config.routes[0];
+
+        assert!(authorize(&request(Some("Bearer token-2")), route, &config).is_ok());
+
+        let resp = authorize(&request(Some("Bearer nope")), route, &config).unwrap_err();
+        assert_eq!(
+            resp.header("WWW-Authenticate"),
+            Some("Bearer realm=\"localhost\", error=\"invalid_token\"")
+        );
+
+        let resp = authorize(&request(None), route, &config).unwrap_err();
+        assert_eq!(resp.get_status_code(), "401");
+        assert_eq!(
+            resp.header("WWW-Authenticate"),
+            Some("Bearer realm=\"localhost\"")
+        );
+    }
+
+    #[test]
+    fn test_route_without_auth() {
+        let mut config = setup_config(RouteAuth {
+            realm: None,
+            basic: None,
+            bearer: None,
+            ..Default::default()
+        });
+        config.routes[0].auth = None;
+        let route = &config.routes[0];
+        assert_eq!(authorize(&request(None), route, &config).ok(), Some(None));
+    }
+
+    #[test]
+    fn test_verify_password() {
+        let sha256 = hex::encode(Sha256::digest(b"password"));
+        assert!(verify_password("password", &format!("{{SHA256}}{}", sha256)));
+        assert!(!verify_password("password", "$2y$05$abcdefghijklmnopqrstuv"));
+        assert!(!verify_password("password", "passwor"));
+    }
+}
@@ -0,0 +1,101 @@
+// cli.rs
+use crate::config::DEFAULT_CONFIG_PATH;
+
+pub const USAGE: &str = "Usage: web_server [OPTIONS]
+
+Options:
+  -c, --config <path>  configuration file (default: config.toml)
+      --check          validate the configuration and exit
+      --print-config   print the effective configuration and exit
+  -V, --version        print the version and exit
+  -h, --help           print this help and exit";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run,
+    Check,
+    PrintConfig,
+    Version,
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Cli {
+    pub config_path: String,
+    pub command: Command,
+}
+
+impl Cli {
+    // Analyse les arguments de la ligne de commande (sans le nom du programme)
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Cli, String> {
+        let mut config_path = String::from(DEFAULT_CONFIG_PATH);
+        let mut command = Command::Run;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let next_command = match arg.as_str() {
+                "-c" | "--config" => {
+                    config_path = args
+                        .next()
+                        .ok_or_else(|| format!("missing value for {}", arg))?;
+                    continue;
+                }
+                _ if arg.starts_with("--config=") => {
+                    config_path = arg["--config=".len()..].to_string();
+                    continue;
+                }
+                "--check" => Command::Check,
+                "--print-config" => Command::PrintConfig,
+                "-V" | "--version" => Command::Version,
+                "-h" | "--help" => Command::Help,
+                _ => return Err(format!("unknown argument: {}", arg)),
+            };
+            if command != Command::Run && command != next_command {
+                return Err(format!("{} cannot be combined with another mode", arg));
+            }
+            command = next_command;
+        }
+        if config_path.is_empty() {
+            return Err(String::from("empty configuration path"));
+        }
+        Ok(Cli {
+            config_path,
+            command,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        let cli = parse(&[]).unwrap();
+        assert_eq!(cli.config_path, "config.toml");
+        assert_eq!(cli.command, Command::Run);
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let cli = parse(&["-c", "/etc/localhost.toml", "--check"]).unwrap();
+        assert_eq!(cli.config_path, "/etc/localhost.toml");
+        assert_eq!(cli.command, Command::Check);
+
+        let cli = parse(&["--print-config", "--config=prod.toml"]).unwrap();
+        assert_eq!(cli.config_path, "prod.toml");
+        assert_eq!(cli.command, Command::PrintConfig);
+
+        assert_eq!(parse(&["--version"]).unwrap().command, Command::Version);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+        assert!(parse(&["--check", "--print-config"]).is_err());
+    }
+}
@@ -1,68 +1,385 @@
 // config.rs
+use crate::auth::RouteAuth;
+use crate::csrf::CsrfPolicy;
+use crate::docroot::DocumentRoot;
+use crate::loader::{ load_sources, toml_error, ConfigSource };
+use crate::proxy::ProxyOptions;
+use crate::redirect::RedirectRule;
+use crate::sse::EventSource;
+use crate::tls::TlsConfig;
+use crate::upload::UploadPolicy;
+use crate::upstream::UpstreamGroup;
+use crate::validate::validate_config;
+use crate::websocket::WebSocketPolicy;
 use http::httprequest::Resource;
-use serde::Deserialize;
+use regex::Regex;
+use serde::{ Deserialize, Serialize };
 use std::collections::HashSet;
-use std::fs::{ File, OpenOptions };
-use std::path::Path;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::net::{ IpAddr, SocketAddr };
+use std::path::{ Path, PathBuf };
 use std::{ collections::HashMap, fs };
 
-#[derive(Deserialize, Debug, Clone)]
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Config {
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout: u64, // secondes accordées aux connexions en attente à l'arrêt
     pub servers: Vec<ConfigServer>,
+    #[serde(default)]
+    pub upstreams: HashMap<String, UpstreamGroup>, // groupes `[upstreams.<nom>]` des routes proxy_pass
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ConfigServer {
+    #[serde(default = "default_host_name")]
     pub host_name: String,
+    #[serde(default = "default_host")]
     pub host: String,
-    pub ports: Vec<u16>,
+    #[serde(default)]
+    pub ports: Vec<u16>, // écoutés sur `host`
+    #[serde(default)]
+    pub listen: Vec<String>, // "0.0.0.0:8080", "[::1]:8443" ou "unix:/run/web.sock"
     pub root: String,
+    #[serde(default)]
     pub error_pages: HashMap<String, String>,
+    #[serde(default = "default_client_body_limit")]
     pub client_body_limit: usize,
+    #[serde(default)]
     pub routes: Vec<Route>,
+    #[serde(default)]
+    pub redirects: Vec<RedirectRule>, // appliquées avant la recherche de la route
+    #[serde(default)]
     pub cgi_extensions: HashMap<String, String>,
+    #[serde(default)]
     pub directory_listing: bool,
+    #[serde(default = "default_backlog")]
+    pub backlog: i32, // connexions en attente d'acceptation par socket
+    #[serde(default)]
+    pub reuse_port: bool, // SO_REUSEPORT: plusieurs processus sur la même adresse
+    pub socket_mode: Option<u32>, // permissions des sockets Unix, par exemple 0o660
+    pub tls: Option<TlsConfig>,   // certificat servi sur toutes les adresses du serveur
+    #[serde(default = "default_cookie_file")]
+    pub cookie_file: String, // sessions émises, une par ligne
+}
+
+// Adresse d'écoute d'un serveur
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Route {
     pub alias: String,
+    #[serde(default)]
     pub pages: Vec<String>,
+    #[serde(default)]
     pub default_page: String,
+    #[serde(default)]
     pub check_cookie: bool,
     pub redirect: Option<HashMap<String, String>>, // Champ rendu optionnel
+    #[serde(default)]
     pub links: Vec<String>,
+    #[serde(default = "default_methods")]
     pub methods: Vec<String>,
+    pub auth: Option<RouteAuth>,
+    pub csrf: Option<CsrfPolicy>,
+    pub cgi: Option<String>,     // script CGI recevant le corps des requêtes POST
+    pub service: Option<String>, // service web (JSON) servant la route
+    pub upload: Option<UploadPolicy>, // destination et règles des fichiers envoyés
+    pub proxy_pass: Option<String>,   // serveur amont recevant les requêtes: "http://127.0.0.1:3000"
+    pub proxy: Option<ProxyOptions>,  // délais d'attente du serveur amont
+    pub websocket: Option<WebSocketPolicy>, // passage en WebSocket: écho, diffusion ou relais
+    pub sse: Option<EventSource>,           // flux d'événements (text/event-stream)
+    #[serde(skip)]
+    pub(crate) pattern: Option<Regex>, // motif d'un alias "~regex", compilé par `prepare`
 }
 
-impl Route {
-    fn new() -> Self {
-        Self {
+// Valeurs par défaut du fichier de configuration (`root` reste à définir)
+impl Default for ConfigServer {
+    fn default() -> Self {
+        ConfigServer {
+            host_name: default_host_name(),
+            host: default_host(),
+            ports: Vec::new(),
+            listen: Vec::new(),
+            root: String::new(),
+            error_pages: HashMap::new(),
+            client_body_limit: default_client_body_limit(),
+            routes: Vec::new(),
+            redirects: Vec::new(),
+            cgi_extensions: HashMap::new(),
+            directory_listing: false,
+            backlog: default_backlog(),
+            reuse_port: false,
+            socket_mode: None,
+            tls: None,
+            cookie_file: default_cookie_file(),
+        }
+    }
+}
+
+impl Default for Route {
+    fn default() -> Self {
+        Route {
             alias: String::new(),
             pages: Vec::new(),
             default_page: String::new(),
             check_cookie: false,
-            redirect: Some(HashMap::new()),
+            redirect: None,
             links: Vec::new(),
+            methods: default_methods(),
+            auth: None,
+            csrf: None,
+            cgi: None,
+            service: None,
+            upload: None,
+            proxy_pass: None,
+            proxy: None,
+            websocket: None,
+            sse: None,
+            pattern: None,
+        }
+    }
+}
+
+fn default_drain_timeout() -> u64 {
+    30
+}
+
+fn default_host_name() -> String {
+    String::from("localhost")
+}
+
+fn default_host() -> String {
+    String::from("127.0.0.1")
+}
+
+fn default_client_body_limit() -> usize {
+    1024 * 1024
+}
+
+fn default_backlog() -> i32 {
+    128
+}
+
+fn default_cookie_file() -> String {
+    String::from("cookies.txt")
+}
+
+fn default_methods() -> Vec<String> {
+    vec![String::from("GET")]
+}
+
+impl Config {
+    // Données tirées de la configuration une fois par chargement (au
+    // démarrage puis à chaque SIGHUP) plutôt qu'à chaque requête
+    pub fn prepare(&mut self) {
+        for server in &mut self.servers {
+            server.prepare();
+        }
+    }
+}
+
+impl ConfigServer {
+    pub fn prepare(&mut self) {
+        for route in &mut self.routes {
+            if let Some(auth) = route.auth.as_mut() {
+                auth.load();
+            }
+            // Motif déjà vérifié par la validation
+            route.pattern = route.alias.strip_prefix('~').and_then(|pattern| Regex::new(pattern).ok());
+        }
+        for rule in &mut self.redirects {
+            rule.prepare();
+        }
+    }
+
+    // Adresses écoutées: chaque port de `ports` sur `host`, puis `listen`
+    pub fn listen_addrs(&self) -> Result<Vec<ListenAddr>, String> {
+        let mut addrs = Vec::new();
+        if !self.ports.is_empty() {
+            let host = self.host.trim();
+            let ip: IpAddr = host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse()
+                .map_err(|_| format!("host `{}` is not an IP address", host))?;
+            addrs.extend(self.ports.iter().map(|port| ListenAddr::Tcp(SocketAddr::new(ip, *port))));
+        }
+        for addr in &self.listen {
+            if let Some(path) = addr.trim().strip_prefix("unix:") {
+                if path.is_empty() {
+                    return Err(format!("listen address `{}` has no socket path", addr));
+                }
+                addrs.push(ListenAddr::Unix(PathBuf::from(path)));
+                continue;
+            }
+            let parsed = addr.trim().parse().map_err(|_| {
+                format!("listen address `{}` is not of the form ip:port, [ipv6]:port or unix:path", addr)
+            })?;
+            addrs.push(ListenAddr::Tcp(parsed));
+        }
+        Ok(addrs)
+    }
+}
+
+impl Route {
+    fn new() -> Self {
+        Self {
+            redirect: Some(HashMap::new()),
             methods: Vec::new(),
+            ..Default::default()
         }
     }
 }
 
-pub fn read_config() -> Option<Config> {
-    let config_str = match fs::read_to_string("config.toml") {
-        Ok(s) => s,
-        Err(_) => {
-            return None;
+// Problème de configuration, avec sa position dans le fichier si elle est connue
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub path: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl ConfigError {
+    pub(crate) fn new(path: &str, message: String) -> Self {
+        ConfigError {
+            path: path.to_string(),
+            line: None,
+            column: None,
+            message,
+        }
+    }
+
+    // Position d'un décalage en octets: (ligne, colonne) à partir de 1
+    pub(crate) fn at_offset(mut self, source: &str, offset: usize) -> Self {
+        let before = &source[..offset.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+        self.line = Some(line);
+        self.column = Some(column);
+        self
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{}:{}:{}: {}", self.path, line, column, self.message),
+            _ => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
+pub fn read_config(path: &str) -> Result<Config, ConfigError> {
+    let config_str = fs
+        ::read_to_string(path)
+        .map_err(|e| ConfigError::new(path, format!("cannot read file: {}", e)))?;
+    parse_config(path, &config_str)
+}
+
+pub fn parse_config(path: &str, source: &str) -> Result<Config, ConfigError> {
+    parse_sources(path, source).map(|(config, _)| config)
+}
+
+// Analyse le fichier principal et ses `include`; les fichiers lus sont
+// retournés pour positionner les erreurs de validation
+fn parse_sources(path: &str, source: &str) -> Result<(Config, Vec<ConfigSource>), ConfigError> {
+    let (mut table, sources) = load_sources(path, source)?;
+    apply_defaults(&mut table);
+    match Config::deserialize(toml::Value::Table(table)) {
+        Ok(mut config) => {
+            config.prepare();
+            Ok((config, sources))
+        }
+        // Les valeurs fusionnées n'ont plus de position: on la retrouve en
+        // analysant chaque fichier d'origine lorsqu'il produit la même erreur
+        Err(e) => {
+            let located = sources.iter().find_map(|file| {
+                match toml::from_str::<Config>(&file.text) {
+                    Err(original) if original.span().is_some() && original.message() == e.message() => {
+                        Some(toml_error(&file.path, &file.text, &original))
+                    }
+                    _ => None,
+                }
+            });
+            Err(located.unwrap_or_else(|| ConfigError::new(path, e.message().trim().to_string())))
         }
+    }
+}
+
+// Le bloc [defaults] est hérité par chaque serveur et [defaults.route] par
+// chaque route; les valeurs définies localement sont prioritaires et les
+// tables (error_pages, cgi_extensions...) sont complétées clé par clé
+fn apply_defaults(table: &mut toml::Table) {
+    let mut defaults = match table.remove("defaults") {
+        Some(toml::Value::Table(defaults)) => defaults,
+        _ => return,
+    };
+    let route_defaults = match defaults.remove("route") {
+        Some(toml::Value::Table(route_defaults)) => route_defaults,
+        _ => toml::Table::new(),
     };
-    match toml::from_str(&config_str) {
-        Ok(c) => Some(c),
-        Err(_) => None,
+    let servers = match table.get_mut("servers") {
+        Some(toml::Value::Array(servers)) => servers,
+        _ => return,
+    };
+    for server in servers.iter_mut().filter_map(toml::Value::as_table_mut) {
+        inherit(server, &defaults);
+        if let Some(toml::Value::Array(routes)) = server.get_mut("routes") {
+            for route in routes.iter_mut().filter_map(toml::Value::as_table_mut) {
+                inherit(route, &route_defaults);
+            }
+        }
+    }
+}
+
+fn inherit(target: &mut toml::Table, defaults: &toml::Table) {
+    for (key, value) in defaults {
+        match (target.get_mut(key), value) {
+            (None, _) => {
+                target.insert(key.clone(), value.clone());
+            }
+            (Some(toml::Value::Table(local)), toml::Value::Table(inherited)) => {
+                for (name, value) in inherited {
+                    local.entry(name.clone()).or_insert_with(|| value.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Lit puis valide la configuration et retourne tous les problèmes trouvés
+pub fn check_config(path: &str) -> Result<Config, Vec<ConfigError>> {
+    let source = fs
+        ::read_to_string(path)
+        .map_err(|e| vec![ConfigError::new(path, format!("cannot read file: {}", e))])?;
+    let (config, sources) = parse_sources(path, &source).map_err(|e| vec![e])?;
+    let errors = validate_config(&sources, &config);
+    if errors.is_empty() {
+        Ok(config)
+    } else {
+        Err(errors)
     }
 }
 
-pub fn ok_count_redirect(routes: &Vec<Route>) -> bool {
+pub fn ok_count_redirect(routes: &[Route]) -> bool {
     routes.iter().all(|route| {
         match &route.redirect {
             Some(map) => map.len() == 1, // Compte le nombre d'éléments dans le HashMap
@@ -108,98 +425,26 @@ pub fn found_links(config: &ConfigServer, path: &str) -> bool {
 // utils
 use hmac::{ Hmac, Mac };
 use http::{ httprequest::HttpRequest, httpresponse::HttpResponse };
+use http::multipart::{ self, Multipart };
 use rand::Rng;
 use sha2::Sha256;
 use std::io::{ self, prelude::* };
 type HmacSha256 = Hmac<Sha256>;
 use crate::handler::{ redirection, Handler, PageErrorHandler, StaticPageHandler };
 use hex;
-const COOKIE_FILE: &str = "cookies.txt";
-
-pub fn parse_multipart_body(body: &[u8], boundary: &str) -> HashMap<String, (String, Vec<u8>)> {
-    let boundary_str = format!("--{}", boundary);
-    let boundary_bytes = boundary_str.as_bytes();
-    let mut parts = HashMap::new();
-    let mut start = 0;
-
-    while let Some(start_boundary) = find_bytes(&body[start..], boundary_bytes) {
-        let start_pos = start + start_boundary + boundary_bytes.len();
-        let next_boundary_pos = find_bytes(&body[start_pos..], boundary_bytes).map_or(
-            body.len(),
-            |p| start_pos + p
-        );
-
-        if start_pos >= body.len() || next_boundary_pos > body.len() {
-            eprintln!("Calculated boundary indices are out of range");
-            break;
-        }
-
-        let part = &body[start_pos..next_boundary_pos];
-
-        if let Some(content_disposition_start) = find_bytes(part, b"Content-Disposition:") {
-            let content_start = find_bytes(&part[content_disposition_start..], b"\r\n\r\n").map_or(
-                part.len(),
-                |p| content_disposition_start + p + 4
-            );
-
-            if content_start >= part.len() {
-                eprintln!("Content start index is out of range");
-                break;
-            }
-
-            let content = &part[content_start..];
-            if let Some(filename) = extract_filename(part) {
-                let file_content = extract_content(content);
-                parts.insert("filename".to_string(), (filename, file_content));
-            } else {
-                let name = extract_name(part);
-                let value = extract_content(content);
-                parts.insert(name.clone(), (name, value));
-            }
-        } else {
-            eprintln!("Content-Disposition not found");
-        }
-
-        start = next_boundary_pos;
-    }
-
-    parts
-}
-
-fn extract_filename(part: &[u8]) -> Option<String> {
-    find_bytes(part, b"filename=").map(|filename_start| {
-        let filename_end = find_bytes(&part[filename_start..], b"\r\n").map_or(
-            part.len(),
-            |p| filename_start + p
-        );
-        String::from_utf8_lossy(&part[filename_start + 9..filename_end])
-            .trim_matches('"')
-            .to_string()
-    })
-}
-
-fn extract_name(part: &[u8]) -> String {
-    let name_start = find_bytes(part, b"name=").unwrap_or(0) + 5;
-    let name_end = find_bytes(&part[name_start..], b"\r\n").map_or(part.len(), |p| name_start + p);
-    String::from_utf8_lossy(&part[name_start..name_end])
-        .trim_matches('"')
-        .to_string()
-}
-
-fn extract_content(content: &[u8]) -> Vec<u8> {
-    let content_end = find_bytes(content, b"\r\n--").unwrap_or(content.len());
-    content[..content_end].to_vec()
-}
 
-pub fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
-    haystack.windows(needle.len()).position(|window| window == needle)
+// Analyse un corps multipart déjà lu en mémoire (les requêtes lues sur la
+// socket sont analysées au fil de l'eau par MultipartParser)
+pub fn parse_multipart_body(body: &[u8], boundary: &str) -> io::Result<Multipart> {
+    multipart::parse(body, boundary)
 }
 
-pub fn save_file(root: &str, filename: &str, data: &[u8]) -> io::Result<()> {
-    let path = format!("{}/{}",root, filename);
-    let mut file = File::create(path)?;
-    file.write_all(data)?;
-    Ok(())
+// Comparaison en temps constant pour les secrets (jetons, mots de passe)
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
 }
 
 pub fn generate_session_id() -> String {
@@ -229,20 +474,25 @@ pub fn set_cookie(
     req: &HttpRequest,
     stream: &mut impl Write,
     secret: String,
-    config: &ConfigServer
+    config: &ConfigServer,
+    same_site: &str
 ) {
     let session_id = generate_session_id();
     let signed_cookie = sign_cookie(&session_id, secret);
 
     let cookie_value = format!("sessionId={}", signed_cookie);
     // Enregistrer le cookie dans un fichier
-    if let Err(e) = save_cookie_to_file(&cookie_value) {
+    if let Err(e) = save_cookie_to_file(&config.cookie_file, &cookie_value) {
         eprintln!("Failed to save cookie: {}", e);
+        let _ = PageErrorHandler::error_response(config, "500").send_response(stream);
         return;
     }
     // Set a cookie
     let mut headers: HashMap<&str, &str> = HashMap::new();
-    let val = format!("{}; Path=/; HttpOnly;", cookie_value);
+    // Reçu en TLS, le cookie n'est renvoyé par le navigateur qu'en HTTPS;
+    // SameSite=None n'est accepté par la validation que dans ce cas
+    let secure = if req.secure { "; Secure" } else { "" };
+    let val = format!("{}; Path=/; HttpOnly; SameSite={}{}", cookie_value, same_site, secure);
 
     headers.insert("Set-Cookie", val.as_str());
     let mut resp: HttpResponse = StaticPageHandler.handle(&req, config);
@@ -253,18 +503,18 @@ pub fn set_cookie(
     }
 }
 
-fn save_cookie_to_file(cookie: &str) -> std::io::Result<()> {
-    let mut file = OpenOptions::new().append(true).create(true).open(COOKIE_FILE)?;
+fn save_cookie_to_file(path: &str, cookie: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
     writeln!(file, "{}", cookie)?;
     Ok(())
 }
 
-pub fn verify_cookie(signed_cookie: &str) -> bool {
-    if !Path::new(COOKIE_FILE).exists() {
+pub fn verify_cookie(config: &ConfigServer, signed_cookie: &str) -> bool {
+    if !Path::new(&config.cookie_file).exists() {
         return false;
     }
 
-    let contents = fs::read_to_string(COOKIE_FILE);
+    let contents = fs::read_to_string(&config.cookie_file);
     let contents = contents.ok().expect("msg: Failed to open cookie file");
     let cookies = contents.split("\n").collect::<Vec<&str>>();
 
@@ -276,75 +526,71 @@ pub fn handle_get_request(
     alias: String,
     stream: &mut impl Write,
     config: &ConfigServer,
-    route: Route
+    route: Route,
+    authenticated: bool
 ) {
-    if let Some(cookie_header) = req.headers.get("Cookie") {
-        if verify_cookie(cookie_header) {
-            let Resource::Path(s) = &req.resource;
-            let rout: Vec<&str> = s.split("/").collect();
-            let file: Option<&&str> = rout.last();
-            let file = file.expect("invalid");
-            let path = format!("{}/{}", config.root, file);
-            match fs::remove_file(path) {
-                Ok(_) => {
-                    if let Some(redirect_page) = route.redirect {
-                        println!("redirect: {} {:?}", alias, redirect_page);
-                        let response = redirection(&alias, redirect_page, config);
-                        response.send_response(stream).expect("faild to send_response");
-                        return;
-                    }
+    // Une session valide est requise sauf si la route a déjà authentifié le client
+    let has_session = req.headers
+        .get("Cookie")
+        .is_some_and(|cookie_header| verify_cookie(config, cookie_header));
+    if !authenticated && !has_session {
+        let resp = PageErrorHandler::error_response(config, "401");
+        resp.send_response(stream).expect("faild to send_response");
+        return;
+    }
 
-                    let response = HttpResponse::new(
-                        "200",
-                        config.host_name.clone(),
-                        None,
-                        Some("File Deleted".into())
-                    );
-                    response.send_response(stream).expect("faild to send_response");
-                }
-                Err(_) => {
-                    let resp = PageErrorHandler::error_response(config, "404");
-                    resp.send_response(stream).expect("faild to send_response");
-                }
+    let Resource::Path(s) = &req.resource;
+    let rout: Vec<&str> = s.split("/").collect();
+    let file: Option<&&str> = rout.last();
+    let file = file.expect("invalid");
+    match fs::remove_file(DocumentRoot::of(config).path(file)) {
+        Ok(_) => {
+            if let Some(redirect_page) = route.redirect {
+                println!("redirect: {} {:?}", alias, redirect_page);
+                let response = redirection(&alias, redirect_page, config);
+                response.send_response(stream).expect("faild to send_response");
+                return;
             }
-        } else {
-            let resp = PageErrorHandler::error_response(config, "401");
+
+            let response = HttpResponse::new(
+                "200",
+                config.host_name.clone(),
+                None,
+                Some("File Deleted".into())
+            );
+            response.send_response(stream).expect("faild to send_response");
+        }
+        Err(_) => {
+            let resp = PageErrorHandler::error_response(config, "404");
             resp.send_response(stream).expect("faild to send_response");
         }
-    } else {
-        let resp = PageErrorHandler::error_response(config, "401");
-        resp.send_response(stream).expect("faild to send_response");
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::temp_dir;
     use std::collections::HashMap;
 
     fn setup_config() -> ConfigServer {
         ConfigServer {
-            host_name: String::from("localhost"),
             root: String::from("/public"),
-            directory_listing: false,
-            error_pages: HashMap::new(),
-            cgi_extensions: HashMap::new(),
-            host: String::from("127.0.0.1"),
             ports: vec![8080],
             client_body_limit: 1024,
             routes: vec![Route {
                 alias: "/test".to_string(),
                 pages: vec!["index.html".to_string()],
                 default_page: "index.html".to_string(),
-                check_cookie: false,
                 redirect: Some({
                     let mut m = HashMap::new();
                     m.insert("/old".to_string(), "/new".to_string());
                     m
                 }),
                 links: vec!["/index.html".to_string()],
-                methods: vec!["GET".to_string()],
+                ..Default::default()
             }],
+            ..Default::default()
         }
     }
 
@@ -358,6 +604,72 @@ mod tests {
         assert!(!ok_count_redirect(&routes)); // Should return false since map is empty
     }
 
+    #[test]
+    fn test_parse_config_error_position() {
+        let source = "[[servers]]\nhost_name = \"a\"\nports = [8080]\nroot = 1\n";
+        let error = parse_config("bad.toml", source).unwrap_err();
+        assert_eq!((error.line, error.column), (Some(4), Some(8)));
+        assert!(error.to_string().starts_with("bad.toml:4:8: invalid type"));
+    }
+
+    #[test]
+    fn test_parse_minimal_config() {
+        let source = "[[servers]]\nports = [8080]\nroot = \"public\"\n\n[[servers.routes]]\nalias = \"/\"\n";
+        let config = parse_config("config.toml", source).unwrap();
+        let server = &config.servers[0];
+        assert_eq!(server.host, "127.0.0.1");
+        assert_eq!(server.client_body_limit, 1024 * 1024);
+        assert!(server.error_pages.is_empty());
+        assert_eq!(server.routes[0].methods, vec!["GET"]);
+        assert!(!server.routes[0].check_cookie);
+
+        let error = parse_config("config.toml", "[[servers]]\nports = [8080]\n").unwrap_err();
+        assert_eq!(error.message, "missing field `root`");
+    }
+
+    #[test]
+    fn test_parse_config_defaults_block() {
+        let source = r#"
+[defaults]
+host = "0.0.0.0"
+root = "public"
+error_pages = { "404" = "404.html", "500" = "error.html" }
+
+[defaults.route]
+methods = ["GET", "POST"]
+links = ["/styles.css"]
+
+[[servers]]
+ports = [8080]
+error_pages = { "404" = "not_found.html" }
+
+[[servers.routes]]
+alias = "/"
+
+[[servers.routes]]
+alias = "/api/"
+methods = ["GET"]
+
+[[servers]]
+host = "127.0.0.1"
+ports = [9000]
+root = "other"
+"#;
+        let config = parse_config("/etc/web_server/config.toml", source).unwrap();
+        let first = &config.servers[0];
+        assert_eq!(first.host, "0.0.0.0");
+        assert_eq!(first.root, "/etc/web_server/public");
+        assert_eq!(first.error_pages["404"], "not_found.html");
+        assert_eq!(first.error_pages["500"], "error.html");
+        assert_eq!(first.routes[0].methods, vec!["GET", "POST"]);
+        assert_eq!(first.routes[0].links, vec!["/styles.css"]);
+        assert_eq!(first.routes[1].methods, vec!["GET"]);
+
+        let second = &config.servers[1];
+        assert_eq!(second.host, "127.0.0.1");
+        assert_eq!(second.root, "/etc/web_server/other");
+    }
+
     #[test]
     fn test_find_route() {
         let config = setup_config();
@@ -399,21 +711,10 @@ mod tests {
         )
         .into_bytes();
         
-        let parts = parse_multipart_body(&body, boundary);
-        assert!(parts.contains_key("filename"));
-        let (filename, content) = &parts["filename"];
-        assert_eq!(filename, "test.txt");
-        assert_eq!(content, b"Hello World");
-    }
-
-    #[test]
-    fn test_find_bytes() {
-        let haystack = b"Hello, this is a test";
-        let needle = b"test";
-        assert_eq!(find_bytes(haystack, needle), Some(15));
-
-        let needle = b"not_found";
-        assert_eq!(find_bytes(haystack, needle), None);
+        let parts = parse_multipart_body(&body, boundary).expect("valid multipart body");
+        let part = parts.files().next().expect("file part");
+        assert_eq!(part.filename.as_deref(), Some("test.txt"));
+        assert_eq!(part.bytes(), Some(&b"Hello World"[..]));
     }
 
     #[test]
@@ -432,17 +733,16 @@ mod tests {
 
     #[test]
     fn test_verify_cookie() {
+        let config = ConfigServer {
+            cookie_file: temp_dir("verify_cookie").join("cookies.txt").to_string_lossy().into_owned(),
+            ..Default::default()
+        };
         let cookie_value = "sessionId=abcd1234|signature";
-        save_cookie_to_file(cookie_value).expect("Failed to save cookie");
-        assert!(verify_cookie(cookie_value));
+        assert!(!verify_cookie(&config, cookie_value));
+        save_cookie_to_file(&config.cookie_file, cookie_value).expect("Failed to save cookie");
+        assert!(verify_cookie(&config, cookie_value));
 
         let non_existent_cookie = "sessionId=nonexistent|signature";
-        assert!(!verify_cookie(non_existent_cookie));
-    }
-
-    #[test]
-    fn test_save_file() {
-        let result = save_file("/tmp", "test.txt", b"Hello, world!");
-        assert!(result.is_ok());
+        assert!(!verify_cookie(&config, non_existent_cookie));
     }
 }
@@ -0,0 +1,307 @@
+// csrf.rs
+use crate::config::{constant_time_eq, generate_session_id, sign_cookie, verify_cookie, ConfigServer, Route};
+use http::httprequest::HttpRequest;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+pub const CSRF_FIELD: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+pub const CSRF_PLACEHOLDER: &str = "{csrf_token}";
+
+// Protection CSRF d'une route, activée route par route
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CsrfPolicy {
+    #[serde(default)]
+    pub token: bool, // jeton synchronisé dérivé du cookie de session
+    #[serde(default)]
+    pub check_origin: bool, // Origin/Referer doit correspondre à l'en-tête Host
+    #[serde(default)]
+    pub trusted_origins: Vec<String>, // origines supplémentaires acceptées
+    pub same_site: Option<String>, // attribut SameSite du cookie de session
+}
+
+impl CsrfPolicy {
+    pub fn same_site(&self) -> &str {
+        self.same_site.as_deref().unwrap_or("Lax")
+    }
+}
+
+// Secret propre au processus pour dériver les jetons
+fn csrf_secret() -> String {
+    static SECRET: OnceLock<String> = OnceLock::new();
+    SECRET.get_or_init(generate_session_id).clone()
+}
+
+fn session_cookie(req: &HttpRequest, config: &ConfigServer) -> Option<String> {
+    let cookie = req.headers.get("Cookie")?.trim().to_string();
+    if verify_cookie(config, &cookie) {
+        Some(cookie)
+    } else {
+        None
+    }
+}
+
+// Jeton CSRF lié à la session: HMAC du cookie de session
+pub fn csrf_token(session: &str) -> String {
+    let signed = sign_cookie(session, csrf_secret());
+    signed
+        .rsplit('|')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+// Remplace {csrf_token} dans une page servie par une route protégée
+pub fn inject_token(req: &HttpRequest, route: &Route, config: &ConfigServer, body: &str) -> Option<String> {
+    route.csrf.as_ref().filter(|policy| policy.token)?;
+    if !body.contains(CSRF_PLACEHOLDER) {
+        return None;
+    }
+    let session = session_cookie(req, config)?;
+    Some(body.replace(CSRF_PLACEHOLDER, &csrf_token(&session)))
+}
+
+// Vérifie qu'une requête modifiant l'état n'est pas forgée par un site tiers.
+// `form_token` est le champ csrf_token du corps de la requête s'il existe.
+pub fn verify_csrf(req: &HttpRequest, route: &Route, form_token: Option<&str>) -> bool {
+    let policy = match &route.csrf {
+        Some(policy) => policy,
+        None => return true,
+    };
+
+    // Les navigateurs récents indiquent l'origine de la requête
+    if let Some(site) = req.headers.get("Sec-Fetch-Site") {
+        if site.trim().eq_ignore_ascii_case("cross-site") {
+            eprintln!("CSRF: cross-site request rejected");
+            return false;
+        }
+    }
+
+    let origin = origin_allowed(req, policy);
+    if policy.check_origin && origin == Some(false) {
+        eprintln!("CSRF: origin not allowed");
+        return false;
+    }
+
+    // Autorité ambiante: ce que le navigateur joint de lui-même à une requête
+    // forgée, le cookie de session ou des identifiants Basic mémorisés
+    let session = req.headers.get("Cookie").map(|cookie| cookie.trim().to_string());
+    let basic = req
+        .headers
+        .get("Authorization")
+        .and_then(|value| value.trim().get(..6))
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("Basic "));
+
+    if policy.token {
+        // Sans session, pas de jeton: une requête authentifiée par Basic doit
+        // venir de notre origine, une requête anonyme n'a rien à détourner
+        let session = match session {
+            Some(session) => session,
+            None if basic && origin != Some(true) => {
+                eprintln!("CSRF: authenticated request without same-origin Origin");
+                return false;
+            }
+            None => return true,
+        };
+        let provided = req
+            .headers
+            .get(CSRF_HEADER)
+            .map(|token| token.trim().to_string())
+            .or(form_token.map(|token| token.trim().to_string()));
+        let expected = csrf_token(&session);
+        return match provided {
+            Some(token) => constant_time_eq(token.as_bytes(), expected.as_bytes()),
+            None => {
+                eprintln!("CSRF: missing token");
+                false
+            }
+        };
+    }
+    // Sans Origin ni Referer, l'origine ne peut être vérifiée: seule une
+    // requête sans autorité ambiante (curl, scripts...) est acceptée
+    if policy.check_origin && origin.is_none() && (session.is_some() || basic) {
+        eprintln!("CSRF: missing origin");
+        return false;
+    }
+    true
+}
+
+// Some(true) si Origin (ou à défaut Referer) est la nôtre ou une origine de
+// confiance, None si la requête n'en indique aucune
+fn origin_allowed(req: &HttpRequest, policy: &CsrfPolicy) -> Option<bool> {
+    let source = req
+        .headers
+        .get("Origin")
+        .or_else(|| req.headers.get("Referer"))
+        .map(|value| value.trim())?;
+    if source == "null" {
+        return Some(false);
+    }
+    let origin = origin_of(source);
+    if policy
+        .trusted_origins
+        .iter()
+        .any(|trusted| origin_of(trusted) == origin)
+    {
+        return Some(true);
+    }
+    // Notre origine: schéma de la connexion, hôte et port de l'en-tête Host
+    let scheme = if req.secure { "https" } else { "http" };
+    Some(match req.headers.get("Host") {
+        Some(host) => origin == origin_of(&format!("{}://{}", scheme, host.trim())),
+        None => false,
+    })
+}
+
+// "http://localhost:8080/page?x" -> "http://localhost:8080", le port par
+// défaut du schéma étant omis: "https://example.com:443" -> "https://example.com"
+fn origin_of(url: &str) -> String {
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            let scheme = scheme.to_ascii_lowercase();
+            let authority = rest.split(['/', '?', '#']).next().unwrap_or_default().to_ascii_lowercase();
+            let default_port = match scheme.as_str() {
+                "http" => Some(":80"),
+                "https" => Some(":443"),
+                _ => None,
+            };
+            let authority = default_port
+                .and_then(|port| authority.strip_suffix(port))
+                .unwrap_or(&authority);
+            format!("{}://{}", scheme, authority)
+        }
+        None => url.to_ascii_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::httprequest::{Method, Resource, Version};
+    use std::collections::HashMap;
+
+    fn route(policy: CsrfPolicy) -> Route {
+        Route {
+            alias: "/upload/".to_string(),
+            methods: vec!["POST".to_string()],
+            csrf: Some(policy),
+            ..Default::default()
+        }
+    }
+
+    fn request(headers: &[(&str, &str)]) -> HttpRequest {
+        let headers: HashMap<String, String> = headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), format!(" {}\r\n", v)))
+            .collect();
+        HttpRequest::new(
+            Method::Post,
+            Version::V1_1,
+            Resource::Path("/upload/".to_string()),
+            headers,
+            String::new(),
+        )
+    }
+
+    #[test]
+    fn test_origin_check() {
+        let route = route(CsrfPolicy {
+            token: false,
+            check_origin: true,
+            trusted_origins: vec!["https://admin.example.com".to_string()],
+            same_site: None,
+        });
+        let same = request(&[("Host", "localhost:8080"), ("Origin", "http://localhost:8080")]);
+        assert!(verify_csrf(&same, &route, None));
+
+        let referer = request(&[
+            ("Host", "localhost:8080"),
+            ("Referer", "http://localhost:8080/login/"),
+        ]);
+        assert!(verify_csrf(&referer, &route, None));
+
+        let trusted = request(&[("Host", "localhost:8080"), ("Origin", "https://admin.example.com")]);
+        assert!(verify_csrf(&trusted, &route, None));
+
+        let forged = request(&[("Host", "localhost:8080"), ("Origin", "http://evil.example")]);
+        assert!(!verify_csrf(&forged, &route, None));
+
+        // Même hôte mais autre schéma ou autre port: origine différente
+        let scheme = request(&[("Host", "localhost:8080"), ("Origin", "https://localhost:8080")]);
+        assert!(!verify_csrf(&scheme, &route, None));
+        let port = request(&[("Host", "localhost:8080"), ("Origin", "http://localhost:9090")]);
+        assert!(!verify_csrf(&port, &route, None));
+        let mut secure = request(&[("Host", "example.com"), ("Origin", "https://example.com:443")]);
+        secure.secure = true;
+        assert!(verify_csrf(&secure, &route, None));
+        let mut downgraded = request(&[("Host", "example.com"), ("Origin", "http://example.com")]);
+        downgraded.secure = true;
+        assert!(!verify_csrf(&downgraded, &route, None));
+
+        let opaque = request(&[("Host", "localhost:8080"), ("Origin", "null")]);
+        assert!(!verify_csrf(&opaque, &route, None));
+
+        let cross = request(&[("Sec-Fetch-Site", "cross-site")]);
+        assert!(!verify_csrf(&cross, &route, None));
+
+        // Sans Origin ni Referer, seule une requête anonyme passe
+        assert!(verify_csrf(&request(&[("Host", "localhost:8080")]), &route, None));
+        let session = request(&[("Host", "localhost:8080"), ("Cookie", "sessionId=abc|def")]);
+        assert!(!verify_csrf(&session, &route, None));
+        let basic = request(&[("Host", "localhost:8080"), ("Authorization", "Basic YWxpY2U6cHc=")]);
+        assert!(!verify_csrf(&basic, &route, None));
+    }
+
+    #[test]
+    fn test_token_with_basic_auth() {
+        let route = route(CsrfPolicy {
+            token: true,
+            check_origin: false,
+            trusted_origins: vec![],
+            same_site: None,
+        });
+        // Les navigateurs renvoient seuls les identifiants Basic: sans session
+        // pour dériver un jeton, l'origine doit être la nôtre
+        let basic = ("Authorization", "Basic YWxpY2U6cHc=");
+        assert!(!verify_csrf(&request(&[("Host", "localhost:8080"), basic]), &route, None));
+        let forged = request(&[("Host", "localhost:8080"), ("Origin", "http://evil.example"), basic]);
+        assert!(!verify_csrf(&forged, &route, None));
+        let same = request(&[("Host", "localhost:8080"), ("Origin", "http://localhost:8080"), basic]);
+        assert!(verify_csrf(&same, &route, None));
+        // Un jeton Bearer n'est jamais joint automatiquement
+        let bearer = request(&[("Host", "localhost:8080"), ("Authorization", "Bearer token-1")]);
+        assert!(verify_csrf(&bearer, &route, None));
+    }
+
+    #[test]
+    fn test_synchronizer_token() {
+        let route = route(CsrfPolicy {
+            token: true,
+            check_origin: false,
+            trusted_origins: vec![],
+            same_site: Some("Strict".to_string()),
+        });
+        let session = "sessionId=abc|def";
+        let token = csrf_token(session);
+
+        let valid = request(&[("Cookie", session), (CSRF_HEADER, &token)]);
+        assert!(verify_csrf(&valid, &route, None));
+
+        let form = request(&[("Cookie", session)]);
+        assert!(verify_csrf(&form, &route, Some(&token)));
+        assert!(!verify_csrf(&form, &route, None));
+        assert!(!verify_csrf(&form, &route, Some("forged")));
+
+        // Un jeton d'une autre session est refusé
+        let other = request(&[("Cookie", "sessionId=other|sig"), (CSRF_HEADER, &token)]);
+        assert!(!verify_csrf(&other, &route, None));
+    }
+
+    #[test]
+    fn test_origin_of() {
+        assert_eq!(origin_of("HTTP://LocalHost:8080/a?b"), "http://localhost:8080");
+        assert_eq!(origin_of("https://example.com"), "https://example.com");
+        assert_eq!(origin_of("https://example.com:443/"), "https://example.com");
+        assert_eq!(origin_of("http://example.com:443"), "http://example.com:443");
+    }
+}
@@ -0,0 +1,94 @@
+// docroot.rs
+use crate::config::ConfigServer;
+use http::httpresponse::get_status_code_text_n_message;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Modèles compilés dans le binaire, utilisés lorsque la racine du serveur ne
+// fournit pas son propre error.html ou dir.html
+const ERROR_TEMPLATE: &str = include_str!("../public/error.html");
+const DIRECTORY_TEMPLATE: &str = include_str!("../public/dir.html");
+
+// Tous les fichiers servis (pages, pages d'erreur, modèles, scripts CGI,
+// fichiers envoyés) sont retrouvés à partir de la racine du serveur
+pub struct DocumentRoot<'a> {
+    root: &'a Path,
+}
+
+impl<'a> DocumentRoot<'a> {
+    pub fn new(root: &'a str) -> Self {
+        DocumentRoot { root: Path::new(root) }
+    }
+
+    pub fn of(config: &'a ConfigServer) -> Self {
+        Self::new(&config.root)
+    }
+
+    // "/css/style.css" et "css/style.css" désignent le même fichier
+    pub fn path(&self, file: &str) -> PathBuf {
+        self.root.join(file.trim_start_matches('/'))
+    }
+
+    pub fn read(&self, file: &str) -> Option<String> {
+        fs::read_to_string(self.path(file)).ok()
+    }
+
+    // Page configurée dans error_pages, sinon error.html de la racine, sinon
+    // le modèle intégré
+    pub fn error_page(&self, code: &str, custom: Option<&String>) -> String {
+        if let Some(contents) = custom.and_then(|page| self.read(page)) {
+            return contents;
+        }
+        let template = self.read("error.html");
+        let (code, text, message) = get_status_code_text_n_message(code);
+        template
+            .as_deref()
+            .unwrap_or(ERROR_TEMPLATE)
+            .replace("{code}", code)
+            .replace("{text}", text)
+            .replace("{message}", message)
+    }
+
+    pub fn directory_listing(&self, files: &str) -> String {
+        let template = self.read("dir.html");
+        template
+            .as_deref()
+            .unwrap_or(DIRECTORY_TEMPLATE)
+            .replace("{files}", files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    #[test]
+    fn test_path() {
+        let docroot = DocumentRoot::new("/srv/www");
+        assert_eq!(docroot.path("/css/style.css"), PathBuf::from("/srv/www/css/style.css"));
+        assert_eq!(docroot.path("index.html"), PathBuf::from("/srv/www/index.html"));
+    }
+
+    #[test]
+    fn test_embedded_templates() {
+        let docroot = DocumentRoot::new("/nonexistent");
+        let page = docroot.error_page("404", Some(&"404.html".to_string()));
+        assert!(page.contains("<h1>404 Error</h1>"));
+        assert!(!page.contains("{message}"));
+        assert!(docroot.directory_listing("<li>a</li>").contains("<li>a</li>"));
+    }
+
+    #[test]
+    fn test_root_templates_override() {
+        let root = temp_dir("docroot_templates");
+        fs::write(root.join("error.html"), "custom {code}").unwrap();
+        fs::write(root.join("404.html"), "not here").unwrap();
+        fs::write(root.join("dir.html"), "files: {files}").unwrap();
+        let root = root.to_string_lossy().into_owned();
+        let docroot = DocumentRoot::new(&root);
+        assert_eq!(docroot.error_page("404", Some(&"/404.html".to_string())), "not here");
+        assert_eq!(docroot.error_page("500", None), "custom 500");
+        assert_eq!(docroot.directory_listing("x"), "files: x");
+    }
+}
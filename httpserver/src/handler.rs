@@ -1,32 +1,25 @@
 // handlers.rs
+use crate::auth::authorize;
 use crate::config::{
     check_methods, find_route, found_links, is_page_found, verify_cookie, ConfigServer,
 };
-use http::httpresponse::get_status_code_text_n_message;
-use http::{httprequest::HttpRequest, httpresponse::HttpResponse};
+use crate::docroot::DocumentRoot;
+use crate::matcher::{match_route, RouteMatch};
+use crate::redirect::route_redirect_loop;
+use http::{httprequest::{HttpRequest, Method}, httpresponse::HttpResponse};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::env;
 use std::fs::{self};
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::thread;
 
 pub trait Handler {
     fn handle(&self, req: &HttpRequest, config: &ConfigServer) -> HttpResponse;
     fn load_file(file_name: &str, root: &str) -> Option<String> {
-        let public_path = env::var("PUBLIC_PATH").unwrap_or(root.to_string());
-        let full_path = format!("{}{}", public_path, file_name);
-        let contents = fs::read_to_string(full_path);
-        contents.ok()
-    }
-
-    fn load_default_file() -> Option<String> {
-        let default_path = format!("{}/public/", env!("CARGO_MANIFEST_DIR"));
-        // println!("corgo manifest: {}", env!("CARGO_MANIFEST_DIR"));
-        let public_path = env::var("PUBLIC_PATH").unwrap_or(default_path);
-        let full_path = format!("{}{}", public_path, "index.html");
-        let contents = fs::read_to_string(full_path);
-        contents.ok()
+        DocumentRoot::new(root).read(file_name)
     }
 }
 
@@ -36,6 +29,8 @@ pub struct OrderStatus {
     order_date: String,
     order_status: String,
 }
+const SAMPLE_ORDERS: &str = include_str!("../data/orders.json");
+
 pub struct StaticPageHandler;
 pub struct PageErrorHandler<'a> {
     pub status_code: &'a str,
@@ -45,51 +40,9 @@ impl<'a> PageErrorHandler<'a> {
     pub fn new(code: &'a str) -> Self {
         PageErrorHandler { status_code: code }
     }
-    pub fn load_file_error(code: &str) -> Option<String> {
-        let (code, error, message) = get_status_code_text_n_message(code);
-        let default_path = format!("{}/public", env!("CARGO_MANIFEST_DIR"));
-        let public_path = env::var("PUBLIC_PATH").unwrap_or(default_path);
-        let full_path = format!("{}/{}", public_path, "error.html");
-        let contents = fs::read_to_string(full_path);
-        let contents = contents.ok()?;
-
-        let contents = contents.replace("{code}", code);
-        let contents = contents.replace("{text}", error);
-        let contents = contents.replace("{message}", message);
-
-        return Some(contents);
-    }
-    pub fn load_file_error_client(file_error: &str, root: &str) -> Option<String> {
-        let paths = root
-            .split(env!("CARGO_MANIFEST_DIR"))
-            .collect::<Vec<&str>>();
-        let path = paths
-            .last()
-            .expect("msg: could not find 'CARGO_MANIFEST_DIR'");
-        let default_path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), path);
-        let public_path = env::var("PUBLIC_PATH").unwrap_or(default_path);
-        let full_path = format!("{}/{}", public_path, file_error);
-        let contents = fs::read_to_string(full_path);
-        let contents = contents.ok()?;
-
-        return Some(contents);
-    }
     pub fn error_response(config: &ConfigServer, status_code: &'a str) -> HttpResponse<'a> {
-        if let Some(error_page) = config.error_pages.get(status_code) {
-            return HttpResponse::new(
-                status_code,
-                config.host_name.clone(),
-                None,
-                PageErrorHandler::load_file_error_client(&error_page, &config.root),
-            );
-        } else {
-            return HttpResponse::new(
-                status_code,
-                config.host_name.clone(),
-                None,
-                PageErrorHandler::load_file_error(status_code),
-            );
-        }
+        let page = DocumentRoot::of(config).error_page(status_code, config.error_pages.get(status_code));
+        HttpResponse::new(status_code, config.host_name.clone(), None, Some(page))
     }
 }
 
@@ -97,12 +50,8 @@ pub struct WebServiceHandler;
 
 impl<'a> Handler for PageErrorHandler<'a> {
     fn handle(&self, _req: &HttpRequest, config: &ConfigServer) -> HttpResponse {
-        let content = if let Some(error_page) = config.error_pages.get(self.status_code) {
-            Self::load_file_error_client(&error_page, &config.root)
-        } else {
-            Self::load_file_error(&self.status_code)
-        };
-        HttpResponse::new(&self.status_code, config.host_name.clone(), None, content)
+        let page = DocumentRoot::of(config).error_page(self.status_code, config.error_pages.get(self.status_code));
+        HttpResponse::new(self.status_code, config.host_name.clone(), None, Some(page))
     }
 }
 
@@ -111,25 +60,29 @@ impl Handler for StaticPageHandler {
         // Get the path of static page resource being requested
         let http::httprequest::Resource::Path(paths) = &req.resource;
         let route: Vec<&str> = paths.split("/").collect();
-        let mut alias = String::new();
-        let mut is_alias = false;
+        let RouteMatch { alias, prefix, .. } = match_route(config, paths);
+        let is_alias = alias != "/";
 
         // println!("url complet: {}", paths);
         // let file: &str = route.last().expect("faild to take last");
 
-        let dir = format!("{}{}{}", config.root, "/", paths);
-        let path_dir = Path::new(&dir);
+        let dir = DocumentRoot::of(config).path(paths);
+        let path_dir = dir.as_path();
         if config.directory_listing {
-            let routes = find_route(config, "/").1;
+            // Les fichiers servis par le listing restent soumis à la route qui les couvre
+            let routes = find_route(config, &alias).1;
             if routes.check_cookie {
                 if let Some(cookie_header) = req.headers.get("Cookie") {
-                    if !verify_cookie(cookie_header) {
+                    if !verify_cookie(config, cookie_header) {
                         return PageErrorHandler::error_response(config, "401");
                     }
                 } else {
                     return PageErrorHandler::error_response(config, "401");
                 }
             }
+            if let Err(resp) = authorize(req, &routes, config) {
+                return *resp;
+            }
             if path_dir.exists() {
                 // println!("exit");
                 if path_dir.is_dir() {
@@ -138,12 +91,8 @@ impl Handler for StaticPageHandler {
                     if !paths.ends_with('/') {
                         url.push('/');
                     }
-                    return match list_directory_contents(&config.root, &url) {
-                        Some(contents) => {
-                            HttpResponse::new("200", config.host_name.clone(), None, Some(contents))
-                        }
-                        None => PageErrorHandler::error_response(config, "404"),
-                    };
+                    let contents = list_directory_contents(config, &url);
+                    return HttpResponse::new("200", config.host_name.clone(), None, Some(contents));
                 }
                 // println!("is file {} paths: {}", file, paths);
                 // let root = format!("{}{}", config.root, paths.replace(file, ""));
@@ -155,23 +104,17 @@ impl Handler for StaticPageHandler {
                 );
             }
         }
-        if route[0] == "" && route.len() == 2 {
-            alias.push_str("/");
-        } else {
-            alias = format!("/{}/", route[1]);
-            is_alias = true;
-        }
         let method = format!("{:?}", req.method);
 
-        if &alias == paths {
+        if &prefix == paths {
             if !check_methods(config, &method.to_ascii_uppercase(), &alias) {
                 return PageErrorHandler::error_response(config, "405");
             }
-            let (is_match, route) = find_route(config, &paths);
+            let (is_match, route) = find_route(config, &alias);
             if is_match {
                 if route.check_cookie {
                     if let Some(cookie_header) = req.headers.get("Cookie") {
-                        if !verify_cookie(cookie_header) {
+                        if !verify_cookie(config, cookie_header) {
                             // println!("Not cookie in file");
                             return PageErrorHandler::error_response(config, "401");
                         }
@@ -180,6 +123,9 @@ impl Handler for StaticPageHandler {
                         return PageErrorHandler::error_response(config, "401");
                     }
                 }
+                if let Err(resp) = authorize(req, &route, config) {
+                    return *resp;
+                }
                 if let Some(redirect_page) = route.redirect {
                     return redirection(&alias, redirect_page, config);
                 }
@@ -203,25 +149,24 @@ impl Handler for StaticPageHandler {
             }
         }
 
-        let page = paths.replacen(&alias, "", 1);
+        let page = paths.replacen(&prefix, "", 1);
         let file = route.last().expect("faild to take last");
 
         if file.ends_with(".php") || file.ends_with(".py") {
-            let mut path = String::from("/");
-            if route.len() > 2 {
-                path = format!("/{}/", route[1]);
-            }
-            let (is_match, route) = find_route(config, &path);
+            let (is_match, route) = find_route(config, &alias);
             if is_match {
                 if route.check_cookie {
                     if let Some(cookie_header) = req.headers.get("Cookie") {
-                        if !verify_cookie(cookie_header) {
+                        if !verify_cookie(config, cookie_header) {
                             return PageErrorHandler::error_response(config, "401");
                         }
                     } else {
                         return PageErrorHandler::error_response(config, "401");
                     }
                 }
+                if let Err(resp) = authorize(req, &route, config) {
+                    return *resp;
+                }
                 let output = StaticPageHandler::handle_cgi_request(&paths, &config);
                 if output.is_empty() {
                     return PageErrorHandler::error_response(config, "404");
@@ -239,13 +184,16 @@ impl Handler for StaticPageHandler {
                 if is_match {
                     if route.check_cookie {
                         if let Some(cookie_header) = req.headers.get("Cookie") {
-                            if !verify_cookie(cookie_header) {
+                            if !verify_cookie(config, cookie_header) {
                                 return PageErrorHandler::error_response(config, "401");
                             }
                         } else {
                             return PageErrorHandler::error_response(config, "401");
                         }
                     }
+                    if let Err(resp) = authorize(req, &route, config) {
+                        return *resp;
+                    }
                     if let Some(redirect_page) = route.redirect {
                         return redirection(&alias, redirect_page, config);
                     }
@@ -259,7 +207,7 @@ impl Handler for StaticPageHandler {
                         "200",
                         config.host_name.clone(),
                         None,
-                        Self::load_default_file(),
+                        Self::load_file(&route.default_page, &config.root),
                     )
                 } else {
                     if path_dir.exists() {
@@ -272,10 +220,15 @@ impl Handler for StaticPageHandler {
                 let mut full_path = format!("/{}", other);
                 let (is_match, route) = find_route(config, &alias);
                 if is_alias {
-                    full_path = format!("/{}", paths.replacen(&alias, "", 1))
+                    full_path = format!("/{}", paths.replacen(&prefix, "", 1))
                 }
 
                 if found_links(config, &full_path) {
+                    if is_match {
+                        if let Err(resp) = authorize(req, &route, config) {
+                            return *resp;
+                        }
+                    }
                     match Self::load_file(&full_path, &config.root) {
                         Some(contents) => {
                             let mut map: HashMap<&str, &str> = HashMap::new();
@@ -298,13 +251,16 @@ impl Handler for StaticPageHandler {
                 } else if is_match {
                     if route.check_cookie {
                         if let Some(cookie_header) = req.headers.get("Cookie") {
-                            if !verify_cookie(cookie_header) {
+                            if !verify_cookie(config, cookie_header) {
                                 return PageErrorHandler::error_response(config, "401");
                             }
                         } else {
                             return PageErrorHandler::error_response(config, "401");
                         }
                     }
+                    if let Err(resp) = authorize(req, &route, config) {
+                        return *resp;
+                    }
                     let file_path = format!("/{}", file);
                     if !check_methods(config, &method.to_ascii_uppercase(), &alias) {
                         return PageErrorHandler::error_response(config, "405");
@@ -322,9 +278,7 @@ impl Handler for StaticPageHandler {
                         Self::load_file(&file_path, &config.root),
                     )
                 } else {
-                    let dir = format!("{}/{}", config.root, other);
-                    let path_dir = Path::new(&dir);
-                    if path_dir.exists() {
+                    if DocumentRoot::of(config).path(other).exists() {
                         return PageErrorHandler::error_response(config, "403");
                     }
                     PageErrorHandler::error_response(config, "404")
@@ -334,15 +288,38 @@ impl Handler for StaticPageHandler {
     }
 }
 
+// Services web disponibles pour l'option `service` des routes
+pub const SERVICES: [&str; 1] = ["shipping"];
+
 impl WebServiceHandler {
-    fn load_json() -> Vec<OrderStatus> {
-        let default_path = format!("{}/data", env!("CARGO_MANIFEST_DIR"));
-        let data_path = env::var("DATA_PATH").unwrap_or(default_path);
-        let full_path = format!("{}/{}", data_path, "orders.json");
-        let json_contents = fs::read_to_string(full_path);
-        let orders: Vec<OrderStatus> =
-            serde_json::from_str(json_contents.unwrap().as_str()).unwrap();
-        orders
+    pub fn serve<'a>(service: &str, req: &HttpRequest, config: &ConfigServer) -> HttpResponse<'a> {
+        match service {
+            "shipping" => WebServiceHandler.handle(req, config),
+            _ => {
+                eprintln!("Unknown web service: {}", service);
+                PageErrorHandler::error_response(config, "404")
+            }
+        }
+    }
+
+    // Fichier des commandes, si $DATA_PATH est défini
+    fn orders_file() -> Option<PathBuf> {
+        env::var_os("DATA_PATH").map(|data_path| Path::new(&data_path).join("orders.json"))
+    }
+
+    // Les commandes sont lues dans orders.json, sinon les données d'exemple
+    // compilées dans le binaire sont utilisées
+    fn load_json(file: Option<&Path>) -> serde_json::Result<Vec<OrderStatus>> {
+        let json_contents = file.and_then(|file| fs::read_to_string(file).ok());
+        serde_json::from_str(json_contents.as_deref().unwrap_or(SAMPLE_ORDERS))
+    }
+
+    // Ajoute une commande à la fin de orders.json
+    fn append_order(file: &Path, order: OrderStatus) -> io::Result<()> {
+        let mut orders = Self::load_json(Some(file)).map_err(io::Error::other)?;
+        orders.push(order);
+        let json = serde_json::to_string_pretty(&orders).map_err(io::Error::other)?;
+        fs::write(file, json)
     }
 }
 
@@ -353,13 +330,43 @@ impl Handler for WebServiceHandler {
         // Parse the URI
         let route: Vec<&str> = s.split("/").collect();
         // if route if /api/shipping/orders, return json
-        match route[2] {
-            "shipping" if route.len() > 2 && route[3] == "orders" => {
-                let body =
-                    Some(serde_json::to_string(&Self::load_json()).expect("Error loading json"));
+        // Un segment manquant donne 404, sans indexer au-delà du chemin
+        match route.as_slice() {
+            [_, _, "shipping", "orders", ..] => {
                 let mut headers: HashMap<&str, &str> = HashMap::new();
                 headers.insert("Content-Type", "application/json");
-                HttpResponse::new("200", config.host_name.clone(), Some(headers), body)
+                if req.method == Method::Post {
+                    // Une nouvelle commande est envoyée en JSON
+                    let order: OrderStatus = match req
+                        .json
+                        .clone()
+                        .map(serde_json::from_value)
+                    {
+                        Some(Ok(order)) => order,
+                        Some(Err(_)) => return PageErrorHandler::error_response(config, "400"),
+                        None => return PageErrorHandler::error_response(config, "415"),
+                    };
+                    // Sans $DATA_PATH, la commande ne peut être enregistrée nulle part
+                    let Some(file) = Self::orders_file() else {
+                        return PageErrorHandler::error_response(config, "501");
+                    };
+                    let body = Some(serde_json::to_string(&order).expect("Error encoding json"));
+                    if let Err(e) = Self::append_order(&file, order) {
+                        eprintln!("Failed to store order in {}: {}", file.display(), e);
+                        return PageErrorHandler::error_response(config, "500");
+                    }
+                    return HttpResponse::new("201", config.host_name.clone(), Some(headers), body);
+                }
+                match Self::load_json(Self::orders_file().as_deref()) {
+                    Ok(orders) => {
+                        let body = Some(serde_json::to_string(&orders).expect("Error encoding json"));
+                        HttpResponse::new("200", config.host_name.clone(), Some(headers), body)
+                    }
+                    Err(e) => {
+                        eprintln!("Invalid orders.json: {}", e);
+                        PageErrorHandler::error_response(config, "500")
+                    }
+                }
             }
             _ => PageErrorHandler::error_response(config, "404"),
         }
@@ -371,8 +378,8 @@ impl StaticPageHandler {
         let keys: Vec<&str> = path.split('.').collect();
         let k = keys.last().expect("faild to take last");
         if let Some(script_path) = server.cgi_extensions.get(*k) {
-            let url_cgi = format!("{}{}", server.root, script_path);
-            if !Path::new(&url_cgi).exists() {
+            let url_cgi = DocumentRoot::of(server).path(script_path);
+            if !url_cgi.exists() {
                 return "".to_string();
             }
             let output = if script_path.ends_with(".php") {
@@ -396,6 +403,84 @@ impl StaticPageHandler {
         }
         "".to_string()
     }
+
+    // Commande lançant le script CGI d'une route avec les variables CGI/1.1
+    // habituelles; None si le script n'existe pas
+    pub fn cgi_command(
+        script: &str,
+        req: &HttpRequest,
+        content_length: usize,
+        server: &ConfigServer,
+    ) -> Option<Command> {
+        let script_path = DocumentRoot::of(server).path(script);
+        if !script_path.exists() {
+            eprintln!("CGI script not found: {}", script_path.display());
+            return None;
+        }
+        let mut command = if script.ends_with(".py") {
+            let mut command = Command::new("python3");
+            command.arg(&script_path);
+            command
+        } else {
+            Command::new(&script_path)
+        };
+        let http::httprequest::Resource::Path(path) = &req.resource;
+        let (path_info, query) = path.split_once('?').unwrap_or((path, ""));
+        command
+            .env("GATEWAY_INTERFACE", "CGI/1.1")
+            .env("SERVER_PROTOCOL", "HTTP/1.1")
+            .env("SERVER_NAME", server.host_name.trim())
+            .env("REQUEST_METHOD", format!("{:?}", req.method).to_ascii_uppercase())
+            .env("SCRIPT_NAME", script)
+            .env("PATH_INFO", path_info)
+            .env("QUERY_STRING", query)
+            .env("CONTENT_LENGTH", content_length.to_string())
+            .env("CONTENT_TYPE", req.headers.get("Content-Type").map_or("", |c| c.trim()))
+            .envs(req.params.iter().map(|(name, value)| {
+                (format!("ROUTE_PARAM_{}", name.to_ascii_uppercase()), value)
+            }));
+        Some(command)
+    }
+
+    // Exécute le script CGI d'une route en lui transmettant le corps de la
+    // requête sur l'entrée standard et les variables CGI/1.1 habituelles
+    pub fn run_cgi_script(
+        script: &str,
+        req: &HttpRequest,
+        body: &[u8],
+        server: &ConfigServer,
+    ) -> Option<String> {
+        let mut command = Self::cgi_command(script, req, body.len(), server)?;
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!("Failed to execute CGI script: {}", e);
+                return None;
+            }
+        };
+        // Écrire le corps depuis un thread évite un blocage si le script
+        // produit beaucoup de sortie avant de lire son entrée
+        let writer = child.stdin.take().map(|mut stdin| {
+            let body = body.to_vec();
+            thread::spawn(move || {
+                let _ = stdin.write_all(&body);
+            })
+        });
+        let output = child.wait_with_output().ok()?;
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+        if output.status.success() {
+            return Some(String::from_utf8_lossy(&output.stdout).to_string());
+        }
+        eprintln!("CGI script failed: {}", String::from_utf8_lossy(&output.stderr));
+        None
+    }
 }
 
 // Fonction pour gerer la redirection
@@ -405,73 +490,43 @@ pub fn redirection<'a>(
     config: &ConfigServer,
 ) -> HttpResponse<'a> {
     if let Some((new_alias, new_file)) = redirect_page.into_iter().next() {
-        if alias == new_alias || is_cycle(alias, &new_alias, config) {
+        // Les boucles sont refusées au chargement de la configuration
+        if let Some(cycle) = route_redirect_loop(config, alias) {
+            eprintln!("Redirect loop: {}", cycle.join(" -> "));
             return PageErrorHandler::error_response(config, "500");
         }
         let new_url = format!("{}{}", new_alias, new_file);
-        let new_url_str: &'a str = Box::leak(new_url.into_boxed_str());
-
-        let mut headers = HashMap::new();
-        headers.insert("Location", new_url_str);
 
-        return HttpResponse::new("302", config.host_name.clone(), Some(headers), None);
+        let mut response = HttpResponse::new("302", config.host_name.clone(), Some(HashMap::new()), None);
+        response.set_header("Location", new_url);
+        return response;
     }
     HttpResponse::new("", "".to_string(), None, None)
 }
 
-// Fonction pour verifier qu'il n'y a pas de cycle infie entre les redirection
-fn is_cycle(past_alias: &str, new_alias: &str, config: &ConfigServer) -> bool {
-    let (is_match, route) = find_route(config, &new_alias);
-    if is_match {
-        if let Some(redirect_page) = route.redirect {
-            let mut seen: HashSet<String> = HashSet::new();
-            seen.insert(past_alias.to_string());
-
-            for (futur_alias, _) in redirect_page {
-                if seen.contains(futur_alias.as_str()) {
-                    return true;
-                }
-                seen.insert(futur_alias);
-            }
-        }
-    }
-    false
-}
-
 // Helper function to list directory contents
-fn list_directory_contents(root: &str, url: &str) -> Option<String> {
+fn list_directory_contents(config: &ConfigServer, url: &str) -> String {
+    let docroot = DocumentRoot::of(config);
     let mut entries = Vec::new();
-    let dir = format!("{}{}", root, url);
-    if let Ok(paths) = fs::read_dir(dir) {
-        for path in paths {
-            if let Ok(entry) = path {
-                let file_name = entry.file_name().into_string().ok()?;
-                let file_type = entry.file_type().ok()?;
-                if file_type.is_dir() {
-                    entries.push(format!(
-                        "<li><a href=\"{}{}/\"><strong>{}/</strong></a></li>",
-                        url, file_name, file_name
-                    ));
-                } else if file_type.is_file() {
-                    entries.push(format!(
-                        "<li><a href=\"{}{}\">{}</a></li>",
-                        url, file_name, file_name
-                    ));
-                }
+    if let Ok(paths) = fs::read_dir(docroot.path(url)) {
+        for entry in paths.flatten() {
+            let (Ok(file_name), Ok(file_type)) = (entry.file_name().into_string(), entry.file_type()) else {
+                continue;
+            };
+            if file_type.is_dir() {
+                entries.push(format!(
+                    "<li><a href=\"{}{}/\"><strong>{}/</strong></a></li>",
+                    url, file_name, file_name
+                ));
+            } else if file_type.is_file() {
+                entries.push(format!(
+                    "<li><a href=\"{}{}\">{}</a></li>",
+                    url, file_name, file_name
+                ));
             }
         }
     }
-    parse_html(&entries.join(""))
-}
-
-fn parse_html(files: &str) -> Option<String> {
-    let default_path = format!("{}/public", env!("CARGO_MANIFEST_DIR"));
-    let public_path = env::var("PUBLIC_PATH").unwrap_or(default_path);
-    let full_path = format!("{}/{}", public_path, "dir.html");
-    let contents = fs::read_to_string(full_path);
-    let contents = contents.ok()?;
-    let contents = contents.replace("{files}", files);
-    Some(contents)
+    docroot.directory_listing(&entries.join(""))
 }
 
 #[cfg(test)]
@@ -479,6 +534,8 @@ mod tests {
     use crate::config::Route;
 
     use super::*;
+    use crate::auth::RouteAuth;
+    use crate::testing::temp_dir;
     use http::httprequest::{HttpRequest, Method, Resource, Version};
     use std::collections::HashMap;
 
@@ -490,26 +547,61 @@ mod tests {
         cgi_extensions.insert("py".to_string(), "/cgi-bin/script.py".to_string());
 
         ConfigServer {
-            host_name: String::from("localhost"),
             root: String::from("/public"),
-            directory_listing: false,
             error_pages,
             cgi_extensions,
-            host: String::from("127.0.0.1"),
             ports: vec![8080],
             client_body_limit: 1024,
             routes: vec![Route {
                 alias: "/".to_string(),
                 pages: vec!["index.html".to_string()],
                 default_page: "index.html".to_string(),
-                check_cookie: false,
-                redirect: None,
                 links: vec!["index.html".to_string()],
-                methods: vec!["GET".to_string()],
+                ..Default::default()
             }],
+            ..Default::default()
         }
     }
 
+    #[test]
+    fn test_protected_files_through_listing_and_links() {
+        let root = temp_dir("handler_listing_auth");
+        fs::create_dir_all(root.join("ci")).unwrap();
+        fs::write(root.join("ci/secret.txt"), "secret").unwrap();
+        fs::write(root.join("style.css"), "body {}").unwrap();
+        let tokens = root.join("tokens.txt");
+        fs::write(&tokens, "ci-token\n").unwrap();
+        let mut auth = RouteAuth {
+            bearer: Some(tokens.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        auth.load();
+        let mut config = setup_config();
+        config.root = root.to_string_lossy().into_owned();
+        config.routes.push(Route {
+            alias: "/ci/".to_string(),
+            links: vec!["/style.css".to_string()],
+            auth: Some(auth),
+            ..Default::default()
+        });
+        let status = |config: &ConfigServer, path: &str, token: Option<&str>| {
+            let headers = token
+                .map(|token| HashMap::from([("Authorization".to_string(), format!(" Bearer {}\r\n", token))]))
+                .unwrap_or_default();
+            let req = HttpRequest::new(Method::Get, Version::V1_1, Resource::Path(path.to_string()), headers, String::new());
+            StaticPageHandler.handle(&req, config).get_status_code().to_string()
+        };
+
+        // Lien déclaré par la route protégée
+        assert_eq!(status(&config, "/ci/style.css", None), "401");
+        assert_eq!(status(&config, "/ci/style.css", Some("ci-token")), "200");
+
+        // Fichier atteint par le listing des répertoires
+        config.directory_listing = true;
+        assert_eq!(status(&config, "/ci/secret.txt", None), "401");
+        assert_eq!(status(&config, "/ci/secret.txt", Some("ci-token")), "200");
+    }
+
     #[test]
     fn test_static_page_handler_handle() {
         let handler = StaticPageHandler;
@@ -539,14 +631,40 @@ mod tests {
         let response = handler.handle(&req, &config);
         assert_eq!(response.get_status_code(), "200");
         assert!(response.get_body().contains("order_id"));
+
+        // Chemins trop courts pour désigner les commandes
+        for path in ["/api/shipping", "/", "/shipping"] {
+            let req = HttpRequest::new(Method::Get, Version::V1_1, Resource::Path(path.to_string()), HashMap::new(), String::new());
+            assert_eq!(handler.handle(&req, &config).get_status_code(), "404", "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_web_service_handler_append_order() {
+        let file = temp_dir("orders").join("orders.json");
+        let order = |id| OrderStatus {
+            order_id: id,
+            order_date: "1 Mar 2020".to_string(),
+            order_status: "Pending".to_string(),
+        };
+
+        WebServiceHandler::append_order(&file, order(100)).unwrap();
+        WebServiceHandler::append_order(&file, order(101)).unwrap();
+        let orders = WebServiceHandler::load_json(Some(&file)).unwrap();
+        let sample = WebServiceHandler::load_json(None).unwrap();
+        assert_eq!(orders.len(), sample.len() + 2);
+        assert_eq!(orders.last().map(|o| o.order_id), Some(101));
+
+        fs::write(&file, "not json").unwrap();
+        assert!(WebServiceHandler::load_json(Some(&file)).is_err());
+        assert!(WebServiceHandler::append_order(&file, order(102)).is_err());
     }
 
     #[test]
     fn test_page_error_handler_load_file_error() {
-        let error_page = PageErrorHandler::load_file_error("404");
-        assert!(error_page.is_some());
-        let contents = error_page.unwrap();
-        assert!(contents.contains("404"));
+        let config = setup_config();
+        let response = PageErrorHandler::error_response(&config, "404");
+        assert!(response.get_body().contains("404"));
     }
 
     #[test]
@@ -586,6 +704,7 @@ mod response_tests {
                 h.insert("Server", "localhost");
                 Some(h)
             },
+            owned_headers: Vec::new(),
             body: Some("Item was shipped on 21st Dec 2020".into()),
         };
         assert_eq!(response_actual, response_expected);
@@ -609,6 +728,7 @@ mod response_tests {
                 h.insert("Server", "localhost");
                 Some(h)
             },
+            owned_headers: Vec::new(),
             body: Some("Item was shipped on 21st Dec 2020".into()),
         };
         assert_eq!(response_actual, response_expected);
@@ -626,6 +746,7 @@ mod response_tests {
                 h.insert("Server", "localhost");
                 Some(h)
             },
+            owned_headers: Vec::new(),
             body: Some("Item was shipped on 21st Dec 2020".into()),
         };
         let http_string: String = response_expected.clone().into();
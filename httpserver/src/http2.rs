@@ -0,0 +1,1242 @@
+// http2.rs
+use crate::config::{ConfigServer, Route};
+use crate::handler::PageErrorHandler;
+use crate::listener::Stream;
+use crate::proxy::{self, PendingStream, Relays};
+use crate::router::Router;
+use crate::server::resolved_route;
+use crate::upstream::{watch, Upstreams};
+use crate::websocket::WebSocketMode;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use http::hpack::{Decoder, Encoder};
+use http::http2::{
+    encode_headers, parse_settings, Frame, H2Error, Settings, COMPRESSION_ERROR, DEFAULT_MAX_FRAME_SIZE,
+    DEFAULT_WINDOW_SIZE, FLOW_CONTROL_ERROR, HTTP_1_1_REQUIRED, INTERNAL_ERROR, MAX_WINDOW_SIZE, NO_ERROR,
+    PREFACE, PROTOCOL_ERROR, REFUSED_STREAM, SETTINGS_MAX_CONCURRENT_STREAMS, STREAM_CLOSED,
+};
+use http::httprequest::{process_header_line, process_req_line, HttpRequest, Resource};
+use http::multipart::{boundary, MultipartParser};
+use http::websocket::header;
+use libc::{EPOLLIN, EPOLLOUT, EPOLLRDHUP, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::io::{self, prelude::*};
+use std::net::{IpAddr, Shutdown};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+// Octets lus à la fois sur la socket
+const READ_CHUNK: usize = 16 * 1024;
+
+// Données en attente d'envoi au-delà desquelles les corps ne sont plus
+// découpés en trames et la lecture est suspendue
+const HIGH_WATER: usize = 256 * 1024;
+
+const MAX_CONCURRENT_STREAMS: u32 = 100;
+
+// Taille maximale d'un bloc d'en-têtes reconstitué (HEADERS + CONTINUATION)
+const MAX_HEADER_BLOCK: usize = 64 * 1024;
+
+// Connexion sans flux ouvert fermée après ce délai
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+// En-têtes propres à une connexion HTTP/1.1, interdits en HTTP/2 (RFC 9113, 8.2.2)
+const CONNECTION_HEADERS: [&str; 5] = ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+const SWITCHING_PROTOCOLS: &[u8] = b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+
+// Paramètres d'une demande de passage en h2c (`Upgrade: h2c` et
+// `HTTP2-Settings`), None si la requête ne le demande pas ou qu'ils sont invalides
+pub fn h2c_upgrade(headers: &HashMap<String, String>) -> Option<Vec<(u16, u32)>> {
+    let upgrade = header(headers, "Upgrade")?;
+    if !upgrade.split(',').any(|token| token.trim().eq_ignore_ascii_case("h2c")) {
+        return None;
+    }
+    let encoded = header(headers, "HTTP2-Settings")?;
+    let payload = URL_SAFE_NO_PAD.decode(encoded.trim_end_matches('=')).ok()?;
+    let params = parse_settings(&payload).ok()?;
+    Settings::default().apply(&params).ok()?;
+    Some(params)
+}
+
+// Réponse HTTP/1.1 produite par le Router ou le relais, remise en forme pour HTTP/2
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+// Les réponses intermédiaires (100 Continue) sont ignorées, les noms passent
+// en minuscules et le corps est reconstitué s'il était découpé en chunks
+fn parse_response(mut raw: &[u8]) -> Option<Response> {
+    loop {
+        let end = raw.windows(4).position(|w| w == b"\r\n\r\n")?;
+        let head = std::str::from_utf8(&raw[..end]).ok()?;
+        let rest = &raw[end + 4..];
+        let mut lines = head.split("\r\n");
+        let status: u16 = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+        if (100..200).contains(&status) {
+            raw = rest;
+            continue;
+        }
+        let mut headers = Vec::new();
+        let mut chunked = false;
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let name = name.trim().to_ascii_lowercase();
+            let value = value.trim();
+            if name == "transfer-encoding" {
+                chunked = value.to_ascii_lowercase().contains("chunked");
+            }
+            if CONNECTION_HEADERS.contains(&name.as_str()) || name == "content-length" {
+                continue;
+            }
+            headers.push((name, value.to_string()));
+        }
+        let body = if chunked { dechunk(rest)? } else { rest.to_vec() };
+        headers.push(("content-length".to_string(), body.len().to_string()));
+        return Some(Response { status, headers, body });
+    }
+}
+
+fn dechunk(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let end = data.windows(2).position(|w| w == b"\r\n")?;
+        let line = std::str::from_utf8(&data[..end]).ok()?;
+        let size = usize::from_str_radix(line.split(';').next()?.trim(), 16).ok()?;
+        data = &data[end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
+
+// "content-type" -> "Content-Type", forme attendue par les recherches d'en-têtes
+fn canonical(name: &str) -> String {
+    name.split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// Requête équivalente à la liste d'en-têtes d'un flux (RFC 9113, 8.3): les
+// pseudo-en-têtes forment la ligne de requête et `:authority` l'en-tête Host
+fn request(fields: &[(String, String)], body_len: usize) -> Result<HttpRequest, &'static str> {
+    let (mut method, mut path, mut scheme, mut authority) = (None, None, None, None);
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for (name, value) in fields {
+        if value.contains(['\r', '\n', '\0']) {
+            return Err("invalid header value");
+        }
+        if let Some(pseudo) = name.strip_prefix(':') {
+            if !headers.is_empty() {
+                return Err("pseudo-header after regular header");
+            }
+            let slot = match pseudo {
+                "method" => &mut method,
+                "path" => &mut path,
+                "scheme" => &mut scheme,
+                "authority" => &mut authority,
+                _ => return Err("unknown pseudo-header"),
+            };
+            if slot.replace(value.as_str()).is_some() {
+                return Err("duplicate pseudo-header");
+            }
+            continue;
+        }
+        if name.is_empty() || name.bytes().any(|b| b.is_ascii_uppercase() || b == b':' || b <= b' ') {
+            return Err("invalid header name");
+        }
+        if CONNECTION_HEADERS.contains(&name.as_str()) || (name == "te" && value != "trailers") {
+            return Err("connection-specific header");
+        }
+        // Les valeurs répétées sont jointes, les cookies par "; " (RFC 9113, 8.2.3)
+        let name = canonical(name);
+        match headers.iter_mut().find(|(known, _)| *known == name) {
+            Some((_, known)) => {
+                known.push_str(if name == "Cookie" { "; " } else { ", " });
+                known.push_str(value);
+            }
+            None => headers.push((name, value.clone())),
+        }
+    }
+    let (Some(method), Some(path), Some(_)) = (method, path, scheme) else {
+        return Err("missing pseudo-header");
+    };
+    if !path.starts_with('/') || path.contains(char::is_whitespace) || method.contains(char::is_whitespace) {
+        return Err("invalid request line");
+    }
+    if let Some(authority) = authority.filter(|_| !headers.iter().any(|(name, _)| name == "Host")) {
+        headers.push(("Host".to_string(), authority.to_string()));
+    }
+    match headers.iter().find(|(name, _)| name == "Content-Length") {
+        Some((_, length)) if length.trim().parse::<usize>() != Ok(body_len) => {
+            return Err("content-length does not match the body")
+        }
+        Some(_) => {}
+        None => headers.push(("Content-Length".to_string(), body_len.to_string())),
+    }
+    let (method, resource, version) = process_req_line(&format!("{} {} HTTP/2.0\r\n", method, path))?;
+    let headers = headers
+        .iter()
+        .map(|(name, value)| process_header_line(&format!("{}: {}\r\n", name, value)))
+        .collect();
+    Ok(HttpRequest::new(method, version, resource, headers, String::new()))
+}
+
+// Réponse d'un flux: produite sur place, ou attendue d'un serveur amont
+enum Reply {
+    Output(Vec<u8>),
+    Proxy(Box<Proxied>),
+}
+
+// Requête d'une route `proxy_pass`, relayée une fois la trame traitée
+struct Proxied {
+    req: HttpRequest,
+    body: Vec<u8>,
+    route: Route,
+    prefix: String,
+}
+
+// Sert une requête complète comme le ferait handle_client et retourne la
+// réponse HTTP/1.1 produite, la requête à relayer pour une route
+// `proxy_pass`, ou le code de réinitialisation du flux
+fn serve(mut req: HttpRequest, body: Vec<u8>, config: &ConfigServer) -> Result<Reply, u32> {
+    let mut output = Vec::new();
+    let send = |status: &str, output: &mut Vec<u8>| {
+        let _ = PageErrorHandler::error_response(config, status).send_response(output);
+    };
+    if let Some((route, found, path)) = resolved_route(config, &req.resource) {
+        // WebSocket et flux d'événements restent servis en HTTP/1.1
+        let upgrading = route.websocket.as_ref().is_some_and(|policy| policy.mode != WebSocketMode::Proxy);
+        if upgrading || route.sse.is_some() {
+            return Err(HTTP_1_1_REQUIRED);
+        }
+        if route.proxy_pass.is_some() {
+            req.resource = Resource::Path(path);
+            req.params = found.params;
+            let prefix = found.prefix;
+            return Ok(Reply::Proxy(Box::new(Proxied { req, body, route, prefix })));
+        }
+    }
+    if body.len() > config.client_body_limit {
+        send("413", &mut output);
+        return Ok(Reply::Output(output));
+    }
+    let multipart = req
+        .headers
+        .get("Content-Type")
+        .filter(|content_type| content_type.trim_start().starts_with("multipart/form-data"))
+        .and_then(|content_type| boundary(content_type));
+    let mut msg_body = body;
+    if let Some(boundary) = multipart {
+        let max_file_size = resolved_route(config, &req.resource)
+            .and_then(|(route, ..)| route.upload)
+            .and_then(|policy| policy.max_file_size);
+        let mut parser =
+            MultipartParser::new(&boundary, Some(&env::temp_dir())).with_max_file_size(max_file_size);
+        match parser.feed(&msg_body).and_then(|_| parser.finish()) {
+            Ok(multipart) => req = req.with_multipart(multipart),
+            Err(e) if e.kind() == io::ErrorKind::FileTooLarge => {
+                eprintln!("Invalid multipart body: {}", e);
+                send("413", &mut output);
+                return Ok(Reply::Output(output));
+            }
+            Err(e) => {
+                eprintln!("Invalid multipart body: {}", e);
+                send("400", &mut output);
+                return Ok(Reply::Output(output));
+            }
+        }
+        msg_body = Vec::new();
+    }
+    req.msg_body = String::from_utf8_lossy(&msg_body).into_owned();
+    if let Err(e) = req.decode_body(&msg_body) {
+        eprintln!("Invalid request body: {}", e);
+        send("400", &mut output);
+        return Ok(Reply::Output(output));
+    }
+    Router::route(req, &mut output, config, msg_body);
+    Ok(Reply::Output(output))
+}
+
+// Flux ouvert par le client: requête en cours de réception, puis corps de
+// la réponse en cours d'envoi
+struct H2Stream {
+    fields: Vec<(String, String)>,
+    body: Vec<u8>,
+    remote_closed: bool, // END_STREAM reçu
+    recv_window: i64,
+    send_window: i64,
+    response: Option<Vec<u8>>, // corps restant à envoyer, une fois les en-têtes partis
+    sent: usize,
+}
+
+impl H2Stream {
+    fn new(fields: Vec<(String, String)>, send_window: u32) -> Self {
+        H2Stream {
+            fields,
+            body: Vec::new(),
+            remote_closed: false,
+            recv_window: DEFAULT_WINDOW_SIZE as i64,
+            send_window: send_window as i64,
+            response: None,
+            sent: 0,
+        }
+    }
+}
+
+// Bloc d'en-têtes dont les trames CONTINUATION sont attendues
+struct HeaderBlock {
+    stream: u32,
+    block: Vec<u8>,
+    end_stream: bool,
+}
+
+struct Connection {
+    stream: Stream,
+    serial: u64, // distingue la connexion d'une autre qui reprendrait son descripteur
+    config: ConfigServer,
+    client_ip: Option<IpAddr>,
+    inbound: Vec<u8>,
+    outbound: Vec<u8>,
+    blocked: bool,           // l'envoi attend que la socket soit prête en écriture
+    preface: bool,           // préface du client reçue
+    settings_received: bool, // première trame SETTINGS du client reçue
+    settings: Settings,      // paramètres du client
+    decoder: Decoder,
+    streams: BTreeMap<u32, H2Stream>,
+    last_stream: u32, // plus grand identifiant de flux ouvert par le client
+    header_block: Option<HeaderBlock>,
+    send_window: i64,
+    recv_window: i64,
+    last_seen: Instant,
+    goaway_sent: bool,     // la connexion se termine une fois les données transmises
+    goaway_received: bool, // plus de nouveau flux: fermeture après les réponses en cours
+    draining: bool,        // arrêt du serveur: GOAWAY envoyé, les flux en cours se terminent
+    proxied: Vec<(u32, Proxied)>, // flux à relayer vers un serveur amont
+}
+
+impl Connection {
+    fn new(stream: Stream, config: &ConfigServer, serial: u64) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        // Les trames courtes (WINDOW_UPDATE, fin d'un corps) partent sans attendre
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            client_ip: stream.peer_ip(),
+            stream,
+            serial,
+            config: config.clone(),
+            inbound: Vec::new(),
+            outbound: Vec::new(),
+            blocked: false,
+            preface: false,
+            settings_received: false,
+            settings: Settings::default(),
+            decoder: Decoder::default(),
+            streams: BTreeMap::new(),
+            last_stream: 0,
+            header_block: None,
+            send_window: DEFAULT_WINDOW_SIZE as i64,
+            recv_window: DEFAULT_WINDOW_SIZE as i64,
+            last_seen: Instant::now(),
+            goaway_sent: false,
+            goaway_received: false,
+            draining: false,
+            proxied: Vec::new(),
+        })
+    }
+
+    fn queue(&mut self, frame: Frame) {
+        self.outbound.extend_from_slice(&frame.encode());
+    }
+
+    fn goaway(&mut self, code: u32) {
+        if !self.goaway_sent {
+            self.queue(Frame::GoAway { last_stream: self.last_stream, code });
+            self.goaway_sent = true;
+        }
+    }
+
+    // GOAWAY sans erreur: les flux déjà ouverts continuent d'être servis,
+    // les suivants sont refusés
+    fn drain(&mut self) {
+        if !self.goaway_sent && !self.draining {
+            self.queue(Frame::GoAway { last_stream: self.last_stream, code: NO_ERROR });
+            self.draining = true;
+        }
+    }
+
+    // Traite les trames reçues; une erreur de connexion envoie GOAWAY
+    fn receive(&mut self) {
+        if let Err(e) = self.process() {
+            eprintln!("HTTP/2 connection error: {}", e.reason);
+            self.goaway(e.code);
+        }
+    }
+
+    fn process(&mut self) -> Result<(), H2Error> {
+        if !self.preface {
+            if self.inbound.len() < PREFACE.len() {
+                return match PREFACE.starts_with(&self.inbound) {
+                    true => Ok(()),
+                    false => Err(H2Error::protocol("invalid connection preface")),
+                };
+            }
+            if !self.inbound.starts_with(PREFACE) {
+                return Err(H2Error::protocol("invalid connection preface"));
+            }
+            self.inbound.drain(..PREFACE.len());
+            self.preface = true;
+        }
+        while !self.goaway_sent {
+            let Some((frame, used)) = Frame::parse(&self.inbound, DEFAULT_MAX_FRAME_SIZE)? else {
+                break;
+            };
+            self.inbound.drain(..used);
+            if !self.settings_received && !matches!(frame, Frame::Settings { ack: false, .. }) {
+                return Err(H2Error::protocol("expected SETTINGS"));
+            }
+            self.on_frame(frame)?;
+        }
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: Frame) -> Result<(), H2Error> {
+        // Un bloc d'en-têtes ne peut pas être entrecoupé d'autres trames
+        if let Some(pending) = &self.header_block {
+            match &frame {
+                Frame::Continuation { stream, .. } if *stream == pending.stream => {}
+                _ => return Err(H2Error::protocol("expected CONTINUATION")),
+            }
+        }
+        match frame {
+            Frame::Settings { ack: true, .. } => {}
+            Frame::Settings { params, .. } => {
+                let previous = self.settings.initial_window_size as i64;
+                self.settings.apply(&params)?;
+                let delta = self.settings.initial_window_size as i64 - previous;
+                for stream in self.streams.values_mut() {
+                    stream.send_window += delta;
+                    if stream.send_window > MAX_WINDOW_SIZE as i64 {
+                        return Err(H2Error { code: FLOW_CONTROL_ERROR, reason: "stream window overflow" });
+                    }
+                }
+                self.settings_received = true;
+                self.queue(Frame::Settings { ack: true, params: Vec::new() });
+            }
+            Frame::Ping { ack: false, data } => self.queue(Frame::Ping { ack: true, data }),
+            Frame::GoAway { .. } => self.goaway_received = true,
+            Frame::WindowUpdate { stream, increment } => self.window_update(stream, increment)?,
+            Frame::Headers { stream, block, end_stream, end_headers } => {
+                self.header_block = Some(HeaderBlock { stream, block, end_stream });
+                if end_headers {
+                    self.end_headers()?;
+                }
+            }
+            Frame::Continuation { block, end_headers, .. } => {
+                let Some(pending) = self.header_block.as_mut() else {
+                    return Err(H2Error::protocol("unexpected CONTINUATION"));
+                };
+                pending.block.extend_from_slice(&block);
+                if pending.block.len() > MAX_HEADER_BLOCK {
+                    return Err(H2Error::protocol("header block too large"));
+                }
+                if end_headers {
+                    self.end_headers()?;
+                }
+            }
+            Frame::Data { stream, payload, end_stream, flow_len } => {
+                self.data(stream, payload, end_stream, flow_len)?
+            }
+            Frame::RstStream { stream, .. } => {
+                if stream > self.last_stream {
+                    return Err(H2Error::protocol("RST_STREAM on idle stream"));
+                }
+                self.streams.remove(&stream);
+            }
+            Frame::Ping { .. } | Frame::Priority { .. } | Frame::Unknown { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn window_update(&mut self, id: u32, increment: u32) -> Result<(), H2Error> {
+        if id == 0 {
+            if increment == 0 {
+                return Err(H2Error::protocol("WINDOW_UPDATE with zero increment"));
+            }
+            self.send_window += increment as i64;
+            if self.send_window > MAX_WINDOW_SIZE as i64 {
+                return Err(H2Error { code: FLOW_CONTROL_ERROR, reason: "connection window overflow" });
+            }
+        } else if let Some(stream) = self.streams.get_mut(&id) {
+            stream.send_window += increment as i64;
+            if increment == 0 {
+                self.reset(id, PROTOCOL_ERROR);
+            } else if stream.send_window > MAX_WINDOW_SIZE as i64 {
+                self.reset(id, FLOW_CONTROL_ERROR);
+            }
+        } else if id > self.last_stream {
+            return Err(H2Error::protocol("WINDOW_UPDATE on idle stream"));
+        }
+        Ok(())
+    }
+
+    fn end_headers(&mut self) -> Result<(), H2Error> {
+        let Some(HeaderBlock { stream: id, block, end_stream }) = self.header_block.take() else {
+            return Ok(());
+        };
+        // Le bloc est décodé même si le flux est refusé: la table dynamique en dépend
+        let fields = self
+            .decoder
+            .decode(&block)
+            .map_err(|_| H2Error { code: COMPRESSION_ERROR, reason: "invalid header block" })?;
+        if let Some(stream) = self.streams.get_mut(&id) {
+            // En-têtes de fin (trailers): ils terminent le corps
+            if stream.remote_closed || !end_stream {
+                return Err(H2Error::protocol("unexpected HEADERS"));
+            }
+            stream.remote_closed = true;
+            if stream.response.is_none() {
+                self.complete(id);
+            }
+            return Ok(());
+        }
+        if id <= self.last_stream {
+            return Err(H2Error { code: STREAM_CLOSED, reason: "HEADERS on closed stream" });
+        }
+        if id % 2 == 0 {
+            return Err(H2Error::protocol("even stream identifier"));
+        }
+        self.last_stream = id;
+        if self.draining || self.streams.len() >= MAX_CONCURRENT_STREAMS as usize {
+            self.queue(Frame::RstStream { stream: id, code: REFUSED_STREAM });
+            return Ok(());
+        }
+        let mut stream = H2Stream::new(fields, self.settings.initial_window_size);
+        stream.remote_closed = end_stream;
+        self.streams.insert(id, stream);
+        if end_stream {
+            self.complete(id);
+        }
+        Ok(())
+    }
+
+    fn data(
+        &mut self,
+        id: u32,
+        payload: Vec<u8>,
+        end_stream: bool,
+        flow_len: u32,
+    ) -> Result<(), H2Error> {
+        let flow_len = flow_len as i64;
+        if flow_len > self.recv_window {
+            return Err(H2Error { code: FLOW_CONTROL_ERROR, reason: "connection window exceeded" });
+        }
+        self.recv_window -= flow_len;
+        if self.recv_window < DEFAULT_WINDOW_SIZE as i64 / 2 {
+            let increment = DEFAULT_WINDOW_SIZE as i64 - self.recv_window;
+            self.queue(Frame::WindowUpdate { stream: 0, increment: increment as u32 });
+            self.recv_window += increment;
+        }
+        let Some(stream) = self.streams.get_mut(&id) else {
+            if id > self.last_stream {
+                return Err(H2Error::protocol("DATA on idle stream"));
+            }
+            // Flux déjà réinitialisé: les données encore en vol sont ignorées
+            return Ok(());
+        };
+        if stream.remote_closed {
+            self.reset(id, STREAM_CLOSED);
+            return Ok(());
+        }
+        if flow_len > stream.recv_window {
+            self.reset(id, FLOW_CONTROL_ERROR);
+            return Ok(());
+        }
+        stream.recv_window -= flow_len;
+        stream.remote_closed = end_stream;
+        // Réponse déjà envoyée (corps trop grand): le reste du corps est ignoré
+        if stream.response.is_some() {
+            return Ok(());
+        }
+        if stream.body.len() + payload.len() > self.config.client_body_limit {
+            eprintln!("Request body exceeds limit");
+            let mut output = Vec::new();
+            let _ = PageErrorHandler::error_response(&self.config, "413").send_response(&mut output);
+            self.respond(id, &output);
+            return Ok(());
+        }
+        stream.body.extend_from_slice(&payload);
+        if end_stream {
+            self.complete(id);
+        } else if stream.recv_window < DEFAULT_WINDOW_SIZE as i64 / 2 {
+            let increment = DEFAULT_WINDOW_SIZE as i64 - stream.recv_window;
+            stream.recv_window += increment;
+            self.queue(Frame::WindowUpdate { stream: id, increment: increment as u32 });
+        }
+        Ok(())
+    }
+
+    // Requête reçue en entier: elle est servie tout de suite, ou relayée
+    // par `Http2Connections` une fois les trames traitées
+    fn complete(&mut self, id: u32) {
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return;
+        };
+        let fields = std::mem::take(&mut stream.fields);
+        let body = std::mem::take(&mut stream.body);
+        let result = match request(&fields, body.len()) {
+            Ok(mut req) => {
+                req.secure = matches!(self.stream, Stream::Tls(_));
+                serve(req, body, &self.config)
+            }
+            Err(reason) => {
+                eprintln!("Malformed HTTP/2 request: {}", reason);
+                Err(PROTOCOL_ERROR)
+            }
+        };
+        self.reply(id, result);
+    }
+
+    fn reply(&mut self, id: u32, result: Result<Reply, u32>) {
+        match result {
+            Ok(Reply::Output(output)) => self.respond(id, &output),
+            Ok(Reply::Proxy(proxied)) => self.proxied.push((id, *proxied)),
+            Err(code) => self.reset(id, code),
+        }
+    }
+
+    // Envoie les en-têtes de la réponse; le corps part ensuite avec `pump`
+    fn respond(&mut self, id: u32, output: &[u8]) {
+        let Some(response) = parse_response(output) else {
+            eprintln!("Invalid response for HTTP/2 stream {}", id);
+            return self.reset(id, INTERNAL_ERROR);
+        };
+        let status = response.status.to_string();
+        let fields = [(":status", status.as_str())]
+            .into_iter()
+            .chain(response.headers.iter().map(|(name, value)| (name.as_str(), value.as_str())));
+        let block = Encoder.encode(fields);
+        let end_stream = response.body.is_empty();
+        let headers = encode_headers(id, &block, end_stream, self.settings.max_frame_size);
+        self.outbound.extend_from_slice(&headers);
+        if end_stream {
+            self.finish(id);
+        } else if let Some(stream) = self.streams.get_mut(&id) {
+            stream.response = Some(response.body);
+        }
+    }
+
+    // Réponse terminée; si le client envoie encore un corps, il est arrêté
+    fn finish(&mut self, id: u32) {
+        if let Some(stream) = self.streams.remove(&id) {
+            if !stream.remote_closed {
+                self.queue(Frame::RstStream { stream: id, code: NO_ERROR });
+            }
+        }
+    }
+
+    fn reset(&mut self, id: u32, code: u32) {
+        self.streams.remove(&id);
+        self.queue(Frame::RstStream { stream: id, code });
+    }
+
+    // Découpe les corps en attente en trames DATA, une par flux et par tour,
+    // dans la limite des fenêtres de contrôle de flux du client
+    fn pump(&mut self) {
+        loop {
+            let mut progress = false;
+            let ids: Vec<u32> = self
+                .streams
+                .iter()
+                .filter(|(_, stream)| stream.response.is_some())
+                .map(|(id, _)| *id)
+                .collect();
+            for id in ids {
+                if self.outbound.len() >= HIGH_WATER {
+                    return;
+                }
+                let Some(stream) = self.streams.get_mut(&id) else {
+                    continue;
+                };
+                let Some(body) = &stream.response else {
+                    continue;
+                };
+                let window = self.send_window.min(stream.send_window).max(0) as usize;
+                let size = (body.len() - stream.sent).min(window).min(self.settings.max_frame_size as usize);
+                if size == 0 {
+                    continue;
+                }
+                let payload = body[stream.sent..stream.sent + size].to_vec();
+                stream.sent += size;
+                stream.send_window -= size as i64;
+                let end_stream = stream.sent == body.len();
+                self.send_window -= size as i64;
+                self.queue(Frame::Data { stream: id, payload, end_stream, flow_len: size as u32 });
+                if end_stream {
+                    self.finish(id);
+                }
+                progress = true;
+            }
+            if !progress {
+                return;
+            }
+        }
+    }
+
+    // Envoie ce qui peut l'être sans bloquer, en complétant avec les corps
+    // en attente à mesure que la socket se libère
+    fn send(&mut self) -> io::Result<()> {
+        loop {
+            while !self.outbound.is_empty() {
+                match (&self.stream).write(&self.outbound) {
+                    Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                    Ok(n) => {
+                        self.outbound.drain(..n);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        self.blocked = true;
+                        return Ok(());
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            // Enregistrements TLS encore gardés par rustls
+            match (&self.stream).flush() {
+                Ok(()) => self.blocked = false,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.blocked = true;
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            }
+            if self.goaway_sent {
+                return Ok(());
+            }
+            self.pump();
+            if self.outbound.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn events(&self) -> u32 {
+        let mut events = EPOLLRDHUP as u32;
+        if self.outbound.len() < HIGH_WATER {
+            events |= EPOLLIN as u32;
+        }
+        if self.blocked {
+            events |= EPOLLOUT as u32;
+        }
+        events
+    }
+
+    fn done(&self) -> bool {
+        let idle = self.streams.is_empty() || self.goaway_sent;
+        self.outbound.is_empty() && !self.blocked && idle && (self.goaway_sent || self.goaway_received || self.draining)
+    }
+}
+
+// Connexions HTTP/2, servies par la boucle epoll du serveur
+#[derive(Default)]
+pub struct Http2Connections {
+    connections: HashMap<RawFd, Connection>,
+    next_serial: u64,
+    epoll_fd: Option<RawFd>,
+}
+
+impl Http2Connections {
+    pub fn attach(&mut self, epoll_fd: RawFd) {
+        self.epoll_fd = Some(epoll_fd);
+    }
+
+    // Confie une connexion à HTTP/2: après ALPN "h2", une préface reçue en
+    // clair (`inbound`) ou une requête passée en h2c qui devient le flux 1
+    pub fn open(
+        &mut self,
+        stream: Stream,
+        inbound: Vec<u8>,
+        config: &ConfigServer,
+        upgrade: Option<(HttpRequest, Vec<(u16, u32)>)>,
+        upstreams: &mut Upstreams,
+        relays: &mut Relays,
+    ) {
+        self.next_serial += 1;
+        let mut conn = match Connection::new(stream, config, self.next_serial) {
+            Ok(conn) => conn,
+            Err(e) => return eprintln!("HTTP/2 setup failed: {}", e),
+        };
+        if upgrade.is_some() {
+            conn.outbound.extend_from_slice(SWITCHING_PROTOCOLS);
+        }
+        conn.queue(Frame::Settings {
+            ack: false,
+            params: vec![(SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS)],
+        });
+        if let Some((mut req, params)) = upgrade {
+            // Paramètres déjà validés par `h2c_upgrade`
+            let _ = conn.settings.apply(&params);
+            for name in ["Connection", "Upgrade", "HTTP2-Settings"] {
+                req.headers.retain(|key, _| !key.trim().eq_ignore_ascii_case(name));
+            }
+            let mut stream = H2Stream::new(Vec::new(), conn.settings.initial_window_size);
+            stream.remote_closed = true;
+            conn.streams.insert(1, stream);
+            conn.last_stream = 1;
+            conn.reply(1, serve(req, Vec::new(), config));
+        }
+        let fd = conn.stream.as_raw_fd();
+        conn.inbound = inbound;
+        conn.receive();
+        watch(self.epoll_fd, EPOLL_CTL_ADD, fd, (EPOLLIN | EPOLLRDHUP) as u32);
+        self.connections.insert(fd, conn);
+        self.relay(fd, upstreams, relays);
+        self.flush(fd);
+    }
+
+    // Événement epoll: false si le descripteur n'est pas une connexion HTTP/2
+    pub fn on_event(&mut self, token: u64, upstreams: &mut Upstreams, relays: &mut Relays) -> bool {
+        let fd = token as RawFd;
+        let Some(conn) = self.connections.get_mut(&fd) else {
+            return false;
+        };
+        conn.last_seen = Instant::now();
+        // Lecture jusqu'à WouldBlock: avec TLS, rustls peut garder des données
+        // déjà déchiffrées qu'epoll ne signalerait plus
+        let mut chunk = vec![0; READ_CHUNK];
+        while conn.outbound.len() < HIGH_WATER {
+            match (&conn.stream).read(&mut chunk) {
+                Ok(0) => {
+                    self.teardown(fd);
+                    return true;
+                }
+                // GOAWAY envoyé: les trames suivantes sont ignorées
+                Ok(_) if conn.goaway_sent => {}
+                Ok(n) => {
+                    conn.inbound.extend_from_slice(&chunk[..n]);
+                    conn.receive();
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => {
+                    self.teardown(fd);
+                    return true;
+                }
+            }
+        }
+        self.relay(fd, upstreams, relays);
+        self.flush(fd);
+        true
+    }
+
+    // Réponse d'un flux relayé vers un serveur amont, ignorée si le flux ou
+    // la connexion ont disparu entre-temps
+    pub fn deliver(&mut self, pending: PendingStream, output: &[u8]) {
+        let Some(conn) = self.connections.get_mut(&pending.conn) else {
+            return;
+        };
+        if conn.serial != pending.serial || !conn.streams.contains_key(&pending.stream) {
+            return;
+        }
+        conn.respond(pending.stream, output);
+        self.flush(pending.conn);
+    }
+
+    // Transmet aux serveurs amont les requêtes reçues sur les routes `proxy_pass`
+    fn relay(&mut self, fd: RawFd, upstreams: &mut Upstreams, relays: &mut Relays) {
+        let Some(conn) = self.connections.get_mut(&fd) else {
+            return;
+        };
+        for (id, proxied) in std::mem::take(&mut conn.proxied) {
+            let pending = PendingStream { conn: fd, serial: conn.serial, stream: id };
+            let Proxied { req, body, route, prefix } = proxied;
+            if let Some(output) = proxy::serve_http2(&req, body, &conn.config, &route, &prefix, conn.client_ip, pending, upstreams, relays) {
+                conn.respond(id, &output);
+            }
+        }
+    }
+
+    // Ferme les connexions restées sans flux plus de IDLE_TIMEOUT
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        let idle: Vec<RawFd> = self
+            .connections
+            .iter()
+            .filter(|(_, conn)| conn.streams.is_empty() && !conn.goaway_sent)
+            .filter(|(_, conn)| now.duration_since(conn.last_seen) >= IDLE_TIMEOUT)
+            .map(|(fd, _)| *fd)
+            .collect();
+        for fd in idle {
+            if let Some(conn) = self.connections.get_mut(&fd) {
+                conn.goaway(NO_ERROR);
+            }
+            self.flush(fd);
+        }
+    }
+
+    // Début de l'arrêt: chaque client reçoit GOAWAY, la connexion se ferme
+    // une fois les réponses en cours transmises
+    pub fn shut_down(&mut self) {
+        let fds: Vec<RawFd> = self.connections.keys().cloned().collect();
+        for fd in fds {
+            if let Some(conn) = self.connections.get_mut(&fd) {
+                conn.drain();
+            }
+            self.flush(fd);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    // Fin du délai d'arrêt: les connexions restantes sont fermées
+    pub fn close_all(&mut self) {
+        let fds: Vec<RawFd> = self.connections.keys().cloned().collect();
+        for fd in fds {
+            if let Some(conn) = self.connections.get_mut(&fd) {
+                conn.goaway(NO_ERROR);
+                let _ = conn.send();
+            }
+            self.teardown(fd);
+        }
+    }
+
+    // Envoie ce qui peut l'être puis ajuste les événements surveillés
+    fn flush(&mut self, fd: RawFd) {
+        let Some(conn) = self.connections.get_mut(&fd) else {
+            return;
+        };
+        if conn.send().is_err() || conn.done() {
+            return self.teardown(fd);
+        }
+        watch(self.epoll_fd, EPOLL_CTL_MOD, fd, conn.events());
+    }
+
+    fn teardown(&mut self, fd: RawFd) {
+        let Some(conn) = self.connections.remove(&fd) else {
+            return;
+        };
+        watch(self.epoll_fd, EPOLL_CTL_DEL, fd, 0);
+        let _ = conn.stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Route;
+    use crate::websocket::WebSocketPolicy;
+    use http::http2::{CANCEL, SETTINGS_INITIAL_WINDOW_SIZE};
+    use http::httprequest::{Method, Version};
+    use libc::{epoll_create1, epoll_event, epoll_wait, EPOLL_CLOEXEC};
+    use std::net::{TcpListener, TcpStream};
+
+    fn setup_config() -> ConfigServer {
+        ConfigServer {
+            root: String::from("/public"),
+            client_body_limit: 1024,
+            routes: vec![Route {
+                alias: "/ws/".to_string(),
+                websocket: Some(WebSocketPolicy::default()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    fn get(path: &str) -> Vec<u8> {
+        Encoder.encode([(":method", "GET"), (":scheme", "http"), (":authority", "localhost"), (":path", path)])
+    }
+
+    fn attached() -> (Http2Connections, RawFd) {
+        let epoll_fd = unsafe { epoll_create1(EPOLL_CLOEXEC) };
+        let mut connections = Http2Connections::default();
+        connections.attach(epoll_fd);
+        (connections, epoll_fd)
+    }
+
+    // Connexion d'un client confiée à `connections`
+    fn open(connections: &mut Http2Connections, upgrade: Option<(HttpRequest, Vec<(u16, u32)>)>) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        connections.open(stream.into(), Vec::new(), &setup_config(), upgrade, &mut Upstreams::default(), &mut Relays::default());
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        client
+    }
+
+    // Traite les événements jusqu'à ce qu'il n'y en ait plus
+    fn pump(connections: &mut Http2Connections, epoll_fd: RawFd) {
+        let mut events = vec![epoll_event { events: 0, u64: 0 }; 16];
+        loop {
+            let n = unsafe { epoll_wait(epoll_fd, events.as_mut_ptr(), events.len() as i32, 100) };
+            if n <= 0 {
+                return;
+            }
+            for event in &events[..n as usize] {
+                assert!(connections.on_event(event.u64, &mut Upstreams::default(), &mut Relays::default()));
+            }
+        }
+    }
+
+    fn send(client: &mut TcpStream, frame: Frame) {
+        client.write_all(&frame.encode()).unwrap();
+    }
+
+    fn receive(client: &mut TcpStream) -> Frame {
+        let mut header = [0; 9];
+        client.read_exact(&mut header).unwrap();
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let mut bytes = header.to_vec();
+        bytes.resize(9 + len, 0);
+        client.read_exact(&mut bytes[9..]).unwrap();
+        Frame::parse(&bytes, 1 << 24).unwrap().unwrap().0
+    }
+
+    // Préface et SETTINGS du client, puis SETTINGS du serveur et son accusé
+    fn start(client: &mut TcpStream, connections: &mut Http2Connections, epoll_fd: RawFd, params: Vec<(u16, u32)>) {
+        client.write_all(PREFACE).unwrap();
+        send(client, Frame::Settings { ack: false, params });
+        pump(connections, epoll_fd);
+        let settings = Frame::Settings { ack: false, params: vec![(SETTINGS_MAX_CONCURRENT_STREAMS, 100)] };
+        assert_eq!(receive(client), settings);
+        assert_eq!(receive(client), Frame::Settings { ack: true, params: Vec::new() });
+    }
+
+    fn status(decoder: &mut Decoder, frame: Frame) -> (u32, String) {
+        let Frame::Headers { stream, block, .. } = frame else {
+            panic!("expected HEADERS, got {:?}", frame);
+        };
+        (stream, decoder.decode(&block).unwrap()[0].1.clone())
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let raw = b"HTTP/1.1 100 Continue\r\n\r\n\
+            HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\nX-Id: 7\r\n\r\n\
+            3\r\ntwo\r\n2;ext\r\n!!\r\n0\r\n\r\n";
+        let expected = Response {
+            status: 200,
+            headers: fields(&[("x-id", "7"), ("content-length", "5")]),
+            body: b"two!!".to_vec(),
+        };
+        assert_eq!(parse_response(raw), Some(expected));
+        assert_eq!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n"), None);
+    }
+
+    #[test]
+    fn test_request_mapping() {
+        let req = request(
+            &fields(&[
+                (":method", "POST"),
+                (":scheme", "https"),
+                (":authority", "localhost"),
+                (":path", "/upload?x=1"),
+                ("cookie", "a=1"),
+                ("cookie", "b=2"),
+                ("content-type", "text/plain"),
+            ]),
+            4,
+        )
+        .unwrap();
+        assert_eq!(req.method, Method::Post);
+        assert!(matches!(req.version, Version::V2_0));
+        assert_eq!(req.resource, Resource::Path("/upload?x=1".to_string()));
+        let header = |name: &str| req.headers.get(name).map(String::as_str);
+        assert_eq!(header("Host"), Some(" localhost\r\n"));
+        assert_eq!(header("Cookie"), Some(" a=1; b=2\r\n"));
+        assert_eq!(header("Content-Type"), Some(" text/plain\r\n"));
+        assert_eq!(header("Content-Length"), Some(" 4\r\n"));
+
+        let base = [(":method", "GET"), (":scheme", "https"), (":path", "/")];
+        let malformed = |extra: &[(&str, &str)]| request(&fields(&[&base[..], extra].concat()), 0).is_err();
+        assert!(malformed(&[("Accept", "*/*")]));
+        assert!(malformed(&[("connection", "keep-alive")]));
+        assert!(malformed(&[("te", "gzip")]));
+        assert!(malformed(&[("accept", "*/*"), (":authority", "localhost")]));
+        assert!(malformed(&[(":path", "/again")]));
+        assert!(malformed(&[("content-length", "3")]));
+        assert!(request(&fields(&base[..2]), 0).is_err());
+        assert!(!malformed(&[("te", "trailers")]));
+    }
+
+    #[test]
+    fn test_streams_and_flow_control() {
+        let (mut connections, epoll_fd) = attached();
+        let mut client = open(&mut connections, None);
+        // Fenêtre de 16 octets par flux
+        start(&mut client, &mut connections, epoll_fd, vec![(SETTINGS_INITIAL_WINDOW_SIZE, 16)]);
+        let mut decoder = Decoder::default();
+        let requests: Vec<u8> = [1, 3]
+            .into_iter()
+            .flat_map(|stream| Frame::Headers { stream, block: get("/missing"), end_stream: true, end_headers: true }.encode())
+            .collect();
+        client.write_all(&requests).unwrap();
+        pump(&mut connections, epoll_fd);
+        assert_eq!(status(&mut decoder, receive(&mut client)), (1, "404".to_string()));
+        assert_eq!(status(&mut decoder, receive(&mut client)), (3, "404".to_string()));
+        for stream in [1, 3] {
+            let Frame::Data { stream: id, payload, end_stream, .. } = receive(&mut client) else {
+                panic!("expected DATA");
+            };
+            assert_eq!((id, payload.len(), end_stream), (stream, 16, false));
+        }
+
+        // La suite du corps du flux 1 part après WINDOW_UPDATE
+        send(&mut client, Frame::WindowUpdate { stream: 1, increment: 1 << 20 });
+        pump(&mut connections, epoll_fd);
+        let Frame::Data { stream: 1, end_stream: true, .. } = receive(&mut client) else {
+            panic!("expected the end of stream 1");
+        };
+        send(&mut client, Frame::RstStream { stream: 3, code: CANCEL });
+        send(&mut client, Frame::Ping { ack: false, data: *b"12345678" });
+        pump(&mut connections, epoll_fd);
+        assert_eq!(receive(&mut client), Frame::Ping { ack: true, data: *b"12345678" });
+        let conn = connections.connections.values().next().unwrap();
+        assert!(conn.streams.is_empty());
+
+        // Les WebSocket restent en HTTP/1.1
+        send(&mut client, Frame::Headers { stream: 5, block: get("/ws/"), end_stream: true, end_headers: true });
+        pump(&mut connections, epoll_fd);
+        assert_eq!(receive(&mut client), Frame::RstStream { stream: 5, code: HTTP_1_1_REQUIRED });
+
+        // GOAWAY du client: la connexion se ferme
+        send(&mut client, Frame::GoAway { last_stream: 0, code: NO_ERROR });
+        pump(&mut connections, epoll_fd);
+        assert!(connections.connections.is_empty());
+        assert!(matches!(client.read(&mut [0; 16]), Ok(0)));
+        unsafe {
+            libc::close(epoll_fd);
+        }
+    }
+
+    #[test]
+    fn test_shut_down_finishes_streams() {
+        let (mut connections, epoll_fd) = attached();
+        let mut client = open(&mut connections, None);
+        start(&mut client, &mut connections, epoll_fd, vec![(SETTINGS_INITIAL_WINDOW_SIZE, 16)]);
+        let mut decoder = Decoder::default();
+        send(&mut client, Frame::Headers { stream: 1, block: get("/missing"), end_stream: true, end_headers: true });
+        pump(&mut connections, epoll_fd);
+        assert_eq!(status(&mut decoder, receive(&mut client)), (1, "404".to_string()));
+        assert!(matches!(receive(&mut client), Frame::Data { stream: 1, end_stream: false, .. }));
+
+        // GOAWAY sans erreur: le flux 1 se termine, les nouveaux sont refusés
+        connections.shut_down();
+        assert_eq!(receive(&mut client), Frame::GoAway { last_stream: 1, code: NO_ERROR });
+        send(&mut client, Frame::Headers { stream: 3, block: get("/missing"), end_stream: true, end_headers: true });
+        send(&mut client, Frame::WindowUpdate { stream: 1, increment: 1 << 20 });
+        pump(&mut connections, epoll_fd);
+        assert_eq!(receive(&mut client), Frame::RstStream { stream: 3, code: REFUSED_STREAM });
+        assert!(matches!(receive(&mut client), Frame::Data { stream: 1, end_stream: true, .. }));
+        assert!(matches!(client.read(&mut [0; 16]), Ok(0)));
+        assert!(connections.is_empty());
+        unsafe {
+            libc::close(epoll_fd);
+        }
+    }
+
+    #[test]
+    fn test_connection_errors() {
+        let (mut connections, epoll_fd) = attached();
+        let cases: [(Vec<u8>, u32); 4] = [
+            // Flux pair, puis flux déjà fermé
+            (Frame::Headers { stream: 2, block: get("/"), end_stream: true, end_headers: true }.encode(), PROTOCOL_ERROR),
+            (
+                [
+                    Frame::Headers { stream: 3, block: get("/"), end_stream: true, end_headers: true }.encode(),
+                    Frame::Headers { stream: 1, block: get("/"), end_stream: true, end_headers: true }.encode(),
+                ]
+                .concat(),
+                STREAM_CLOSED,
+            ),
+            // Bloc d'en-têtes interrompu par une autre trame
+            (
+                [
+                    Frame::Headers { stream: 1, block: get("/"), end_stream: true, end_headers: false }.encode(),
+                    Frame::Ping { ack: false, data: [0; 8] }.encode(),
+                ]
+                .concat(),
+                PROTOCOL_ERROR,
+            ),
+            (Frame::Headers { stream: 1, block: vec![0xff; 6], end_stream: true, end_headers: true }.encode(), COMPRESSION_ERROR),
+        ];
+        for (bytes, code) in cases {
+            let mut client = open(&mut connections, None);
+            start(&mut client, &mut connections, epoll_fd, Vec::new());
+            client.write_all(&bytes).unwrap();
+            pump(&mut connections, epoll_fd);
+            let goaway = loop {
+                match receive(&mut client) {
+                    Frame::GoAway { code, .. } => break code,
+                    _ => continue,
+                }
+            };
+            assert_eq!(goaway, code);
+            assert!(matches!(client.read(&mut [0; 16]), Ok(0)));
+        }
+
+        // Préface invalide
+        let mut client = open(&mut connections, None);
+        client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        pump(&mut connections, epoll_fd);
+        receive(&mut client);
+        assert!(matches!(receive(&mut client), Frame::GoAway { code: PROTOCOL_ERROR, .. }));
+        assert!(connections.connections.is_empty());
+        unsafe {
+            libc::close(epoll_fd);
+        }
+    }
+
+    #[test]
+    fn test_h2c_upgrade() {
+        let header = |name: &str, value: &str| (name.to_string(), format!(" {}\r\n", value));
+        let mut headers: HashMap<String, String> =
+            [header("Host", "localhost"), header("HTTP2-Settings", "AAMAAABkAAQAoAAAAAIAAAAA")].into();
+        assert_eq!(h2c_upgrade(&headers), None);
+        headers.extend([header("Upgrade", "h2c"), header("Connection", "Upgrade, HTTP2-Settings")]);
+        let params = h2c_upgrade(&headers).unwrap();
+        assert_eq!(params, vec![(SETTINGS_MAX_CONCURRENT_STREAMS, 100), (SETTINGS_INITIAL_WINDOW_SIZE, 10 << 20), (0x2, 0)]);
+        headers.extend([header("HTTP2-Settings", "AAQAgAAAAA")]);
+        assert_eq!(h2c_upgrade(&headers), None);
+
+        // La requête d'origine devient le flux 1
+        let (mut connections, epoll_fd) = attached();
+        let req = HttpRequest::new(Method::Get, Version::V1_1, Resource::Path("/missing".to_string()), headers, String::new());
+        let mut client = open(&mut connections, Some((req, params)));
+        let mut head = vec![0; SWITCHING_PROTOCOLS.len()];
+        client.read_exact(&mut head).unwrap();
+        assert_eq!(head, SWITCHING_PROTOCOLS);
+        assert!(matches!(receive(&mut client), Frame::Settings { ack: false, .. }));
+        assert_eq!(status(&mut Decoder::default(), receive(&mut client)), (1, "404".to_string()));
+        assert!(matches!(receive(&mut client), Frame::Data { stream: 1, end_stream: true, .. }));
+        client.write_all(PREFACE).unwrap();
+        send(&mut client, Frame::Settings { ack: false, params: Vec::new() });
+        pump(&mut connections, epoll_fd);
+        assert_eq!(receive(&mut client), Frame::Settings { ack: true, params: Vec::new() });
+        connections.close_all();
+        assert_eq!(receive(&mut client), Frame::GoAway { last_stream: 1, code: NO_ERROR });
+        unsafe {
+            libc::close(epoll_fd);
+        }
+    }
+}
@@ -1,2 +1,19 @@
+// httpserver/lib.rs
+pub mod auth;
+pub mod csrf;
+pub mod docroot;
 pub mod handler;
-pub mod config;
\ No newline at end of file
+pub mod listener;
+pub mod loader;
+pub mod matcher;
+pub mod proxy;
+pub mod redirect;
+pub mod sse;
+#[cfg(test)]
+pub mod testing;
+pub mod tls;
+pub mod config;
+pub mod upload;
+pub mod upstream;
+pub mod validate;
+pub mod websocket;
\ No newline at end of file
@@ -0,0 +1,429 @@
+// listener.rs
+use crate::config::ListenAddr;
+use crate::tls::TlsStream;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::Duration;
+
+// Socket d'écoute à ouvrir et ses options
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListenSpec {
+    pub addr: ListenAddr,
+    pub backlog: i32,
+    pub reuse_port: bool,
+    pub v6_only: bool, // sinon une socket [::] accepte aussi les connexions IPv4
+    pub mode: Option<u32>, // permissions du fichier d'une socket Unix
+}
+
+impl ListenSpec {
+    pub fn new(addr: ListenAddr) -> Self {
+        ListenSpec {
+            addr,
+            backlog: 128,
+            reuse_port: false,
+            v6_only: false,
+            mode: None,
+        }
+    }
+
+    pub fn bind(&self) -> io::Result<Listener> {
+        match &self.addr {
+            ListenAddr::Tcp(addr) => self.bind_tcp(*addr).map(Listener::Tcp),
+            ListenAddr::Unix(path) => self.bind_unix(path).map(Listener::Unix),
+        }
+    }
+
+    // Crée, configure puis lie la socket; std ne permet pas de régler
+    // SO_REUSEPORT, IPV6_V6ONLY ni la file d'attente avant bind
+    fn bind_tcp(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let domain = match addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        unsafe {
+            let fd = libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0);
+            if fd == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            // Le listener ferme la socket en cas d'erreur
+            let listener = TcpListener::from_raw_fd(fd);
+            set_option(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, true)?;
+            if self.reuse_port {
+                set_option(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, true)?;
+            }
+            if addr.is_ipv6() {
+                set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, self.v6_only)?;
+            }
+            let (raw, len) = raw_sockaddr(addr);
+            let result = libc::bind(fd, &raw as *const libc::sockaddr_storage as *const libc::sockaddr, len);
+            if result == -1 || libc::listen(fd, self.backlog) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(listener)
+        }
+    }
+
+    fn bind_unix(&self, path: &Path) -> io::Result<UnixListener> {
+        remove_stale_socket(path)?;
+        let listener = UnixListener::bind(path)?;
+        // Un second listen() ajuste la file d'attente choisie par std
+        if unsafe { libc::listen(listener.as_raw_fd(), self.backlog) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if let Some(mode) = self.mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(listener)
+    }
+}
+
+// Adresse au format attendu par bind et connect
+pub fn raw_sockaddr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let raw = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            raw.sin_family = libc::AF_INET as libc::sa_family_t;
+            raw.sin_port = addr.port().to_be();
+            raw.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+            std::mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let raw = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            raw.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            raw.sin6_port = addr.port().to_be();
+            raw.sin6_addr.s6_addr = addr.ip().octets();
+            raw.sin6_scope_id = addr.scope_id();
+            std::mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+fn set_option(fd: RawFd, level: libc::c_int, name: libc::c_int, enabled: bool) -> io::Result<()> {
+    let value = enabled as libc::c_int;
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Un fichier de socket laissé par un processus arrêté brutalement empêche
+// bind; il n'est supprimé que si plus personne n'y écoute
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+    match UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("another process is listening on {}", path.display()),
+        )),
+        Err(_) => fs::remove_file(path),
+    }
+}
+
+// Socket d'écoute TCP ou Unix surveillée par epoll
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Socket transmise par le processus précédent, déjà liée et à l'écoute
+    ///
+    /// # Safety
+    /// `fd` doit être une socket ouverte du type de `addr`, dont le listener
+    /// devient propriétaire
+    pub unsafe fn from_inherited(addr: &ListenAddr, fd: RawFd) -> Self {
+        match addr {
+            ListenAddr::Tcp(_) => Listener::Tcp(TcpListener::from_raw_fd(fd)),
+            ListenAddr::Unix(_) => Listener::Unix(UnixListener::from_raw_fd(fd)),
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            Listener::Unix(listener) => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    // Supprime le fichier d'une socket Unix; sans effet pour TCP
+    pub fn remove_socket_file(&self) {
+        if let Listener::Unix(listener) = self {
+            if let Some(path) = listener.local_addr().ok().as_ref().and_then(|a| a.as_pathname()) {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+// Connexion acceptée sur une socket TCP ou Unix, éventuellement chiffrée
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+    Tls(Box<TlsStream>),
+}
+
+impl Stream {
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+            Stream::Tls(stream) => stream.socket().set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_write_timeout(timeout),
+            Stream::Unix(stream) => stream.set_write_timeout(timeout),
+            Stream::Tls(stream) => stream.socket().set_write_timeout(timeout),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            Stream::Unix(stream) => stream.shutdown(how),
+            Stream::Tls(stream) => stream.shutdown(how),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+            Stream::Tls(stream) => stream.socket().set_nonblocking(nonblocking),
+        }
+    }
+
+    // Sans objet pour une socket Unix
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nodelay(nodelay),
+            Stream::Unix(_) => Ok(()),
+            Stream::Tls(stream) => stream.socket().set_nodelay(nodelay),
+        }
+    }
+
+    // Adresse de destination de la connexion: adresse IP réelle pour TCP,
+    // même si la socket est liée à 0.0.0.0, chemin de la socket pour Unix
+    pub fn local_addr(&self) -> Option<ListenAddr> {
+        match self {
+            Stream::Tcp(stream) => stream.local_addr().ok().map(ListenAddr::Tcp),
+            Stream::Unix(stream) => {
+                let addr = stream.local_addr().ok()?;
+                addr.as_pathname().map(|path| ListenAddr::Unix(path.to_path_buf()))
+            }
+            Stream::Tls(stream) => stream.socket().local_addr(),
+        }
+    }
+
+    // Adresse IP du client, sans objet pour une socket Unix
+    pub fn peer_ip(&self) -> Option<IpAddr> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr().ok().map(|addr| addr.ip().to_canonical()),
+            Stream::Unix(_) => None,
+            Stream::Tls(stream) => stream.socket().peer_ip(),
+        }
+    }
+
+    // Nom demandé par le client lors de la poignée de main TLS (SNI)
+    pub fn server_name(&self) -> Option<String> {
+        match self {
+            Stream::Tls(stream) => stream.server_name(),
+            _ => None,
+        }
+    }
+
+    // Protocole négocié par ALPN lors de la poignée de main TLS
+    pub fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        match self {
+            Stream::Tls(stream) => stream.alpn_protocol(),
+            _ => None,
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+            Stream::Unix(stream) => stream.as_raw_fd(),
+            Stream::Tls(stream) => stream.socket().as_raw_fd(),
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Stream::Tcp(stream)
+    }
+}
+
+impl From<UnixStream> for Stream {
+    fn from(stream: UnixStream) -> Self {
+        Stream::Unix(stream)
+    }
+}
+
+impl Read for &Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).read(buf),
+            Stream::Unix(stream) => (&*stream).read(buf),
+            Stream::Tls(stream) => (&**stream).read(buf),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for &Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).write(buf),
+            Stream::Unix(stream) => (&*stream).write(buf),
+            Stream::Tls(stream) => (&**stream).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).flush(),
+            Stream::Unix(stream) => (&*stream).flush(),
+            Stream::Tls(stream) => (&**stream).flush(),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use std::path::PathBuf;
+
+    fn socket_path(name: &str) -> PathBuf {
+        temp_dir("listener").join(name)
+    }
+
+    fn tcp(addr: &str) -> ListenSpec {
+        ListenSpec::new(ListenAddr::Tcp(addr.parse().unwrap()))
+    }
+
+    #[test]
+    fn test_bind_options() {
+        let dual = tcp("[::]:0").bind_tcp("[::]:0".parse().unwrap()).unwrap();
+        let port = dual.local_addr().unwrap().port();
+        let client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let (stream, _) = dual.accept().unwrap();
+        assert_eq!(stream.local_addr().unwrap().ip().to_canonical().to_string(), "127.0.0.1");
+        drop(client);
+
+        let shared = ListenSpec {
+            reuse_port: true,
+            ..tcp("127.0.0.1:0")
+        };
+        let first = shared.bind_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = first.local_addr().unwrap();
+        assert!(shared.bind_tcp(addr).is_ok());
+        assert!(tcp(&addr.to_string()).bind().is_err());
+    }
+
+    #[test]
+    fn test_unix_listener() {
+        let path = socket_path("listener_test.sock");
+        let spec = ListenSpec {
+            mode: Some(0o600),
+            ..ListenSpec::new(ListenAddr::Unix(path.clone()))
+        };
+        let listener = spec.bind().unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        let mut stream = listener.accept().unwrap();
+        assert_eq!(stream.local_addr(), Some(ListenAddr::Unix(path.clone())));
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // Un serveur actif n'est pas remplacé
+        assert_eq!(spec.bind().unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        listener.remove_socket_file();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_stale_socket_is_removed() {
+        let path = socket_path("listener_stale.sock");
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        let spec = ListenSpec::new(ListenAddr::Unix(path.clone()));
+        assert!(spec.bind().is_ok());
+
+        // Un fichier ordinaire n'est jamais supprimé
+        let file = socket_path("listener_regular.sock");
+        fs::write(&file, "data").unwrap();
+        let spec = ListenSpec::new(ListenAddr::Unix(file.clone()));
+        assert_eq!(spec.bind().unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert!(file.exists());
+    }
+}
@@ -0,0 +1,527 @@
+// loader.rs
+use crate::config::ConfigError;
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Spanned;
+
+// Fichier de configuration lu, conservé pour positionner les erreurs
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    pub path: String,
+    pub text: String,
+}
+
+// Position d'une table du fichier ([[servers]], [[servers.routes]]...) et
+// de la valeur de chacune de ses clés, en octets
+#[derive(Debug, Clone, Default)]
+pub struct TableSpan {
+    pub start: usize,
+    pub keys: HashMap<String, usize>,
+}
+
+impl TableSpan {
+    fn of(table: &Spanned<KeySpans>) -> Self {
+        TableSpan {
+            start: table.span().start,
+            keys: table
+                .get_ref()
+                .iter()
+                .map(|(key, value)| (key.clone(), value.span().start))
+                .collect(),
+        }
+    }
+
+    // Position de la clé si elle est présente, sinon celle de la table
+    pub fn offset(&self, key: &str) -> usize {
+        self.keys.get(key).copied().unwrap_or(self.start)
+    }
+}
+
+// Positions d'un serveur, de ses routes et de ses règles [[redirects]]
+#[derive(Debug, Clone, Default)]
+pub struct ServerSpan {
+    pub table: TableSpan,
+    pub routes: Vec<TableSpan>,
+    pub redirects: Vec<TableSpan>,
+}
+
+type KeySpans = HashMap<String, Spanned<IgnoredAny>>;
+
+// Les positions sont relevées avec toml::Spanned; les tables implicites
+// n'en ont pas, le fichier est alors traité comme s'il n'en avait aucune
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct FileSpans {
+    servers: Vec<Spanned<KeySpans>>,
+    upstreams: KeySpans,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct FileTables {
+    servers: Vec<ServerTables>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ServerTables {
+    routes: Vec<Spanned<KeySpans>>,
+    redirects: Vec<Spanned<KeySpans>>,
+}
+
+// Clés de premier niveau, pour positionner les erreurs du chargement
+#[derive(Deserialize)]
+struct IncludeKey {
+    include: Spanned<IgnoredAny>,
+}
+
+#[derive(Deserialize)]
+struct ServersKey {
+    servers: Spanned<IgnoredAny>,
+}
+
+// Toute autre clé d'un fichier inclus est refusée, avec sa position
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IncludedFile {
+    #[serde(rename = "include")]
+    _include: Option<IgnoredAny>,
+    #[serde(rename = "servers")]
+    _servers: Option<IgnoredAny>,
+}
+
+impl ConfigSource {
+    // Serveurs déclarés dans ce fichier, dans leur ordre d'apparition
+    pub fn server_spans(&self) -> Vec<ServerSpan> {
+        let spans: FileSpans = toml::from_str(&self.text).unwrap_or_default();
+        let tables: FileTables = toml::from_str(&self.text).unwrap_or_default();
+        let mut tables = tables.servers.into_iter();
+        spans
+            .servers
+            .iter()
+            .map(|server| {
+                let tables = tables.next().unwrap_or_default();
+                ServerSpan {
+                    table: TableSpan::of(server),
+                    routes: tables.routes.iter().map(TableSpan::of).collect(),
+                    redirects: tables.redirects.iter().map(TableSpan::of).collect(),
+                }
+            })
+            .collect()
+    }
+
+    // Position de la table [upstreams.<name>]
+    pub fn upstream_span(&self, name: &str) -> Option<usize> {
+        let spans: FileSpans = toml::from_str(&self.text).ok()?;
+        spans.upstreams.get(name).map(|table| table.span().start)
+    }
+}
+
+// Position de la valeur d'une clé de premier niveau (include, servers), ou
+// de la première clé qui n'a pas sa place dans un fichier inclus
+fn top_level_offset(text: &str, key: &str) -> Option<usize> {
+    let span = match key {
+        "include" => toml::from_str::<IncludeKey>(text).ok()?.include.span(),
+        "servers" => toml::from_str::<ServersKey>(text).ok()?.servers.span(),
+        _ => toml::from_str::<IncludedFile>(text).err()?.span()?,
+    };
+    Some(span.start)
+}
+
+fn located(error: ConfigError, text: &str, offset: Option<usize>) -> ConfigError {
+    match offset {
+        Some(offset) => error.at_offset(text, offset),
+        None => error,
+    }
+}
+
+// Lit le fichier principal et ses `include`, remplace les ${VAR} et rend les
+// chemins relatifs au répertoire du fichier qui les déclare. Retourne la table
+// fusionnée et tous les fichiers lus, le fichier principal en premier.
+pub fn load_sources(path: &str, text: &str) -> Result<(toml::Table, Vec<ConfigSource>), ConfigError> {
+    let mut sources = Vec::new();
+    let mut visited = HashSet::new();
+    if let Ok(canonical) = fs::canonicalize(path) {
+        visited.insert(canonical);
+    }
+    let table = load_file(path, text, &mut sources, &mut visited)?;
+    Ok((table, sources))
+}
+
+fn load_file(
+    path: &str,
+    text: &str,
+    sources: &mut Vec<ConfigSource>,
+    visited: &mut HashSet<PathBuf>,
+) -> Result<toml::Table, ConfigError> {
+    sources.push(ConfigSource {
+        path: path.to_string(),
+        text: text.to_string(),
+    });
+    let mut table: toml::Table = toml::from_str(text).map_err(|e| toml_error(path, text, &e))?;
+    // La variable manquante est repérée par son texte ${VAR dans le fichier
+    interpolate_table(&mut table).map_err(|(var, message)| {
+        located(ConfigError::new(path, message), text, text.find(&format!("${{{}", var)))
+    })?;
+
+    let dir = config_dir(path);
+    resolve_paths(&mut table, &dir);
+    let include_error =
+        |message: String| located(ConfigError::new(path, message), text, top_level_offset(text, "include"));
+
+    let patterns = match table.remove("include") {
+        None => Vec::new(),
+        Some(toml::Value::String(pattern)) => vec![pattern],
+        Some(toml::Value::Array(patterns)) => patterns
+            .into_iter()
+            .map(|pattern| match pattern {
+                toml::Value::String(pattern) => Ok(pattern),
+                _ => Err(include_error(String::from("include must contain strings"))),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(_) => {
+            return Err(include_error(String::from("include must be a string or an array")))
+        }
+    };
+
+    for pattern in patterns {
+        let files = expand_include(&dir, &pattern).map_err(include_error)?;
+        for file in files {
+            let canonical = fs::canonicalize(&file).unwrap_or_else(|_| file.clone());
+            if !visited.insert(canonical) {
+                continue;
+            }
+            let file_path = file.to_string_lossy().into_owned();
+            let file_text = fs::read_to_string(&file)
+                .map_err(|e| include_error(format!("cannot read included file {}: {}", file_path, e)))?;
+            let included = load_file(&file_path, &file_text, sources, visited)?;
+            merge_included(&mut table, included, &file_path, &file_text)?;
+        }
+    }
+    Ok(table)
+}
+
+// Un fichier inclus apporte des serveurs (et éventuellement d'autres include)
+fn merge_included(
+    table: &mut toml::Table,
+    mut included: toml::Table,
+    path: &str,
+    text: &str,
+) -> Result<(), ConfigError> {
+    let servers = match included.remove("servers") {
+        Some(toml::Value::Array(servers)) => servers,
+        Some(_) => {
+            let error = ConfigError::new(path, String::from("servers must be an array of tables"));
+            return Err(located(error, text, top_level_offset(text, "servers")));
+        }
+        None => Vec::new(),
+    };
+    if let Some(key) = included.keys().next() {
+        let error = ConfigError::new(
+            path,
+            format!("`{}` is not allowed in an included file, only [[servers]]", key),
+        );
+        return Err(located(error, text, top_level_offset(text, key)));
+    }
+    match table
+        .entry("servers")
+        .or_insert_with(|| toml::Value::Array(Vec::new()))
+    {
+        toml::Value::Array(existing) => existing.extend(servers),
+        _ => return Err(ConfigError::new(path, String::from("servers must be an array of tables"))),
+    }
+    Ok(())
+}
+
+pub(crate) fn toml_error(path: &str, text: &str, e: &toml::de::Error) -> ConfigError {
+    let error = ConfigError::new(path, e.message().trim().to_string());
+    match e.span() {
+        Some(span) => error.at_offset(text, span.start),
+        None => error,
+    }
+}
+
+// Répertoire absolu du fichier de configuration
+fn config_dir(path: &str) -> PathBuf {
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    if dir.is_absolute() {
+        dir.to_path_buf()
+    } else {
+        env::current_dir().unwrap_or_default().join(dir)
+    }
+}
+
+// Les chemins de fichiers (root, sockets Unix, certificats, fichiers d'authentification) sont relatifs
+// au fichier qui les déclare, y compris dans [defaults]
+fn resolve_paths(table: &mut toml::Table, dir: &Path) {
+    if let Some(toml::Value::Table(defaults)) = table.get_mut("defaults") {
+        resolve_server_paths(defaults, dir);
+        if let Some(toml::Value::Table(route)) = defaults.get_mut("route") {
+            resolve_route_paths(route, dir);
+        }
+    }
+    if let Some(toml::Value::Array(servers)) = table.get_mut("servers") {
+        for server in servers.iter_mut().filter_map(toml::Value::as_table_mut) {
+            resolve_server_paths(server, dir);
+        }
+    }
+}
+
+fn resolve_server_paths(server: &mut toml::Table, dir: &Path) {
+    resolve_path(server.get_mut("root"), dir);
+    if let Some(toml::Value::Array(listen)) = server.get_mut("listen") {
+        for addr in listen.iter_mut() {
+            if let toml::Value::String(text) = addr {
+                if let Some(path) = text.strip_prefix("unix:") {
+                    let mut path = toml::Value::String(path.to_string());
+                    resolve_path(Some(&mut path), dir);
+                    *addr = toml::Value::String(format!("unix:{}", path.as_str().unwrap_or_default()));
+                }
+            }
+        }
+    }
+    if let Some(toml::Value::Table(tls)) = server.get_mut("tls") {
+        resolve_path(tls.get_mut("cert"), dir);
+        resolve_path(tls.get_mut("key"), dir);
+    }
+    if let Some(toml::Value::Array(routes)) = server.get_mut("routes") {
+        for route in routes.iter_mut().filter_map(toml::Value::as_table_mut) {
+            resolve_route_paths(route, dir);
+        }
+    }
+}
+
+fn resolve_route_paths(route: &mut toml::Table, dir: &Path) {
+    if let Some(toml::Value::Table(auth)) = route.get_mut("auth") {
+        resolve_path(auth.get_mut("basic"), dir);
+        resolve_path(auth.get_mut("bearer"), dir);
+    }
+}
+
+fn resolve_path(value: Option<&mut toml::Value>, dir: &Path) {
+    if let Some(toml::Value::String(path)) = value {
+        if !path.is_empty() && Path::new(path.as_str()).is_relative() {
+            let relative = path.trim_start_matches("./").to_string();
+            *path = dir.join(relative).to_string_lossy().into_owned();
+        }
+    }
+}
+
+// Remplace ${VAR} et ${VAR:-défaut} dans toutes les chaînes; l'erreur
+// contient le nom de la variable et le message
+fn interpolate_table(table: &mut toml::Table) -> Result<(), (String, String)> {
+    for (_, value) in table.iter_mut() {
+        interpolate_value(value)?;
+    }
+    Ok(())
+}
+
+fn interpolate_value(value: &mut toml::Value) -> Result<(), (String, String)> {
+    match value {
+        toml::Value::String(s) => *s = interpolate(s)?,
+        toml::Value::Array(values) => {
+            for value in values {
+                interpolate_value(value)?;
+            }
+        }
+        toml::Value::Table(table) => interpolate_table(table)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+// "$${" produit "${" sans interpolation
+pub fn interpolate(s: &str) -> Result<String, (String, String)> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("$${") {
+            out.push_str("${");
+            rest = after;
+            continue;
+        }
+        let expr = match rest.strip_prefix("${") {
+            Some(expr) => expr,
+            None => {
+                out.push('$');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let end = match expr.find('}') {
+            Some(end) => end,
+            None => return Err((String::new(), format!("unterminated `${{` in `{}`", s))),
+        };
+        let (name, default) = match expr[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&expr[..end], None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err((name.to_string(), format!("invalid variable name `{}`", name)));
+        }
+        match (env::var(name), default) {
+            (Ok(value), Some(default)) if value.is_empty() => out.push_str(default),
+            (Ok(value), _) => out.push_str(&value),
+            (Err(_), Some(default)) => out.push_str(default),
+            (Err(_), None) => {
+                return Err((name.to_string(), format!("environment variable `{}` is not set", name)))
+            }
+        }
+        rest = &expr[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+// "sites/*.toml": le joker n'est accepté que dans le nom du fichier
+fn expand_include(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let full = dir.join(pattern);
+    let name = full
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let parent = full.parent().unwrap_or(dir);
+    if parent.to_string_lossy().contains(['*', '?']) {
+        return Err(format!("wildcards are only allowed in the file name: `{}`", pattern));
+    }
+    if !name.contains(['*', '?']) {
+        if !full.is_file() {
+            return Err(format!("included file `{}` not found", pattern));
+        }
+        return Ok(vec![full]);
+    }
+    let entries = fs::read_dir(parent)
+        .map_err(|e| format!("cannot read directory {}: {}", parent.display(), e))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|file| wildcard_match(&name, &file.to_string_lossy()))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    #[test]
+    fn test_interpolate() {
+        env::set_var("LOADER_TEST_ROOT", "/srv/www");
+        env::set_var("LOADER_TEST_EMPTY", "");
+        assert_eq!(interpolate("${LOADER_TEST_ROOT}/public").unwrap(), "/srv/www/public");
+        assert_eq!(interpolate("${LOADER_TEST_UNSET:-public}").unwrap(), "public");
+        assert_eq!(interpolate("${LOADER_TEST_EMPTY:-public}").unwrap(), "public");
+        assert_eq!(interpolate("cost: $5, $${HOME}").unwrap(), "cost: $5, ${HOME}");
+        assert_eq!(
+            interpolate("${LOADER_TEST_UNSET}").unwrap_err().1,
+            "environment variable `LOADER_TEST_UNSET` is not set"
+        );
+        assert!(interpolate("${LOADER_TEST_ROOT").is_err());
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.toml", "blog.toml"));
+        assert!(wildcard_match("site-?.toml", "site-1.toml"));
+        assert!(wildcard_match("*a*b", "xxaxxb"));
+        assert!(!wildcard_match("*.toml", "blog.toml.bak"));
+        assert!(!wildcard_match("site-?.toml", "site-10.toml"));
+    }
+
+    #[test]
+    fn test_includes_and_relative_paths() {
+        let dir = temp_dir("loader_include_test");
+        fs::create_dir_all(dir.join("sites")).unwrap();
+        fs::write(
+            dir.join("sites/a.toml"),
+            "[[servers]]\nhost_name = \"a\"\nports = [8081]\nroot = \"a-public\"\ntls = { cert = \"certs/a.crt\", key = \"/etc/a.key\" }\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("sites/b.toml"),
+            "[[servers]]\nhost_name = \"b\"\nports = [8082]\nroot = \"/srv/b\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("sites/notes.txt"), "ignored").unwrap();
+        let main = dir.join("config.toml").to_string_lossy().into_owned();
+        let text = "include = [\"sites/*.toml\"]\n\n[[servers]]\nports = [8080]\nroot = \"./public\"\nlisten = [\"unix:run/web.sock\", \"[::1]:80\"]\n";
+
+        let (table, sources) = load_sources(&main, text).unwrap();
+        let servers = table["servers"].as_array().unwrap();
+        let roots: Vec<&str> = servers.iter().map(|s| s["root"].as_str().unwrap()).collect();
+        let expected_main = dir.join("public").to_string_lossy().into_owned();
+        let expected_a = dir.join("sites/a-public").to_string_lossy().into_owned();
+        assert_eq!(roots, vec![expected_main.as_str(), expected_a.as_str(), "/srv/b"]);
+        let listen = servers[0]["listen"].as_array().unwrap();
+        assert_eq!(listen[0].as_str().unwrap(), format!("unix:{}", dir.join("run/web.sock").display()));
+        assert_eq!(listen[1].as_str().unwrap(), "[::1]:80");
+        let tls = &servers[1]["tls"];
+        assert_eq!(tls["cert"].as_str().unwrap(), dir.join("sites/certs/a.crt").to_string_lossy());
+        assert_eq!(tls["key"].as_str().unwrap(), "/etc/a.key");
+        assert_eq!(sources.len(), 3);
+        assert!(sources[1].path.ends_with("sites/a.toml"));
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let dir = temp_dir("loader_include_errors");
+        fs::write(dir.join("bad.toml"), "ports = [1]\n").unwrap();
+        fs::write(dir.join("self.toml"), "include = \"self.toml\"\n").unwrap();
+        let main = dir.join("config.toml").to_string_lossy().into_owned();
+
+        let error = load_sources(&main, "include = \"missing.toml\"\n").unwrap_err();
+        assert_eq!(error.message, "included file `missing.toml` not found");
+        assert_eq!(error.line, Some(1));
+
+        let error = load_sources(&main, "include = \"bad.toml\"\n").unwrap_err();
+        assert!(error.path.ends_with("bad.toml"));
+        assert!(error.message.contains("`ports` is not allowed"));
+        assert_eq!((error.line, error.column), (Some(1), Some(1)));
+
+        // Un fichier qui s'inclut lui-même n'est lu qu'une fois
+        assert!(load_sources(&main, "include = \"self.toml\"\n").is_ok());
+
+        let error = load_sources(&main, "root = \"${LOADER_TEST_NOPE}\"\n").unwrap_err();
+        assert_eq!((error.line, error.column), (Some(1), Some(9)));
+    }
+}
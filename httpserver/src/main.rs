@@ -1,22 +1,84 @@
 // main.rs
+mod auth;
+mod cli;
 mod config;
+mod csrf;
+mod docroot;
 mod handler;
+mod http2;
+mod listener;
+mod loader;
+mod matcher;
+mod proxy;
+mod redirect;
 mod router;
 mod server;
+mod signals;
+mod sse;
+mod systemd;
+#[cfg(test)]
+mod testing;
+mod tls;
+mod upload;
+mod upstream;
+mod validate;
+mod websocket;
 
-use config::read_config;
+use cli::{Cli, Command, USAGE};
+use config::{check_config, read_config};
 use server::Server;
+use std::{env, process};
 
 fn main() {
-    // Load configuration
-    let configs = read_config();
-    match configs {
-        Some(config) => {
-            // Start servers on configured ports
-            let server = Server::new(config);
-            // Run the server
-            server.run();
+    let cli = match Cli::parse(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
         }
-        _ => eprintln!("⚠️ Incorrect configuration⚠️"),
+    };
+
+    match cli.command {
+        Command::Version => println!("web_server {}", env!("CARGO_PKG_VERSION")),
+        Command::Help => println!("{}", USAGE),
+        Command::Check => match check_config(&cli.config_path) {
+            Ok(_) => println!("{}: configuration OK", cli.config_path),
+            Err(errors) => {
+                for error in &errors {
+                    eprintln!("{}", error);
+                }
+                eprintln!("⚠️ {} problem(s) found in {}⚠️", errors.len(), cli.config_path);
+                process::exit(1);
+            }
+        },
+        Command::PrintConfig => match read_config(&cli.config_path) {
+            Ok(config) => match toml::to_string_pretty(&config) {
+                Ok(dump) => print!("{}", dump),
+                Err(e) => {
+                    eprintln!("failed to serialize configuration: {}", e);
+                    process::exit(1);
+                }
+            },
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        },
+        Command::Run => match check_config(&cli.config_path) {
+            Ok(config) => {
+                // Start servers on configured ports
+                signals::install();
+                let server = Server::new(config).with_config_path(&cli.config_path);
+                // Run the server
+                server.run();
+            }
+            Err(errors) => {
+                eprintln!("⚠️ Incorrect configuration⚠️");
+                for error in &errors {
+                    eprintln!("{}", error);
+                }
+                process::exit(1);
+            }
+        },
     }
 }
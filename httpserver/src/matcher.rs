@@ -0,0 +1,195 @@
+// matcher.rs
+use crate::config::ConfigServer;
+use http::httprequest::percent_decode_path;
+use regex::Regex;
+use std::collections::HashMap;
+
+// Route retenue pour une URL: `alias` permet de la retrouver avec find_route,
+// `prefix` est la partie de l'URL qu'elle couvre ("/users/42/" pour l'alias
+// "/users/{id}/") et `params` les valeurs capturées
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteMatch {
+    pub alias: String,
+    pub prefix: String,
+    pub params: HashMap<String, String>,
+}
+
+// Rang d'une correspondance: nombre de segments couverts, puis nombre de
+// segments littéraux (un littéral l'emporte sur {param} et *), les routes
+// regex perdant les égalités
+type Rank = (usize, usize, bool);
+
+// Le préfixe le plus long l'emporte. Les alias sont comparés aux répertoires
+// de l'URL: "/docs/" couvre "/docs/api/index.html" sauf si "/docs/api/" existe.
+// La route "/" ne couvre que les fichiers à la racine; sans route, l'alias
+// est "/{premier segment}/" comme auparavant.
+pub fn match_route(config: &ConfigServer, url: &str) -> RouteMatch {
+    let path = url.split('?').next().unwrap_or_default();
+    let mut best: Option<(Rank, RouteMatch)> = None;
+    for route in &config.routes {
+        let candidate = match &route.pattern {
+            Some(regex) => match_regex(regex, path),
+            None if route.alias.starts_with('~') => None,
+            None => match_segments(&route.alias, path),
+        };
+        let Some((rank, prefix, params)) = candidate else {
+            continue;
+        };
+        if best.as_ref().is_none_or(|(best_rank, _)| rank > *best_rank) {
+            let alias = route.alias.clone();
+            best = Some((rank, RouteMatch { alias, prefix, params }));
+        }
+    }
+    match best {
+        Some((_, found)) => found,
+        None => fallback(path),
+    }
+}
+
+// Répertoires d'une URL: "/a/b/c.html" donne ["a", "b"]
+fn directories(path: &str) -> Vec<&str> {
+    let dirs = path.rfind('/').map_or("", |end| &path[..end]);
+    dirs.split('/').filter(|segment| !segment.is_empty()).collect()
+}
+
+fn match_segments(alias: &str, path: &str) -> Option<(Rank, String, HashMap<String, String>)> {
+    let pattern: Vec<&str> = alias.split('/').filter(|segment| !segment.is_empty()).collect();
+    let dirs = directories(path);
+    if pattern.len() > dirs.len() || (pattern.is_empty() && !dirs.is_empty()) {
+        return None;
+    }
+    let mut params = HashMap::new();
+    let mut literals = 0;
+    for (expected, segment) in pattern.iter().zip(&dirs) {
+        if *expected == "*" {
+            continue;
+        }
+        match param_name(expected) {
+            Some(name) => {
+                params.insert(name.to_string(), percent_decode_path(segment));
+            }
+            None if expected == segment => literals += 1,
+            None => return None,
+        }
+    }
+    let prefix = match pattern.len() {
+        0 => String::from("/"),
+        n => format!("/{}/", dirs[..n].join("/")),
+    };
+    Some(((pattern.len(), literals, true), prefix, params))
+}
+
+// "{id}" capture un segment sous le nom "id"
+pub fn param_name(segment: &str) -> Option<&str> {
+    segment.strip_prefix('{')?.strip_suffix('}')
+}
+
+// Une route "~motif" doit reconnaître le début du chemin; ses groupes nommés
+// (?P<nom>...) deviennent des paramètres. Le motif est compilé au chargement
+// de la configuration (ConfigServer::prepare)
+fn match_regex(regex: &Regex, path: &str) -> Option<(Rank, String, HashMap<String, String>)> {
+    let captures = regex.captures(path)?;
+    let whole = captures.get(0)?;
+    if whole.start() != 0 {
+        return None;
+    }
+    let params = regex
+        .capture_names()
+        .flatten()
+        .filter_map(|name| {
+            let value = captures.name(name)?;
+            Some((name.to_string(), percent_decode_path(value.as_str())))
+        })
+        .collect();
+    let prefix = whole.as_str().to_string();
+    Some(((directories(&prefix).len(), 0, false), prefix, params))
+}
+
+fn fallback(path: &str) -> RouteMatch {
+    let segments: Vec<&str> = path.split('/').collect();
+    let alias = if segments.len() <= 2 {
+        String::from("/")
+    } else {
+        format!("/{}/", segments[1])
+    };
+    RouteMatch {
+        prefix: alias.clone(),
+        alias,
+        params: HashMap::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Route;
+
+    fn setup_config(aliases: &[&str]) -> ConfigServer {
+        let mut config = ConfigServer {
+            root: String::from("/public"),
+            client_body_limit: 1024,
+            routes: aliases
+                .iter()
+                .map(|alias| Route {
+                    alias: alias.to_string(),
+                    pages: vec!["index.html".to_string()],
+                    default_page: "index.html".to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        config.prepare();
+        config
+    }
+
+    #[test]
+    fn test_longest_prefix() {
+        let config = setup_config(&["/", "/docs/", "/docs/api/v1/"]);
+        assert_eq!(match_route(&config, "/docs/api/v1/index.html").alias, "/docs/api/v1/");
+        assert_eq!(match_route(&config, "/docs/api/v1/").prefix, "/docs/api/v1/");
+        let docs = match_route(&config, "/docs/api/v2/index.html");
+        assert_eq!((docs.alias.as_str(), docs.prefix.as_str()), ("/docs/", "/docs/"));
+        assert_eq!(match_route(&config, "/index.html?x=1").alias, "/");
+        assert_eq!(match_route(&config, "/docs").alias, "/");
+    }
+
+    #[test]
+    fn test_fallback_alias() {
+        let config = setup_config(&["/"]);
+        let found = match_route(&config, "/css/style.css");
+        assert_eq!((found.alias.as_str(), found.prefix.as_str()), ("/css/", "/css/"));
+        assert_eq!(match_route(&config, "/").alias, "/");
+    }
+
+    #[test]
+    fn test_params_and_wildcards() {
+        let config = setup_config(&["/users/{id}/", "/users/me/", "/files/*/{name}/"]);
+        let user = match_route(&config, "/users/42/avatar.png");
+        assert_eq!(user.alias, "/users/{id}/");
+        assert_eq!(user.prefix, "/users/42/");
+        assert_eq!(user.params["id"], "42");
+        // Un segment littéral l'emporte sur un paramètre
+        assert_eq!(match_route(&config, "/users/me/").alias, "/users/me/");
+        let file = match_route(&config, "/files/2024/r%C3%A9sum%C3%A9/");
+        assert_eq!(file.alias, "/files/*/{name}/");
+        assert_eq!(file.params.len(), 1);
+        assert_eq!(file.params["name"], "résumé");
+        // Dans un chemin, "+" n'est pas une espace
+        assert_eq!(match_route(&config, "/users/c++%20dev/").params["id"], "c++ dev");
+    }
+
+    #[test]
+    fn test_regex_routes() {
+        let config = setup_config(&["/articles/", r"~/articles/(?P<year>[0-9]{4})/"]);
+        let article = match_route(&config, "/articles/2024/intro.html");
+        assert_eq!(article.alias, r"~/articles/(?P<year>[0-9]{4})/");
+        assert_eq!(article.prefix, "/articles/2024/");
+        assert_eq!(article.params["year"], "2024");
+        assert_eq!(match_route(&config, "/articles/draft/").alias, "/articles/");
+        // Le motif doit reconnaître le début du chemin
+        assert_eq!(match_route(&config, "/old/articles/2024/").alias, "/old/");
+        let config = setup_config(&[r"~/tags/(?P<tag>[^/]+)/"]);
+        assert_eq!(match_route(&config, "/tags/a+b/").params["tag"], "a+b");
+    }
+}
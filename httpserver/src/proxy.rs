@@ -0,0 +1,1780 @@
+// proxy.rs
+use crate::auth::authorize;
+use crate::csrf::verify_csrf;
+use crate::config::{ConfigServer, Route};
+use crate::handler::PageErrorHandler;
+use crate::listener::Stream;
+use crate::upstream::{watch, Lease, Upstreams};
+use crate::websocket::{self, WebSocketPolicy};
+use http::httprequest::{expects_continue, HttpRequest, Method, Resource, Version};
+use http::httpresponse::CONTINUE;
+use http::websocket::accept_key;
+use libc::{EPOLLIN, EPOLLOUT, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, prelude::*};
+use std::net::{IpAddr, Shutdown, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+// En-têtes propres à une connexion, jamais transmis tels quels
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+// Octets lus à la fois sur une socket
+const READ_CHUNK: usize = 16 * 1024;
+
+// Données en attente d'envoi au-delà desquelles l'autre côté n'est plus lu
+const MAX_PENDING: usize = 256 * 1024;
+
+// Taille maximale des en-têtes d'une réponse amont, réponses 1xx comprises
+const MAX_RESPONSE_HEAD: usize = 64 * 1024;
+
+// Taille maximale d'une ligne de taille de bloc ou d'un en-tête de fin
+const MAX_CHUNK_LINE: usize = 8 * 1024;
+
+// Attente du corps envoyé par le client, comme pour la lecture d'une requête
+const BODY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Section `proxy` d'une route `proxy_pass`, délais en secondes
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ProxyOptions {
+    pub connect_timeout: u64,
+    pub read_timeout: u64,
+}
+
+impl Default for ProxyOptions {
+    fn default() -> Self {
+        ProxyOptions {
+            connect_timeout: 5,
+            read_timeout: 60,
+        }
+    }
+}
+
+// Serveur amont "http://hôte:port/base", où l'hôte peut aussi être le nom
+// d'un groupe `[upstreams.<nom>]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upstream {
+    pub name: String,      // hôte tel qu'écrit, envoyé dans l'en-tête Host
+    pub authority: String, // "hôte:port", le port 80 étant ajouté si absent
+    pub base: String,      // chemin remplaçant l'alias de la route, vide s'il est conservé
+}
+
+impl Upstream {
+    pub fn parse(url: &str) -> Result<Upstream, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("proxy_pass `{}` must start with http://", url))?;
+        let (name, base) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], rest[slash..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        if name.is_empty() {
+            return Err(format!("proxy_pass `{}` has no host", url));
+        }
+        let has_port = name
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.contains(']'));
+        let authority = if has_port {
+            name.to_string()
+        } else {
+            format!("{}:80", name)
+        };
+        Ok(Upstream {
+            name: name.to_string(),
+            authority,
+            base: base.to_string(),
+        })
+    }
+
+    // Sans chemin dans proxy_pass, l'URL est transmise telle quelle; sinon la
+    // partie couverte par la route est remplacée: "/api/users" devient
+    // "/v1/users" pour l'alias "/api/" et "http://amont/v1"
+    fn path(&self, url: &str, prefix: &str) -> String {
+        if self.base.is_empty() {
+            return url.to_string();
+        }
+        let rest = url.strip_prefix(prefix).unwrap_or(url.trim_start_matches('/'));
+        format!("{}/{}", self.base, rest)
+    }
+}
+
+// Transmet la requête au serveur amont de la route. L'échange se poursuit
+// ensuite dans la boucle epoll (`relays`): le corps est lu au fil de l'envoi,
+// sans être chargé en mémoire, et la réponse relayée à mesure qu'elle arrive.
+// `leftover` est le début du corps, lu avec les en-têtes.
+#[allow(clippy::too_many_arguments)]
+pub fn serve(
+    req: &HttpRequest,
+    stream: Stream,
+    leftover: Vec<u8>,
+    config: &ConfigServer,
+    route: &Route,
+    prefix: &str,
+    upstreams: &mut Upstreams,
+    relays: &mut Relays,
+) {
+    let mut client = &stream;
+    let status_code = match check(req, route, config) {
+        Ok(body) => {
+            // Le corps n'est envoyé qu'une fois la requête acceptée ici;
+            // l'amont ne reçoit pas l'en-tête Expect
+            if body.is_some() && expects_continue(&req.version, &req.headers) {
+                let _ = client.write_all(CONTINUE);
+            }
+            let exchange = Exchange {
+                req,
+                route,
+                prefix,
+                client_ip: stream.peer_ip(),
+                scheme: scheme(req),
+                chunked: matches!(body, Some(Body::Chunked(_))),
+                upgrade: false,
+            };
+            match exchange.prepare(upstreams) {
+                Ok(plan) => {
+                    let mut relay = Relay::new(Client::Stream(stream), config, plan);
+                    // Un client HTTP/1.0 ne connaît pas le découpage en blocs: le
+                    // corps lui est transmis décodé, délimité par la fermeture
+                    relay.decode = req.version == Version::V1_0;
+                    relay.head_request = req.method == Method::Head;
+                    relay.body = body;
+                    if let Err(failure) = relay.forward_body(&leftover) {
+                        relay.fail(failure, upstreams);
+                    }
+                    relays.start(relay, upstreams);
+                    return;
+                }
+                Err(status_code) => status_code,
+            }
+        }
+        Err(Some(resp)) => {
+            let _ = resp.send_response(&mut client);
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        }
+        Err(None) => "400",
+    };
+    let _ = PageErrorHandler::error_response(config, status_code).send_response(&mut client);
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+// Comme `serve`, pour un flux HTTP/2 dont le corps est déjà reçu. La réponse
+// est retournée si elle est connue tout de suite; sinon elle revient à la
+// connexion par `Relays::take_handoffs`.
+#[allow(clippy::too_many_arguments)]
+pub fn serve_http2(
+    req: &HttpRequest,
+    body: Vec<u8>,
+    config: &ConfigServer,
+    route: &Route,
+    prefix: &str,
+    client_ip: Option<IpAddr>,
+    pending: PendingStream,
+    upstreams: &mut Upstreams,
+    relays: &mut Relays,
+) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let status_code = match check(req, route, config) {
+        Ok(_) => {
+            let exchange = Exchange {
+                req,
+                route,
+                prefix,
+                client_ip,
+                scheme: scheme(req),
+                chunked: false,
+                upgrade: false,
+            };
+            match exchange.prepare(upstreams) {
+                Ok(plan) => {
+                    let mut relay = Relay::new(Client::Http2(pending), config, plan);
+                    relay.head_request = req.method == Method::Head;
+                    relay.request.extend_from_slice(&body);
+                    relays.start(relay, upstreams);
+                    return None;
+                }
+                Err(status_code) => status_code,
+            }
+        }
+        Err(Some(resp)) => {
+            let _ = resp.send_response(&mut output);
+            return Some(output);
+        }
+        Err(None) => "400",
+    };
+    let _ = PageErrorHandler::error_response(config, status_code).send_response(&mut output);
+    Some(output)
+}
+
+// Transmet une poignée de main WebSocket, déjà vérifiée, au serveur amont de
+// la route. Si l'amont accepte (101), sa réponse est relayée puis les deux
+// connexions reviennent à `WebSockets` par `Relays::take_handoffs`; sinon le
+// client reçoit la réponse de l'amont ou une erreur.
+#[allow(clippy::too_many_arguments)]
+pub fn open_websocket(
+    req: &HttpRequest,
+    stream: Stream,
+    leftover: Vec<u8>,
+    config: &ConfigServer,
+    route: &Route,
+    prefix: &str,
+    upstreams: &mut Upstreams,
+    relays: &mut Relays,
+) {
+    let exchange = Exchange {
+        req,
+        route,
+        prefix,
+        client_ip: stream.peer_ip(),
+        scheme: scheme(req),
+        chunked: false,
+        upgrade: true,
+    };
+    let key = header(req, "Sec-WebSocket-Key").ok_or("400");
+    match key.and_then(|key| exchange.prepare(upstreams).map(|plan| (key, plan))) {
+        Ok((key, plan)) => {
+            let mut relay = Relay::new(Client::Stream(stream), config, plan);
+            relay.tunnel = Some(Tunnel {
+                key: key.to_string(),
+                leftover,
+                policy: route.websocket.clone().unwrap_or_default(),
+                channel: websocket::channel(config, route),
+            });
+            relays.start(relay, upstreams);
+        }
+        Err(status_code) => {
+            let _ = PageErrorHandler::error_response(config, status_code).send_response(&mut &stream);
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+// Méthode, authentification et taille du corps, avant de contacter l'amont;
+// retourne la délimitation du corps à transmettre
+fn check<'a>(
+    req: &HttpRequest,
+    route: &Route,
+    config: &ConfigServer,
+) -> Result<Option<Body>, Option<Box<http::httpresponse::HttpResponse<'a>>>> {
+    let method = format!("{:?}", req.method).to_ascii_uppercase();
+    if !route.methods.contains(&method) {
+        return Err(Some(Box::new(PageErrorHandler::error_response(config, "405"))));
+    }
+    authorize(req, route, config).map_err(Some)?;
+    // Le corps est relayé sans être lu: le jeton CSRF vient de l'en-tête
+    if !matches!(method.as_str(), "GET" | "HEAD" | "OPTIONS") && !verify_csrf(req, route, None) {
+        return Err(Some(Box::new(PageErrorHandler::error_response(config, "403"))));
+    }
+    if let Some(encoding) = header(req, "Transfer-Encoding") {
+        // Avec aussi Content-Length, l'amont pourrait délimiter la requête
+        // autrement que le proxy
+        let last = encoding.rsplit(',').next().unwrap_or_default().trim();
+        if !last.eq_ignore_ascii_case("chunked") || header(req, "Content-Length").is_some() {
+            return Err(None);
+        }
+        return Ok(Some(Body::Chunked(Chunks::default())));
+    }
+    let content_length = match header(req, "Content-Length") {
+        Some(value) => value.parse::<usize>().map_err(|_| None)?,
+        None => 0,
+    };
+    if content_length > config.client_body_limit {
+        return Err(Some(Box::new(PageErrorHandler::error_response(config, "413"))));
+    }
+    Ok((content_length > 0).then_some(Body::Length(content_length as u64)))
+}
+
+// Schéma de la connexion du client: https en TLS, HTTP/2 compris
+fn scheme(req: &HttpRequest) -> &'static str {
+    if req.secure {
+        "https"
+    } else {
+        "http"
+    }
+}
+
+struct Exchange<'r> {
+    req: &'r HttpRequest,
+    route: &'r Route,
+    prefix: &'r str,
+    client_ip: Option<IpAddr>,
+    scheme: &'static str, // connexion du client, annoncée par X-Forwarded-Proto
+    chunked: bool, // corps découpé en blocs, transmis tel quel
+    upgrade: bool, // poignée de main WebSocket
+}
+
+// Serveurs à essayer dans l'ordre, requête à leur envoyer et délais de la route
+struct Plan {
+    leases: Vec<Lease>,
+    head: String,
+    options: ProxyOptions,
+}
+
+impl Exchange<'_> {
+    fn prepare(&self, upstreams: &mut Upstreams) -> Result<Plan, &'static str> {
+        let upstream = self
+            .route
+            .proxy_pass
+            .as_deref()
+            .map(Upstream::parse)
+            .and_then(Result::ok)
+            .ok_or("502")?;
+        let leases = upstreams.candidates(&upstream.name, &upstream.authority, self.client_ip);
+        if leases.is_empty() {
+            eprintln!("No live server in upstream `{}`", upstream.name);
+            return Err("502");
+        }
+        // La connexion d'un tunnel ne retourne jamais dans le pool du groupe
+        let keep_alive = !self.upgrade && upstreams.keep_alive(&upstream.name);
+        Ok(Plan {
+            leases,
+            head: self.request_head(&upstream, keep_alive),
+            options: self.route.proxy.clone().unwrap_or_default(),
+        })
+    }
+
+    fn request_head(&self, upstream: &Upstream, keep_alive: bool) -> String {
+        let req = self.req;
+        let Resource::Path(url) = &req.resource;
+        let path = upstream.path(url, self.prefix);
+        let method = format!("{:?}", req.method).to_ascii_uppercase();
+        let connection = connection_tokens(header(req, "Connection"));
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, upstream.name);
+        let mut names: Vec<&String> = req.headers.keys().collect();
+        names.sort();
+        for name in names {
+            let key = name.trim();
+            let skipped = ["host", "expect", "x-forwarded-for", "x-forwarded-proto", "x-forwarded-host"];
+            if is_hop_by_hop(key, &connection) || skipped.contains(&key.to_ascii_lowercase().as_str()) {
+                continue;
+            }
+            // Les trames sont décodées au passage: aucune extension (compression)
+            // ne doit être négociée
+            if self.upgrade && key.eq_ignore_ascii_case("Sec-WebSocket-Extensions") {
+                continue;
+            }
+            head.push_str(&format!("{}: {}\r\n", key, req.headers[name].trim()));
+        }
+        let forwarded_for = match (header(req, "X-Forwarded-For"), self.client_ip) {
+            (Some(previous), Some(ip)) => Some(format!("{}, {}", previous, ip)),
+            (Some(previous), None) => Some(previous.to_string()),
+            (None, ip) => ip.map(|ip| ip.to_string()),
+        };
+        if let Some(forwarded_for) = forwarded_for {
+            head.push_str(&format!("X-Forwarded-For: {}\r\n", forwarded_for));
+        }
+        head.push_str(&format!("X-Forwarded-Proto: {}\r\n", self.scheme));
+        if let Some(host) = header(req, "Host") {
+            head.push_str(&format!("X-Forwarded-Host: {}\r\n", host));
+        }
+        if self.upgrade {
+            head.push_str("Upgrade: websocket\r\nConnection: Upgrade\r\n\r\n");
+            return head;
+        }
+        if self.chunked {
+            head.push_str("Transfer-Encoding: chunked\r\n");
+        }
+        // Les groupes gardent leurs connexions ouvertes; sinon une connexion
+        // par requête
+        let connection = if keep_alive { "keep-alive" } else { "close" };
+        head.push_str(&format!("Connection: {}\r\n\r\n", connection));
+        head
+    }
+}
+
+// Échec d'un échange avec un serveur amont
+enum Failure {
+    Unreachable(&'static str), // aucun serveur du groupe joignable
+    Stale,                     // connexion réutilisée déjà fermée par l'amont: nouvel essai
+    Upstream(io::Error),       // aucune réponse exploitable
+    Body(&'static str),        // corps de la requête invalide, trop long ou trop lent
+    Relay,                     // réponse interrompue alors qu'elle était déjà relayée
+}
+
+// Reste d'un corps à transmettre
+enum Body {
+    Length(u64),
+    Chunked(Chunks),
+    UntilClose,
+}
+
+impl Body {
+    fn new(framing: Framing) -> Option<Body> {
+        match framing {
+            Framing::Empty | Framing::Length(0) => None,
+            Framing::Length(length) => Some(Body::Length(length)),
+            Framing::Chunked => Some(Body::Chunked(Chunks::default())),
+            Framing::UntilClose => Some(Body::UntilClose),
+        }
+    }
+
+    // Recopie dans `output` la partie de `input` qui appartient au corps;
+    // retourne les octets utilisés et si le corps est complet
+    fn feed(&mut self, input: &[u8], output: &mut Vec<u8>, decode: bool) -> io::Result<(usize, bool)> {
+        match self {
+            Body::Length(remaining) => {
+                let used = (*remaining).min(input.len() as u64) as usize;
+                output.extend_from_slice(&input[..used]);
+                *remaining -= used as u64;
+                Ok((used, *remaining == 0))
+            }
+            Body::Chunked(chunks) => {
+                let used = chunks.feed(input, output, decode)?;
+                Ok((used, chunks.is_done()))
+            }
+            Body::UntilClose => {
+                output.extend_from_slice(input);
+                Ok((input.len(), false))
+            }
+        }
+    }
+}
+
+// Lecture progressive d'un corps découpé en blocs (chunked): les octets sont
+// recopiés tels quels, ou seulement les données des blocs avec `decode`
+#[derive(Default)]
+struct Chunks {
+    state: ChunkState,
+    line: Vec<u8>, // ligne en cours: taille d'un bloc ou en-tête de fin
+    size: u64,     // total des données des blocs
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum ChunkState {
+    #[default]
+    Size,
+    Data(u64),      // octets restant dans le bloc
+    DataEnd(usize), // octets restant du CRLF qui termine le bloc
+    Trailer,        // en-têtes de fin éventuels puis ligne vide
+    Done,
+}
+
+impl Chunks {
+    // Consomme le début de `input` et retourne le nombre d'octets utilisés;
+    // ce qui suit la fin du corps n'est pas lu
+    fn feed(&mut self, input: &[u8], output: &mut Vec<u8>, decode: bool) -> io::Result<usize> {
+        let mut used = 0;
+        while used < input.len() {
+            let rest = &input[used..];
+            match self.state {
+                ChunkState::Size | ChunkState::Trailer => {
+                    let (taken, complete) = match rest.iter().position(|byte| *byte == b'\n') {
+                        Some(end) => (end + 1, true),
+                        None => (rest.len(), false),
+                    };
+                    self.line.extend_from_slice(&rest[..taken]);
+                    if !decode {
+                        output.extend_from_slice(&rest[..taken]);
+                    }
+                    used += taken;
+                    if self.line.len() > MAX_CHUNK_LINE {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "chunk line too long"));
+                    }
+                    if complete {
+                        self.end_line()?;
+                    }
+                }
+                ChunkState::Data(remaining) => {
+                    let taken = remaining.min(rest.len() as u64) as usize;
+                    output.extend_from_slice(&rest[..taken]);
+                    used += taken;
+                    self.state = match remaining - taken as u64 {
+                        0 => ChunkState::DataEnd(2),
+                        remaining => ChunkState::Data(remaining),
+                    };
+                }
+                ChunkState::DataEnd(remaining) => {
+                    let taken = remaining.min(rest.len());
+                    if !decode {
+                        output.extend_from_slice(&rest[..taken]);
+                    }
+                    used += taken;
+                    self.state = match remaining - taken {
+                        0 => ChunkState::Size,
+                        remaining => ChunkState::DataEnd(remaining),
+                    };
+                }
+                ChunkState::Done => break,
+            }
+        }
+        Ok(used)
+    }
+
+    fn end_line(&mut self) -> io::Result<()> {
+        let line = String::from_utf8_lossy(&self.line).trim().to_string();
+        self.line.clear();
+        self.state = match self.state {
+            ChunkState::Size => {
+                let size = line.split(';').next().unwrap_or_default().trim();
+                let size = u64::from_str_radix(size, 16)
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))?;
+                self.size = self.size.saturating_add(size);
+                match size {
+                    0 => ChunkState::Trailer,
+                    size => ChunkState::Data(size),
+                }
+            }
+            _ if line.is_empty() => ChunkState::Done,
+            state => state,
+        };
+        Ok(())
+    }
+
+    fn is_done(&self) -> bool {
+        self.state == ChunkState::Done
+    }
+}
+
+// Flux HTTP/2 attendant une réponse relayée. Le numéro distingue la
+// connexion d'une autre qui reprendrait son descripteur entre-temps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PendingStream {
+    pub conn: RawFd,
+    pub serial: u64,
+    pub stream: u32,
+}
+
+// Client d'un échange relayé
+enum Client {
+    Stream(Stream),       // connexion HTTP/1: la réponse lui est relayée au fil de l'eau
+    Http2(PendingStream), // la réponse complète est rendue à la connexion HTTP/2
+}
+
+// Poignée de main WebSocket relayée: après un 101, les connexions sont
+// confiées à `WebSockets`
+struct Tunnel {
+    key: String,
+    leftover: Vec<u8>, // trames du client reçues avec la poignée de main
+    policy: WebSocketPolicy,
+    channel: String,
+}
+
+// Échange terminé dont le client revient à un autre module
+pub enum Handoff {
+    Http2 {
+        pending: PendingStream,
+        output: Vec<u8>,
+    },
+    WebSocket {
+        client: Stream,
+        leftover: Vec<u8>,
+        upstream: TcpStream,
+        upstream_leftover: Vec<u8>,
+        policy: WebSocketPolicy,
+        channel: String,
+    },
+}
+
+// Échange en cours avec un serveur amont; chaque appel à `advance` le fait
+// progresser autant que possible sans bloquer
+struct Relay {
+    client: Client,
+    config: ConfigServer,
+    leases: Vec<Lease>,
+    attempt: usize, // serveur essayé dans `leases`
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    upstream: Option<TcpStream>,
+    connecting: bool,      // nouvelle connexion pas encore établie
+    // Statut retenu si aucun serveur du groupe ne répond
+    unreachable: &'static str,
+    reused: bool,          // connexion reprise du pool
+    received: bool,        // l'amont a commencé à répondre
+    request: Vec<u8>,      // en-tête et corps déjà reçu, renvoyés à chaque connexion
+    body: Option<Body>,    // reste du corps à lire chez le client
+    body_read: bool,       // corps lu sur la socket: la requête ne peut plus être renvoyée
+    to_upstream: Vec<u8>,
+    inbound: Vec<u8>,      // reçu de l'amont, pas encore traité
+    head_sent: bool,       // en-têtes de la réponse relayés au client
+    response: Option<Body>, // reste du corps de la réponse
+    reusable: bool,
+    decode: bool,
+    head_request: bool,    // réponse à HEAD: en-têtes seuls, quel que soit Content-Length
+    tunnel: Option<Tunnel>,
+    to_client: Vec<u8>,
+    blocked: bool,         // l'envoi au client attend que la socket soit prête
+    finished: bool,        // réponse complète ou échec: il ne reste qu'à l'envoyer
+    client_gone: bool,
+    client_events: u32,    // événements surveillés, 0 hors de l'epoll
+    upstream_events: u32,
+    epoll_fd: Option<RawFd>,
+    last_seen: Instant,
+}
+
+impl Relay {
+    fn new(client: Client, config: &ConfigServer, plan: Plan) -> Relay {
+        if let Client::Stream(stream) = &client {
+            let _ = stream.set_nonblocking(true);
+        }
+        Relay {
+            client,
+            config: config.clone(),
+            leases: plan.leases,
+            attempt: 0,
+            connect_timeout: Duration::from_secs(plan.options.connect_timeout),
+            read_timeout: Duration::from_secs(plan.options.read_timeout),
+            upstream: None,
+            connecting: false,
+            unreachable: "502",
+            reused: false,
+            received: false,
+            request: plan.head.into_bytes(),
+            body: None,
+            body_read: false,
+            to_upstream: Vec::new(),
+            inbound: Vec::new(),
+            head_sent: false,
+            response: None,
+            reusable: false,
+            decode: false,
+            head_request: false,
+            tunnel: None,
+            to_client: Vec::new(),
+            blocked: false,
+            finished: false,
+            client_gone: false,
+            client_events: 0,
+            upstream_events: 0,
+            epoll_fd: None,
+            last_seen: Instant::now(),
+        }
+    }
+
+    fn advance(&mut self, upstreams: &mut Upstreams) {
+        if !self.finished {
+            match self.exchange(upstreams) {
+                Ok(()) if self.finished => self.settle(upstreams),
+                Ok(()) => {}
+                Err(failure) => self.fail(failure, upstreams),
+            }
+        }
+        if self.client_gone {
+            return;
+        }
+        if let Err(e) = self.send_client() {
+            self.client_gone = true;
+            if !self.finished {
+                eprintln!("Proxied response interrupted: {}", e);
+                self.fail(Failure::Relay, upstreams);
+            }
+        }
+    }
+
+    // Envoie la requête et relaie la réponse. Un serveur injoignable cède la
+    // place au suivant du groupe; une connexion réutilisée déjà fermée par
+    // l'amont est remplacée.
+    fn exchange(&mut self, upstreams: &mut Upstreams) -> Result<(), Failure> {
+        loop {
+            if self.upstream.is_none() {
+                self.connect(upstreams)?;
+            }
+            // Le corps n'est pas lu avant la connexion: la requête doit pouvoir
+            // être renvoyée en entier au serveur suivant
+            if self.connecting {
+                match self.connected() {
+                    Ok(true) => {}
+                    Ok(false) => return Ok(()),
+                    Err(e) => {
+                        self.abandon(e, upstreams);
+                        continue;
+                    }
+                }
+            }
+            let result = self
+                .read_body()
+                .and_then(|_| self.send_upstream())
+                .and_then(|_| self.receive_upstream());
+            match result {
+                Err(Failure::Stale) => {
+                    upstreams.release(&self.leases[self.attempt], None, true);
+                    self.take_upstream();
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Ouvre une connexion au serveur courant, ou au suivant si l'ouverture
+    // échoue tout de suite
+    fn connect(&mut self, upstreams: &mut Upstreams) -> Result<(), Failure> {
+        while let Some(lease) = self.leases.get(self.attempt) {
+            match upstreams.checkout(lease) {
+                Ok((connection, reused)) => {
+                    let nonblocking = connection.set_nonblocking(true);
+                    self.upstream = Some(connection);
+                    self.connecting = !reused;
+                    self.reused = reused;
+                    self.received = false;
+                    self.to_upstream = self.request.clone();
+                    self.last_seen = Instant::now();
+                    return nonblocking.map_err(Failure::Upstream);
+                }
+                Err(e) => {
+                    self.unreachable = gateway_error(&lease.addr, &e);
+                    self.attempt += 1;
+                }
+            }
+        }
+        Err(Failure::Unreachable(self.unreachable))
+    }
+
+    // Une connexion en cours est établie une fois la socket prête en écriture,
+    // sans erreur en attente
+    fn connected(&mut self) -> io::Result<bool> {
+        let Some(upstream) = &self.upstream else {
+            return Ok(false);
+        };
+        if let Some(e) = upstream.take_error()? {
+            return Err(e);
+        }
+        match upstream.peer_addr() {
+            Ok(_) => {
+                self.connecting = false;
+                Ok(true)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotConnected => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Connexion refusée ou trop lente: le serveur est compté en échec et
+    // l'échange passe au suivant
+    fn abandon(&mut self, e: io::Error, upstreams: &mut Upstreams) {
+        let lease = &self.leases[self.attempt];
+        self.unreachable = gateway_error(&lease.addr, &e);
+        upstreams.release(lease, None, false);
+        self.take_upstream();
+        self.connecting = false;
+        self.attempt += 1;
+    }
+
+    // Lit la suite du corps chez le client tant que l'amont suit
+    fn read_body(&mut self) -> Result<(), Failure> {
+        let mut chunk = vec![0; READ_CHUNK];
+        while self.body.is_some() && self.to_upstream.len() < MAX_PENDING {
+            let Client::Stream(stream) = &self.client else {
+                return Ok(());
+            };
+            let mut stream = stream;
+            match stream.read(&mut chunk) {
+                Ok(0) => {
+                    eprintln!("unexpected end of request body");
+                    return Err(Failure::Body("400"));
+                }
+                Ok(n) => {
+                    self.body_read = true;
+                    self.request.clear();
+                    self.last_seen = Instant::now();
+                    self.forward_body(&chunk[..n])?;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return Err(Failure::Body("400")),
+            }
+        }
+        Ok(())
+    }
+
+    // Transmet la partie de `bytes` qui appartient au corps de la requête;
+    // avant la connexion, elle s'ajoute à la requête à envoyer
+    fn forward_body(&mut self, bytes: &[u8]) -> Result<(), Failure> {
+        let Some(body) = &mut self.body else {
+            return Ok(());
+        };
+        let output = match self.upstream {
+            Some(_) => &mut self.to_upstream,
+            None => &mut self.request,
+        };
+        let (_, complete) = body.feed(bytes, output, false).map_err(|e| {
+            eprintln!("Invalid request body: {}", e);
+            Failure::Body("400")
+        })?;
+        if let Body::Chunked(chunks) = body {
+            if chunks.size > self.config.client_body_limit as u64 {
+                eprintln!("Request body exceeds limit");
+                return Err(Failure::Body("413"));
+            }
+        }
+        if complete {
+            self.body = None;
+        }
+        Ok(())
+    }
+
+    fn send_upstream(&mut self) -> Result<(), Failure> {
+        let Some(mut upstream) = self.upstream.as_ref() else {
+            return Ok(());
+        };
+        while !self.to_upstream.is_empty() {
+            match upstream.write(&self.to_upstream) {
+                Ok(0) => return Err(self.upstream_error(io::ErrorKind::WriteZero.into())),
+                Ok(n) => {
+                    self.to_upstream.drain(..n);
+                    self.last_seen = Instant::now();
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(self.upstream_error(e)),
+            }
+        }
+        Ok(())
+    }
+
+    fn receive_upstream(&mut self) -> Result<(), Failure> {
+        let mut chunk = vec![0; READ_CHUNK];
+        while !self.finished && self.wants_upstream() {
+            let Some(mut upstream) = self.upstream.as_ref() else {
+                return Ok(());
+            };
+            match upstream.read(&mut chunk) {
+                Ok(0) => return self.upstream_closed(),
+                Ok(n) => {
+                    self.received = true;
+                    self.last_seen = Instant::now();
+                    self.inbound.extend_from_slice(&chunk[..n]);
+                    self.process()?;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(self.upstream_error(e)),
+            }
+        }
+        Ok(())
+    }
+
+    // Un client HTTP/1 lent suspend la lecture de l'amont
+    fn wants_upstream(&self) -> bool {
+        matches!(self.client, Client::Http2(_)) || self.to_client.len() < MAX_PENDING
+    }
+
+    // La fin du flux amont termine un corps délimité par la fermeture
+    fn upstream_closed(&mut self) -> Result<(), Failure> {
+        if self.head_sent && matches!(self.response, Some(Body::UntilClose)) {
+            self.response = None;
+            self.finished = true;
+            return Ok(());
+        }
+        let reason = match self.head_sent {
+            true => "truncated response body",
+            false => "upstream closed the connection",
+        };
+        Err(self.upstream_error(io::Error::new(io::ErrorKind::UnexpectedEof, reason)))
+    }
+
+    // Une connexion réutilisée fermée par l'amont avant toute réponse est
+    // remplacée, tant que la requête peut être renvoyée en entier
+    fn upstream_error(&self, e: io::Error) -> Failure {
+        if self.reused && !self.body_read && !self.received && is_closed(&e) {
+            Failure::Stale
+        } else if self.head_sent {
+            eprintln!("Proxied response interrupted: {}", e);
+            Failure::Relay
+        } else {
+            Failure::Upstream(e)
+        }
+    }
+
+    // Traite ce qui a été reçu de l'amont: en-têtes de la réponse, puis corps
+    fn process(&mut self) -> Result<(), Failure> {
+        if !self.head_sent {
+            let Some(end) = head_end(&self.inbound) else {
+                if self.inbound.len() > MAX_RESPONSE_HEAD {
+                    let e = io::Error::new(io::ErrorKind::InvalidData, "response head too large");
+                    return Err(Failure::Upstream(e));
+                }
+                return Ok(());
+            };
+            let head = response_head(&mut &self.inbound[..end], self.decode).map_err(Failure::Upstream)?;
+            self.inbound.drain(..end);
+            if let Some(tunnel) = &self.tunnel {
+                if head.status == 101 {
+                    if head.accept.as_deref() != Some(accept_key(&tunnel.key).as_str()) {
+                        let e = io::Error::new(io::ErrorKind::InvalidData, "invalid Sec-WebSocket-Accept");
+                        return Err(Failure::Upstream(e));
+                    }
+                    self.head_sent = true;
+                    self.to_client.extend_from_slice(head.text.as_bytes());
+                    self.finished = true;
+                    return Ok(());
+                }
+                // Refus de l'amont: sa réponse est transmise telle quelle
+                self.tunnel = None;
+            }
+            self.head_sent = true;
+            self.reusable = head.reusable;
+            self.to_client.extend_from_slice(head.text.as_bytes());
+            self.response = Body::new(if self.head_request { Framing::Empty } else { head.framing });
+            if self.response.is_none() {
+                self.finished = true;
+                return Ok(());
+            }
+        }
+        let Some(body) = &mut self.response else {
+            return Ok(());
+        };
+        let (used, complete) = body.feed(&self.inbound, &mut self.to_client, self.decode).map_err(|e| {
+            eprintln!("Proxied response interrupted: {}", e);
+            Failure::Relay
+        })?;
+        self.inbound.drain(..used);
+        if complete {
+            self.response = None;
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    // Réponse complète: la connexion amont retourne au pool si elle peut
+    // resservir, ou reste au tunnel WebSocket
+    fn settle(&mut self, upstreams: &mut Upstreams) {
+        let lease = &self.leases[self.attempt];
+        if self.tunnel.is_some() {
+            upstreams.release(lease, None, true);
+            return;
+        }
+        // Rien ne doit rester au-delà de la réponse, ni de la requête à envoyer
+        let reusable = self.reusable && self.body.is_none() && self.to_upstream.is_empty() && self.inbound.is_empty();
+        let connection = self.take_upstream().filter(|_| reusable);
+        upstreams.release(&self.leases[self.attempt], connection, true);
+    }
+
+    // Termine l'échange; tant que rien n'a été relayé, le client reçoit une
+    // page d'erreur
+    fn fail(&mut self, failure: Failure, upstreams: &mut Upstreams) {
+        let addr = self.leases.get(self.attempt).map(|lease| lease.addr.clone()).unwrap_or_default();
+        if self.upstream.is_some() {
+            let ok = !matches!(failure, Failure::Upstream(_));
+            upstreams.release(&self.leases[self.attempt], None, ok);
+            self.take_upstream();
+        }
+        let status_code = match failure {
+            Failure::Unreachable(status_code) | Failure::Body(status_code) => Some(status_code),
+            Failure::Upstream(e) => Some(gateway_error(&addr, &e)),
+            Failure::Stale => Some("502"),
+            Failure::Relay => None,
+        };
+        self.finished = true;
+        self.tunnel = None;
+        self.body = None;
+        if let Some(status_code) = status_code.filter(|_| !self.head_sent) {
+            let _ = PageErrorHandler::error_response(&self.config, status_code).send_response(&mut self.to_client);
+        }
+    }
+
+    // Envoie au client HTTP/1 ce qui peut l'être sans bloquer
+    fn send_client(&mut self) -> io::Result<()> {
+        let Client::Stream(stream) = &self.client else {
+            return Ok(());
+        };
+        let mut stream = stream;
+        while !self.to_client.is_empty() {
+            match stream.write(&self.to_client) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.to_client.drain(..n);
+                    self.last_seen = Instant::now();
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.blocked = true;
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        // Enregistrements TLS encore gardés par rustls
+        match stream.flush() {
+            Ok(()) => self.blocked = false,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.blocked = true,
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    // Le client n'envoie plus rien alors que l'amont attend la suite du corps
+    fn waits_for_body(&self) -> bool {
+        self.body.is_some() && self.upstream.is_some() && self.to_upstream.is_empty() && !self.head_sent
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        let timeout = if self.connecting {
+            self.connect_timeout
+        } else if self.waits_for_body() {
+            BODY_TIMEOUT
+        } else {
+            self.read_timeout
+        };
+        now.duration_since(self.last_seen) >= timeout
+    }
+
+    fn done(&self) -> bool {
+        let sent = self.to_client.is_empty() && !self.blocked;
+        self.finished && (self.client_gone || sent || matches!(self.client, Client::Http2(_)))
+    }
+
+    fn fds(&self) -> Vec<RawFd> {
+        let client = match &self.client {
+            Client::Stream(stream) => Some(stream.as_raw_fd()),
+            Client::Http2(_) => None,
+        };
+        client.into_iter().chain(self.upstream.as_ref().map(AsRawFd::as_raw_fd)).collect()
+    }
+
+    // Ajuste les événements surveillés sur les deux connexions
+    fn rewatch(&mut self) {
+        if let Client::Stream(stream) = &self.client {
+            let mut events = 0;
+            let connected = self.upstream.is_some() && !self.connecting;
+            if !self.finished && self.body.is_some() && connected && self.to_upstream.len() < MAX_PENDING {
+                events |= EPOLLIN as u32;
+            }
+            if self.blocked {
+                events |= EPOLLOUT as u32;
+            }
+            set_events(self.epoll_fd, stream.as_raw_fd(), &mut self.client_events, events);
+        }
+        if let Some(upstream) = &self.upstream {
+            let mut events = 0;
+            if !self.finished && !self.to_upstream.is_empty() {
+                events |= EPOLLOUT as u32;
+            }
+            if !self.finished && self.wants_upstream() {
+                events |= EPOLLIN as u32;
+            }
+            set_events(self.epoll_fd, upstream.as_raw_fd(), &mut self.upstream_events, events);
+        }
+    }
+
+    fn unwatch(&mut self) {
+        if let Client::Stream(stream) = &self.client {
+            set_events(self.epoll_fd, stream.as_raw_fd(), &mut self.client_events, 0);
+        }
+        if let Some(upstream) = &self.upstream {
+            set_events(self.epoll_fd, upstream.as_raw_fd(), &mut self.upstream_events, 0);
+        }
+    }
+
+    // Retire la connexion amont de l'epoll avant de la rendre ou de la fermer
+    fn take_upstream(&mut self) -> Option<TcpStream> {
+        let upstream = self.upstream.take()?;
+        set_events(self.epoll_fd, upstream.as_raw_fd(), &mut self.upstream_events, 0);
+        Some(upstream)
+    }
+}
+
+// Ajuste les événements surveillés sur `fd`, `current` valant 0 hors de
+// l'epoll. Sans événement utile le descripteur est retiré: une fermeture
+// (EPOLLHUP, toujours signalée) réveillerait sinon la boucle sans fin.
+fn set_events(epoll_fd: Option<RawFd>, fd: RawFd, current: &mut u32, events: u32) {
+    let op = match (*current, events) {
+        (current, events) if current == events => return,
+        (0, _) => EPOLL_CTL_ADD,
+        (_, 0) => EPOLL_CTL_DEL,
+        _ => EPOLL_CTL_MOD,
+    };
+    watch(epoll_fd, op, fd, events);
+    *current = events;
+}
+
+// Échanges relayés vers les serveurs amont, servis par la boucle epoll du
+// serveur: les connexions du client et de l'amont sont non bloquantes et
+// l'échange reprend à chaque événement de l'une ou l'autre
+#[derive(Default)]
+pub struct Relays {
+    relays: HashMap<u64, Relay>,
+    fds: HashMap<RawFd, u64>, // descripteur -> échange
+    next_id: u64,
+    handoffs: Vec<Handoff>,
+    epoll_fd: Option<RawFd>,
+}
+
+impl Relays {
+    pub fn attach(&mut self, epoll_fd: RawFd) {
+        self.epoll_fd = Some(epoll_fd);
+    }
+
+    fn start(&mut self, mut relay: Relay, upstreams: &mut Upstreams) {
+        relay.epoll_fd = self.epoll_fd;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.relays.insert(id, relay);
+        self.update(id, upstreams);
+    }
+
+    // Événement epoll: false si le descripteur n'appartient à aucun échange
+    pub fn on_event(&mut self, token: u64, upstreams: &mut Upstreams) -> bool {
+        let Some(id) = self.fds.get(&(token as RawFd)).copied() else {
+            return false;
+        };
+        self.update(id, upstreams);
+        true
+    }
+
+    // Termine les échanges restés inactifs au-delà de leur délai: corps de la
+    // requête qui n'arrive pas, amont qui ne répond pas ou client qui ne lit plus
+    pub fn tick(&mut self, upstreams: &mut Upstreams) {
+        let now = Instant::now();
+        let expired: Vec<u64> = self
+            .relays
+            .iter()
+            .filter(|(_, relay)| relay.expired(now))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            if let Some(relay) = self.relays.get_mut(&id) {
+                // L'échec peut rendre la connexion amont: elle quitte la table avant
+                for fd in relay.fds() {
+                    self.fds.remove(&fd);
+                }
+                if relay.finished {
+                    relay.client_gone = true;
+                } else if relay.connecting {
+                    let e = io::Error::new(io::ErrorKind::TimedOut, "connection timed out");
+                    relay.abandon(e, upstreams);
+                } else if relay.waits_for_body() {
+                    eprintln!("Timeout reading request body");
+                    relay.fail(Failure::Body("408"), upstreams);
+                } else {
+                    let e = io::Error::new(io::ErrorKind::TimedOut, "upstream timed out");
+                    let failure = relay.upstream_error(e);
+                    relay.fail(failure, upstreams);
+                }
+            }
+            self.update(id, upstreams);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.relays.is_empty()
+    }
+
+    // Échanges terminés dont le client doit être rendu à HTTP/2 ou WebSocket
+    pub fn take_handoffs(&mut self) -> Vec<Handoff> {
+        std::mem::take(&mut self.handoffs)
+    }
+
+    // Fin du délai d'arrêt: les échanges restants sont abandonnés
+    pub fn close_all(&mut self, upstreams: &mut Upstreams) {
+        let ids: Vec<u64> = self.relays.keys().cloned().collect();
+        for id in ids {
+            if let Some(relay) = self.relays.get_mut(&id) {
+                if !relay.finished {
+                    relay.fail(Failure::Relay, upstreams);
+                }
+                relay.client_gone = true;
+            }
+            self.finish(id);
+        }
+        self.fds.clear();
+        self.handoffs.clear();
+    }
+
+    fn update(&mut self, id: u64, upstreams: &mut Upstreams) {
+        let Some(relay) = self.relays.get_mut(&id) else {
+            return;
+        };
+        let before = relay.fds();
+        relay.advance(upstreams);
+        for fd in before {
+            self.fds.remove(&fd);
+        }
+        if relay.done() {
+            return self.finish(id);
+        }
+        relay.rewatch();
+        for fd in relay.fds() {
+            self.fds.insert(fd, id);
+        }
+    }
+
+    fn finish(&mut self, id: u64) {
+        let Some(mut relay) = self.relays.remove(&id) else {
+            return;
+        };
+        for fd in relay.fds() {
+            self.fds.remove(&fd);
+        }
+        relay.unwatch();
+        let tunnel = relay.tunnel.take().filter(|_| !relay.client_gone);
+        match (relay.client, tunnel, relay.upstream) {
+            (Client::Http2(pending), ..) => self.handoffs.push(Handoff::Http2 {
+                pending,
+                output: relay.to_client,
+            }),
+            (Client::Stream(client), Some(tunnel), Some(upstream)) => self.handoffs.push(Handoff::WebSocket {
+                client,
+                leftover: tunnel.leftover,
+                upstream,
+                upstream_leftover: relay.inbound,
+                policy: tunnel.policy,
+                channel: tunnel.channel,
+            }),
+            (Client::Stream(client), ..) => {
+                let _ = client.shutdown(Shutdown::Both);
+            }
+        }
+    }
+}
+
+// Un délai dépassé donne 504, toute autre erreur de l'amont 502
+fn gateway_error(addr: &str, e: &io::Error) -> &'static str {
+    eprintln!("Upstream {} failed: {}", addr, e);
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => "504",
+        _ => "502",
+    }
+}
+
+fn is_closed(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe
+    )
+}
+
+fn header<'r>(req: &'r HttpRequest, name: &str) -> Option<&'r str> {
+    req.headers
+        .iter()
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+// En-têtes à ne pas transmettre: ceux de la connexion et ceux que l'en-tête
+// Connection désigne
+fn is_hop_by_hop(name: &str, connection: &[String]) -> bool {
+    let name = name.trim().to_ascii_lowercase();
+    HOP_BY_HOP.contains(&name.as_str()) || connection.contains(&name)
+}
+
+fn connection_tokens(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(|token| token.trim().to_ascii_lowercase())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+// Délimitation du corps d'une réponse amont
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Framing {
+    Empty,
+    Length(u64),
+    Chunked,
+    UntilClose,
+}
+
+struct ResponseHead {
+    status: u16,
+    text: String, // ligne de statut et en-têtes à envoyer au client
+    framing: Framing,
+    reusable: bool, // la connexion amont peut resservir après le corps
+    accept: Option<String>, // Sec-WebSocket-Accept d'une réponse 101
+}
+
+// Fin des en-têtes de la réponse finale, après les réponses intermédiaires
+// (1xx sauf 101) qui la précèdent
+fn head_end(buf: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    loop {
+        let end = start + buf[start..].windows(4).position(|window| window == b"\r\n\r\n")? + 4;
+        let status = buf.get(start + 9..start + 12).unwrap_or_default();
+        if !status.starts_with(b"1") || status == b"101" {
+            return Some(end);
+        }
+        start = end;
+    }
+}
+
+// Lit la ligne de statut et les en-têtes de la réponse amont et les prépare
+// pour le client. Transfer-Encoding est conservé car le corps est relayé
+// sans être décodé, sauf si `decode` demande de retirer le découpage en
+// blocs. Les réponses intermédiaires (100 Continue) sont ignorées,
+// sauf 101 qui termine l'échange HTTP d'une poignée de main WebSocket.
+fn response_head(response: &mut impl BufRead, decode: bool) -> io::Result<ResponseHead> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid response from upstream");
+    let (status_line, status, headers) = loop {
+        let mut status_line = String::new();
+        if response.read_line(&mut status_line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "upstream closed the connection"));
+        }
+        if !status_line.starts_with("HTTP/1.") {
+            return Err(invalid());
+        }
+        let status: u16 = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(invalid)?;
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            if response.read_line(&mut line)? == 0 {
+                return Err(invalid());
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').ok_or_else(invalid)?;
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+        if status == 101 || !(100..200).contains(&status) {
+            break (status_line, status, headers);
+        }
+    };
+    let value = |wanted: &str| {
+        headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+            .map(|(_, value)| value.as_str())
+    };
+    let connection = connection_tokens(value("Connection"));
+    let framing = if status == 101 || status == 204 || status == 304 {
+        Framing::Empty
+    } else if value("Transfer-Encoding").is_some_and(|te| te.to_ascii_lowercase().contains("chunked")) {
+        Framing::Chunked
+    } else if let Some(length) = value("Content-Length") {
+        Framing::Length(length.parse().map_err(|_| invalid())?)
+    } else {
+        Framing::UntilClose
+    };
+    let reusable = status != 101
+        && status_line.starts_with("HTTP/1.1")
+        && !connection.iter().any(|token| token == "close")
+        && framing != Framing::UntilClose;
+
+    let mut text = status_line.trim_end().to_string();
+    text.push_str("\r\n");
+    for (name, value) in &headers {
+        let dropped = if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            decode && framing == Framing::Chunked
+        } else {
+            is_hop_by_hop(name, &connection)
+        };
+        if dropped {
+            continue;
+        }
+        if status == 101 && name.eq_ignore_ascii_case("Sec-WebSocket-Extensions") {
+            continue;
+        }
+        text.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if status == 101 {
+        text.push_str("Upgrade: websocket\r\nConnection: Upgrade\r\n\r\n");
+    } else {
+        text.push_str("Connection: close\r\n\r\n");
+    }
+    Ok(ResponseHead {
+        status,
+        text,
+        framing,
+        reusable,
+        accept: value("Sec-WebSocket-Accept").map(str::to_string),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::httprequest::{Method, Version};
+    use libc::{epoll_create1, epoll_event, epoll_wait, EPOLL_CLOEXEC};
+    use std::io::BufReader;
+    use std::net::TcpListener;
+    use crate::upstream::{Strategy, UpstreamGroup};
+    use crate::csrf::CsrfPolicy;
+    use std::thread;
+
+    fn setup_config() -> ConfigServer {
+        ConfigServer {
+            root: String::from("/public"),
+            client_body_limit: 1024,
+            ..Default::default()
+        }
+    }
+
+    fn proxy_route(upstream: &str) -> Route {
+        Route {
+            alias: "/api/".to_string(),
+            methods: vec!["GET".to_string(), "POST".to_string()],
+            proxy_pass: Some(upstream.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn request(method: Method, path: &str, headers: &[(&str, &str)]) -> HttpRequest {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), format!(" {}\r\n", value)))
+            .collect();
+        HttpRequest::new(method, Version::V1_1, Resource::Path(path.to_string()), headers, String::new())
+    }
+
+    // Serveur amont d'une seule connexion: renvoie la requête reçue à la
+    // fonction de test et répond `response`
+    fn upstream(response: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut received = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                received.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = Vec::new();
+            if received.contains("Transfer-Encoding: chunked\r\n") {
+                // Dernier bloc, éventuellement suivi de champs de fin
+                let last = |body: &[u8]| body.starts_with(b"0\r\n") || body.windows(5).any(|w| w == b"\r\n0\r\n");
+                while !(last(&body) && body.ends_with(b"\r\n\r\n")) {
+                    let mut byte = [0; 1];
+                    reader.read_exact(&mut byte).unwrap();
+                    body.push(byte[0]);
+                }
+            } else {
+                let length = received
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .map_or(0, |length| length.parse().unwrap());
+                body.resize(length, 0);
+                reader.read_exact(&mut body).unwrap();
+            }
+            received.push_str(&String::from_utf8(body).unwrap());
+            stream.write_all(response.as_bytes()).unwrap();
+            received
+        });
+        (addr, handle)
+    }
+
+    fn attached() -> Relays {
+        let mut relays = Relays::default();
+        relays.attach(unsafe { epoll_create1(EPOLL_CLOEXEC) });
+        relays
+    }
+
+    // Traite les événements jusqu'à ce qu'il reste au plus `remaining` échanges
+    fn run(relays: &mut Relays, upstreams: &mut Upstreams, remaining: usize) {
+        let epoll_fd = relays.epoll_fd.unwrap();
+        let mut events = vec![epoll_event { events: 0, u64: 0 }; 16];
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while relays.relays.len() > remaining && Instant::now() < deadline {
+            let n = unsafe { epoll_wait(epoll_fd, events.as_mut_ptr(), events.len() as i32, 50) };
+            for event in &events[..n.max(0) as usize] {
+                assert!(relays.on_event(event.u64, upstreams));
+            }
+            relays.tick(upstreams);
+        }
+    }
+
+    // Connexion acceptée par le serveur et socket du client
+    fn connection() -> (Stream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        (stream.into(), client)
+    }
+
+    fn response(client: &mut TcpStream) -> String {
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    // Sert `req` sur une vraie connexion: `leftover` a été lu avec les
+    // en-têtes, `body` arrive ensuite sur la socket. Retourne ce que le
+    // client a reçu.
+    fn send(req: &HttpRequest, leftover: &[u8], body: &[u8], route: &Route, upstreams: &mut Upstreams) -> String {
+        let (stream, mut client) = connection();
+        client.write_all(body).unwrap();
+        let mut relays = attached();
+        serve(req, stream, leftover.to_vec(), &setup_config(), route, "/api/", upstreams, &mut relays);
+        run(&mut relays, upstreams, 0);
+        assert!(relays.is_empty() && relays.fds.is_empty());
+        unsafe {
+            libc::close(relays.epoll_fd.unwrap());
+        }
+        response(&mut client)
+    }
+
+    fn get(route: &Route, upstreams: &mut Upstreams) -> String {
+        send(&request(Method::Get, "/api/", &[]), b"", b"", route, upstreams)
+    }
+
+    fn upstream_group(servers: &[&str]) -> Upstreams {
+        let group = UpstreamGroup {
+            servers: servers.iter().map(|s| s.to_string()).collect(),
+            strategy: Strategy::RoundRobin,
+            max_fails: 1,
+            fail_timeout: 10,
+            keepalive: 2,
+            keepalive_timeout: 60,
+            health_check: None,
+        };
+        Upstreams::new(&HashMap::from([(String::from("backend"), group)]))
+    }
+
+    #[test]
+    fn test_parse_upstream() {
+        let upstream = Upstream::parse("http://127.0.0.1:3000").unwrap();
+        assert_eq!((upstream.authority.as_str(), upstream.base.as_str()), ("127.0.0.1:3000", ""));
+        assert_eq!(upstream.path("/api/users?x=1", "/api/"), "/api/users?x=1");
+        let upstream = Upstream::parse("http://backend/v1/").unwrap();
+        assert_eq!((upstream.name.as_str(), upstream.authority.as_str()), ("backend", "backend:80"));
+        assert_eq!(upstream.path("/api/users?x=1", "/api/"), "/v1/users?x=1");
+        assert_eq!(Upstream::parse("http://[::1]").unwrap().authority, "[::1]:80");
+        assert!(Upstream::parse("https://backend").is_err());
+        assert!(Upstream::parse("http:///path").is_err());
+    }
+
+    #[test]
+    fn test_forward_request_and_response() {
+        let (addr, handle) = upstream(
+            "HTTP/1.1 201 Created\r\nContent-Length: 2\r\nKeep-Alive: timeout=5\r\nConnection: close, X-Secret\r\nX-Secret: 1\r\nX-App: yes\r\n\r\nok",
+        );
+        let route = proxy_route(&format!("http://{}/v1", addr));
+        let req = request(
+            Method::Post,
+            "/api/orders?id=3",
+            &[
+                ("Host", "example.com"),
+                ("Content-Length", "5"),
+                ("Connection", "keep-alive, X-Debug"),
+                ("X-Debug", "1"),
+                ("X-Forwarded-For", "10.0.0.1"),
+                ("Accept", "*/*"),
+            ],
+        );
+        // Début du corps lu avec les en-têtes, suite sur la socket
+        let response = send(&req, b"he", b"llo", &route, &mut Upstreams::default());
+
+        let received = handle.join().unwrap();
+        assert!(received.starts_with(&format!("POST /v1/orders?id=3 HTTP/1.1\r\nHost: {}\r\n", addr)));
+        assert!(received.contains("Accept: */*\r\n"));
+        assert!(received.contains("X-Forwarded-For: 10.0.0.1, 127.0.0.1\r\n"));
+        assert!(received.contains("X-Forwarded-Proto: http\r\n"));
+        assert!(received.contains("X-Forwarded-Host: example.com\r\n"));
+        assert!(!received.contains("X-Debug"));
+        assert!(!received.contains("keep-alive"));
+        assert!(received.ends_with("\r\n\r\nhello"));
+
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(response.contains("X-App: yes\r\n"));
+        assert!(!response.contains("Keep-Alive") && !response.contains("X-Secret"));
+        assert!(response.ends_with("Connection: close\r\n\r\nok"));
+    }
+
+    #[test]
+    fn test_forwarded_proto_over_tls() {
+        let (addr, handle) = upstream("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        let route = proxy_route(&format!("http://{}", addr));
+        let mut req = request(Method::Get, "/api/", &[("Host", "example.com")]);
+        req.secure = true;
+        assert!(send(&req, b"", b"", &route, &mut Upstreams::default()).ends_with("\r\n\r\nok"));
+        let received = handle.join().unwrap();
+        assert!(received.contains("X-Forwarded-Proto: https\r\n"));
+        assert!(!received.contains("X-Forwarded-Proto: http\r\n"));
+    }
+
+    #[test]
+    fn test_methods_beyond_get_and_post() {
+        let (addr, handle) = upstream("HTTP/1.1 204 No Content\r\n\r\n");
+        let mut route = proxy_route(&format!("http://{}", addr));
+        route.methods.extend(["PUT", "PATCH", "HEAD", "OPTIONS"].map(String::from));
+        let req = request(Method::Put, "/api/orders/3", &[("Content-Length", "2")]);
+        let response = send(&req, b"{}", b"", &route, &mut Upstreams::default());
+        assert!(handle.join().unwrap().starts_with("PUT /api/orders/3 HTTP/1.1\r\n"));
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+
+        // La réponse à HEAD annonce une longueur mais n'a pas de corps
+        let (addr, handle) = upstream("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n");
+        let mut route = proxy_route(&format!("http://{}", addr));
+        route.methods.push(String::from("HEAD"));
+        let response = send(&request(Method::Head, "/api/", &[]), b"", b"", &route, &mut Upstreams::default());
+        assert!(handle.join().unwrap().starts_with("HEAD /api/ HTTP/1.1\r\n"));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("Content-Length: 5\r\nConnection: close\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_chunked_request_body() {
+        // Le corps est transmis tel quel, découpage compris
+        let (addr, handle) = upstream("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        let route = proxy_route(&format!("http://{}", addr));
+        let req = request(Method::Post, "/api/", &[("Transfer-Encoding", "chunked")]);
+        let response = send(&req, b"5\r\nhel", b"lo\r\n0\r\nX-Sum: 1\r\n\r\n", &route, &mut Upstreams::default());
+        let received = handle.join().unwrap();
+        assert!(received.contains("Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n"));
+        assert!(received.ends_with("\r\n\r\n5\r\nhello\r\n0\r\nX-Sum: 1\r\n\r\n"));
+        assert!(response.ends_with("\r\n\r\nok"));
+
+        // Au-delà de client_body_limit, l'amont n'est pas contacté
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let route = proxy_route(&format!("http://{}", closed));
+        let response = send(&req, b"800\r\n", b"", &route, &mut Upstreams::default());
+        assert!(response.contains("413 Payload Too Large"));
+        let response = send(&req, b"zz\r\n", b"", &route, &mut Upstreams::default());
+        assert!(response.contains("400 Bad Request"));
+
+        // Content-Length et découpage en blocs ensemble sont refusés
+        let req = request(Method::Post, "/api/", &[("Transfer-Encoding", "chunked"), ("Content-Length", "5")]);
+        assert!(send(&req, b"", b"", &route, &mut Upstreams::default()).contains("400 Bad Request"));
+    }
+
+    #[test]
+    fn test_expect_continue_and_http_1_0_client() {
+        // Le 100 Continue vient du proxy, pas de l'amont
+        let (addr, handle) = upstream("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        let route = proxy_route(&format!("http://{}", addr));
+        let req = request(Method::Post, "/api/", &[("Content-Length", "5"), ("Expect", "100-continue")]);
+        let response = send(&req, b"", b"hello", &route, &mut Upstreams::default());
+        assert!(!handle.join().unwrap().contains("Expect"));
+        assert!(response.starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n"));
+
+        // Un client HTTP/1.0 reçoit le corps décodé, sans Transfer-Encoding
+        let (addr, handle) = upstream(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\none\r\n4;x=y\r\n two\r\n0\r\nX-Sum: 1\r\n\r\n",
+        );
+        let route = proxy_route(&format!("http://{}", addr));
+        let mut req = request(Method::Get, "/api/", &[]);
+        req.version = Version::V1_0;
+        let response = send(&req, b"", b"", &route, &mut Upstreams::default());
+        handle.join().unwrap();
+        assert!(!response.contains("Transfer-Encoding"));
+        assert!(response.ends_with("Connection: close\r\n\r\none two"));
+    }
+
+    #[test]
+    fn test_gateway_errors() {
+        // Port fermé: connexion refusée
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let route = proxy_route(&format!("http://{}", closed));
+        assert!(get(&route, &mut Upstreams::default()).contains("502 Bad Gateway"));
+
+        // L'amont accepte mais ne répond pas
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut route = proxy_route(&format!("http://{}", silent.local_addr().unwrap()));
+        route.proxy = Some(ProxyOptions {
+            connect_timeout: 1,
+            read_timeout: 1,
+        });
+        assert!(get(&route, &mut Upstreams::default()).contains("504 Gateway Timeout"));
+
+        // Méthode non autorisée par la route
+        let req = request(Method::Delete, "/api/", &[]);
+        let response = send(&req, b"", b"", &route, &mut Upstreams::default());
+        assert!(response.contains("405 Method Not Allowed"));
+
+        // Requête forgée par un site tiers sur une route protégée contre le CSRF
+        route.csrf = Some(CsrfPolicy {
+            token: false,
+            check_origin: true,
+            trusted_origins: vec![],
+            same_site: None,
+        });
+        let req = request(Method::Post, "/api/", &[("Host", "localhost:8080"), ("Origin", "http://evil.example")]);
+        let response = send(&req, b"", b"", &route, &mut Upstreams::default());
+        assert!(response.contains("403 Forbidden"));
+    }
+
+    #[test]
+    fn test_slow_upstream_does_not_block() {
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut slow = proxy_route(&format!("http://{}", silent.local_addr().unwrap()));
+        slow.proxy = Some(ProxyOptions {
+            connect_timeout: 1,
+            read_timeout: 1,
+        });
+        let (addr, handle) = upstream("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        let fast = proxy_route(&format!("http://{}", addr));
+
+        let mut upstreams = Upstreams::default();
+        let mut relays = attached();
+        let config = setup_config();
+        let req = request(Method::Get, "/api/", &[]);
+        let start = Instant::now();
+        let (stream, mut waiting) = connection();
+        serve(&req, stream, Vec::new(), &config, &slow, "/api/", &mut upstreams, &mut relays);
+        let (stream, mut served) = connection();
+        serve(&req, stream, Vec::new(), &config, &fast, "/api/", &mut upstreams, &mut relays);
+
+        // La seconde requête est servie pendant que la première attend
+        run(&mut relays, &mut upstreams, 1);
+        handle.join().unwrap();
+        assert!(response(&mut served).ends_with("\r\n\r\nok"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        run(&mut relays, &mut upstreams, 0);
+        assert!(response(&mut waiting).contains("504 Gateway Timeout"));
+        unsafe {
+            libc::close(relays.epoll_fd.unwrap());
+        }
+    }
+
+    #[test]
+    fn test_keep_alive_group() {
+        // Un seul accept: les trois requêtes passent par la même connexion
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let responses = [
+                "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\none",
+                "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\ntwo\r\n0\r\n\r\n",
+                "HTTP/1.1 204 No Content\r\n\r\n",
+            ];
+            let mut hosts = Vec::new();
+            for response in responses {
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(host) = line.strip_prefix("Host: ") {
+                        hosts.push(host.trim().to_string());
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                writer.write_all(response.as_bytes()).unwrap();
+            }
+            hosts
+        });
+        let mut upstreams = upstream_group(&[&addr]);
+        let route = proxy_route("http://backend");
+        assert!(get(&route, &mut upstreams).ends_with("\r\n\r\none"));
+        let chunked = get(&route, &mut upstreams);
+        assert!(chunked.starts_with("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(chunked.ends_with("\r\n\r\n3\r\ntwo\r\n0\r\n\r\n"));
+        assert!(get(&route, &mut upstreams).starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert_eq!(server.join().unwrap(), vec!["backend"; 3]);
+    }
+
+    #[test]
+    fn test_failover_to_next_server() {
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let (addr, handle) = upstream("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        let mut upstreams = upstream_group(&[&closed, &addr]);
+        let route = proxy_route("http://backend");
+        assert!(get(&route, &mut upstreams).ends_with("\r\n\r\nok"));
+        assert!(handle.join().unwrap().contains("Connection: keep-alive\r\n"));
+        // Le serveur injoignable est écarté, l'autre ne répond plus
+        assert!(get(&route, &mut upstreams).contains("502 Bad Gateway"));
+    }
+}
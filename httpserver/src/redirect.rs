@@ -0,0 +1,292 @@
+// redirect.rs
+use crate::config::ConfigServer;
+use crate::matcher::match_route;
+use http::httpresponse::HttpResponse;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Nombre maximal de réécritures internes pour une même requête
+pub const MAX_REWRITES: usize = 10;
+
+// Au-delà, une chaîne de redirections est considérée comme infinie même si
+// aucun chemin ne se répète ("/a" -> "/a/a" -> ...)
+const MAX_CHAIN: usize = 32;
+
+// Règle `[[servers.redirects]]`: un chemin reconnu par l'expression `from`
+// est redirigé vers `to`, où $1 ou $nom sont remplacés par les captures.
+// Avec `rewrite`, le nouveau chemin est servi directement, sans aller-retour.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RedirectRule {
+    pub from: String,
+    pub to: String, // chemin local ou URL externe
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub rewrite: bool,
+    #[serde(default = "default_preserve_query")]
+    pub preserve_query: bool,
+    #[serde(skip)]
+    pub(crate) regex: Option<Regex>, // `from` compilé par `prepare`
+}
+
+impl Default for RedirectRule {
+    fn default() -> Self {
+        RedirectRule {
+            from: String::new(),
+            to: String::new(),
+            status: default_status(),
+            rewrite: false,
+            preserve_query: default_preserve_query(),
+            regex: None,
+        }
+    }
+}
+
+fn default_status() -> u16 {
+    302
+}
+
+fn default_preserve_query() -> bool {
+    true
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Redirect { status: u16, location: String },
+    Rewrite(String),
+}
+
+impl RedirectRule {
+    // Compile `from` au chargement de la configuration; une expression
+    // invalide, signalée par la validation, ne reconnaît aucun chemin
+    pub fn prepare(&mut self) {
+        self.regex = Regex::new(&self.from).ok();
+    }
+
+    pub fn apply(&self, url: &str) -> Option<Outcome> {
+        let (path, query) = match url.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (url, None),
+        };
+        let captures = self.regex.as_ref()?.captures(path)?;
+        let mut target = String::new();
+        captures.expand(&self.to, &mut target);
+        if let Some(query) = query.filter(|query| self.preserve_query && !query.is_empty()) {
+            target.push(if target.contains('?') { '&' } else { '?' });
+            target.push_str(query);
+        }
+        Some(if self.rewrite {
+            Outcome::Rewrite(target)
+        } else {
+            Outcome::Redirect { status: self.status, location: target }
+        })
+    }
+}
+
+// Les règles sont essayées dans l'ordre de la configuration
+pub fn find_redirect(rules: &[RedirectRule], url: &str) -> Option<Outcome> {
+    rules.iter().find_map(|rule| rule.apply(url))
+}
+
+// Chemin finalement servi après application des règles
+#[derive(Debug, PartialEq)]
+pub enum Resolution {
+    Path(String),
+    Redirect { status: u16, location: String },
+    Loop(Vec<String>),
+}
+
+// Applique les règles: une réécriture relance la recherche avec le nouveau
+// chemin. Les réécritures dont la cible dépend des captures ne peuvent être
+// vérifiées qu'ici.
+pub fn resolve(rules: &[RedirectRule], url: &str) -> Resolution {
+    let path = |url: &str| url.split('?').next().unwrap_or_default().to_string();
+    let mut rewrites: Vec<String> = Vec::new();
+    let mut url = url.to_string();
+    loop {
+        match find_redirect(rules, &url) {
+            Some(Outcome::Redirect { status, location }) => {
+                return Resolution::Redirect { status, location }
+            }
+            Some(Outcome::Rewrite(target)) => {
+                rewrites.push(url);
+                let looping = rewrites.iter().any(|seen| path(seen) == path(&target));
+                if looping || rewrites.len() > MAX_REWRITES {
+                    rewrites.push(target);
+                    return Resolution::Loop(rewrites);
+                }
+                url = target;
+            }
+            None => return Resolution::Path(url),
+        }
+    }
+}
+
+// Suit les redirections des routes (`redirect` d'une route vers l'alias d'une
+// autre) à partir de `alias` et retourne la boucle atteinte, par exemple
+// ["/a/", "/b/", "/c/", "/a/"]
+pub fn route_redirect_loop(server: &ConfigServer, alias: &str) -> Option<Vec<String>> {
+    let mut chain = vec![alias.to_string()];
+    let mut current = alias;
+    loop {
+        let route = server.routes.iter().find(|route| route.alias == current)?;
+        let (target, _) = route.redirect.as_ref()?.iter().next()?;
+        if let Some(start) = chain.iter().position(|seen| seen == target) {
+            chain.push(target.clone());
+            return Some(chain.split_off(start));
+        }
+        chain.push(target.clone());
+        current = target;
+    }
+}
+
+// Étape suivante d'une requête pour `path`, comme au service: une règle
+// `[[redirects]]` d'abord, sinon le `redirect` de la route qui sert le chemin.
+// Une redirection externe termine la chaîne: le serveur ne la voit pas revenir,
+// et les navigateurs limitent eux-mêmes le nombre de redirections suivies.
+fn next_hop(server: &ConfigServer, path: &str) -> Option<String> {
+    let target = match find_redirect(&server.redirects, path) {
+        Some(Outcome::Redirect { location, .. }) if location.starts_with('/') => location,
+        Some(Outcome::Redirect { .. }) => return None,
+        Some(Outcome::Rewrite(target)) => target,
+        None => {
+            let alias = match_route(server, path).alias;
+            let route = server.routes.iter().find(|route| route.alias == alias)?;
+            let (target_alias, target_page) = route.redirect.as_ref()?.iter().next()?;
+            format!("{}{}", target_alias, target_page)
+        }
+    };
+    Some(target.split('?').next().unwrap_or_default().to_string())
+}
+
+// Suit les règles et les routes à partir d'un chemin tant qu'elles mènent à
+// un chemin local; retourne la boucle si un chemin revient
+pub fn redirect_loop(server: &ConfigServer, path: &str) -> Option<Vec<String>> {
+    let mut chain = vec![path.to_string()];
+    while chain.len() < MAX_CHAIN {
+        let target = next_hop(server, chain.last()?)?;
+        if let Some(start) = chain.iter().position(|seen| *seen == target) {
+            chain.push(target);
+            return Some(chain.split_off(start));
+        }
+        chain.push(target);
+    }
+    None
+}
+
+pub fn status_code(status: u16) -> Option<&'static str> {
+    match status {
+        301 => Some("301"),
+        302 => Some("302"),
+        303 => Some("303"),
+        307 => Some("307"),
+        308 => Some("308"),
+        _ => None,
+    }
+}
+
+pub fn redirect_response<'a>(config: &ConfigServer, status: u16, location: String) -> HttpResponse<'a> {
+    let mut response = HttpResponse::new(
+        status_code(status).unwrap_or("302"),
+        config.host_name.clone(),
+        Some(HashMap::new()),
+        None,
+    );
+    response.set_header("Location", location);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Route;
+
+    fn rule(from: &str, to: &str) -> RedirectRule {
+        let mut rule = RedirectRule {
+            from: from.to_string(),
+            to: to.to_string(),
+            ..Default::default()
+        };
+        rule.prepare();
+        rule
+    }
+
+    #[test]
+    fn test_captures_and_query() {
+        let mut old_blog = rule(r"^/blog/(?P<year>[0-9]{4})/(.*)$", "/articles/$year/$2");
+        old_blog.status = 301;
+        assert_eq!(
+            old_blog.apply("/blog/2024/intro.html?ref=rss"),
+            Some(Outcome::Redirect {
+                status: 301,
+                location: "/articles/2024/intro.html?ref=rss".to_string()
+            })
+        );
+        assert_eq!(old_blog.apply("/blog/latest"), None);
+
+        let mut external = rule("^/docs/(.*)$", "https://docs.example.com/$1?lang=fr");
+        assert_eq!(
+            external.apply("/docs/api?v=2"),
+            Some(Outcome::Redirect {
+                status: 302,
+                location: "https://docs.example.com/api?lang=fr&v=2".to_string()
+            })
+        );
+        external.preserve_query = false;
+        assert_eq!(
+            external.apply("/docs/api?v=2"),
+            Some(Outcome::Redirect {
+                status: 302,
+                location: "https://docs.example.com/api?lang=fr".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_first_matching_rule() {
+        let mut rewrite = rule("^/latest/$", "/v2/");
+        rewrite.rewrite = true;
+        let rules = vec![rewrite, rule("^/", "/fallback/")];
+        assert_eq!(find_redirect(&rules, "/latest/"), Some(Outcome::Rewrite("/v2/".to_string())));
+        assert!(matches!(find_redirect(&rules, "/other"), Some(Outcome::Redirect { .. })));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut latest = rule("^/latest/(.*)$", "/v2/$1");
+        latest.rewrite = true;
+        let mut back = rule("^/v2/old$", "/latest/old");
+        back.rewrite = true;
+        let rules = vec![latest, back, rule("^/v1/", "/v2/")];
+        assert_eq!(resolve(&rules, "/latest/a?x=1"), Resolution::Path("/v2/a?x=1".to_string()));
+        assert!(matches!(resolve(&rules, "/v1/"), Resolution::Redirect { status: 302, .. }));
+        assert_eq!(
+            resolve(&rules, "/latest/old"),
+            Resolution::Loop(vec!["/latest/old".to_string(), "/v2/old".to_string(), "/latest/old".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_redirect_loop() {
+        let server = |rules: Vec<RedirectRule>| ConfigServer { redirects: rules, ..Default::default() };
+        let mut rewrite = rule("^/c$", "/a");
+        rewrite.rewrite = true;
+        let rules = vec![rule("^/a$", "/b"), rule("^/b$", "/c?from=b"), rewrite];
+        assert_eq!(redirect_loop(&server(rules), "/a").unwrap(), vec!["/a", "/b", "/c", "/a"]);
+        // Une cible externe termine la chaîne
+        let rules = vec![rule("^/a$", "/b"), rule("^/b$", "https://example.com/a")];
+        assert_eq!(redirect_loop(&server(rules), "/a"), None);
+        // Un chemin qui grandit sans se répéter est limité
+        assert_eq!(redirect_loop(&server(vec![rule("^/(.*)$", "/x/$1")]), "/a"), None);
+
+        // Une route qui redirige vers une règle qui y ramène
+        let mut config = server(vec![rule("^/b/(.*)$", "/a/$1")]);
+        config.routes = vec![Route {
+            alias: "/a/".to_string(),
+            redirect: Some(HashMap::from([("/b/".to_string(), String::new())])),
+            ..Default::default()
+        }];
+        assert_eq!(redirect_loop(&config, "/a/").unwrap(), vec!["/a/", "/b/", "/a/"]);
+    }
+}
@@ -1,7 +1,13 @@
 // router.rs
-use super::handler::{Handler, PageErrorHandler, StaticPageHandler};
+use super::auth::authorize;
+use super::csrf::{inject_token, verify_csrf, CSRF_FIELD};
+use super::handler::{Handler, PageErrorHandler, StaticPageHandler, WebServiceHandler};
+use super::matcher::match_route;
+use super::redirect::{redirect_response, resolve, Resolution};
+use super::upload::{store_uploads, upload_result, UploadPolicy};
 use crate::config::*;
 use http::httprequest::Resource;
+use http::multipart::{boundary, Multipart};
 use http::{httprequest, httprequest::HttpRequest, httpresponse::HttpResponse};
 use std::io::prelude::*;
 
@@ -9,18 +15,34 @@ pub struct Router;
 
 impl Router {
     pub fn route(
-        req: HttpRequest,
+        mut req: HttpRequest,
         stream: &mut impl Write,
         config: &ConfigServer,
         parsed_msg_body: Vec<u8>,
-        addr: &str,
     ) -> () {
+        // Les règles de redirection passent avant les routes
+        let Resource::Path(url) = &req.resource;
+        match resolve(&config.redirects, url) {
+            Resolution::Path(path) => req.resource = Resource::Path(path),
+            Resolution::Redirect { status, location } => {
+                let _ = redirect_response(config, status, location).send_response(stream);
+                return;
+            }
+            Resolution::Loop(rewrites) => {
+                eprintln!("Rewrite loop: {}", rewrites.join(" -> "));
+                respond_with_error(stream, config, "500");
+                return;
+            }
+        }
+        // Les paramètres de la route ({id}, groupes nommés) sont transmis aux handlers
+        let Resource::Path(path) = &req.resource;
+        req.params = match_route(config, path).params;
         match req.method {
             httprequest::Method::Get => {
                 Self::handle_get(req, stream, config);
             }
             httprequest::Method::Post => {
-                Self::handle_post(req, stream, config, parsed_msg_body, addr);
+                Self::handle_post(req, stream, config, parsed_msg_body);
             }
             httprequest::Method::Delete => {
                 Self::handle_delete(req, stream, config);
@@ -41,12 +63,27 @@ impl Router {
                 let path = route[1];
 
                 if path.is_empty() {
-                    let resp = StaticPageHandler.handle(&req, config);
+                    let resp = with_csrf_token(&req, config, "/", StaticPageHandler.handle(&req, config));
                     let _ = resp.send_response(stream);
                     return;
                 }
 
+                let alias = match_route(config, s).alias;
+                let (is_match, service_route) = find_route(config, &alias);
+                if let Some(service) = service_route.service.as_ref().filter(|_| is_match) {
+                    let resp = if !check_methods(config, "GET", &alias) {
+                        PageErrorHandler::error_response(config, "405")
+                    } else {
+                        match authorize(&req, &service_route, config) {
+                            Ok(_) => WebServiceHandler::serve(service, &req, config),
+                            Err(resp) => *resp,
+                        }
+                    };
+                    let _ = resp.send_response(stream);
+                    return;
+                }
                 let resp: HttpResponse = StaticPageHandler.handle(&req, config);
+                let resp = with_csrf_token(&req, config, &alias, resp);
                 resp.send_response(stream)
                     .expect("msg: faild to serve static file");
             }
@@ -54,31 +91,24 @@ impl Router {
     }
 
     fn handle_post(
-        req: HttpRequest,
+        mut req: HttpRequest,
         stream: &mut impl Write,
         config: &ConfigServer,
         parsed_msg_body: Vec<u8>,
-        addr: &str,
     ) {
         // verifier si la methode sur la route
         let Resource::Path(url) = &req.resource;
-        if !url.ends_with("/") {
+        let url = url.clone();
+        let alias = match_route(config, &url).alias;
+        let method = format!("{:?}", req.method);
+
+        let (is_match, route) = find_route(config, &alias);
+        // Les routes CGI et les services web acceptent les sous-chemins
+        let is_endpoint = route.cgi.is_some() || route.service.is_some();
+        if !(url.ends_with("/") || (is_match && is_endpoint)) {
             respond_with_error(stream, config, "404");
             return;
         }
-        // Parse the URI
-        let route: Vec<&str> = url.split("/").collect();
-
-        let mut alias = String::new();
-
-        if route[0] == "" && route.len() == 2 {
-            alias.push_str("/");
-        } else {
-            alias = format!("/{}/", route[1]);
-        }
-        let method = format!("{:?}", req.method);
-
-        let is_match = find_route(config, &alias).0;
         if is_match {
             if !check_methods(config, &method.to_ascii_uppercase(), &alias) {
                 respond_with_error(stream, config, "405");
@@ -88,51 +118,130 @@ impl Router {
             respond_with_error(stream, config, "404");
             return;
         }
+        // Les clients authentifiés (Basic/Bearer) n'ont pas besoin de session
+        let authenticated = match authorize(&req, &route, config) {
+            Ok(user) => user.is_some(),
+            Err(resp) => {
+                let _ = resp.send_response(stream);
+                return;
+            }
+        };
 
-        // Implement something if you want to render any page after deleting
-
-        if let Some(content_type) = req.headers.get("Content-Type") {
-            if content_type.trim_start().starts_with("multipart/form-data") {
-                if let Some(boundary) = content_type.split("boundary=").nth(1) {
-                    let parts = parse_multipart_body(&parsed_msg_body, boundary);
+        // Les corps urlencoded et JSON sont décodés par handle_client; on
+        // décode ici ceux des requêtes construites sans passer par la socket
+        if req.form.is_none() && req.json.is_none() {
+            if let Err(e) = req.decode_body(&parsed_msg_body) {
+                eprintln!("Invalid request body: {}", e);
+                respond_with_error(stream, config, "400");
+                return;
+            }
+        }
 
-                    if parts.is_empty() {
-                        respond_with_error(stream, config, "400");
-                        return;
-                    }
+        if let Some(script) = &route.cgi {
+            if !verify_csrf(&req, &route, req.form_value(CSRF_FIELD)) {
+                respond_with_error(stream, config, "403");
+                return;
+            }
+            match StaticPageHandler::run_cgi_script(script, &req, &parsed_msg_body, config) {
+                Some(output) => {
+                    let response = HttpResponse::new("200", config.host_name.clone(), None, Some(output));
+                    let _ = response.send_response(stream);
+                }
+                None => respond_with_error(stream, config, "500"),
+            }
+            return;
+        }
+        if let Some(service) = &route.service {
+            if !verify_csrf(&req, &route, None) {
+                respond_with_error(stream, config, "403");
+                return;
+            }
+            let _ = WebServiceHandler::serve(service, &req, config).send_response(stream);
+            return;
+        }
 
-                    // Vérifier la présence du cookie avant de traiter les champs
-                    let cookie_present = req.headers.get("Cookie").is_some();
-                    let Resource::Path(s) = &req.resource;
-                    if !cookie_present {
-                        println!("set cookie no present s: {}", s);
-                        if s == "/" {
-                            println!("Setting cookie...");
-                            set_cookie(&req, stream, "secret".to_string(), config);
-                        } else {
-                            respond_with_redirect(stream, addr, "/login/");
+        let content_type = match req.content_type() {
+            Some(content_type) => content_type,
+            None => {
+                respond_with_error(stream, config, "400");
+                return;
+            }
+        };
+        let multipart = match content_type.as_str() {
+            "multipart/form-data" => {
+                // Le corps est normalement analysé au fil de la lecture de la socket
+                let multipart = match req.multipart.take() {
+                    Some(multipart) => Ok(multipart),
+                    None => match req.headers.get("Content-Type").and_then(|c| boundary(c)) {
+                        Some(boundary) => parse_multipart_body(&parsed_msg_body, &boundary),
+                        None => {
+                            eprintln!("Boundary not specified in Content-Type");
+                            respond_with_error(stream, config, "400");
                             return;
                         }
+                    },
+                };
+                match multipart {
+                    Ok(multipart) if !multipart.is_empty() => Some(multipart),
+                    Ok(_) => {
+                        respond_with_error(stream, config, "400");
+                        return;
                     }
-
-                    for (key, (field_name, data)) in parts.iter() {
-                        if key == "filename" {
-                            handle_file_upload(req, stream, config, addr, field_name, data);
-                            return;
-                        } else {
-                            handle_text_field(&req, stream, config, addr, key, field_name, data);
-                        }
+                    Err(e) => {
+                        eprintln!("Invalid multipart body: {}", e);
+                        respond_with_error(stream, config, "400");
+                        return;
                     }
-                } else {
-                    eprintln!("Boundary not specified in Content-Type");
+                }
+            }
+            "application/x-www-form-urlencoded" => {
+                if req.form.as_ref().is_none_or(|form| form.is_empty()) {
                     respond_with_error(stream, config, "400");
+                    return;
                 }
-            } else {
+                None
+            }
+            _ => {
                 eprintln!("Unsupported content type:{}", content_type);
-                respond_with_error(stream, config, "400");
+                respond_with_error(stream, config, "415");
+                return;
+            }
+        };
+
+        let form_token = match &multipart {
+            Some(multipart) => multipart
+                .field(CSRF_FIELD)
+                .map(|value| String::from_utf8_lossy(value).into_owned()),
+            None => req.form_value(CSRF_FIELD).map(String::from),
+        };
+        if !verify_csrf(&req, &route, form_token.as_deref()) {
+            respond_with_error(stream, config, "403");
+            return;
+        }
+
+        // Vérifier la présence du cookie avant de traiter les champs
+        let cookie_present = req.headers.contains_key("Cookie");
+        if !cookie_present && !authenticated {
+            if url == "/" {
+                let same_site = route.csrf.as_ref().map_or("Lax", |p| p.same_site());
+                set_cookie(&req, stream, "secret".to_string(), config, same_site);
+            } else {
+                respond_with_redirect(stream, config, "/login/");
+            }
+            return;
+        }
+
+        if let Some(multipart) = &multipart {
+            if multipart.files().next().is_some() {
+                handle_file_upload(&req, stream, config, &route, multipart, authenticated);
+                return;
             }
+        }
+        // Les champs texte seuls n'ont pas de traitement: on renvoie le client vers une page
+        if url == "/" {
+            respond_with_redirect(stream, config, "/singin/cookie.html");
         } else {
-            respond_with_error(stream, config, "400");
+            respond_with_redirect(stream, config, &url);
         }
     }
 
@@ -142,13 +251,7 @@ impl Router {
                 // Parse the URI
                 let route: Vec<&str> = s.split("/").collect();
                 let path = route[1];
-                let mut alias = String::new();
-
-                if route[0] == "" && route.len() == 2 {
-                    alias.push_str("/");
-                } else {
-                    alias = format!("/{}/", route[1]);
-                }
+                let alias = match_route(config, s).alias;
                 let method = format!("{:?}", req.method);
 
                 if path.is_empty() {
@@ -161,56 +264,75 @@ impl Router {
                         respond_with_error(stream, config, "405");
                         return;
                     }
-                    handle_get_request(req, alias, stream, config, route);
+                    let authenticated = match authorize(&req, &route, config) {
+                        Ok(user) => user.is_some(),
+                        Err(resp) => {
+                            let _ = resp.send_response(stream);
+                            return;
+                        }
+                    };
+                    if !verify_csrf(&req, &route, None) {
+                        respond_with_error(stream, config, "403");
+                        return;
+                    }
+                    handle_get_request(req, alias, stream, config, route, authenticated);
                 }
             }
         }
     }
 }
 
-fn handle_file_upload(
-    req: HttpRequest,
-    stream: &mut impl Write,
+// Insérer le jeton CSRF dans les pages des routes protégées
+fn with_csrf_token<'a>(
+    req: &HttpRequest,
     config: &ConfigServer,
-    addr: &str,
-    field_name: &str,
-    data: &[u8],
-) {
-    if let Some(cookie) = req.headers.get("Cookie") {
-        if !verify_cookie(cookie) {
-            eprintln!("invalid session cookie: {}", cookie);
-            respond_with_error(stream, config, "403");
-            return;
-        }
-        if let Err(e) = save_file(&config.root, field_name, data) {
-            eprintln!("Error creating file: {}", e);
-            respond_with_error(stream, config, "500");
-            return;
+    alias: &str,
+    mut resp: HttpResponse<'a>,
+) -> HttpResponse<'a> {
+    let (is_match, route) = find_route(config, alias);
+    if is_match {
+        if let Some(body) = inject_token(req, &route, config, resp.get_body()) {
+            resp.body = Some(body);
         }
-        respond_with_redirect(stream, addr, "/");
-    } else {
-        respond_with_redirect(stream, addr, "/login/");
     }
+    resp
 }
 
-fn handle_text_field(
+fn handle_file_upload(
     req: &HttpRequest,
     stream: &mut impl Write,
-    _config: &ConfigServer,
-    addr: &str,
-    key: &str,
-    field_name: &str,
-    data: &[u8],
+    config: &ConfigServer,
+    route: &Route,
+    multipart: &Multipart,
+    authenticated: bool,
 ) {
-    println!("key: {}", key);
-    println!("field_name: {}", field_name);
-    println!("Value: {}", String::from_utf8_lossy(data));
-
-    let Resource::Path(s) = &req.resource;
-    if s == "/" {
-        if req.headers.get("Cookie").is_some() {
-            respond_with_redirect(stream, addr, "/singin/cookie.html");
+    if !authenticated {
+        match req.headers.get("Cookie") {
+            Some(cookie) if !verify_cookie(config, cookie) => {
+                eprintln!("invalid session cookie: {}", cookie);
+                respond_with_error(stream, config, "403");
+                return;
+            }
+            Some(_) => {}
+            None => {
+                respond_with_redirect(stream, config, "/login/");
+                return;
+            }
+        }
+    }
+    // Tous les fichiers envoyés sont vérifiés puis enregistrés avant de répondre
+    let policy = route.upload.clone().unwrap_or_else(UploadPolicy::legacy);
+    let stored = match store_uploads(multipart, &policy, config) {
+        Ok(stored) => stored,
+        Err(status_code) => {
+            respond_with_error(stream, config, status_code);
+            return;
         }
+    };
+    if route.upload.is_some() {
+        let _ = upload_result(req, route, &stored, config).send_response(stream);
+    } else {
+        respond_with_redirect(stream, config, "/");
     }
 }
 
@@ -221,42 +343,35 @@ pub fn respond_with_error(stream: &mut impl Write, config: &ConfigServer, status
         .expect("faild to send_response");
 }
 
-fn respond_with_redirect(stream: &mut impl Write, addr: &str, location: &str) {
-    let response = format!(
-        "HTTP/1.1 301 Found\r\nLocation: http://{}{}\r\n\r\n",
-        addr, location
-    );
-    stream
-        .write(response.as_bytes())
-        .expect("faild to write response");
-    stream.flush().expect("faild to send_response");
+// Après un formulaire, le client est renvoyé vers une page avec GET
+fn respond_with_redirect(stream: &mut impl Write, config: &ConfigServer, location: &str) {
+    let response = redirect_response(config, 303, location.to_string());
+    response
+        .send_response(stream)
+        .expect("faild to send_response");
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::temp_dir;
     use std::collections::HashMap;
     use std::io::Cursor;
 
     fn setup_config() -> ConfigServer {
         ConfigServer {
-            host_name: String::from("localhost"),
             root: String::from("/public"),
-            directory_listing: false,
-            error_pages: HashMap::new(),
-            cgi_extensions: HashMap::new(),
-            host: String::from("127.0.0.1"),
             ports: vec![8080],
             client_body_limit: 1024,
             routes: vec![Route {
                 alias: "/test".to_string(),
                 pages: vec!["index.html".to_string()],
                 default_page: "index.html".to_string(),
-                check_cookie: false,
-                redirect: None,
                 links: vec!["/index.html".to_string()],
                 methods: vec!["GET".to_string(), "POST".to_string(), "DELETE".to_string()],
+                ..Default::default()
             }],
+            ..Default::default()
         }
     }
 
@@ -272,7 +387,7 @@ mod tests {
         );
 
         let mut stream = Cursor::new(Vec::new());
-        Router::route(req, &mut stream, &config, Vec::new(), "localhost");
+        Router::route(req, &mut stream, &config, Vec::new());
 
         let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
         assert!(response.contains("200 OK"));
@@ -301,10 +416,305 @@ mod tests {
         ).into_bytes();
 
         let mut stream = Cursor::new(Vec::new());
-        Router::route(req, &mut stream, &config, body, "localhost");
+        Router::route(req, &mut stream, &config, body);
+
+        let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
+        assert!(response.contains("HTTP/1.1 303 See Other")); // Redirect after file upload
+    }
+
+    #[test]
+    fn test_route_post_rejects_forged_origin() {
+        let mut config = setup_config();
+        config.routes[0].alias = "/upload/".to_string();
+        config.routes[0].csrf = Some(crate::csrf::CsrfPolicy {
+            token: false,
+            check_origin: true,
+            trusted_origins: vec![],
+            same_site: None,
+        });
+        let mut headers = HashMap::new();
+        headers.insert(
+            "Content-Type".to_string(),
+            " multipart/form-data; boundary=XyZ".to_string(),
+        );
+        headers.insert("Host".to_string(), " localhost:8080".to_string());
+        headers.insert("Origin".to_string(), " http://evil.example".to_string());
+        headers.insert("Cookie".to_string(), " sessionId=abc|def".to_string());
+        let req = HttpRequest::new(
+            httprequest::Method::Post,
+            httprequest::Version::V1_1,
+            Resource::Path("/upload/".to_string()),
+            headers,
+            String::new(),
+        );
+        let body = b"--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"x.txt\"\r\n\r\nx\r\n--XyZ--".to_vec();
+
+        let mut stream = Cursor::new(Vec::new());
+        Router::route(req, &mut stream, &config, body);
+
+        let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
+        assert!(response.contains("403 Forbidden"));
+    }
+
+    #[test]
+    fn test_route_post_multiple_files() {
+        let root = temp_dir("router_multi_upload");
+        let tokens = root.join("tokens.txt");
+        std::fs::write(&tokens, "upload-token\n").unwrap();
+
+        let mut config = setup_config();
+        config.root = root.to_string_lossy().into_owned();
+        config.routes[0].alias = "/upload/".to_string();
+        config.routes[0].auth = Some(crate::auth::RouteAuth {
+            realm: None,
+            basic: None,
+            bearer: Some(tokens.to_string_lossy().into_owned()),
+            ..Default::default()
+        });
+        config.prepare();
+        let mut headers = HashMap::new();
+        headers.insert(
+            "Content-Type".to_string(),
+            " multipart/form-data; boundary=XyZ\r\n".to_string(),
+        );
+        headers.insert("Authorization".to_string(), " Bearer upload-token\r\n".to_string());
+        let req = HttpRequest::new(
+            httprequest::Method::Post,
+            httprequest::Version::V1_1,
+            Resource::Path("/upload/".to_string()),
+            headers,
+            String::new(),
+        );
+        let body = b"--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"one.txt\"\r\n\r\n1\r\n\
+--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"two.txt\"\r\n\r\n2\r\n--XyZ--"
+            .to_vec();
+
+        let mut stream = Cursor::new(Vec::new());
+        Router::route(req, &mut stream, &config, body);
+
+        let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
+        assert!(response.contains("HTTP/1.1 303 See Other"));
+        assert_eq!(std::fs::read(root.join("one.txt")).unwrap(), b"1");
+        assert_eq!(std::fs::read(root.join("two.txt")).unwrap(), b"2");
+    }
+
+    #[test]
+    fn test_route_post_upload_policy() {
+        let root = temp_dir("router_upload_policy");
+        std::fs::create_dir_all(root.join("files")).unwrap();
+        std::fs::write(root.join("files/report.txt"), "old").unwrap();
+        let tokens = root.join("tokens.txt");
+        std::fs::write(&tokens, "upload-token\n").unwrap();
+
+        let mut config = setup_config();
+        config.root = root.to_string_lossy().into_owned();
+        config.routes[0].alias = "/upload/".to_string();
+        config.routes[0].upload = Some(crate::upload::UploadPolicy {
+            dir: Some("files".to_string()),
+            allowed_extensions: vec!["txt".to_string()],
+            ..Default::default()
+        });
+        config.routes[0].auth = Some(crate::auth::RouteAuth {
+            realm: None,
+            basic: None,
+            bearer: Some(tokens.to_string_lossy().into_owned()),
+            ..Default::default()
+        });
+        config.prepare();
+        let mut req = post_request("/upload/", "multipart/form-data; boundary=XyZ");
+        req.headers.insert("Authorization".to_string(), " Bearer upload-token\r\n".to_string());
+        req.headers.insert("Accept".to_string(), " application/json\r\n".to_string());
+        let body = b"--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"../report.txt\"\r\n\r\nnew\r\n--XyZ--"
+            .to_vec();
+
+        let mut stream = Cursor::new(Vec::new());
+        Router::route(req, &mut stream, &config, body);
+
+        let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
+        assert!(response.contains("201 Created"));
+        assert!(response.contains("\"stored_name\":\"report-1.txt\""));
+        assert_eq!(std::fs::read(root.join("files/report.txt")).unwrap(), b"old");
+        assert_eq!(std::fs::read(root.join("files/report-1.txt")).unwrap(), b"new");
+    }
+
+    fn post_request(path: &str, content_type: &str) -> HttpRequest {
+        let mut headers = HashMap::new();
+        headers.insert("Content-Type".to_string(), format!(" {}\r\n", content_type));
+        HttpRequest::new(
+            httprequest::Method::Post,
+            httprequest::Version::V1_1,
+            Resource::Path(path.to_string()),
+            headers,
+            String::new(),
+        )
+    }
+
+    #[test]
+    fn test_route_post_json_to_service() {
+        let mut config = setup_config();
+        config.routes[0].alias = "/api/".to_string();
+        config.routes[0].service = Some("shipping".to_string());
+        let req = post_request("/api/shipping/orders", "application/json");
+        let body = br#"{"order_id": 3, "order_date": "1 Mar 2020", "order_status": "Pending"}"#;
+
+        let mut stream = Cursor::new(Vec::new());
+        Router::route(req, &mut stream, &config, body.to_vec());
+
+        // Sans $DATA_PATH la commande n'est pas enregistrée
+        let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
+        assert!(response.contains("501 Not Implemented"));
+    }
+
+    #[test]
+    fn test_route_post_urlencoded_to_cgi() {
+        let root = temp_dir("router_cgi_post");
+        std::fs::write(
+            root.join("echo.py"),
+            "import os, sys\nprint(os.environ['REQUEST_METHOD'] + ' ' + sys.stdin.read())\n",
+        )
+        .unwrap();
+        let mut config = setup_config();
+        config.root = root.to_string_lossy().into_owned();
+        config.routes[0].alias = "/form/".to_string();
+        config.routes[0].cgi = Some("echo.py".to_string());
+        let req = post_request("/form/submit", "application/x-www-form-urlencoded");
+
+        let mut stream = Cursor::new(Vec::new());
+        Router::route(req, &mut stream, &config, b"name=Ada&x=1".to_vec());
+
+        let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
+        assert!(response.contains("200 OK"));
+        assert!(response.contains("POST name=Ada&x=1"));
+    }
+
+    #[test]
+    fn test_route_post_params_to_cgi() {
+        let root = temp_dir("router_cgi_params");
+        std::fs::write(
+            root.join("user.py"),
+            "import os\nprint('user ' + os.environ['ROUTE_PARAM_ID'])\n",
+        )
+        .unwrap();
+        let mut config = setup_config();
+        config.root = root.to_string_lossy().into_owned();
+        config.routes[0].alias = "/users/{id}/".to_string();
+        config.routes[0].cgi = Some("user.py".to_string());
+        let mut catch_all = config.routes[0].clone();
+        catch_all.alias = "/users/".to_string();
+        catch_all.cgi = None;
+        config.routes.push(catch_all);
+        let req = post_request("/users/42/profile", "application/x-www-form-urlencoded");
+
+        let mut stream = Cursor::new(Vec::new());
+        Router::route(req, &mut stream, &config, b"name=Ada".to_vec());
+
+        let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
+        assert!(response.contains("200 OK"));
+        assert!(response.contains("user 42"));
+    }
+
+    #[test]
+    fn test_route_post_urlencoded_fields() {
+        let mut config = setup_config();
+        config.routes[0].alias = "/form/".to_string();
+        let mut req = post_request("/form/", "application/x-www-form-urlencoded");
+        req.headers.insert("Cookie".to_string(), "sessionId=abc".to_string());
+
+        let mut stream = Cursor::new(Vec::new());
+        Router::route(req, &mut stream, &config, b"password=hunter2".to_vec());
+
+        let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
+        assert!(response.contains("303 See Other"));
+        assert!(response.contains("Location:/form/"));
+
+        let req = post_request("/form/", "application/x-www-form-urlencoded");
+        let mut stream = Cursor::new(Vec::new());
+        Router::route(req, &mut stream, &config, b"password=hunter2".to_vec());
+
+        let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
+        assert!(response.contains("Location:/login/"));
+    }
+
+    #[test]
+    fn test_route_post_unsupported_media_type() {
+        let mut config = setup_config();
+        config.routes[0].alias = "/upload/".to_string();
+        let req = post_request("/upload/", "text/plain");
+
+        let mut stream = Cursor::new(Vec::new());
+        Router::route(req, &mut stream, &config, b"hello".to_vec());
+
+        let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
+        assert!(response.contains("415 Unsupported Media Type"));
+    }
+
+    #[test]
+    fn test_route_redirect_rules() {
+        let mut config = setup_config();
+        config.routes[0].alias = "/api/".to_string();
+        config.routes[0].service = Some("shipping".to_string());
+        config.redirects = vec![
+            crate::redirect::RedirectRule {
+                from: "^/v1/(.*)$".to_string(),
+                to: "/api/$1".to_string(),
+                rewrite: true,
+                ..Default::default()
+            },
+            crate::redirect::RedirectRule {
+                from: "^/old/(?P<page>.*)$".to_string(),
+                to: "/new/$page".to_string(),
+                status: 308,
+                ..Default::default()
+            },
+        ];
+        config.prepare();
+        let get = |path: &str| {
+            let req = HttpRequest::new(
+                httprequest::Method::Get,
+                httprequest::Version::V1_1,
+                Resource::Path(path.to_string()),
+                HashMap::new(),
+                String::new(),
+            );
+            let mut stream = Cursor::new(Vec::new());
+            Router::route(req, &mut stream, &config, Vec::new());
+            String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8")
+        };
+
+        let response = get("/old/page.html?x=1");
+        assert!(response.contains("HTTP/1.1 308 Permanent Redirect"));
+        assert!(response.contains("Location:/new/page.html?x=1"));
+        // La réécriture est servie sans redirection
+        let response = get("/v1/shipping/orders");
+        assert!(response.contains("200 OK"));
+        assert!(response.contains("order_id"));
+    }
+
+    #[test]
+    fn test_route_rewrite_loop() {
+        let mut config = setup_config();
+        let rewrite = |from: &str, to: &str| crate::redirect::RedirectRule {
+            from: from.to_string(),
+            to: to.to_string(),
+            rewrite: true,
+            ..Default::default()
+        };
+        // La cible dépend de la requête: la boucle n'est visible qu'à l'exécution
+        config.redirects = vec![rewrite("^/a/(.*)$", "/b/$1"), rewrite("^/b/(.*)$", "/a/$1")];
+        config.prepare();
+        let req = HttpRequest::new(
+            httprequest::Method::Get,
+            httprequest::Version::V1_1,
+            Resource::Path("/a/page.html".to_string()),
+            HashMap::new(),
+            String::new(),
+        );
+
+        let mut stream = Cursor::new(Vec::new());
+        Router::route(req, &mut stream, &config, Vec::new());
 
         let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
-        assert!(response.contains("HTTP/1.1 301 Found")); // Redirect after file upload
+        assert!(response.contains("500 Internal Server Error"));
     }
 
     #[test]
@@ -319,7 +729,7 @@ mod tests {
         );
 
         let mut stream = Cursor::new(Vec::new());
-        Router::route(req, &mut stream, &config, Vec::new(), "localhost");
+        Router::route(req, &mut stream, &config, Vec::new());
 
         let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
         assert!(response.contains("200 OK") || response.contains("404 Not Found")); // Depending on file existence
@@ -337,7 +747,7 @@ mod tests {
         );
 
         let mut stream = Cursor::new(Vec::new());
-        Router::route(req, &mut stream, &config, Vec::new(), "localhost");
+        Router::route(req, &mut stream, &config, Vec::new());
 
         let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
         assert!(response.contains("405 Method Not Allowed"));
@@ -358,10 +768,10 @@ mod tests {
     fn test_respond_with_redirect() {
         let mut stream = Cursor::new(Vec::new());
 
-        respond_with_redirect(&mut stream, "localhost", "/redirect-path");
+        respond_with_redirect(&mut stream, &setup_config(), "/redirect-path");
 
         let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
-        assert!(response.contains("HTTP/1.1 301 Found"));
-        assert!(response.contains("Location: http://localhost/redirect-path"));
+        assert!(response.contains("HTTP/1.1 303 See Other"));
+        assert!(response.contains("Location:/redirect-path"));
     }
 }
@@ -1,114 +1,693 @@
 //server.rs
 use super::router::Router;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, prelude::*, BufReader};
-use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::net::{Shutdown, SocketAddr};
 
-use crate::config::{ok_count_redirect, ok_same_port, Config, ConfigServer};
+use crate::config::{
+    check_config, find_route, ok_count_redirect, ok_same_port, Config, ConfigServer, ListenAddr,
+    Route,
+};
+use crate::listener::{ListenSpec, Listener, Stream};
 use crate::handler::PageErrorHandler;
+use crate::http2::{self, Http2Connections};
+use crate::matcher::{match_route, RouteMatch};
+use crate::proxy::{self, Handoff, Relays};
+use crate::redirect::{resolve, Resolution};
+use crate::sse::{self, EventStreams};
 use http::httprequest::{
-    process_header_line, process_req_line, HttpRequest, Method, Resource, Version,
+    expects_continue, process_header_line, process_req_line, HttpRequest, Method, Resource, Version,
+};
+use http::httpresponse::CONTINUE;
+use crate::signals;
+use crate::systemd;
+use crate::tls::{Handshakes, TlsContexts};
+use crate::upstream::Upstreams;
+use crate::websocket::{self, WebSocketMode, WebSockets};
+use http::websocket::is_upgrade;
+use libc::{
+    epoll_create1, epoll_ctl, epoll_event, epoll_wait, EPOLLIN, EPOLL_CLOEXEC, EPOLL_CTL_ADD,
+    EPOLL_CTL_DEL,
 };
-use libc::{epoll_create1, epoll_ctl, epoll_event, epoll_wait, EPOLLIN, EPOLL_CTL_ADD};
-use std::os::unix::io::AsRawFd;
-use std::time::Duration;
+use http::multipart::{boundary, MultipartParser};
+use std::env;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command};
+use std::time::{Duration, Instant};
+
+// Taille des blocs lus sur la socket pour le corps de la requête
+const BODY_CHUNK_SIZE: usize = 8192;
+
+// Variables d'environnement transmises au nouveau binaire lors d'une mise à
+// jour (SIGUSR2): sockets d'écoute héritées et processus à arrêter
+const INHERITED_FDS_ENV: &str = "WEB_SERVER_LISTEN_FDS";
+const UPGRADE_PARENT_ENV: &str = "WEB_SERVER_UPGRADE_PID";
 
 pub struct Server {
     config: Config,
+    config_path: Option<String>, // fichier relu à la réception de SIGHUP
+    upstreams: Upstreams,        // état des groupes de serveurs amont
+    relays: Relays,              // échanges relayés vers les serveurs amont
+    websockets: WebSockets,      // connexions passées en WebSocket
+    events: EventStreams,        // flux d'événements ouverts et canaux
+    tls: TlsContexts,            // certificats des adresses servies en TLS
+    handshakes: Handshakes,      // poignées de main TLS en cours
+    http2: Http2Connections,     // connexions servies en HTTP/2
 }
 
 impl Server {
     pub fn new(config: Config) -> Self {
-        Server { config }
+        Server {
+            upstreams: Upstreams::new(&config.upstreams),
+            relays: Relays::default(),
+            websockets: WebSockets::default(),
+            events: EventStreams::default(),
+            tls: TlsContexts::default(),
+            handshakes: Handshakes::default(),
+            http2: Http2Connections::default(),
+            config,
+            config_path: None,
+        }
     }
 
-    pub fn run(&self) {
-        let epoll_fd = unsafe { epoll_create1(0) };
+    // Active le rechargement de la configuration depuis `path` sur SIGHUP
+    pub fn with_config_path(mut self, path: &str) -> Self {
+        self.config_path = Some(path.to_string());
+        self
+    }
 
-        let mut listeners = vec![];
+    pub fn run(mut self) {
+        let mut listeners = Listeners::new();
+        // Sockets reprises d'un processus précédent (SIGUSR2) ou ouvertes par systemd
+        listeners.inherited = inherited_fds();
+        let activated = systemd::listen_fds();
+        listeners.activated = activated.keys().cloned().collect();
+        listeners.inherited.extend(activated);
+        self.tls = TlsContexts::load(&self.config).expect("error to load TLS certificates");
 
         // Créer des instances de serveurs pour chaque configuration
-        for server_config in &self.config.servers {
-            if !ok_count_redirect(&server_config.routes) || !ok_same_port(server_config) {
-                eprintln!("⚠️ Incorrect configuration {}⚠️", server_config.host_name);
+        for spec in listen_specs(&self.config) {
+            listeners.open(&spec).expect("error to bind addr");
+            let scheme = if self.tls.get(&spec.addr.to_string()).is_some() { "https" } else { "http" };
+            println!("Server running on {}://{}", scheme, spec.addr);
+        }
+        listeners.close_inherited();
+        // Après une mise à jour, systemd doit suivre le nouveau processus
+        if env::var_os(UPGRADE_PARENT_ENV).is_some() {
+            systemd::notify(&format!("MAINPID={}\nREADY=1", process::id()));
+        } else {
+            systemd::notify("READY=1");
+        }
+        notify_upgrade_parent();
+        self.upstreams.attach(listeners.epoll_fd);
+        self.relays.attach(listeners.epoll_fd);
+        self.websockets.attach(listeners.epoll_fd);
+        self.events.attach(listeners.epoll_fd);
+        self.handshakes.attach(listeners.epoll_fd);
+        self.http2.attach(listeners.epoll_fd);
+
+        let mut events = vec![epoll_event { events: 0, u64: 0 }; 10];
+        let mut upgrade: Option<Child> = None;
+
+        loop {
+            // Le délai borne l'attente si un signal arrive juste avant epoll_wait
+            let nfds = unsafe {
+                epoll_wait(listeners.epoll_fd, events.as_mut_ptr(), events.len() as i32, 1000)
+            };
+            // errno est lu tout de suite: le traitement des signaux le modifie
+            let wait_error = (nfds == -1).then(io::Error::last_os_error);
+            if signals::take_shutdown_request() {
+                // Le nouveau processus a repris les sockets: les fichiers des
+                // sockets Unix ne doivent pas être supprimés
+                let handed_over = matches!(upgrade.as_mut().map(Child::try_wait), Some(Ok(None)));
+                listeners.keep_socket_files = handed_over;
+                if !handed_over {
+                    systemd::notify("STOPPING=1");
+                }
+                break;
+            }
+            if signals::take_reload_request() {
+                self.reload(&mut listeners);
+            }
+            if signals::take_upgrade_request() {
+                if upgrade.is_some() {
+                    eprintln!("⚠️ An upgrade is already in progress⚠️");
+                } else {
+                    match spawn_upgrade(&listeners) {
+                        Ok(child) => {
+                            println!("Started new process {} for upgrade", child.id());
+                            upgrade = Some(child);
+                        }
+                        Err(e) => eprintln!("⚠️ Upgrade failed: {}⚠️", e),
+                    }
+                }
+            }
+            // Si le nouveau processus échoue, celui-ci continue de servir
+            if let Some(Ok(Some(status))) = upgrade.as_mut().map(Child::try_wait) {
+                eprintln!("⚠️ Upgrade failed: new process exited with {}⚠️", status);
+                upgrade = None;
+            }
+            // Sondes des serveurs amont et connexions inactives expirées
+            self.upstreams.tick();
+            self.relays.tick(&mut self.upstreams);
+            self.hand_off();
+            self.websockets.tick();
+            self.events.tick();
+            self.handshakes.tick();
+            self.http2.tick();
+            if let Some(e) = wait_error {
+                // Un signal interrompt epoll_wait
+                if e.kind() != io::ErrorKind::Interrupted {
+                    eprintln!("epoll_wait failed: {}", e);
+                }
                 continue;
             }
-            for port in &server_config.ports {
-                let addr = format!("{}:{}", &server_config.host, port);
-                let listener = TcpListener::bind(addr).expect("error to bind addr");
-                listener
-                    .set_nonblocking(true)
-                    .expect("error to set true non blocking");
-                println!("Server running on http://{}:{}", server_config.host, *port);
-
-                // Ajouter le listener à epoll sans EPOLLET (Level-Triggered par défaut)
-                let mut event = epoll_event {
-                    events: EPOLLIN as u32,
-                    u64: listener.as_raw_fd() as u64,
+
+            for n in 0..nfds {
+                let token = events[n as usize].u64;
+                let Some((addr, listener)) = listeners.find(token) else {
+                    self.on_event(token);
+                    continue;
                 };
-                unsafe {
-                    epoll_ctl(epoll_fd, EPOLL_CTL_ADD, listener.as_raw_fd(), &mut event);
+                match listener.accept() {
+                    Ok(stream) => self.accepted(addr, stream),
+                    Err(e) => {
+                        eprintln!("Error accepting connection: {:?}", e);
+                    }
                 }
-                listeners.push(listener);
             }
         }
+        self.drain(listeners);
+    }
 
-        let mut events = vec![epoll_event { events: 0, u64: 0 }; 10];
+    // Événement d'une connexion déjà acceptée
+    fn on_event(&mut self, token: u64) {
+        if self.handshakes.owns(token) {
+            if let Some(stream) = self.handshakes.on_event(token) {
+                self.serve(stream);
+            }
+        } else if !self.relays.on_event(token, &mut self.upstreams)
+            && !self.http2.on_event(token, &mut self.upstreams, &mut self.relays)
+            && !self.websockets.on_event(token)
+            && !self.events.on_event(token)
+        {
+            self.upstreams.on_event(token);
+        }
+        self.hand_off();
+    }
 
+    // Réponses relayées à rendre à un flux HTTP/2 et poignées de main
+    // WebSocket acceptées par l'amont, qui deviennent des sessions relayées
+    fn hand_off(&mut self) {
+        for handoff in self.relays.take_handoffs() {
+            match handoff {
+                Handoff::Http2 { pending, output } => self.http2.deliver(pending, &output),
+                Handoff::WebSocket { client, leftover, upstream, upstream_leftover, policy, channel } => {
+                    self.websockets.open(client, leftover, policy, channel, Some((upstream.into(), upstream_leftover)));
+                }
+            }
+        }
+    }
+
+    // Arrêt propre: les sockets d'écoute sont fermées, les clients WebSocket,
+    // SSE et HTTP/2 sont prévenus puis les connexions se terminent d'elles-mêmes
+    // jusqu'au délai `drain_timeout`; celles qui restent sont alors fermées
+    fn drain(&mut self, mut listeners: Listeners) {
+        let deadline = Instant::now() + Duration::from_secs(self.config.drain_timeout);
+        println!(
+            "Shutting down, closing connections within {}s",
+            self.config.drain_timeout
+        );
+        let addrs: Vec<String> = listeners.sockets.keys().cloned().collect();
+        for addr in &addrs {
+            listeners.close(addr);
+        }
+        let mut events = vec![epoll_event { events: 0, u64: 0 }; 10];
         loop {
-            let nfds =
-                unsafe { epoll_wait(epoll_fd, events.as_mut_ptr(), events.len() as i32, -1) };
-            if nfds == -1 {
-                eprintln!("epoll_wait failed");
+            // Aussi pour les connexions dont la poignée de main TLS vient de se terminer
+            self.websockets.shut_down();
+            self.events.shut_down();
+            self.http2.shut_down();
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let idle = self.handshakes.is_empty()
+                && self.relays.is_empty()
+                && self.websockets.is_empty()
+                && self.events.is_empty()
+                && self.http2.is_empty();
+            if idle || remaining.is_zero() {
+                break;
+            }
+            let timeout = remaining.min(Duration::from_millis(100)).as_millis() as i32;
+            let nfds = unsafe {
+                epoll_wait(listeners.epoll_fd, events.as_mut_ptr(), events.len() as i32, timeout)
+            };
+            self.handshakes.tick();
+            self.relays.tick(&mut self.upstreams);
+            self.hand_off();
+            for n in 0..nfds.max(0) {
+                self.on_event(events[n as usize].u64);
+            }
+        }
+        self.relays.close_all(&mut self.upstreams);
+        self.websockets.close_all();
+        self.events.close_all();
+        self.http2.close_all();
+        drop(listeners);
+        println!("Server stopped");
+    }
+
+    // Sur une adresse TLS, la requête n'est lue qu'une fois la poignée de
+    // main terminée
+    fn accepted(&mut self, addr: &str, stream: Stream) {
+        let stream = match self.tls.get(addr) {
+            Some(tls) => self.handshakes.start(stream, tls),
+            None => Some(stream),
+        };
+        if let Some(stream) = stream {
+            self.serve(stream);
+        }
+    }
+
+    // Les connexions TLS ayant négocié "h2" par ALPN sont servies en HTTP/2
+    fn serve(&mut self, stream: Stream) {
+        // Adresse de destination réelle, même pour une socket liée à 0.0.0.0
+        let Some(servers) = virtual_hosts(&self.config, &stream) else {
+            return;
+        };
+        if stream.alpn_protocol().as_deref() == Some(b"h2") {
+            self.http2.open(stream, Vec::new(), &servers[0], None, &mut self.upstreams, &mut self.relays);
+        } else {
+            handle_client(
+                stream,
+                &servers,
+                &mut self.upstreams,
+                &mut self.relays,
+                &mut self.websockets,
+                &mut self.events,
+                &mut self.http2,
+            );
+        }
+        // Un échange peut se terminer dès son ouverture
+        self.hand_off();
+    }
+
+    // Relit et valide la configuration puis la remplace d'un seul coup; la
+    // configuration courante est conservée si la nouvelle est invalide ou si
+    // un nouveau port ne peut pas être ouvert
+    fn reload(&mut self, listeners: &mut Listeners) -> bool {
+        let path = match &self.config_path {
+            Some(path) => path.clone(),
+            None => return false,
+        };
+        println!("Reloading configuration from {}", path);
+        systemd::notify_reloading();
+        let reloaded = self.apply_config(&path, listeners);
+        systemd::notify("READY=1");
+        reloaded
+    }
+
+    fn apply_config(&mut self, path: &str, listeners: &mut Listeners) -> bool {
+        let config = match check_config(path) {
+            Ok(config) => config,
+            Err(errors) => {
+                for error in &errors {
+                    eprintln!("{}", error);
+                }
+                eprintln!("⚠️ Reload failed, keeping the current configuration⚠️");
+                return false;
+            }
+        };
+        // Les certificats sont relus, même si leurs chemins n'ont pas changé
+        let tls = match TlsContexts::load(&config) {
+            Ok(tls) => tls,
+            Err(e) => {
+                eprintln!("⚠️ Reload failed, keeping the current configuration: {}⚠️", e);
+                return false;
+            }
+        };
+        if let Err(e) = listeners.sync(&listen_specs(&config)) {
+            eprintln!("⚠️ Reload failed, keeping the current configuration: {}⚠️", e);
+            return false;
+        }
+        self.upstreams.update(&config.upstreams);
+        self.tls = tls;
+        self.config = config;
+        println!("Configuration reloaded");
+        true
+    }
+}
+
+// Sockets à écouter pour une configuration; une adresse partagée par
+// plusieurs serveurs prend les options du premier
+fn listen_specs(config: &Config) -> Vec<ListenSpec> {
+    let mut specs: Vec<ListenSpec> = Vec::new();
+    for server_config in &config.servers {
+        if !ok_count_redirect(&server_config.routes) || !ok_same_port(server_config) {
+            eprintln!("⚠️ Incorrect configuration {}⚠️", server_config.host_name);
+            continue;
+        }
+        let addrs = match server_config.listen_addrs() {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                eprintln!("⚠️ Incorrect configuration {}: {}⚠️", server_config.host_name, e);
                 continue;
             }
+        };
+        for addr in addrs {
+            if !specs.iter().any(|spec| spec.addr == addr) {
+                specs.push(ListenSpec {
+                    backlog: server_config.backlog,
+                    reuse_port: server_config.reuse_port,
+                    mode: server_config.socket_mode,
+                    ..ListenSpec::new(addr)
+                });
+            }
+        }
+    }
+    // Double pile par défaut, sauf si le même port est aussi écouté en IPv4:
+    // les deux sockets entreraient alors en conflit
+    let ipv4_ports: Vec<u16> = specs
+        .iter()
+        .filter_map(|spec| match spec.addr {
+            ListenAddr::Tcp(SocketAddr::V4(addr)) => Some(addr.port()),
+            _ => None,
+        })
+        .collect();
+    for spec in &mut specs {
+        if let ListenAddr::Tcp(SocketAddr::V6(addr)) = spec.addr {
+            spec.v6_only = ipv4_ports.contains(&addr.port());
+        }
+    }
+    specs
+}
 
-            for n in 0..nfds {
-                for (_i, listener) in listeners.iter().enumerate() {
-                    if events[n as usize].u64 == (listener.as_raw_fd() as u64) {
-                        match listener.accept() {
-                            Ok((stream, _)) => {
-                                if let Some(addr) = listener.local_addr().ok() {
-                                    let (config, addrs) = get_server(&self.config, addr);
-                                    handle_client(stream, &config, &addrs);
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("Error accepting connection: {:?}", e);
-                            }
-                        }
-                    }
+// Sockets d'écoute enregistrées dans epoll, indexées par adresse "ip:port"
+// ou "unix:chemin"
+struct Listeners {
+    epoll_fd: i32,
+    sockets: HashMap<String, Listener>,
+    inherited: HashMap<String, RawFd>, // sockets reçues du processus précédent
+    keep_socket_files: bool, // fichiers des sockets Unix repris par un nouveau processus
+    activated: HashSet<String>, // sockets de systemd, dont il gère les fichiers
+}
+
+impl Listeners {
+    fn new() -> Self {
+        Listeners {
+            epoll_fd: unsafe { epoll_create1(EPOLL_CLOEXEC) },
+            sockets: HashMap::new(),
+            inherited: HashMap::new(),
+            keep_socket_files: false,
+            activated: HashSet::new(),
+        }
+    }
+
+    fn open(&mut self, spec: &ListenSpec) -> io::Result<()> {
+        let addr = spec.addr.to_string();
+        let listener = match self.inherited.remove(&addr) {
+            // La socket est déjà liée et écoute: aucune connexion n'est refusée
+            Some(fd) => unsafe { Listener::from_inherited(&spec.addr, fd) },
+            None => spec.bind()?,
+        };
+        listener.set_nonblocking(true)?;
+        // Ajouter le listener à epoll sans EPOLLET (Level-Triggered par défaut)
+        let mut event = epoll_event {
+            events: EPOLLIN as u32,
+            u64: listener.as_raw_fd() as u64,
+        };
+        unsafe {
+            epoll_ctl(self.epoll_fd, EPOLL_CTL_ADD, listener.as_raw_fd(), &mut event);
+        }
+        self.sockets.insert(addr, listener);
+        Ok(())
+    }
+
+    // Ferme les sockets héritées qui ne sont plus dans la configuration
+    fn close_inherited(&mut self) {
+        for (addr, fd) in self.inherited.drain() {
+            println!("Closing inherited socket for {}", addr);
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+
+    fn close(&mut self, addr: &str) {
+        if let Some(listener) = self.sockets.remove(addr) {
+            unsafe {
+                epoll_ctl(self.epoll_fd, EPOLL_CTL_DEL, listener.as_raw_fd(), std::ptr::null_mut());
+            }
+            if !self.keep_socket_files && !self.activated.contains(addr) {
+                listener.remove_socket_file();
+            }
+            println!("Server stopped listening on http://{}", addr);
+        }
+    }
+
+    fn find(&self, fd: u64) -> Option<(&str, &Listener)> {
+        self.sockets
+            .iter()
+            .find(|(_, listener)| listener.as_raw_fd() as u64 == fd)
+            .map(|(addr, listener)| (addr.as_str(), listener))
+    }
+
+    // Ouvre les adresses ajoutées puis ferme celles retirées. Si une adresse
+    // ne peut pas être ouverte, les sockets déjà ouvertes sont refermées et
+    // les écoutes existantes restent inchangées. Les options d'une adresse
+    // déjà ouverte ne sont pas réappliquées.
+    fn sync(&mut self, specs: &[ListenSpec]) -> io::Result<()> {
+        let addrs: Vec<String> = specs.iter().map(|spec| spec.addr.to_string()).collect();
+        let added: Vec<&ListenSpec> = specs
+            .iter()
+            .filter(|spec| !self.sockets.contains_key(&spec.addr.to_string()))
+            .collect();
+        let mut opened: Vec<String> = Vec::new();
+        for spec in added {
+            if let Err(e) = self.open(spec) {
+                for addr in &opened {
+                    self.close(addr);
+                }
+                return Err(io::Error::new(e.kind(), format!("{}: {}", spec.addr, e)));
+            }
+            println!("Server running on http://{}", spec.addr);
+            opened.push(spec.addr.to_string());
+        }
+        let removed: Vec<String> = self
+            .sockets
+            .keys()
+            .filter(|addr| !addrs.contains(addr))
+            .cloned()
+            .collect();
+        for addr in removed {
+            self.close(&addr);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Listeners {
+    fn drop(&mut self) {
+        if !self.keep_socket_files {
+            for (addr, listener) in &self.sockets {
+                if !self.activated.contains(addr) {
+                    listener.remove_socket_file();
+                }
+            }
+        }
+        unsafe {
+            libc::close(self.epoll_fd);
+        }
+    }
+}
+
+// Sockets transmises par le processus précédent: "127.0.0.1:8080=3,[::1]:8443=4"
+fn inherited_fds() -> HashMap<String, RawFd> {
+    let value = match env::var(INHERITED_FDS_ENV) {
+        Ok(value) => value,
+        Err(_) => return HashMap::new(),
+    };
+    env::remove_var(INHERITED_FDS_ENV);
+    value
+        .split(',')
+        .filter_map(|entry| entry.rsplit_once('='))
+        .filter_map(|(addr, fd)| Some((addr.to_string(), fd.parse().ok()?)))
+        .collect()
+}
+
+// Le nouveau processus prévient l'ancien qu'il écoute, l'ancien s'arrête alors proprement
+fn notify_upgrade_parent() {
+    if let Ok(pid) = env::var(UPGRADE_PARENT_ENV) {
+        env::remove_var(UPGRADE_PARENT_ENV);
+        if let Ok(pid) = pid.parse::<libc::pid_t>() {
+            unsafe {
+                libc::kill(pid, libc::SIGTERM);
+            }
+        }
+    }
+}
+
+// Relance le binaire avec les mêmes arguments en lui transmettant les sockets
+// d'écoute, qui restent ouvertes pendant toute la mise à jour
+fn spawn_upgrade(listeners: &Listeners) -> io::Result<Child> {
+    let exe = env::current_exe()?;
+    // Le binaire remplacé sur disque apparaît comme "... (deleted)"
+    let exe = PathBuf::from(exe.to_string_lossy().trim_end_matches(" (deleted)"));
+    let fds: Vec<RawFd> = listeners.sockets.values().map(|l| l.as_raw_fd()).collect();
+    let inherited: Vec<String> = listeners
+        .sockets
+        .iter()
+        .map(|(addr, listener)| format!("{}={}", addr, listener.as_raw_fd()))
+        .collect();
+    let mut command = Command::new(exe);
+    command
+        .args(env::args().skip(1))
+        .env(INHERITED_FDS_ENV, inherited.join(","))
+        .env(UPGRADE_PARENT_ENV, process::id().to_string());
+    unsafe {
+        // Exécuté dans le processus fils juste avant exec: les sockets
+        // d'écoute ne doivent pas être fermées par FD_CLOEXEC
+        command.pre_exec(move || {
+            for fd in &fds {
+                if libc::fcntl(*fd, libc::F_SETFD, 0) == -1 {
+                    return Err(io::Error::last_os_error());
                 }
             }
+            Ok(())
+        });
+    }
+    command.spawn()
+}
+
+// Choisit le serveur d'après l'adresse de destination de la connexion: une
+// adresse exacte l'emporte sur une adresse générique (0.0.0.0 ou [::])
+fn get_server(config: &Config, addr: SocketAddr) -> ConfigServer {
+    // Une connexion IPv4 reçue sur une socket [::] apparaît comme ::ffff:a.b.c.d
+    let ip = addr.ip().to_canonical();
+    let mut wildcard = None;
+    for server in &config.servers {
+        for listen in server.listen_addrs().unwrap_or_default() {
+            let listen = match listen {
+                ListenAddr::Tcp(listen) if listen.port() == addr.port() => listen,
+                _ => continue,
+            };
+            if listen.ip() == ip {
+                return server.clone();
+            }
+            if listen.ip().is_unspecified() && wildcard.is_none() {
+                wildcard = Some(server);
+            }
         }
     }
+    wildcard.cloned().unwrap_or_else(empty_server)
 }
 
-fn get_server(config: &Config, addr: SocketAddr) -> (ConfigServer, String) {
-    let host = addr.to_string();
-    let ip = host.split(":").collect::<Vec<&str>>()[0];
-    for server in config.servers.clone() {
-        if server.host == ip {
-            return (server, host);
-        }
-    }
-    (
-        ConfigServer {
-            host_name: String::new(),
-            host: String::new(),
-            ports: Vec::new(),
-            root: String::new(),
-            error_pages: HashMap::new(),
-            client_body_limit: 0,
-            routes: Vec::new(),
-            cgi_extensions: HashMap::new(),
-            directory_listing: false,
-        },
-        host,
-    )
+fn get_unix_server(config: &Config, path: &Path) -> ConfigServer {
+    let addr = ListenAddr::Unix(path.to_path_buf());
+    config
+        .servers
+        .iter()
+        .find(|server| server.listen_addrs().unwrap_or_default().contains(&addr))
+        .cloned()
+        .unwrap_or_else(empty_server)
 }
 
-fn handle_client(mut stream: TcpStream, config: &ConfigServer, addr: &str) {
+fn server_for(config: &Config, stream: &Stream) -> Option<ConfigServer> {
+    let local = stream.local_addr()?;
+    // En TLS, le nom demandé par le client (SNI) départage les serveurs qui
+    // partagent l'adresse
+    if let Some(name) = stream.server_name() {
+        let named = config.servers.iter().find(|server| {
+            server.host_name.trim().eq_ignore_ascii_case(&name)
+                && server.listen_addrs().unwrap_or_default().iter().any(|listen| serves(listen, &local))
+        });
+        if let Some(server) = named {
+            return Some(server.clone());
+        }
+    }
+    match local {
+        ListenAddr::Tcp(addr) => Some(get_server(config, addr)),
+        ListenAddr::Unix(path) => Some(get_unix_server(config, &path)),
+    }
+}
+
+// Serveurs entre lesquels l'en-tête Host choisit: celui désigné par
+// l'adresse (ou par SNI) d'abord, puis les autres serveurs de l'adresse
+fn virtual_hosts(config: &Config, stream: &Stream) -> Option<Vec<ConfigServer>> {
+    let first = server_for(config, stream)?;
+    let mut servers = Vec::new();
+    if stream.server_name().is_none() {
+        let local = stream.local_addr()?;
+        servers.extend(config.servers.iter().filter(|server| {
+            !server.host_name.trim().eq_ignore_ascii_case(first.host_name.trim())
+                && server.listen_addrs().unwrap_or_default().iter().any(|listen| serves(listen, &local))
+        }).cloned());
+    }
+    servers.insert(0, first);
+    Some(servers)
+}
+
+// Serveur dont le host_name correspond à l'en-tête Host, port exclu
+fn virtual_host<'a>(servers: &'a [ConfigServer], headers: &HashMap<String, String>) -> &'a ConfigServer {
+    let host = headers
+        .iter()
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("Host"))
+        .map(|(_, value)| value.trim());
+    let name = host.map(|host| match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    });
+    name.and_then(|name| servers.iter().find(|server| server.host_name.trim().eq_ignore_ascii_case(name)))
+        .unwrap_or(&servers[0])
+}
+
+// Une adresse d'écoute reçoit les connexions destinées à `local`
+fn serves(listen: &ListenAddr, local: &ListenAddr) -> bool {
+    match (listen, local) {
+        (ListenAddr::Tcp(listen), ListenAddr::Tcp(local)) => {
+            listen.port() == local.port()
+                && (listen.ip().is_unspecified() || listen.ip() == local.ip().to_canonical())
+        }
+        _ => listen == local,
+    }
+}
+
+fn empty_server() -> ConfigServer {
+    ConfigServer {
+        host_name: String::new(),
+        host: String::new(),
+        client_body_limit: 0,
+        ..Default::default()
+    }
+}
+
+// Route couvrant le chemin de la requête, une fois les règles de réécriture
+// appliquées; les redirections restent traitées par le Router
+pub fn resolved_route(config: &ConfigServer, resource: &Resource) -> Option<(Route, RouteMatch, String)> {
+    let Resource::Path(url) = resource;
+    let Resolution::Path(path) = resolve(&config.redirects, url) else {
+        return None;
+    };
+    let found = match_route(config, &path);
+    match find_route(config, &found.alias) {
+        (true, route) => Some((route, found, path)),
+        _ => None,
+    }
+}
+
+fn handle_client(
+    stream: impl Into<Stream>,
+    servers: &[ConfigServer],
+    upstreams: &mut Upstreams,
+    relays: &mut Relays,
+    websockets: &mut WebSockets,
+    events: &mut EventStreams,
+    http2: &mut Http2Connections,
+) {
+    let mut stream = stream.into();
+    // Serveur par défaut de l'adresse, jusqu'à la lecture de l'en-tête Host
+    let config = &servers[0];
     stream.set_read_timeout(Some(Duration::new(10, 0))).expect("faild to set_read_timeout");
     stream
         .set_write_timeout(Some(Duration::new(10, 0)))
@@ -121,6 +700,13 @@ fn handle_client(mut stream: TcpStream, config: &ConfigServer, addr: &str) {
     let mut parsed_version = Version::V1_1;
     let mut parsed_resource = Resource::Path("".to_string());
     let mut parsed_msg_body = Vec::new();
+    let mut parsed_multipart = None;
+    // Seule la première ligne est une ligne de requête: un en-tête comme
+    // HTTP2-Settings contient aussi "HTTP"
+    let mut request_line_read = false;
+    // Un en-tête Host et un seul est exigé en HTTP/1.1
+    let mut host_count = 0;
+    let mut malformed = false;
 
     let mut buff = BufReader::new(&stream);
     let mut read_buffer = String::new();
@@ -136,13 +722,32 @@ fn handle_client(mut stream: TcpStream, config: &ConfigServer, addr: &str) {
                     // Fin des en-têtes
                     break;
                 }
-                if read_buffer.contains("HTTP") {
-                    let (method, resource, version) = process_req_line(&read_buffer);
-                    parsed_method = method;
-                    parsed_version = version;
-                    parsed_resource = resource;
+                // Préface HTTP/2 en clair: h2c sans passage par Upgrade
+                if !request_line_read && read_buffer == "PRI * HTTP/2.0\r\n" {
+                    let mut inbound = read_buffer.into_bytes();
+                    inbound.extend_from_slice(buff.buffer());
+                    drop(buff);
+                    http2.open(stream, inbound, config, None, upstreams, relays);
+                    return;
+                }
+                if !request_line_read && read_buffer.contains("HTTP") {
+                    request_line_read = true;
+                    match process_req_line(&read_buffer) {
+                        Ok((method, resource, version)) => {
+                            parsed_method = method;
+                            parsed_version = version;
+                            parsed_resource = resource;
+                        }
+                        Err(e) => {
+                            eprintln!("Invalid request: {}", e);
+                            malformed = true;
+                        }
+                    }
                 } else if read_buffer.contains(":") {
                     let (key, value) = process_header_line(&read_buffer);
+                    if key.trim().eq_ignore_ascii_case("Host") {
+                        host_count += 1;
+                    }
                     parsed_headers.insert(key, value);
                 }
                 read_buffer.clear();
@@ -184,6 +789,135 @@ fn handle_client(mut stream: TcpStream, config: &ConfigServer, addr: &str) {
         }
     }
 
+    let config = virtual_host(servers, &parsed_headers);
+
+    // Seuls HTTP/1.0 et HTTP/1.1 sont servis avec cette syntaxe; HTTP/2 passe
+    // par sa préface, ALPN ou Upgrade
+    let rejected = if !request_line_read {
+        None
+    } else if malformed {
+        Some("400")
+    } else if !matches!(parsed_version, Version::V1_0 | Version::V1_1) {
+        eprintln!("Unsupported HTTP version");
+        Some("505")
+    } else if parsed_version == Version::V1_1 && host_count != 1 {
+        eprintln!("Missing or duplicate Host header");
+        Some("400")
+    } else {
+        None
+    };
+    if let Some(status_code) = rejected {
+        let response = PageErrorHandler::error_response(config, status_code);
+        // Le client a pu fermer la connexion sans attendre la réponse
+        if let Err(e) = response.send_response(&mut stream) {
+            eprintln!("Failed to send response: {}", e);
+        }
+        // Fermeture de la connexion
+        match stream.shutdown(Shutdown::Both) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotConnected => {
+                // Ignorer cette erreur spécifique
+                eprintln!("Stream not connected: {:?}", e);
+            }
+            Err(e) => {
+                // Gérer les autres erreurs
+                eprintln!("Shutdown failed: {:?}", e);
+            }
+        }
+        return;
+    }
+    // Un client HTTP/1.0 ne peut pas changer de protocole: Upgrade est ignoré
+    if parsed_version == Version::V1_0 {
+        parsed_headers.retain(|key, _| !key.trim().eq_ignore_ascii_case("Upgrade"));
+    }
+
+    // Passage en HTTP/2 (h2c) d'une requête en clair sans corps; la réponse
+    // est envoyée sur le flux 1
+    let bodyless = parsed_headers.get("Content-Length").is_none_or(|length| length.trim() == "0")
+        && !parsed_headers.contains_key("Transfer-Encoding");
+    let cleartext = !matches!(stream, Stream::Tls(_));
+    if let Some(params) = http2::h2c_upgrade(&parsed_headers).filter(|_| bodyless && cleartext) {
+        let req = HttpRequest::new(
+            parsed_method,
+            parsed_version,
+            parsed_resource,
+            parsed_headers,
+            String::new(),
+        );
+        // Préface et trames éventuellement envoyées à la suite de la requête
+        let leftover = buff.buffer().to_vec();
+        drop(buff);
+        http2.open(stream, leftover, config, Some((req, params)), upstreams, relays);
+        return;
+    }
+
+    let target = resolved_route(config, &parsed_resource);
+    let max_file_size = target
+        .as_ref()
+        .and_then(|(route, ..)| route.upload.as_ref())
+        .and_then(|policy| policy.max_file_size);
+
+    // Passage en WebSocket: la connexion est ensuite servie par la boucle
+    // epoll. Sur une route relayée, les requêtes ordinaires vont à l'amont.
+    let upgrading = |route: &Route| {
+        route.websocket.as_ref().is_some_and(|policy| {
+            policy.mode != WebSocketMode::Proxy || is_upgrade(&parsed_headers)
+        })
+    };
+    if let Some((route, found, path)) = target.clone().filter(|(route, ..)| upgrading(route)) {
+        let mut req = HttpRequest::new(
+            parsed_method,
+            parsed_version,
+            Resource::Path(path),
+            parsed_headers,
+            String::new(),
+        );
+        req.params = found.params;
+        req.secure = !cleartext;
+        // Trames éventuellement reçues avec la poignée de main
+        let leftover = buff.buffer().to_vec();
+        drop(buff);
+        websocket::upgrade(&req, stream, leftover, config, &route, &found.prefix, upstreams, relays, websockets);
+        return;
+    }
+
+    // Flux d'événements: la connexion reste ouverte, servie par la boucle epoll
+    if let Some((route, found, path)) = target.clone().filter(|(route, ..)| route.sse.is_some()) {
+        let mut req = HttpRequest::new(
+            parsed_method,
+            parsed_version,
+            Resource::Path(path),
+            parsed_headers,
+            String::new(),
+        );
+        req.params = found.params;
+        req.secure = !cleartext;
+        // Début du corps publié, déjà lu avec les en-têtes
+        let leftover = buff.buffer().to_vec();
+        drop(buff);
+        sse::serve(&req, leftover, stream, config, &route, events);
+        return;
+    }
+
+    // Les routes proxy_pass transmettent le corps au serveur amont au fil de
+    // l'eau, depuis la boucle epoll
+    if let Some((route, found, path)) = target.filter(|(route, ..)| route.proxy_pass.is_some()) {
+        let mut req = HttpRequest::new(
+            parsed_method,
+            parsed_version,
+            Resource::Path(path),
+            parsed_headers,
+            String::new(),
+        );
+        req.params = found.params;
+        req.secure = !cleartext;
+        // Début du corps, déjà lu avec les en-têtes
+        let leftover = buff.buffer().to_vec();
+        drop(buff);
+        proxy::serve(&req, stream, leftover, config, &route, &found.prefix, upstreams, relays);
+        return;
+    }
+
     // Lire le corps de la requête
     if let Some(content_length_str) = parsed_headers.get("Content-Length") {
         if let Ok(content_length) = content_length_str.trim().parse::<usize>() {
@@ -206,16 +940,44 @@ fn handle_client(mut stream: TcpStream, config: &ConfigServer, addr: &str) {
                 }
                 return;
             }
+            // Le client attend notre accord avant d'envoyer le corps; un échec
+            // d'écriture se retrouve à la lecture
+            if content_length > 0 && expects_continue(&parsed_version, &parsed_headers) {
+                let _ = (&stream).write_all(CONTINUE);
+            }
+            // Les corps multipart sont analysés au fil de la lecture, les
+            // fichiers étant écrits directement sur disque
+            let mut multipart_parser = parsed_headers
+                .get("Content-Type")
+                .filter(|content_type| content_type.trim_start().starts_with("multipart/form-data"))
+                .and_then(|content_type| boundary(content_type))
+                .map(|boundary| {
+                    MultipartParser::new(&boundary, Some(&env::temp_dir())).with_max_file_size(max_file_size)
+                });
+            let mut multipart_valid = true;
+            let mut file_too_large = false;
             let mut total_bytes_read = 0;
+            let mut buffer = vec![0; content_length.min(BODY_CHUNK_SIZE)];
             while total_bytes_read < content_length {
-                let mut buffer = vec![0; content_length];
-                match buff.read(&mut buffer) {
+                let to_read = (content_length - total_bytes_read).min(buffer.len());
+                match buff.read(&mut buffer[..to_read]) {
                     Ok(bytes_read) => {
                         if bytes_read == 0 {
                             break;
                         }
                         total_bytes_read += bytes_read;
-                        parsed_msg_body.extend_from_slice(&buffer[..bytes_read]);
+                        match multipart_parser.as_mut() {
+                            Some(parser) => {
+                                if let Err(e) = parser.feed(&buffer[..bytes_read]) {
+                                    // Un fichier trop gros interrompt la lecture du corps
+                                    eprintln!("Invalid multipart body: {}", e);
+                                    file_too_large = e.kind() == io::ErrorKind::FileTooLarge;
+                                    multipart_valid = false;
+                                    break;
+                                }
+                            }
+                            None => parsed_msg_body.extend_from_slice(&buffer[..bytes_read]),
+                        }
                     }
                     Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
                         eprintln!("Timeout reading from stream");
@@ -254,10 +1016,23 @@ fn handle_client(mut stream: TcpStream, config: &ConfigServer, addr: &str) {
                 }
             }
 
+            if multipart_valid {
+                parsed_multipart = match multipart_parser.map(|parser| parser.finish()) {
+                    Some(Ok(multipart)) => Some(multipart),
+                    Some(Err(e)) => {
+                        eprintln!("Invalid multipart body: {}", e);
+                        multipart_valid = false;
+                        None
+                    }
+                    None => None,
+                };
+            }
+
             // Vérifier si le corps de la requête est complet
-            if total_bytes_read < content_length {
+            if total_bytes_read < content_length || !multipart_valid {
                 eprintln!("unexpected end of request body");
-                let response = PageErrorHandler::error_response(config, "400");
+                let status_code = if file_too_large { "413" } else { "400" };
+                let response = PageErrorHandler::error_response(config, status_code);
                 response.send_response(&mut stream).expect("faild to send_response");
                 // Fermeture de la connexion
                 match stream.shutdown(Shutdown::Both) {
@@ -300,7 +1075,31 @@ fn handle_client(mut stream: TcpStream, config: &ConfigServer, addr: &str) {
         parsed_headers,
         String::from_utf8_lossy(&parsed_msg_body).into_owned(), // Convertir le corps de la requête en String
     );
-    Router::route(req, &mut stream, config, parsed_msg_body, addr);
+    let mut req = match parsed_multipart {
+        Some(multipart) => req.with_multipart(multipart),
+        None => req,
+    };
+    req.secure = !cleartext;
+    // Décoder les formulaires urlencoded et les corps JSON
+    if let Err(e) = req.decode_body(&parsed_msg_body) {
+        eprintln!("Invalid request body: {}", e);
+        let response = PageErrorHandler::error_response(config, "400");
+        response.send_response(&mut stream).expect("faild to send_response");
+        // Fermeture de la connexion
+        match stream.shutdown(Shutdown::Both) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotConnected => {
+                // Ignorer cette erreur spécifique
+                eprintln!("Stream not connected: {:?}", e);
+            }
+            Err(e) => {
+                // Gérer les autres erreurs
+                eprintln!("Shutdown failed: {:?}", e);
+            }
+        }
+        return;
+    }
+    Router::route(req, &mut stream, config, parsed_msg_body);
     // Fermeture de la connexion
     match stream.shutdown(Shutdown::Both) {
         Ok(_) => {}
@@ -320,31 +1119,29 @@ mod tests {
     use crate::config::Route;
 
     use super::*;
-    use std::net::{SocketAddr, TcpStream};
+    use crate::testing::temp_dir;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
     use std::io::Cursor;
     use std::thread;
     use std::time::Duration;
 
     fn setup_config() -> Config {
         Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
             servers: vec![ConfigServer {
-                host_name: String::from("localhost"),
-                host: String::from("127.0.0.1"),
                 ports: vec![8080],
                 root: String::from("/public"),
-                error_pages: HashMap::new(),
                 client_body_limit: 1024,
                 routes: vec![Route {
                     alias: "/test".to_string(),
                     pages: vec!["index.html".to_string()],
                     default_page: "index.html".to_string(),
-                    check_cookie: false,
-                    redirect: None,
                     links: vec!["/index.html".to_string()],
                     methods: vec!["GET".to_string(), "POST".to_string()],
+                    ..Default::default()
                 }],
-                cgi_extensions: HashMap::new(),
-                directory_listing: false,
+                ..Default::default()
             }],
         }
     }
@@ -353,7 +1150,7 @@ mod tests {
     fn test_get_server() {
         let config = setup_config();
         let addr: SocketAddr = "127.0.0.1:8080".parse().expect("Invalid address");
-        let (server_config, _) = get_server(&config, addr);
+        let server_config = get_server(&config, addr);
 
         assert_eq!(server_config.host, "127.0.0.1");
         assert_eq!(server_config.ports, vec![8080]);
@@ -362,8 +1159,6 @@ mod tests {
     #[test]
     fn test_handle_client_get_request() {
         let config = setup_config().servers[0].clone();
-        let addr = "127.0.0.1:8080".to_string();
-
         let mut stream = Cursor::new(Vec::new());
         let request = b"GET /test/index.html HTTP/1.1\r\nHost: localhost\r\n\r\n";
 
@@ -371,7 +1166,7 @@ mod tests {
         stream.set_position(0);
 
         let tcp_stream = TcpStream::connect("127.0.0.1:8080").unwrap();
-        handle_client(tcp_stream, &config, &addr);
+        handle_client(tcp_stream, std::slice::from_ref(&config), &mut Upstreams::default(), &mut Relays::default(), &mut WebSockets::default(), &mut EventStreams::default(), &mut Http2Connections::default());
 
         stream.set_position(0);
         let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
@@ -381,8 +1176,6 @@ mod tests {
     #[test]
     fn test_handle_client_post_request_exceeds_body_limit() {
         let config = setup_config().servers[0].clone();
-        let addr = "127.0.0.1:8080".to_string();
-
         let mut stream = Cursor::new(Vec::new());
         let request = format!(
             "POST /test/ HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
@@ -394,7 +1187,7 @@ mod tests {
         stream.set_position(0);
 
         let tcp_stream = TcpStream::connect("127.0.0.1:8080").unwrap();
-        handle_client(tcp_stream, &config, &addr);
+        handle_client(tcp_stream, std::slice::from_ref(&config), &mut Upstreams::default(), &mut Relays::default(), &mut WebSockets::default(), &mut EventStreams::default(), &mut Http2Connections::default());
 
         stream.set_position(0);
         let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
@@ -404,8 +1197,6 @@ mod tests {
     #[test]
     fn test_handle_client_timeout() {
         let config = setup_config().servers[0].clone();
-        let addr = "127.0.0.1:8080".to_string();
-
         let mut stream = Cursor::new(Vec::new());
         let request = b"GET /test/index.html HTTP/1.1\r\nHost: localhost\r\n\r\n";
 
@@ -416,13 +1207,272 @@ mod tests {
         tcp_stream
             .set_read_timeout(Some(Duration::from_secs(1)))
             .unwrap();
-        handle_client(tcp_stream, &config, &addr);
+        handle_client(tcp_stream, std::slice::from_ref(&config), &mut Upstreams::default(), &mut Relays::default(), &mut WebSockets::default(), &mut EventStreams::default(), &mut Http2Connections::default());
 
         stream.set_position(0);
         let response = String::from_utf8(stream.into_inner()).expect("Response not valid UTF-8");
         assert!(response.contains("408 Request Timeout"));
     }
 
+    // Envoie `head` puis, après la première réponse si `body` est donné,
+    // le corps; retourne tout ce que le client a reçu
+    fn exchange(head: &'static str, body: Option<&'static str>) -> String {
+        exchange_with(&setup_config().servers[..1], head, body)
+    }
+
+    fn exchange_with(servers: &[ConfigServer], head: &'static str, body: Option<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.write_all(head.as_bytes()).unwrap();
+            let mut received = String::new();
+            if let Some(body) = body {
+                let mut interim = [0; 25];
+                client.read_exact(&mut interim).unwrap();
+                received.push_str(std::str::from_utf8(&interim).unwrap());
+                client.write_all(body.as_bytes()).unwrap();
+            }
+            client.read_to_string(&mut received).unwrap();
+            received
+        });
+        let (stream, _) = listener.accept().unwrap();
+        handle_client(stream, servers, &mut Upstreams::default(), &mut Relays::default(), &mut WebSockets::default(), &mut EventStreams::default(), &mut Http2Connections::default());
+        client.join().unwrap()
+    }
+
+    #[test]
+    fn test_handle_client_virtual_hosts() {
+        let mut config = setup_config();
+        let mut other = config.servers[0].clone();
+        other.host_name = "other".to_string();
+        other.client_body_limit = 4;
+        config.servers.push(other);
+
+        let head = "POST /test/ HTTP/1.1\r\nHost: Other:8080\r\nContent-Length: 5\r\n\r\nhello";
+        let response = exchange_with(&config.servers, head, None);
+        assert!(response.contains("413 Payload Too Large"), "{}", response);
+        // Un nom inconnu est servi par le premier serveur de l'adresse
+        let head = "POST /test/ HTTP/1.1\r\nHost: unknown\r\nContent-Length: 5\r\n\r\nhello";
+        let response = exchange_with(&config.servers, head, None);
+        assert!(!response.contains("413 Payload Too Large"), "{}", response);
+    }
+
+    #[test]
+    fn test_handle_client_versions() {
+        let response = exchange("GET /test/ HTTP/3.0\r\nHost: localhost\r\n\r\n", None);
+        assert!(response.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"));
+        let response = exchange("GET /test/ HTTP/2.0\r\nHost: localhost\r\n\r\n", None);
+        assert!(response.contains("505 HTTP Version Not Supported"));
+        // Host obligatoire et unique en HTTP/1.1, facultatif en HTTP/1.0
+        let response = exchange("GET /test/ HTTP/1.1\r\n\r\n", None);
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        let response = exchange("GET /test/ HTTP/1.1\r\nHost: a\r\nhost: b\r\n\r\n", None);
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        let response = exchange("GET /test/ HTTP/1.0\r\n\r\n", None);
+        assert!(!response.contains("400 Bad Request") && !response.contains("505"));
+        // Ligne de requête incomplète
+        let response = exchange("X HTTP\r\nHost: localhost\r\n\r\n", None);
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        let response = exchange("GET HTTP/1.1\r\nHost: localhost\r\n\r\n", None);
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn test_handle_client_expect_continue() {
+        let response = exchange(
+            "POST /test/ HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n",
+            Some("hello"),
+        );
+        assert!(response.starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 "));
+        // Corps refusé d'emblée: pas de 100 Continue
+        let response = exchange(
+            "POST /test/ HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5000\r\nExpect: 100-continue\r\n\r\n",
+            None,
+        );
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    }
+
+    fn spec(addr: &str) -> ListenSpec {
+        ListenSpec::new(ListenAddr::Tcp(addr.parse().unwrap()))
+    }
+
+    #[test]
+    fn test_listeners_sync() {
+        let mut listeners = Listeners::new();
+        let first = "127.0.0.1:38471".to_string();
+        let second = "127.0.0.1:38472".to_string();
+        listeners.sync(&[spec(&first)]).unwrap();
+        let fd = listeners.sockets[&first].as_raw_fd() as u64;
+
+        listeners.sync(&[spec(&first), spec(&second)]).unwrap();
+        // Les sockets conservées ne sont pas rouvertes
+        assert_eq!(listeners.sockets[&first].as_raw_fd() as u64, fd);
+        assert!(listeners.find(fd).is_some());
+
+        listeners.sync(&[spec(&second)]).unwrap();
+        assert!(!listeners.sockets.contains_key(&first));
+        assert!(TcpListener::bind(&first).is_ok());
+
+        // Une adresse impossible à ouvrir laisse les écoutes inchangées
+        let busy = TcpListener::bind("127.0.0.1:0").unwrap();
+        let busy_addr = busy.local_addr().unwrap().to_string();
+        assert!(listeners.sync(&[spec(&first), spec(&busy_addr)]).is_err());
+        assert_eq!(listeners.sockets.keys().collect::<Vec<_>>(), vec![&second]);
+        assert!(TcpListener::bind(&first).is_ok());
+    }
+
+    #[test]
+    fn test_get_server_by_destination() {
+        let mut config = setup_config();
+        let mut wildcard = config.servers[0].clone();
+        wildcard.host_name = "wildcard".to_string();
+        wildcard.ports = Vec::new();
+        wildcard.listen = vec!["0.0.0.0:9000".to_string(), "[::]:9443".to_string()];
+        let mut exact = config.servers[0].clone();
+        exact.host_name = "exact".to_string();
+        exact.ports = Vec::new();
+        exact.listen = vec!["127.0.0.2:9000".to_string(), "[::1]:9443".to_string()];
+        config.servers = vec![wildcard, exact];
+
+        let name = |addr: &str| get_server(&config, addr.parse().unwrap()).host_name;
+        assert_eq!(name("127.0.0.2:9000"), "exact");
+        assert_eq!(name("10.0.0.1:9000"), "wildcard");
+        assert_eq!(name("[::1]:9443"), "exact");
+        assert_eq!(name("[::ffff:10.0.0.1]:9443"), "wildcard");
+        assert_eq!(name("127.0.0.2:9001"), "");
+    }
+
+    #[test]
+    fn test_listen_specs() {
+        let mut config = setup_config();
+        let server = &mut config.servers[0];
+        server.listen = vec![
+            "0.0.0.0:9000".to_string(),
+            "[::]:9000".to_string(),
+            "[::]:9443".to_string(),
+            "127.0.0.1:8080".to_string(),
+        ];
+        server.backlog = 16;
+        server.reuse_port = true;
+        let specs = listen_specs(&config);
+        let addrs: Vec<String> = specs.iter().map(|spec| spec.addr.to_string()).collect();
+        assert_eq!(addrs, vec!["127.0.0.1:8080", "0.0.0.0:9000", "[::]:9000", "[::]:9443"]);
+        assert!(specs.iter().all(|spec| spec.backlog == 16 && spec.reuse_port));
+        // [::]:9000 cohabite avec 0.0.0.0:9000, [::]:9443 reste en double pile
+        assert!(specs[2].v6_only);
+        assert!(!specs[3].v6_only);
+    }
+
+    #[test]
+    fn test_unix_socket_routing_and_cleanup() {
+        let path = temp_dir("server_unix_test").join("server.sock");
+        let mut config = setup_config();
+        config.servers[0].ports = Vec::new();
+        config.servers[0].listen = vec![format!("unix:{}", path.display())];
+        assert_eq!(get_unix_server(&config, &path).host_name, "localhost");
+
+        let specs = listen_specs(&config);
+        let key = format!("unix:{}", path.display());
+        let mut listeners = Listeners::new();
+        listeners.sync(&specs).unwrap();
+        assert!(listeners.sockets.contains_key(&key));
+        let client = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        let stream = listeners.sockets[&key].accept().unwrap();
+        assert_eq!(server_for(&config, &stream).unwrap().host_name, "localhost");
+        drop(client);
+
+        // Le fichier est supprimé à la fermeture, sauf s'il est repris
+        listeners.sync(&[]).unwrap();
+        assert!(!path.exists());
+        listeners.sync(&specs).unwrap();
+        listeners.keep_socket_files = true;
+        drop(listeners);
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_activated_socket_is_used_and_kept() {
+        use std::os::unix::io::IntoRawFd;
+        use std::os::unix::net::UnixListener;
+        let path = temp_dir("server_activated").join("server.sock");
+        let fd = UnixListener::bind(&path).unwrap().into_raw_fd();
+        let key = format!("unix:{}", path.display());
+
+        let mut config = setup_config();
+        config.servers[0].ports = Vec::new();
+        config.servers[0].listen = vec![key.clone()];
+        let mut listeners = Listeners::new();
+        listeners.inherited.insert(key.clone(), fd);
+        listeners.activated.insert(key.clone());
+        listeners.sync(&listen_specs(&config)).unwrap();
+        assert_eq!(listeners.sockets[&key].as_raw_fd(), fd);
+
+        // Le fichier appartient à systemd: il survit à la fermeture
+        listeners.sync(&[]).unwrap();
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_listeners_open_inherited_socket() {
+        use std::os::unix::io::IntoRawFd;
+        let socket = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        let fd = socket.into_raw_fd();
+
+        let mut listeners = Listeners::new();
+        listeners.inherited.insert(addr.clone(), fd);
+        listeners.open(&spec(&addr)).unwrap();
+        // La socket héritée est réutilisée au lieu d'un nouveau bind
+        assert_eq!(listeners.sockets[&addr].as_raw_fd(), fd);
+        assert!(listeners.inherited.is_empty());
+        assert!(TcpStream::connect(&addr).is_ok());
+    }
+
+    #[test]
+    fn test_drain_closes_listeners_first() {
+        let mut config = setup_config();
+        config.drain_timeout = 5;
+        let mut listeners = Listeners::new();
+        listeners.open(&spec("127.0.0.1:38474")).unwrap();
+
+        let mut client = TcpStream::connect("127.0.0.1:38474").unwrap();
+        client.write_all(b"GET /test/ HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let started = Instant::now();
+        Server::new(config).drain(listeners);
+        // Sans connexion en cours, l'arrêt n'attend pas la fin du délai
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // La connexion restée en attente n'est plus servie
+        let mut response = String::new();
+        let _ = client.read_to_string(&mut response);
+        assert!(response.is_empty());
+        assert!(TcpStream::connect("127.0.0.1:38474").is_err());
+    }
+
+    #[test]
+    fn test_reload_keeps_config_when_invalid() {
+        let dir = temp_dir("server_reload_test");
+        let path = dir.join("config.toml");
+        let path_str = path.to_string_lossy().into_owned();
+        let mut server = Server::new(setup_config()).with_config_path(&path_str);
+        let mut listeners = Listeners::new();
+
+        std::fs::write(&path, "[[servers]]\nports = [38473]\nroot = \"/does/not/exist\"\n").unwrap();
+        assert!(!server.reload(&mut listeners));
+        assert_eq!(server.config.servers[0].ports, vec![8080]);
+        assert!(listeners.sockets.is_empty());
+
+        let valid = format!(
+            "[[servers]]\nhost_name = \"reloaded\"\nports = [38473]\nroot = \"{}\"\n",
+            dir.display()
+        );
+        std::fs::write(&path, valid).unwrap();
+        assert!(server.reload(&mut listeners));
+        assert_eq!(server.config.servers[0].host_name, "reloaded");
+        assert!(listeners.sockets.contains_key("127.0.0.1:38473"));
+    }
+
     #[test]
     fn test_server_run() {
         let config = setup_config();
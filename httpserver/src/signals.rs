@@ -0,0 +1,40 @@
+// signals.rs
+use libc::{c_int, sighandler_t, signal, SIGHUP, SIGINT, SIGTERM, SIGUSR2};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+static UPGRADE_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// Seules les opérations atomiques sont sûres dans un gestionnaire de signal
+extern "C" fn on_signal(sig: c_int) {
+    match sig {
+        SIGHUP => RELOAD_REQUESTED.store(true, Ordering::SeqCst),
+        SIGTERM | SIGINT => SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst),
+        SIGUSR2 => UPGRADE_REQUESTED.store(true, Ordering::SeqCst),
+        _ => {}
+    }
+}
+
+// SIGHUP recharge la configuration, SIGTERM/SIGINT arrêtent proprement le
+// serveur et SIGUSR2 lance un nouveau binaire qui reprend les sockets
+pub fn install() {
+    for sig in [SIGHUP, SIGTERM, SIGINT, SIGUSR2] {
+        unsafe {
+            signal(sig, on_signal as extern "C" fn(c_int) as sighandler_t);
+        }
+    }
+}
+
+// Chaque fonction retourne vrai une seule fois par signal reçu
+pub fn take_reload_request() -> bool {
+    RELOAD_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+pub fn take_shutdown_request() -> bool {
+    SHUTDOWN_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+pub fn take_upgrade_request() -> bool {
+    UPGRADE_REQUESTED.swap(false, Ordering::SeqCst)
+}
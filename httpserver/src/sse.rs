@@ -0,0 +1,708 @@
+// sse.rs
+use crate::auth::authorize;
+use crate::csrf::verify_csrf;
+use crate::config::{ConfigServer, Route};
+use crate::handler::{PageErrorHandler, StaticPageHandler};
+use crate::listener::Stream;
+use crate::upstream::watch;
+use http::httprequest::{expects_continue, parse_urlencoded, HttpRequest, Method, Resource};
+use http::httpresponse::{HttpResponse, CONTINUE};
+use libc::{EPOLLIN, EPOLLOUT, EPOLLRDHUP, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, prelude::*};
+use std::net::Shutdown;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::process::{Child, ChildStdout, Stdio};
+use std::time::{Duration, Instant};
+
+// Octets lus sur la sortie d'un script par événement epoll
+const READ_CHUNK: usize = 16 * 1024;
+
+// Données en attente d'envoi au-delà desquelles la sortie du script n'est
+// plus lue; un abonné de canal aussi lent est déconnecté et reprendra grâce
+// à Last-Event-ID
+const MAX_PENDING: usize = 1024 * 1024;
+
+// Section `sse` d'une route: flux `text/event-stream` alimenté par la sortie
+// d'un script CGI ou par un canal interne
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct EventSource {
+    pub script: Option<String>,  // script lancé pour chaque client, relatif à root
+    pub channel: Option<String>, // canal interne; un POST sur la route y publie un événement
+    pub heartbeat: u64,          // secondes entre deux commentaires de maintien, 0 pour aucun
+    pub history: usize,          // événements du canal conservés pour Last-Event-ID
+    pub retry: Option<u64>,      // délai de reconnexion suggéré au client, en millisecondes
+}
+
+impl Default for EventSource {
+    fn default() -> Self {
+        EventSource {
+            script: None,
+            channel: None,
+            heartbeat: 15,
+            history: 100,
+            retry: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Event {
+    id: u64,
+    name: Option<String>,
+    data: String,
+}
+
+impl Event {
+    fn encode(&self) -> String {
+        let mut text = format!("id: {}\n", self.id);
+        if let Some(name) = &self.name {
+            text.push_str(&format!("event: {}\n", name));
+        }
+        for line in self.data.lines() {
+            text.push_str(&format!("data: {}\n", line));
+        }
+        if self.data.is_empty() {
+            text.push_str("data\n");
+        }
+        text.push('\n');
+        text
+    }
+}
+
+// Événements récents d'un canal
+#[derive(Debug, Default)]
+struct Channel {
+    next_id: u64,
+    history: VecDeque<Event>,
+}
+
+// Convertit la sortie d'un script en champs SSE: les lignes `data:`,
+// `event:`, `id:`, `retry:` et les commentaires sont transmis tels quels, une
+// autre ligne devient un événement à elle seule
+#[derive(Debug, Default)]
+pub struct ScriptOutput {
+    partial: Vec<u8>,
+}
+
+impl ScriptOutput {
+    pub fn feed(&mut self, bytes: &[u8]) -> String {
+        self.partial.extend_from_slice(bytes);
+        let mut text = String::new();
+        while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            let is_field = line.is_empty()
+                || line.starts_with(':')
+                || ["data", "event", "id", "retry"]
+                    .iter()
+                    .any(|field| line == *field || line.starts_with(&format!("{}:", field)));
+            if is_field {
+                text.push_str(line);
+                text.push('\n');
+            } else {
+                text.push_str(&format!("data: {}\n\n", line));
+            }
+        }
+        text
+    }
+}
+
+struct Script {
+    child: Child,
+    stdout: ChildStdout,
+    output: ScriptOutput,
+}
+
+struct Subscriber {
+    stream: Stream,
+    outbound: Vec<u8>,
+    script: Option<Script>,
+    channel: Option<String>,
+    heartbeat: u64,
+    last_sent: Instant,
+    closing: bool, // fin du flux: la connexion se termine une fois les données transmises
+}
+
+impl Subscriber {
+    fn queue(&mut self, text: &str) {
+        self.outbound.extend_from_slice(text.as_bytes());
+    }
+
+    // Envoie ce qui peut l'être sans bloquer
+    fn send(&mut self) -> io::Result<()> {
+        while !self.outbound.is_empty() {
+            match (&self.stream).write(&self.outbound) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.outbound.drain(..n);
+                    self.last_sent = Instant::now();
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Flux d'événements ouverts, servis par la boucle epoll du serveur
+#[derive(Default)]
+pub struct EventStreams {
+    subscribers: HashMap<RawFd, Subscriber>, // par descripteur du client
+    pipes: HashMap<RawFd, RawFd>,            // sortie d'un script -> descripteur du client
+    channels: HashMap<String, Channel>,
+    epoll_fd: Option<RawFd>,
+}
+
+// Requête sur une route `sse`: GET ouvre le flux, POST publie sur le canal.
+// `leftover` est le début du corps, lu avec les en-têtes.
+pub fn serve(
+    req: &HttpRequest,
+    leftover: Vec<u8>,
+    stream: Stream,
+    config: &ConfigServer,
+    route: &Route,
+    events: &mut EventStreams,
+) {
+    let source = route.sse.clone().unwrap_or_default();
+    let mut client = &stream;
+    let method = format!("{:?}", req.method).to_ascii_uppercase();
+    let allowed = route.methods.contains(&method)
+        && (req.method == Method::Get || (req.method == Method::Post && source.channel.is_some()));
+    if !allowed {
+        let _ = PageErrorHandler::error_response(config, "405").send_response(&mut client);
+        let _ = stream.shutdown(Shutdown::Both);
+        return;
+    }
+    if let Err(resp) = authorize(req, route, config) {
+        let _ = resp.send_response(&mut client);
+        let _ = stream.shutdown(Shutdown::Both);
+        return;
+    }
+    if req.method == Method::Post && !verify_csrf(req, route, None) {
+        let _ = PageErrorHandler::error_response(config, "403").send_response(&mut client);
+        let _ = stream.shutdown(Shutdown::Both);
+        return;
+    }
+    if req.method == Method::Post {
+        let mut body = io::Cursor::new(leftover).chain(&stream);
+        let resp = publish(req, &mut body, &mut client, config, &source, events);
+        let _ = resp.send_response(&mut client);
+        let _ = stream.shutdown(Shutdown::Both);
+        return;
+    }
+    let last_event_id = header(req, "Last-Event-ID").and_then(|id| id.parse::<u64>().ok());
+    let mut head = format!(
+        "HTTP/1.1 200 OK\r\nServer:{}\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        config.host_name
+    );
+    if let Some(retry) = source.retry {
+        head.push_str(&format!("retry: {}\n\n", retry));
+    }
+    let script = match &source.script {
+        Some(script) => match spawn(script, req, config) {
+            Some(script) => Some(script),
+            None => {
+                let _ = PageErrorHandler::error_response(config, "500").send_response(&mut client);
+                let _ = stream.shutdown(Shutdown::Both);
+                return;
+            }
+        },
+        None => None,
+    };
+    if client.write_all(head.as_bytes()).is_ok() {
+        events.subscribe(stream, &source, script, last_event_id);
+    }
+}
+
+// Le corps de la requête est la donnée de l'événement, `?event=nom` son type
+fn publish<'a>(
+    req: &HttpRequest,
+    body: &mut impl Read,
+    client: &mut impl Write,
+    config: &ConfigServer,
+    source: &EventSource,
+    events: &mut EventStreams,
+) -> HttpResponse<'a> {
+    let length = match header(req, "Content-Length").map(str::parse::<usize>) {
+        Some(Ok(length)) => length,
+        None => 0,
+        Some(Err(_)) => return PageErrorHandler::error_response(config, "400"),
+    };
+    if length > config.client_body_limit {
+        return PageErrorHandler::error_response(config, "413");
+    }
+    if length > 0 && expects_continue(&req.version, &req.headers) {
+        let _ = client.write_all(CONTINUE);
+    }
+    let mut data = vec![0; length];
+    if body.read_exact(&mut data).is_err() {
+        return PageErrorHandler::error_response(config, "400");
+    }
+    let Ok(data) = String::from_utf8(data) else {
+        return PageErrorHandler::error_response(config, "400");
+    };
+    let Resource::Path(url) = &req.resource;
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+    let name = parse_urlencoded(query).get("event").and_then(|names| names.first().cloned());
+    if name.as_deref().is_some_and(|name| name.contains(['\r', '\n'])) {
+        return PageErrorHandler::error_response(config, "400");
+    }
+    let channel = source.channel.as_deref().unwrap_or_default();
+    let (id, subscribers) = events.publish(channel, name, data, source.history);
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type", "application/json");
+    let body = serde_json::json!({ "id": id, "subscribers": subscribers }).to_string();
+    HttpResponse::new("200", config.host_name.clone(), Some(headers), Some(body))
+}
+
+// Le script écrit sur sa sortie standard au fil de l'eau; Last-Event-ID lui
+// est transmis dans HTTP_LAST_EVENT_ID
+fn spawn(script: &str, req: &HttpRequest, config: &ConfigServer) -> Option<Script> {
+    let mut command = StaticPageHandler::cgi_command(script, req, 0, config)?;
+    command
+        .env("HTTP_LAST_EVENT_ID", header(req, "Last-Event-ID").unwrap_or_default())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("Failed to execute CGI script: {}", e);
+            return None;
+        }
+    };
+    let stdout = child.stdout.take()?;
+    set_nonblocking(stdout.as_raw_fd());
+    Some(Script {
+        child,
+        stdout,
+        output: ScriptOutput::default(),
+    })
+}
+
+fn set_nonblocking(fd: RawFd) {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+    }
+}
+
+fn header<'r>(req: &'r HttpRequest, name: &str) -> Option<&'r str> {
+    req.headers
+        .iter()
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+impl EventStreams {
+    pub fn attach(&mut self, epoll_fd: RawFd) {
+        self.epoll_fd = Some(epoll_fd);
+    }
+
+    // Ajoute un événement au canal et l'envoie à ses abonnés; retourne son
+    // identifiant et le nombre d'abonnés
+    pub fn publish(&mut self, channel: &str, name: Option<String>, data: String, history: usize) -> (u64, usize) {
+        let entry = self.channels.entry(channel.to_string()).or_default();
+        entry.next_id += 1;
+        let event = Event {
+            id: entry.next_id,
+            name,
+            data,
+        };
+        let text = event.encode();
+        entry.history.push_back(event);
+        while entry.history.len() > history {
+            entry.history.pop_front();
+        }
+        let id = entry.next_id;
+        let fds: Vec<RawFd> = self
+            .subscribers
+            .iter()
+            .filter(|(_, s)| s.channel.as_deref() == Some(channel) && !s.closing)
+            .map(|(fd, _)| *fd)
+            .collect();
+        for fd in &fds {
+            let Some(subscriber) = self.subscribers.get_mut(fd) else {
+                continue;
+            };
+            if subscriber.outbound.len() >= MAX_PENDING {
+                eprintln!("Event stream client too slow, closing");
+                self.teardown(*fd);
+                continue;
+            }
+            subscriber.queue(&text);
+            self.flush(*fd);
+        }
+        (id, fds.len())
+    }
+
+    fn subscribe(&mut self, stream: Stream, source: &EventSource, script: Option<Script>, last_event_id: Option<u64>) {
+        if let Err(e) = stream.set_nonblocking(true) {
+            return eprintln!("Event stream setup failed: {}", e);
+        }
+        let fd = stream.as_raw_fd();
+        let mut subscriber = Subscriber {
+            stream,
+            outbound: Vec::new(),
+            script,
+            channel: source.channel.clone(),
+            heartbeat: source.heartbeat,
+            last_sent: Instant::now(),
+            closing: false,
+        };
+        // Reprise: événements du canal postérieurs au dernier reçu
+        if let (Some(channel), Some(last)) = (&source.channel, last_event_id) {
+            if let Some(channel) = self.channels.get(channel) {
+                for event in channel.history.iter().filter(|event| event.id > last) {
+                    subscriber.queue(&event.encode());
+                }
+            }
+        }
+        watch(self.epoll_fd, EPOLL_CTL_ADD, fd, (EPOLLIN | EPOLLRDHUP) as u32);
+        if let Some(script) = &subscriber.script {
+            let pipe = script.stdout.as_raw_fd();
+            watch(self.epoll_fd, EPOLL_CTL_ADD, pipe, EPOLLIN as u32);
+            self.pipes.insert(pipe, fd);
+        }
+        self.subscribers.insert(fd, subscriber);
+        self.flush(fd);
+    }
+
+    // Événement epoll: false si le descripteur n'appartient pas à un flux
+    pub fn on_event(&mut self, token: u64) -> bool {
+        let fd = token as RawFd;
+        if let Some(client_fd) = self.pipes.get(&fd).copied() {
+            self.read_script(client_fd);
+            self.flush(client_fd);
+            return true;
+        }
+        let Some(subscriber) = self.subscribers.get_mut(&fd) else {
+            return false;
+        };
+        // Le client n'envoie plus rien: une lecture indique sa déconnexion
+        let mut chunk = [0; 512];
+        match (&subscriber.stream).read(&mut chunk) {
+            Ok(0) => self.teardown(fd),
+            Err(e) if !matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => {
+                self.teardown(fd)
+            }
+            _ => self.flush(fd),
+        }
+        true
+    }
+
+    fn read_script(&mut self, fd: RawFd) {
+        let Some(subscriber) = self.subscribers.get_mut(&fd) else {
+            return;
+        };
+        let Some(script) = subscriber.script.as_mut() else {
+            return;
+        };
+        let mut chunk = vec![0; READ_CHUNK];
+        match script.stdout.read(&mut chunk) {
+            Ok(0) => {
+                // Fin du script: le flux se termine, le client se reconnectera
+                let pipe = script.stdout.as_raw_fd();
+                let _ = script.child.wait();
+                watch(self.epoll_fd, EPOLL_CTL_DEL, pipe, 0);
+                self.pipes.remove(&pipe);
+                subscriber.script = None;
+                subscriber.closing = true;
+            }
+            Ok(n) => {
+                let text = script.output.feed(&chunk[..n]);
+                subscriber.queue(&text);
+            }
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => {}
+            Err(_) => subscriber.closing = true,
+        }
+    }
+
+    // Commentaire de maintien pour les flux sans envoi depuis `heartbeat`
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        let idle: Vec<RawFd> = self
+            .subscribers
+            .iter()
+            .filter(|(_, s)| s.heartbeat > 0 && s.outbound.is_empty() && !s.closing)
+            .filter(|(_, s)| now.duration_since(s.last_sent) >= Duration::from_secs(s.heartbeat))
+            .map(|(fd, _)| *fd)
+            .collect();
+        for fd in idle {
+            if let Some(subscriber) = self.subscribers.get_mut(&fd) {
+                subscriber.queue(": heartbeat\n\n");
+            }
+            self.flush(fd);
+        }
+    }
+
+    // Début de l'arrêt: chaque flux se termine une fois ses événements en
+    // attente transmis
+    pub fn shut_down(&mut self) {
+        let fds: Vec<RawFd> = self.subscribers.keys().cloned().collect();
+        for fd in fds {
+            if let Some(subscriber) = self.subscribers.get_mut(&fd) {
+                subscriber.closing = true;
+            }
+            self.flush(fd);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    // Fin du délai d'arrêt: les flux sont fermés après un dernier envoi
+    pub fn close_all(&mut self) {
+        let fds: Vec<RawFd> = self.subscribers.keys().cloned().collect();
+        for fd in fds {
+            if let Some(subscriber) = self.subscribers.get_mut(&fd) {
+                let _ = subscriber.send();
+            }
+            self.teardown(fd);
+        }
+    }
+
+    // Envoie ce qui peut l'être puis ajuste les événements surveillés
+    fn flush(&mut self, fd: RawFd) {
+        let Some(subscriber) = self.subscribers.get_mut(&fd) else {
+            return;
+        };
+        if subscriber.send().is_err() || (subscriber.closing && subscriber.outbound.is_empty()) {
+            return self.teardown(fd);
+        }
+        let mut events = (EPOLLIN | EPOLLRDHUP) as u32;
+        if !subscriber.outbound.is_empty() {
+            events |= EPOLLOUT as u32;
+        }
+        watch(self.epoll_fd, EPOLL_CTL_MOD, fd, events);
+        // La sortie du script n'est plus lue tant que le client est en retard
+        if let Some(script) = &subscriber.script {
+            let events = if subscriber.outbound.len() < MAX_PENDING { EPOLLIN as u32 } else { 0 };
+            watch(self.epoll_fd, EPOLL_CTL_MOD, script.stdout.as_raw_fd(), events);
+        }
+    }
+
+    fn teardown(&mut self, fd: RawFd) {
+        let Some(subscriber) = self.subscribers.remove(&fd) else {
+            return;
+        };
+        watch(self.epoll_fd, EPOLL_CTL_DEL, fd, 0);
+        let _ = subscriber.stream.shutdown(Shutdown::Both);
+        if let Some(mut script) = subscriber.script {
+            let pipe = script.stdout.as_raw_fd();
+            watch(self.epoll_fd, EPOLL_CTL_DEL, pipe, 0);
+            self.pipes.remove(&pipe);
+            let _ = script.child.kill();
+            let _ = script.child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use crate::csrf::CsrfPolicy;
+    use http::httprequest::Version;
+    use libc::{epoll_create1, epoll_event, epoll_wait, EPOLL_CLOEXEC};
+    use std::fs;
+    use std::net::{TcpListener, TcpStream};
+    use std::path::Path;
+
+    fn setup_config(root: &Path) -> ConfigServer {
+        ConfigServer {
+            root: root.to_string_lossy().into_owned(),
+            client_body_limit: 1024,
+            ..Default::default()
+        }
+    }
+
+    fn sse_route(source: EventSource) -> Route {
+        Route {
+            alias: "/events/".to_string(),
+            methods: vec!["GET".to_string(), "POST".to_string()],
+            sse: Some(source),
+            ..Default::default()
+        }
+    }
+
+    fn request(method: Method, path: &str, headers: &[(&str, &str)]) -> HttpRequest {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), format!(" {}\r\n", value)))
+            .collect();
+        HttpRequest::new(method, Version::V1_1, Resource::Path(path.to_string()), headers, String::new())
+    }
+
+    // Requête servie par `serve`; retourne la socket du client
+    fn open(req: &HttpRequest, body: &[u8], config: &ConfigServer, route: &Route, events: &mut EventStreams) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        serve(req, body.to_vec(), stream.into(), config, route, events);
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        client
+    }
+
+    fn pump(events: &mut EventStreams, epoll_fd: RawFd) {
+        let mut ready = vec![epoll_event { events: 0, u64: 0 }; 16];
+        loop {
+            let n = unsafe { epoll_wait(epoll_fd, ready.as_mut_ptr(), ready.len() as i32, 100) };
+            if n <= 0 {
+                return;
+            }
+            for event in &ready[..n as usize] {
+                assert!(events.on_event(event.u64));
+            }
+        }
+    }
+
+    // Lit jusqu'à obtenir `expected` ou la fin de la connexion
+    fn read_until(client: &mut TcpStream, expected: &str) -> String {
+        let mut received = Vec::new();
+        let mut chunk = [0; 1024];
+        while !String::from_utf8_lossy(&received).contains(expected) {
+            match client.read(&mut chunk) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => received.extend_from_slice(&chunk[..n]),
+            }
+        }
+        String::from_utf8(received).unwrap()
+    }
+
+    fn attached() -> (EventStreams, RawFd) {
+        let epoll_fd = unsafe { epoll_create1(EPOLL_CLOEXEC) };
+        let mut events = EventStreams::default();
+        events.attach(epoll_fd);
+        (events, epoll_fd)
+    }
+
+    #[test]
+    fn test_script_output() {
+        let mut output = ScriptOutput::default();
+        assert_eq!(output.feed(b"tick 1\nti"), "data: tick 1\n\n");
+        assert_eq!(output.feed(b"ck 2\r\n"), "data: tick 2\n\n");
+        assert_eq!(
+            output.feed(b"event: price\ndata: {\"eur\": 1}\nid: 7\n\n: comment\n"),
+            "event: price\ndata: {\"eur\": 1}\nid: 7\n\n: comment\n"
+        );
+    }
+
+    #[test]
+    fn test_event_encoding() {
+        let event = Event {
+            id: 3,
+            name: Some(String::from("update")),
+            data: String::from("a\nb"),
+        };
+        assert_eq!(event.encode(), "id: 3\nevent: update\ndata: a\ndata: b\n\n");
+    }
+
+    #[test]
+    fn test_channel_stream() {
+        let root = temp_dir("sse_channel_stream");
+        let config = setup_config(&root);
+        let route = sse_route(EventSource {
+            channel: Some(String::from("news")),
+            history: 2,
+            retry: Some(3000),
+            ..EventSource::default()
+        });
+        let (mut events, epoll_fd) = attached();
+        for data in ["one", "two", "three"] {
+            events.publish("news", None, data.to_string(), 2);
+        }
+
+        // Reprise après l'événement 2: seul le 3 est renvoyé
+        let req = request(Method::Get, "/events/", &[("Last-Event-ID", "2")]);
+        let mut client = open(&req, b"", &config, &route, &mut events);
+        let head = read_until(&mut client, "data: three\n\n");
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Type: text/event-stream\r\n"));
+        assert!(head.contains("\r\n\r\nretry: 3000\n\nid: 3\ndata: three\n\n"));
+        assert!(!head.contains("two"));
+
+        // Publication par POST
+        let req = request(Method::Post, "/events/?event=alert", &[("Content-Length", "5")]);
+        let mut publisher = open(&req, b"fire!", &config, &route, &mut events);
+        let response = read_until(&mut publisher, "}");
+        assert!(response.contains(r#"{"id":4,"subscribers":1}"#));
+        assert_eq!(read_until(&mut client, "\n\n"), "id: 4\nevent: alert\ndata: fire!\n\n");
+
+        // Publication forgée par un site tiers
+        let mut protected = route.clone();
+        protected.csrf = Some(CsrfPolicy {
+            token: false,
+            check_origin: true,
+            trusted_origins: vec![],
+            same_site: None,
+        });
+        let req = request(Method::Post, "/events/", &[("Host", "localhost:8080"), ("Origin", "http://evil.example")]);
+        let mut forger = open(&req, b"", &config, &protected, &mut events);
+        assert!(read_until(&mut forger, "\r\n").starts_with("HTTP/1.1 403"));
+
+        // Commentaire de maintien
+        events.subscribers.values_mut().for_each(|s| {
+            s.heartbeat = 1;
+            s.last_sent = Instant::now() - Duration::from_secs(2);
+        });
+        events.tick();
+        assert_eq!(read_until(&mut client, "\n\n"), ": heartbeat\n\n");
+
+        // Un abonné en retard est déconnecté
+        events.subscribers.values_mut().for_each(|s| s.outbound.resize(MAX_PENDING, b':'));
+        assert_eq!(events.publish("news", None, "late".to_string(), 2), (5, 1));
+        assert!(events.subscribers.is_empty());
+
+        // Le client qui part est retiré
+        let mut client = open(&request(Method::Get, "/events/", &[]), b"", &config, &route, &mut events);
+        read_until(&mut client, "\r\n\r\n");
+        drop(client);
+        pump(&mut events, epoll_fd);
+        assert!(events.subscribers.is_empty());
+        unsafe {
+            libc::close(epoll_fd);
+        }
+    }
+
+    #[test]
+    fn test_script_stream() {
+        let root = temp_dir("sse_script_stream");
+        fs::write(
+            root.join("ticker.py"),
+            "import os\nprint('resume ' + os.environ['HTTP_LAST_EVENT_ID'], flush=True)\nprint('event: done')\nprint('data: bye')\nprint()\n",
+        )
+        .unwrap();
+        let config = setup_config(&root);
+        let route = sse_route(EventSource {
+            script: Some(String::from("ticker.py")),
+            ..EventSource::default()
+        });
+        let (mut events, epoll_fd) = attached();
+        let req = request(Method::Get, "/events/", &[("Last-Event-ID", "41")]);
+        let mut client = open(&req, b"", &config, &route, &mut events);
+        // Le flux se termine avec le script
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !events.subscribers.is_empty() && Instant::now() < deadline {
+            pump(&mut events, epoll_fd);
+        }
+        let received = read_until(&mut client, "\0");
+        assert!(received.ends_with("\r\n\r\ndata: resume 41\n\nevent: done\ndata: bye\n\n"));
+        assert!(events.pipes.is_empty());
+
+        // Un canal seul accepte les publications
+        let req = request(Method::Post, "/events/", &[]);
+        let mut client = open(&req, b"", &config, &route, &mut events);
+        assert!(read_until(&mut client, "\r\n").starts_with("HTTP/1.1 405"));
+        unsafe {
+            libc::close(epoll_fd);
+        }
+    }
+}
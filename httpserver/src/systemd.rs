@@ -0,0 +1,163 @@
+// systemd.rs
+use crate::config::ListenAddr;
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::net::TcpListener;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::{SocketAddr as UnixSocketAddr, UnixDatagram, UnixListener};
+use std::process;
+
+// Les sockets transmises par systemd commencent au descripteur 3
+const LISTEN_FDS_START: RawFd = 3;
+
+// Sockets ouvertes par systemd (LISTEN_FDS/LISTEN_PID), indexées comme les
+// adresses de la configuration: "ip:port" ou "unix:chemin". Les variables
+// sont retirées pour ne pas être transmises aux scripts CGI.
+pub fn listen_fds() -> HashMap<String, RawFd> {
+    let count = activated_count(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+        process::id(),
+    );
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+    describe_fds((0..count as RawFd).map(|n| LISTEN_FDS_START + n))
+}
+
+// Nombre de sockets destinées à ce processus
+fn activated_count(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> usize {
+    if pid.and_then(|pid| pid.trim().parse::<u32>().ok()) != Some(own_pid) {
+        return 0;
+    }
+    fds.and_then(|fds| fds.trim().parse().ok()).unwrap_or(0)
+}
+
+fn describe_fds(fds: impl Iterator<Item = RawFd>) -> HashMap<String, RawFd> {
+    let mut sockets = HashMap::new();
+    for fd in fds {
+        match listen_addr(fd) {
+            Ok(addr) => {
+                // systemd ne pose pas FD_CLOEXEC: les scripts CGI en hériteraient
+                unsafe {
+                    libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                }
+                sockets.insert(addr.to_string(), fd);
+            }
+            Err(e) => eprintln!("⚠️ Ignoring socket-activated fd {}: {}⚠️", fd, e),
+        }
+    }
+    sockets
+}
+
+// Adresse locale d'une socket sans en prendre possession
+fn listen_addr(fd: RawFd) -> io::Result<ListenAddr> {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockname(fd, &mut storage as *mut _ as *mut libc::sockaddr, &mut len)
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET | libc::AF_INET6 => {
+            let listener = ManuallyDrop::new(unsafe { TcpListener::from_raw_fd(fd) });
+            listener.local_addr().map(ListenAddr::Tcp)
+        }
+        libc::AF_UNIX => {
+            let listener = ManuallyDrop::new(unsafe { UnixListener::from_raw_fd(fd) });
+            let addr = listener.local_addr()?;
+            addr.as_pathname()
+                .map(|path| ListenAddr::Unix(path.to_path_buf()))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unnamed unix socket"))
+        }
+        family => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported address family {}", family),
+        )),
+    }
+}
+
+// Prévient systemd (Type=notify) de l'état du service: "READY=1",
+// "RELOADING=1", "STOPPING=1"... Sans NOTIFY_SOCKET, rien n'est envoyé.
+pub fn notify(state: &str) {
+    if let Ok(path) = env::var("NOTIFY_SOCKET") {
+        if let Err(e) = notify_to(&path, state) {
+            eprintln!("⚠️ sd_notify failed: {}⚠️", e);
+        }
+    }
+}
+
+// Type=notify-reload attend l'horloge monotone avec RELOADING=1
+pub fn notify_reloading() {
+    let mut now: libc::timespec = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now);
+    }
+    let usec = now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000;
+    notify(&format!("RELOADING=1\nMONOTONIC_USEC={}", usec));
+}
+
+// Un chemin commençant par '@' désigne une socket abstraite
+fn notify_to(path: &str, state: &str) -> io::Result<()> {
+    let addr = match path.strip_prefix('@') {
+        Some(name) => UnixSocketAddr::from_abstract_name(name)?,
+        None => UnixSocketAddr::from_pathname(path)?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn test_activated_count() {
+        assert_eq!(activated_count(Some("42"), Some("2"), 42), 2);
+        // Les sockets destinées à un autre processus sont ignorées
+        assert_eq!(activated_count(Some("41"), Some("2"), 42), 0);
+        assert_eq!(activated_count(None, Some("2"), 42), 0);
+        assert_eq!(activated_count(Some("42"), Some("x"), 42), 0);
+    }
+
+    #[test]
+    fn test_describe_fds() {
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let path = temp_dir("systemd_activation").join("activation.sock");
+        let unix = UnixListener::bind(&path).unwrap();
+        let datagram = UnixDatagram::unbound().unwrap();
+
+        let fds = [tcp.as_raw_fd(), unix.as_raw_fd(), datagram.as_raw_fd()];
+        let sockets = describe_fds(fds.into_iter());
+        assert_eq!(sockets.len(), 2);
+        assert_eq!(sockets[&tcp.local_addr().unwrap().to_string()], tcp.as_raw_fd());
+        assert_eq!(sockets[&format!("unix:{}", path.display())], unix.as_raw_fd());
+        let flags = unsafe { libc::fcntl(tcp.as_raw_fd(), libc::F_GETFD) };
+        assert_eq!(flags & libc::FD_CLOEXEC, libc::FD_CLOEXEC);
+    }
+
+    #[test]
+    fn test_notify() {
+        let path = temp_dir("systemd_notify").join("notify.sock");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        notify_to(&path.to_string_lossy(), "READY=1").unwrap();
+        let mut buf = [0; 32];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+
+        let name = format!("web_server_notify_{}", process::id());
+        let abstract_addr = UnixSocketAddr::from_abstract_name(&name).unwrap();
+        let receiver = UnixDatagram::bind_addr(&abstract_addr).unwrap();
+        notify_to(&format!("@{}", name), "STOPPING=1").unwrap();
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"STOPPING=1");
+    }
+}
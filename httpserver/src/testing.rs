@@ -0,0 +1,19 @@
+// testing.rs
+// Outils partagés par les tests unitaires
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+// Répertoire temporaire vide propre à un test: le PID et un compteur évitent
+// les collisions entre tests parallèles ou entre deux `cargo test`
+pub fn temp_dir(name: &str) -> PathBuf {
+    let unique = format!("web_server-{}-{}-{}", name, process::id(), NEXT_DIR.fetch_add(1, Ordering::Relaxed));
+    let dir = env::temp_dir().join(unique);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,513 @@
+// tls.rs
+use crate::config::Config;
+use crate::listener::Stream;
+use crate::upstream::watch;
+use libc::{EPOLLIN, EPOLLOUT, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD};
+use rustls::crypto::ring;
+use rustls::server::{ClientHello, ResolvesServerCert, ServerConfig, ServerConnection};
+use rustls::sign::CertifiedKey;
+use rustls::version::{TLS12, TLS13};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Délai accordé au client pour terminer la poignée de main
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Protocoles proposés par ALPN, par ordre de préférence
+pub const ALPN_PROTOCOLS: [&[u8]; 2] = [b"h2", b"http/1.1"];
+
+// Certificat et clé privée d'un serveur, au format PEM
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    pub cert: String, // chaîne de certificats, celui du serveur en premier
+    pub key: String,  // clé privée PKCS#8, PKCS#1 ou SEC1
+}
+
+impl TlsConfig {
+    // Lit les fichiers et vérifie que la clé correspond au certificat
+    pub fn load(&self) -> Result<CertifiedKey, String> {
+        let certs = rustls_pemfile::certs(&mut open(&self.cert)?)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid certificate `{}`: {}", self.cert, e))?;
+        if certs.is_empty() {
+            return Err(format!("no certificate found in `{}`", self.cert));
+        }
+        let key = rustls_pemfile::private_key(&mut open(&self.key)?)
+            .map_err(|e| format!("invalid private key `{}`: {}", self.key, e))?
+            .ok_or_else(|| format!("no private key found in `{}`", self.key))?;
+        let key = ring::sign::any_supported_type(&key)
+            .map_err(|e| format!("unsupported private key `{}`: {}", self.key, e))?;
+        let certified = CertifiedKey::new(certs, key);
+        certified
+            .keys_match()
+            .map_err(|_| format!("private key `{}` does not match certificate `{}`", self.key, self.cert))?;
+        Ok(certified)
+    }
+}
+
+fn open(path: &str) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| format!("cannot read `{}`: {}", path, e))
+}
+
+// Choisit le certificat d'après le nom demandé par le client (SNI); sans nom
+// ou pour un nom inconnu, celui du premier serveur de l'adresse
+#[derive(Debug)]
+struct SniResolver {
+    names: HashMap<String, Arc<CertifiedKey>>,
+    default: Arc<CertifiedKey>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let named = hello
+            .server_name()
+            .and_then(|name| self.names.get(&name.to_ascii_lowercase()));
+        Some(named.unwrap_or(&self.default).clone())
+    }
+}
+
+// Configurations TLS par adresse d'écoute ("ip:port" ou "unix:chemin"); une
+// adresse absente est servie en clair
+#[derive(Default)]
+pub struct TlsContexts {
+    configs: HashMap<String, Arc<ServerConfig>>,
+}
+
+impl TlsContexts {
+    // Relit les certificats de tous les serveurs, au démarrage et sur SIGHUP
+    pub fn load(config: &Config) -> Result<Self, String> {
+        let mut resolvers: HashMap<String, SniResolver> = HashMap::new();
+        for server in &config.servers {
+            let Some(tls) = &server.tls else {
+                continue;
+            };
+            let key = tls
+                .load()
+                .map(Arc::new)
+                .map_err(|e| format!("server `{}`: tls {}", server.host_name, e))?;
+            for addr in server.listen_addrs().unwrap_or_default() {
+                let resolver = resolvers.entry(addr.to_string()).or_insert_with(|| SniResolver {
+                    names: HashMap::new(),
+                    default: key.clone(),
+                });
+                resolver
+                    .names
+                    .entry(server.host_name.trim().to_ascii_lowercase())
+                    .or_insert_with(|| key.clone());
+            }
+        }
+        let mut configs = HashMap::new();
+        for (addr, resolver) in resolvers {
+            configs.insert(addr, server_config(resolver)?);
+        }
+        Ok(TlsContexts { configs })
+    }
+
+    pub fn get(&self, addr: &str) -> Option<Arc<ServerConfig>> {
+        self.configs.get(addr).cloned()
+    }
+}
+
+// TLS 1.2 et 1.3 uniquement, sans certificat client
+fn server_config(resolver: SniResolver) -> Result<Arc<ServerConfig>, String> {
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_protocol_versions(&[&TLS13, &TLS12])
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(resolver));
+    config.alpn_protocols = ALPN_PROTOCOLS.iter().map(|protocol| protocol.to_vec()).collect();
+    Ok(Arc::new(config))
+}
+
+// Envoie les enregistrements TLS en attente
+fn send_pending(conn: &mut ServerConnection, sock: &Stream) -> io::Result<()> {
+    while conn.wants_write() {
+        if conn.write_tls(&mut &*sock)? == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+    }
+    Ok(())
+}
+
+// Fait avancer la poignée de main sur une socket non bloquante; retourne
+// true une fois la connexion établie
+fn advance(conn: &mut ServerConnection, sock: &Stream) -> io::Result<bool> {
+    loop {
+        match send_pending(conn, sock) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+            result => result?,
+        }
+        if !conn.is_handshaking() {
+            return Ok(true);
+        }
+        match conn.read_tls(&mut &*sock) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => {
+                if let Err(e) = conn.process_new_packets() {
+                    // L'alerte décrivant l'erreur est transmise au client
+                    let _ = send_pending(conn, sock);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+struct Handshake {
+    conn: ServerConnection,
+    stream: Stream,
+    started: Instant,
+}
+
+// Poignées de main en cours, menées par la boucle epoll pour qu'un client
+// lent ne bloque pas les autres connexions
+#[derive(Default)]
+pub struct Handshakes {
+    pending: HashMap<RawFd, Handshake>,
+    epoll_fd: Option<RawFd>,
+}
+
+impl Handshakes {
+    pub fn attach(&mut self, epoll_fd: RawFd) {
+        self.epoll_fd = Some(epoll_fd);
+    }
+
+    // Commence la poignée de main d'une connexion acceptée sur une adresse
+    // TLS; la connexion est retournée si elle s'établit immédiatement
+    pub fn start(&mut self, stream: Stream, config: Arc<ServerConfig>) -> Option<Stream> {
+        let conn = match ServerConnection::new(config) {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("TLS error: {}", e);
+                return None;
+            }
+        };
+        if let Err(e) = stream.set_nonblocking(true) {
+            eprintln!("TLS error: {}", e);
+            return None;
+        }
+        let fd = stream.as_raw_fd();
+        let handshake = Handshake {
+            conn,
+            stream,
+            started: Instant::now(),
+        };
+        self.pending.insert(fd, handshake);
+        watch(self.epoll_fd, EPOLL_CTL_ADD, fd, EPOLLIN as u32);
+        self.progress(fd)
+    }
+
+    pub fn owns(&self, token: u64) -> bool {
+        self.pending.contains_key(&(token as RawFd))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    // Poursuit la poignée de main; retourne la connexion une fois établie
+    pub fn on_event(&mut self, token: u64) -> Option<Stream> {
+        self.progress(token as RawFd)
+    }
+
+    // Abandonne les poignées de main trop longues
+    pub fn tick(&mut self) {
+        let expired: Vec<RawFd> = self
+            .pending
+            .iter()
+            .filter(|(_, handshake)| handshake.started.elapsed() >= HANDSHAKE_TIMEOUT)
+            .map(|(fd, _)| *fd)
+            .collect();
+        for fd in expired {
+            eprintln!("TLS handshake timed out");
+            self.remove(fd);
+        }
+    }
+
+    fn progress(&mut self, fd: RawFd) -> Option<Stream> {
+        let handshake = self.pending.get_mut(&fd)?;
+        match advance(&mut handshake.conn, &handshake.stream) {
+            Ok(true) => {
+                let Handshake { conn, stream, .. } = self.remove(fd)?;
+                // La requête est ensuite lue de façon bloquante, avec délais
+                if let Err(e) = stream.set_nonblocking(false) {
+                    eprintln!("TLS error: {}", e);
+                    return None;
+                }
+                Some(Stream::Tls(Box::new(TlsStream::new(conn, stream))))
+            }
+            Ok(false) => {
+                let mut events = EPOLLIN as u32;
+                if handshake.conn.wants_write() {
+                    events |= EPOLLOUT as u32;
+                }
+                watch(self.epoll_fd, EPOLL_CTL_MOD, fd, events);
+                None
+            }
+            Err(e) => {
+                eprintln!("TLS handshake failed: {}", e);
+                self.remove(fd);
+                None
+            }
+        }
+    }
+
+    fn remove(&mut self, fd: RawFd) -> Option<Handshake> {
+        let handshake = self.pending.remove(&fd)?;
+        watch(self.epoll_fd, EPOLL_CTL_DEL, fd, 0);
+        Some(handshake)
+    }
+}
+
+// Connexion TLS établie. Les lectures et écritures passent par `&Stream`:
+// l'état rustls est emprunté le temps de chaque appel.
+pub struct TlsStream {
+    conn: RefCell<ServerConnection>,
+    sock: Stream,
+}
+
+impl TlsStream {
+    fn new(conn: ServerConnection, sock: Stream) -> Self {
+        TlsStream {
+            conn: RefCell::new(conn),
+            sock,
+        }
+    }
+
+    // Socket sous-jacente, pour les délais et les adresses
+    pub fn socket(&self) -> &Stream {
+        &self.sock
+    }
+
+    // Nom demandé par le client (SNI)
+    pub fn server_name(&self) -> Option<String> {
+        self.conn.borrow().server_name().map(str::to_string)
+    }
+
+    // Protocole choisi par ALPN
+    pub fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.conn.borrow().alpn_protocol().map(<[u8]>::to_vec)
+    }
+
+    // Annonce la fin de la session (close_notify) avant de fermer la socket
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        if how != Shutdown::Read {
+            let mut conn = self.conn.borrow_mut();
+            conn.send_close_notify();
+            let _ = send_pending(&mut conn, &self.sock);
+        }
+        self.sock.shutdown(how)
+    }
+}
+
+impl fmt::Debug for TlsStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsStream")
+            .field("server_name", &self.server_name())
+            .field("sock", &self.sock)
+            .finish()
+    }
+}
+
+impl Read for &TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut conn = self.conn.borrow_mut();
+        loop {
+            match conn.reader().read(buf) {
+                Ok(n) => return Ok(n),
+                // Client parti sans close_notify: traité comme une fin de flux
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                Err(e) if e.kind() != io::ErrorKind::WouldBlock => return Err(e),
+                Err(_) => {}
+            }
+            // Aucune donnée déchiffrée: lire d'autres enregistrements
+            conn.read_tls(&mut &self.sock)?;
+            if let Err(e) = conn.process_new_packets() {
+                let _ = send_pending(&mut conn, &self.sock);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+        }
+    }
+}
+
+impl Write for &TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.conn.borrow_mut();
+        // Les enregistrements en attente partent d'abord: sur une socket non
+        // bloquante pleine, l'appelant reçoit WouldBlock
+        send_pending(&mut conn, &self.sock)?;
+        let written = conn.writer().write(buf)?;
+        match send_pending(&mut conn, &self.sock) {
+            Err(e) if e.kind() != io::ErrorKind::WouldBlock => Err(e),
+            _ => Ok(written),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        send_pending(&mut self.conn.borrow_mut(), &self.sock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use crate::config::ConfigServer;
+    use rustls::pki_types::{CertificateDer, ServerName};
+    use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    // Certificat auto-signé écrit dans un répertoire temporaire
+    fn self_signed(dir: &str, name: &str) -> (TlsConfig, CertificateDer<'static>) {
+        let dir = temp_dir(dir);
+        let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let cert = dir.join(format!("{}.crt", name));
+        let key = dir.join(format!("{}.key", name));
+        fs::write(&cert, generated.cert.pem()).unwrap();
+        fs::write(&key, generated.key_pair.serialize_pem()).unwrap();
+        let tls = TlsConfig {
+            cert: cert.display().to_string(),
+            key: key.display().to_string(),
+        };
+        (tls, generated.cert.der().clone())
+    }
+
+    fn server(name: &str, listen: &str, tls: TlsConfig) -> ConfigServer {
+        ConfigServer {
+            host_name: name.to_string(),
+            root: String::from("."),
+            client_body_limit: 1024,
+            listen: vec![listen.to_string()],
+            tls: Some(tls),
+            ..Default::default()
+        }
+    }
+
+    fn client(root: CertificateDer<'static>, versions: &[&'static rustls::SupportedProtocolVersion]) -> Arc<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        roots.add(root).unwrap();
+        let mut config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_protocol_versions(versions)
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Arc::new(config)
+    }
+
+    // Mène la poignée de main côté serveur comme le ferait la boucle epoll
+    fn accept(listener: &TcpListener, config: Arc<ServerConfig>) -> Option<Stream> {
+        let mut handshakes = Handshakes::default();
+        let (stream, _) = listener.accept().unwrap();
+        let fd = stream.as_raw_fd() as u64;
+        if let Some(stream) = handshakes.start(stream.into(), config) {
+            return Some(stream);
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while handshakes.owns(fd) && Instant::now() < deadline {
+            if let Some(stream) = handshakes.on_event(fd) {
+                return Some(stream);
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        None
+    }
+
+    #[test]
+    fn test_sni_selects_certificate() {
+        let (alpha, _) = self_signed("tls_sni", "alpha.test");
+        let (beta, beta_der) = self_signed("tls_sni", "beta.test");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let config = Config {
+            drain_timeout: 0,
+            servers: vec![server("alpha.test", &addr, alpha), server("beta.test", &addr, beta)],
+            upstreams: HashMap::new(),
+        };
+        let contexts = TlsContexts::load(&config).unwrap();
+        assert!(contexts.get("127.0.0.1:1").is_none());
+
+        // Le client ne fait confiance qu'au certificat de beta.test
+        for versions in [&[&TLS13][..], &[&TLS12][..]] {
+            let client_config = client(beta_der.clone(), versions);
+            let target = addr.clone();
+            let client = thread::spawn(move || {
+                let name = ServerName::try_from("beta.test").unwrap();
+                let conn = ClientConnection::new(client_config, name).unwrap();
+                let mut tls = StreamOwned::new(conn, TcpStream::connect(target).unwrap());
+                tls.write_all(b"ping").unwrap();
+                let mut reply = [0; 4];
+                tls.read_exact(&mut reply).unwrap();
+                assert_eq!(tls.conn.alpn_protocol(), Some(&b"h2"[..]));
+                reply
+            });
+            let stream = accept(&listener, contexts.get(&addr).unwrap()).unwrap();
+            let Stream::Tls(tls) = &stream else {
+                panic!("expected a TLS stream");
+            };
+            assert_eq!(tls.server_name().as_deref(), Some("beta.test"));
+            assert_eq!(stream.alpn_protocol(), Some(b"h2".to_vec()));
+            let mut request = [0; 4];
+            (&stream).read_exact(&mut request).unwrap();
+            assert_eq!(&request, b"ping");
+            (&stream).write_all(b"pong").unwrap();
+            assert_eq!(&client.join().unwrap(), b"pong");
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    #[test]
+    fn test_plain_client_is_rejected() {
+        let (tls, _) = self_signed("tls_plain", "plain.test");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let config = Config {
+            drain_timeout: 0,
+            servers: vec![server("plain.test", &addr, tls)],
+            upstreams: HashMap::new(),
+        };
+        let contexts = TlsContexts::load(&config).unwrap();
+        let mut client = TcpStream::connect(&addr).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nHost: plain.test\r\n\r\n").unwrap();
+        assert!(accept(&listener, contexts.get(&addr).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let (alpha, _) = self_signed("tls_errors", "alpha.test");
+        let (beta, _) = self_signed("tls_errors", "beta.test");
+        assert!(alpha.load().is_ok());
+
+        let missing = TlsConfig {
+            cert: String::from("/nonexistent/cert.pem"),
+            ..alpha.clone()
+        };
+        assert!(missing.load().unwrap_err().starts_with("cannot read"));
+        // La clé est lue dans un fichier sans clé privée
+        let no_key = TlsConfig {
+            key: alpha.cert.clone(),
+            ..alpha.clone()
+        };
+        assert!(no_key.load().unwrap_err().starts_with("no private key"));
+        let mismatch = TlsConfig {
+            key: beta.key.clone(),
+            ..alpha
+        };
+        assert!(mismatch.load().unwrap_err().contains("does not match"));
+    }
+}
@@ -0,0 +1,334 @@
+// upload.rs
+use crate::config::{ConfigServer, Route};
+use http::httprequest::HttpRequest;
+use http::httpresponse::HttpResponse;
+use http::multipart::{Multipart, Part};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAX_FILENAME_LEN: usize = 255;
+
+// Que faire lorsqu'un fichier du même nom existe déjà
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    Reject,
+    #[default]
+    Rename,
+    Overwrite,
+}
+
+// Section `upload` d'une route
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct UploadPolicy {
+    pub dir: Option<String>,           // relatif à root si le chemin n'est pas absolu
+    pub max_file_size: Option<usize>,  // en octets, par fichier
+    pub allowed_extensions: Vec<String>,
+    pub allowed_types: Vec<String>,    // types MIME, "image/*" accepté
+    pub on_conflict: OnConflict,
+}
+
+impl UploadPolicy {
+    // Comportement historique des routes sans section `upload`
+    pub fn legacy() -> Self {
+        UploadPolicy {
+            on_conflict: OnConflict::Overwrite,
+            ..Default::default()
+        }
+    }
+
+    fn target_dir(&self, root: &str) -> PathBuf {
+        match &self.dir {
+            Some(dir) => Path::new(root).join(dir),
+            None => PathBuf::from(root),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StoredFile {
+    pub field: String,
+    pub original_name: String,
+    pub stored_name: String,
+    pub size: usize,
+    pub content_type: Option<String>,
+}
+
+// Garde uniquement le nom de base et des caractères sûrs
+pub fn sanitize_filename(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = base
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '.' | '-' | '_' | ' ') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.');
+    let mut cleaned: String = cleaned.chars().take(MAX_FILENAME_LEN).collect();
+    if cleaned.is_empty() {
+        cleaned = String::from("upload");
+    }
+    cleaned
+}
+
+fn extension(name: &str) -> Option<String> {
+    Path::new(name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+}
+
+fn type_allowed(allowed: &[String], content_type: Option<&str>) -> bool {
+    let media = content_type
+        .map(|c| c.split(';').next().unwrap_or_default().trim().to_ascii_lowercase())
+        .unwrap_or_else(|| String::from("application/octet-stream"));
+    allowed.iter().any(|pattern| {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_suffix("/*") {
+            Some(prefix) => media.split('/').next() == Some(prefix),
+            None => pattern == media,
+        }
+    })
+}
+
+// "photo.png" -> "photo-1.png", "photo-2.png"...
+fn free_name(dir: &Path, name: &str, taken: &HashSet<String>) -> String {
+    let path = Path::new(name);
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| format!("{}-{}{}", stem, n, ext))
+        .find(|candidate| !dir.join(candidate).exists() && !taken.contains(candidate))
+        .unwrap_or_else(|| name.to_string())
+}
+
+// Vérifie chaque fichier selon la politique de la route puis les enregistre.
+// Aucun fichier n'est écrit si l'un d'eux est refusé; l'erreur est le code HTTP.
+pub fn store_uploads(
+    multipart: &Multipart,
+    policy: &UploadPolicy,
+    config: &ConfigServer,
+) -> Result<Vec<StoredFile>, &'static str> {
+    let dir = policy.target_dir(&config.root);
+    let mut planned: Vec<(&Part, String)> = Vec::new();
+    let mut taken = HashSet::new();
+
+    for part in multipart.files() {
+        let original = part.filename.clone().unwrap_or_default();
+        let name = sanitize_filename(&original);
+        if policy.max_file_size.is_some_and(|max| part.size() > max) {
+            eprintln!("Upload {} exceeds max_file_size", name);
+            return Err("413");
+        }
+        if !policy.allowed_extensions.is_empty() {
+            let allowed = extension(&name).is_some_and(|ext| {
+                policy
+                    .allowed_extensions
+                    .iter()
+                    .any(|a| a.trim_start_matches('.').eq_ignore_ascii_case(&ext))
+            });
+            if !allowed {
+                eprintln!("Upload {} has a forbidden extension", name);
+                return Err("415");
+            }
+        }
+        if !policy.allowed_types.is_empty()
+            && !type_allowed(&policy.allowed_types, part.content_type.as_deref())
+        {
+            eprintln!("Upload {} has a forbidden content type", name);
+            return Err("415");
+        }
+        let exists = dir.join(&name).exists() || taken.contains(&name);
+        let name = match policy.on_conflict {
+            OnConflict::Reject if exists => {
+                eprintln!("Upload {} already exists", name);
+                return Err("409");
+            }
+            OnConflict::Rename if exists => free_name(&dir, &name, &taken),
+            _ => name,
+        };
+        taken.insert(name.clone());
+        planned.push((part, name));
+    }
+
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("Error creating upload directory: {}", e);
+        return Err("500");
+    }
+    let mut stored = Vec::new();
+    for (part, name) in planned {
+        if let Err(e) = part.persist(&dir.join(&name)) {
+            eprintln!("Error creating file: {}", e);
+            return Err("500");
+        }
+        stored.push(StoredFile {
+            field: part.name.clone(),
+            original_name: part.filename.clone().unwrap_or_default(),
+            stored_name: name,
+            size: part.size(),
+            content_type: part.content_type.clone(),
+        });
+    }
+    Ok(stored)
+}
+
+// Page de résultat listant les fichiers enregistrés, en JSON si le client
+// le demande dans l'en-tête Accept, en HTML sinon
+pub fn upload_result<'a>(
+    req: &HttpRequest,
+    route: &Route,
+    stored: &[StoredFile],
+    config: &ConfigServer,
+) -> HttpResponse<'a> {
+    let wants_json = req
+        .headers
+        .get("Accept")
+        .is_some_and(|accept| accept.contains("application/json"));
+    let mut headers: HashMap<&str, &str> = HashMap::new();
+    let body = if wants_json {
+        headers.insert("Content-Type", "application/json");
+        serde_json::json!({ "files": stored }).to_string()
+    } else {
+        headers.insert("Content-Type", "text/html");
+        let items: String = stored
+            .iter()
+            .map(|file| {
+                format!(
+                    "<li>{} ({} bytes)</li>",
+                    escape_html(&file.stored_name),
+                    file.size
+                )
+            })
+            .collect();
+        format!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head><meta charset=\"utf-8\" /><title>Upload</title></head>\n\
+             <body>\n<h1>{} file(s) uploaded</h1>\n<ul>{}</ul>\n<a href=\"{}\">Back</a>\n</body>\n</html>",
+            stored.len(),
+            items,
+            escape_html(&route.alias)
+        )
+    };
+    HttpResponse::new("201", config.host_name.clone(), Some(headers), Some(body))
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use http::multipart;
+
+    fn setup_config(root: &Path) -> ConfigServer {
+        ConfigServer {
+            root: root.to_string_lossy().into_owned(),
+            ports: vec![8080],
+            client_body_limit: 1024,
+            ..Default::default()
+        }
+    }
+
+    fn body(files: &[(&str, &str, &str)]) -> Multipart {
+        let mut raw = String::new();
+        for (filename, content_type, data) in files {
+            raw.push_str(&format!(
+                "--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+                 Content-Type: {}\r\n\r\n{}\r\n",
+                filename, content_type, data
+            ));
+        }
+        raw.push_str("--XyZ--");
+        multipart::parse(raw.as_bytes(), "XyZ").unwrap()
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("C:\\Users\\a\\photo 1.png"), "photo 1.png");
+        assert_eq!(sanitize_filename(".htaccess"), "htaccess");
+        assert_eq!(sanitize_filename("a<b>|c.txt"), "a_b__c.txt");
+        assert_eq!(sanitize_filename(".."), "upload");
+    }
+
+    #[test]
+    fn test_rename_on_conflict() {
+        let root = temp_dir("upload_rename_test");
+        fs::write(root.join("a.txt"), "old").unwrap();
+        let config = setup_config(&root);
+        let policy = UploadPolicy {
+            dir: Some("files".to_string()),
+            ..Default::default()
+        };
+        fs::create_dir_all(root.join("files")).unwrap();
+        fs::write(root.join("files/a.txt"), "old").unwrap();
+
+        let stored = store_uploads(
+            &body(&[("a.txt", "text/plain", "new"), ("a.txt", "text/plain", "newer")]),
+            &policy,
+            &config,
+        )
+        .unwrap();
+        let names: Vec<&str> = stored.iter().map(|f| f.stored_name.as_str()).collect();
+        assert_eq!(names, vec!["a-1.txt", "a-2.txt"]);
+        assert_eq!(fs::read_to_string(root.join("files/a.txt")).unwrap(), "old");
+        assert_eq!(fs::read_to_string(root.join("files/a-2.txt")).unwrap(), "newer");
+    }
+
+    #[test]
+    fn test_reject_and_overwrite() {
+        let root = temp_dir("upload_reject_test");
+        fs::write(root.join("a.txt"), "old").unwrap();
+        let config = setup_config(&root);
+
+        let reject = UploadPolicy {
+            on_conflict: OnConflict::Reject,
+            ..Default::default()
+        };
+        let files = body(&[("b.txt", "text/plain", "b"), ("a.txt", "text/plain", "new")]);
+        assert_eq!(store_uploads(&files, &reject, &config), Err("409"));
+        // Rien n'est écrit lorsqu'un fichier est refusé
+        assert!(!root.join("b.txt").exists());
+
+        let stored = store_uploads(&files, &UploadPolicy::legacy(), &config).unwrap();
+        assert_eq!(stored[1].stored_name, "a.txt");
+        assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_size_extension_and_type_limits() {
+        let root = temp_dir("upload_limits_test");
+        let config = setup_config(&root);
+        let policy = UploadPolicy {
+            max_file_size: Some(4),
+            allowed_extensions: vec![".png".to_string(), "txt".to_string()],
+            allowed_types: vec!["image/*".to_string(), "text/plain".to_string()],
+            ..Default::default()
+        };
+        let too_big = body(&[("a.txt", "text/plain", "12345")]);
+        assert_eq!(store_uploads(&too_big, &policy, &config), Err("413"));
+        let bad_ext = body(&[("a.exe", "text/plain", "1")]);
+        assert_eq!(store_uploads(&bad_ext, &policy, &config), Err("415"));
+        let bad_type = body(&[("a.png", "application/zip", "1")]);
+        assert_eq!(store_uploads(&bad_type, &policy, &config), Err("415"));
+        let ok = body(&[("A.PNG", "image/png; q=1", "1")]);
+        assert!(store_uploads(&ok, &policy, &config).is_ok());
+    }
+}
@@ -0,0 +1,737 @@
+// upstream.rs
+use crate::listener::raw_sockaddr;
+use libc::{epoll_ctl, epoll_event, EPOLLIN, EPOLLOUT, EPOLLRDHUP, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, prelude::*};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+// Groupe `[upstreams.<nom>]`: les routes `proxy_pass = "http://<nom>/..."`
+// répartissent leurs requêtes entre ses serveurs
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct UpstreamGroup {
+    pub servers: Vec<String>, // "hôte:port"
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default = "default_max_fails")]
+    pub max_fails: u32, // échecs consécutifs avant la mise à l'écart, 0 pour ne jamais écarter
+    #[serde(default = "default_fail_timeout")]
+    pub fail_timeout: u64, // secondes pendant lesquelles un serveur en échec est écarté
+    #[serde(default = "default_keepalive")]
+    pub keepalive: usize, // connexions inactives conservées par serveur, 0 pour aucune
+    #[serde(default = "default_keepalive_timeout")]
+    pub keepalive_timeout: u64, // secondes avant la fermeture d'une connexion inactive
+    pub health_check: Option<HealthCheck>,
+}
+
+fn default_max_fails() -> u32 {
+    1
+}
+
+fn default_fail_timeout() -> u64 {
+    10
+}
+
+fn default_keepalive() -> usize {
+    8
+}
+
+fn default_keepalive_timeout() -> u64 {
+    60
+}
+
+// Choix du serveur d'un groupe pour une requête
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    #[default]
+    RoundRobin,
+    LeastConnections, // à égalité, dans l'ordre du tourniquet
+    IpHash,           // une même adresse client va toujours au même serveur
+}
+
+// Section `health_check` d'un groupe: `GET path` est envoyé à chaque serveur
+// toutes les `interval` secondes, une réponse 2xx ou 3xx étant un succès
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct HealthCheck {
+    pub path: String,
+    pub interval: u64,
+    pub timeout: u64,
+    pub fails: u32,  // échecs consécutifs pour écarter le serveur
+    pub passes: u32, // succès consécutifs pour le réintégrer
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        HealthCheck {
+            path: String::from("/"),
+            interval: 5,
+            timeout: 2,
+            fails: 1,
+            passes: 1,
+        }
+    }
+}
+
+// Serveur retenu pour une requête; `group` est absent lorsque proxy_pass
+// désigne directement un hôte
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lease {
+    pub group: Option<String>,
+    pub index: usize,
+    pub addr: String,
+}
+
+// État des groupes: serveurs écartés, connexions ouvertes et sondes en cours.
+// Les sondes et les connexions inactives sont surveillées par l'epoll du
+// serveur, qui transmet leurs événements à `on_event` et appelle `tick`
+// à chaque tour de boucle.
+#[derive(Default)]
+pub struct Upstreams {
+    pools: HashMap<String, Pool>,
+    probes: HashMap<RawFd, Probe>,
+    epoll_fd: Option<RawFd>,
+}
+
+struct Pool {
+    name: String,
+    group: UpstreamGroup,
+    peers: Vec<Peer>,
+    next: usize, // position du tourniquet
+}
+
+struct Peer {
+    addr: String,
+    active: usize, // requêtes en cours
+    fails: u32,
+    down_until: Option<Instant>, // écarté après max_fails échecs
+    healthy: bool,               // d'après les sondes
+    checks_failed: u32,
+    checks_passed: u32,
+    next_check: Instant,
+    idle: Vec<(TcpStream, Instant)>,
+}
+
+impl Peer {
+    fn available(&self, now: Instant) -> bool {
+        self.healthy && self.down_until.is_none_or(|until| now >= until)
+    }
+}
+
+impl Pool {
+    fn new(name: &str, group: &UpstreamGroup) -> Self {
+        let now = Instant::now();
+        Pool {
+            name: name.to_string(),
+            group: group.clone(),
+            peers: group
+                .servers
+                .iter()
+                .map(|addr| Peer {
+                    addr: addr.clone(),
+                    active: 0,
+                    fails: 0,
+                    down_until: None,
+                    healthy: true,
+                    checks_failed: 0,
+                    checks_passed: 0,
+                    next_check: now,
+                    idle: Vec::new(),
+                })
+                .collect(),
+            next: 0,
+        }
+    }
+
+    // Serveurs disponibles dans l'ordre où les essayer: celui choisi par la
+    // stratégie, puis les autres en cas d'échec de connexion
+    fn order(&mut self, client_ip: Option<IpAddr>, now: Instant) -> Vec<usize> {
+        let count = self.peers.len();
+        if count == 0 {
+            return Vec::new();
+        }
+        let available: Vec<usize> = (0..count)
+            .map(|i| (self.next + i) % count)
+            .filter(|&i| self.peers[i].available(now))
+            .collect();
+        let first = match (self.group.strategy, client_ip) {
+            (Strategy::IpHash, Some(ip)) => {
+                let start = ip_hash(ip) % count;
+                (0..count).map(|i| (start + i) % count).find(|&i| self.peers[i].available(now))
+            }
+            (Strategy::LeastConnections, _) => {
+                available.iter().copied().min_by_key(|&i| self.peers[i].active)
+            }
+            _ => available.first().copied(),
+        };
+        let Some(first) = first else {
+            return Vec::new();
+        };
+        if !(self.group.strategy == Strategy::IpHash && client_ip.is_some()) {
+            self.next = (first + 1) % count;
+        }
+        let mut order = vec![first];
+        order.extend(available.into_iter().filter(|&i| i != first));
+        order
+    }
+
+    // Échecs passifs (connexion impossible, réponse invalide ou absente).
+    // Un serveur seul dans son groupe n'est jamais écarté.
+    fn record(&mut self, index: usize, ok: bool, now: Instant) {
+        let single = self.peers.len() == 1;
+        let peer = &mut self.peers[index];
+        if ok {
+            peer.fails = 0;
+            return;
+        }
+        peer.fails += 1;
+        if self.group.max_fails > 0 && !single && peer.fails >= self.group.max_fails {
+            peer.fails = 0;
+            peer.down_until = Some(now + Duration::from_secs(self.group.fail_timeout));
+            eprintln!(
+                "⚠️ Upstream `{}`: {} marked down for {}s⚠️",
+                self.name, peer.addr, self.group.fail_timeout
+            );
+        }
+    }
+
+    fn record_check(&mut self, index: usize, ok: bool) {
+        let Some(check) = &self.group.health_check else {
+            return;
+        };
+        let peer = &mut self.peers[index];
+        if ok {
+            peer.checks_failed = 0;
+            peer.checks_passed += 1;
+            if !peer.healthy && peer.checks_passed >= check.passes {
+                peer.healthy = true;
+                println!("Upstream `{}`: {} is healthy again", self.name, peer.addr);
+            }
+        } else {
+            peer.checks_passed = 0;
+            peer.checks_failed += 1;
+            if peer.healthy && peer.checks_failed >= check.fails {
+                peer.healthy = false;
+                eprintln!("⚠️ Upstream `{}`: {} failed its health check⚠️", self.name, peer.addr);
+            }
+        }
+    }
+}
+
+impl Upstreams {
+    pub fn new(groups: &HashMap<String, UpstreamGroup>) -> Self {
+        let mut upstreams = Upstreams::default();
+        upstreams.update(groups);
+        upstreams
+    }
+
+    // Les sondes et les connexions inactives sont enregistrées dans cet epoll
+    pub fn attach(&mut self, epoll_fd: RawFd) {
+        self.epoll_fd = Some(epoll_fd);
+    }
+
+    // Nouvelle configuration: l'état des groupes inchangés est conservé
+    pub fn update(&mut self, groups: &HashMap<String, UpstreamGroup>) {
+        let kept: HashSet<String> = self
+            .pools
+            .iter()
+            .filter(|(name, pool)| groups.get(*name) == Some(&pool.group))
+            .map(|(name, _)| name.clone())
+            .collect();
+        self.pools.retain(|name, _| kept.contains(name));
+        self.probes.retain(|_, probe| kept.contains(&probe.group));
+        for (name, group) in groups {
+            self.pools.entry(name.clone()).or_insert_with(|| Pool::new(name, group));
+        }
+    }
+
+    pub fn keep_alive(&self, name: &str) -> bool {
+        self.pools.get(name).is_some_and(|pool| pool.group.keepalive > 0)
+    }
+
+    // Serveurs à essayer pour `name`: ceux du groupe de ce nom, aucun si tous
+    // sont écartés, sinon l'hôte `authority` lui-même
+    pub fn candidates(&mut self, name: &str, authority: &str, client_ip: Option<IpAddr>) -> Vec<Lease> {
+        let Some(pool) = self.pools.get_mut(name) else {
+            return vec![Lease {
+                group: None,
+                index: 0,
+                addr: authority.to_string(),
+            }];
+        };
+        pool.order(client_ip, Instant::now())
+            .into_iter()
+            .map(|index| Lease {
+                group: Some(name.to_string()),
+                index,
+                addr: pool.peers[index].addr.clone(),
+            })
+            .collect()
+    }
+
+    // Connexion au serveur retenu, en réutilisant si possible une connexion
+    // inactive; le booléen indique une connexion réutilisée. Une nouvelle
+    // connexion est en cours d'établissement: comme pour les sondes, EPOLLOUT
+    // en signale la fin et `take_error` son échec, rendu par `release`
+    pub fn checkout(&mut self, lease: &Lease) -> io::Result<(TcpStream, bool)> {
+        let epoll_fd = self.epoll_fd;
+        let Some(pool) = lease.group.as_ref().and_then(|name| self.pools.get_mut(name)) else {
+            return connect(&lease.addr).map(|connection| (connection, false));
+        };
+        let peer = &mut pool.peers[lease.index];
+        while let Some((connection, _)) = peer.idle.pop() {
+            watch(epoll_fd, EPOLL_CTL_DEL, connection.as_raw_fd(), 0);
+            if is_alive(&connection) {
+                peer.active += 1;
+                return Ok((connection, true));
+            }
+        }
+        match connect(&peer.addr) {
+            Ok(connection) => {
+                peer.active += 1;
+                Ok((connection, false))
+            }
+            Err(e) => {
+                pool.record(lease.index, false, Instant::now());
+                Err(e)
+            }
+        }
+    }
+
+    // Fin d'une requête; une connexion encore utilisable est conservée
+    pub fn release(&mut self, lease: &Lease, connection: Option<TcpStream>, ok: bool) {
+        let epoll_fd = self.epoll_fd;
+        let Some(pool) = lease.group.as_ref().and_then(|name| self.pools.get_mut(name)) else {
+            return;
+        };
+        let now = Instant::now();
+        pool.record(lease.index, ok, now);
+        let keepalive = pool.group.keepalive;
+        let peer = &mut pool.peers[lease.index];
+        peer.active = peer.active.saturating_sub(1);
+        if let Some(connection) = connection.filter(|_| ok && peer.idle.len() < keepalive) {
+            let _ = connection.set_read_timeout(None);
+            watch(epoll_fd, EPOLL_CTL_ADD, connection.as_raw_fd(), (EPOLLIN | EPOLLRDHUP) as u32);
+            peer.idle.push((connection, now));
+        }
+    }
+
+    // Événement epoll d'une sonde ou d'une connexion inactive
+    pub fn on_event(&mut self, token: u64) {
+        let fd = token as RawFd;
+        if let Some(probe) = self.probes.get_mut(&fd) {
+            if let Some(ok) = probe.advance(self.epoll_fd) {
+                self.finish_probe(fd, ok);
+            }
+            return;
+        }
+        // Une connexion inactive lisible a été fermée par le serveur amont
+        for peer in self.pools.values_mut().flat_map(|pool| pool.peers.iter_mut()) {
+            if let Some(position) = peer.idle.iter().position(|(c, _)| c.as_raw_fd() == fd) {
+                watch(self.epoll_fd, EPOLL_CTL_DEL, fd, 0);
+                peer.idle.remove(position);
+                return;
+            }
+        }
+    }
+
+    // Ferme les connexions inactives trop anciennes, termine les sondes
+    // expirées et lance celles qui sont dues
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        for pool in self.pools.values_mut() {
+            let timeout = Duration::from_secs(pool.group.keepalive_timeout);
+            for peer in &mut pool.peers {
+                peer.idle.retain(|(_, since)| now.duration_since(*since) < timeout);
+            }
+        }
+        let expired: Vec<RawFd> = self
+            .probes
+            .iter()
+            .filter(|(_, probe)| now >= probe.deadline)
+            .map(|(fd, _)| *fd)
+            .collect();
+        for fd in expired {
+            self.finish_probe(fd, false);
+        }
+        let Some(epoll_fd) = self.epoll_fd else {
+            return;
+        };
+        for pool in self.pools.values_mut() {
+            let Some(check) = pool.group.health_check.clone() else {
+                continue;
+            };
+            for index in 0..pool.peers.len() {
+                let peer = &mut pool.peers[index];
+                let probing = self.probes.values().any(|p| p.group == pool.name && p.index == index);
+                if probing || now < peer.next_check {
+                    continue;
+                }
+                peer.next_check = now + Duration::from_secs(check.interval);
+                let deadline = now + Duration::from_secs(check.timeout);
+                match Probe::start(&pool.name, index, &peer.addr, &check.path, deadline) {
+                    Ok(probe) => {
+                        let fd = probe.stream.as_raw_fd();
+                        watch(Some(epoll_fd), EPOLL_CTL_ADD, fd, EPOLLOUT as u32);
+                        self.probes.insert(fd, probe);
+                    }
+                    Err(_) => pool.record_check(index, false),
+                }
+            }
+        }
+    }
+
+    fn finish_probe(&mut self, fd: RawFd, ok: bool) {
+        if let Some(probe) = self.probes.remove(&fd) {
+            watch(self.epoll_fd, EPOLL_CTL_DEL, fd, 0);
+            if let Some(pool) = self.pools.get_mut(&probe.group) {
+                pool.record_check(probe.index, ok);
+            }
+        }
+    }
+}
+
+// Sonde non bloquante: connexion, envoi de la requête puis lecture de la
+// ligne de statut, chaque étape reprenant sur un événement epoll
+struct Probe {
+    group: String,
+    index: usize,
+    stream: TcpStream,
+    request: Vec<u8>, // reste à envoyer
+    response: Vec<u8>,
+    deadline: Instant,
+}
+
+impl Probe {
+    fn start(group: &str, index: usize, addr: &str, path: &str, deadline: Instant) -> io::Result<Probe> {
+        Ok(Probe {
+            group: group.to_string(),
+            index,
+            stream: connect(addr)?,
+            request: format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: web_server-health-check\r\nConnection: close\r\n\r\n",
+                path, addr
+            )
+            .into_bytes(),
+            response: Vec::new(),
+            deadline,
+        })
+    }
+
+    // Retourne le résultat une fois connu
+    fn advance(&mut self, epoll_fd: Option<RawFd>) -> Option<bool> {
+        if !self.request.is_empty() {
+            if !matches!(self.stream.take_error(), Ok(None)) {
+                return Some(false);
+            }
+            match self.stream.write(&self.request) {
+                Ok(written) => {
+                    self.request.drain(..written);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return None,
+                Err(_) => return Some(false),
+            }
+            if self.request.is_empty() {
+                watch(epoll_fd, EPOLL_CTL_MOD, self.stream.as_raw_fd(), EPOLLIN as u32);
+            }
+            return None;
+        }
+        let mut buffer = [0; 512];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Some(is_success(&self.response)),
+                Ok(read) => {
+                    self.response.extend_from_slice(&buffer[..read]);
+                    if self.response.windows(2).any(|w| w == b"\r\n") {
+                        return Some(is_success(&self.response));
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return None,
+                Err(_) => return Some(false),
+            }
+        }
+    }
+}
+
+// "HTTP/1.1 200 OK": 2xx et 3xx sont des succès
+fn is_success(response: &[u8]) -> bool {
+    let line = String::from_utf8_lossy(response);
+    let mut parts = line.split_whitespace();
+    let version = parts.next().unwrap_or_default();
+    let status = parts.next().and_then(|status| status.parse::<u16>().ok());
+    version.starts_with("HTTP/") && status.is_some_and(|status| (200..400).contains(&status))
+}
+
+// Ajoute, modifie ou retire `fd` de l'ensemble epoll, le descripteur servant de jeton
+pub fn watch(epoll_fd: Option<RawFd>, op: i32, fd: RawFd, events: u32) {
+    if let Some(epoll_fd) = epoll_fd {
+        let mut event = epoll_event { events, u64: fd as u64 };
+        unsafe {
+            epoll_ctl(epoll_fd, op, fd, &mut event);
+        }
+    }
+}
+
+// Connexion non bloquante à la première adresse de "hôte:port"
+fn connect(authority: &str) -> io::Result<TcpStream> {
+    let addr = authority
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address found"))?;
+    connect_nonblocking(addr)
+}
+
+// std n'offre pas de connexion non bloquante: connect retourne EINPROGRESS
+// et epoll signale la fin de la connexion (EPOLLOUT)
+fn connect_nonblocking(addr: SocketAddr) -> io::Result<TcpStream> {
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    unsafe {
+        let fd = libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, 0);
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let stream = TcpStream::from_raw_fd(fd);
+        let (raw, len) = raw_sockaddr(addr);
+        if libc::connect(fd, &raw as *const libc::sockaddr_storage as *const libc::sockaddr, len) == -1 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::EINPROGRESS) {
+                return Err(e);
+            }
+        }
+        Ok(stream)
+    }
+}
+
+// Une connexion inactive ne doit rien avoir à lire: des données ou la fin du
+// flux signifient que le serveur amont l'a abandonnée
+fn is_alive(connection: &TcpStream) -> bool {
+    if connection.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut byte = [0; 1];
+    let alive = matches!(connection.peek(&mut byte), Err(ref e) if e.kind() == io::ErrorKind::WouldBlock);
+    alive && connection.set_nonblocking(false).is_ok()
+}
+
+// FNV-1a, stable d'une exécution à l'autre
+fn ip_hash(ip: IpAddr) -> usize {
+    let octets = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    let hash = octets.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+    hash as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libc::{epoll_create1, epoll_wait, EPOLL_CLOEXEC};
+    use std::io::BufReader;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn group(servers: &[&str], strategy: Strategy) -> UpstreamGroup {
+        UpstreamGroup {
+            servers: servers.iter().map(|s| s.to_string()).collect(),
+            strategy,
+            max_fails: 1,
+            fail_timeout: 10,
+            keepalive: 2,
+            keepalive_timeout: 60,
+            health_check: None,
+        }
+    }
+
+    fn setup_upstreams(group: UpstreamGroup) -> Upstreams {
+        Upstreams::new(&HashMap::from([(String::from("backend"), group)]))
+    }
+
+    fn picks(upstreams: &mut Upstreams, ip: Option<IpAddr>, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|_| upstreams.candidates("backend", "backend:80", ip)[0].addr.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_round_robin_and_direct_hosts() {
+        let mut upstreams = setup_upstreams(group(&["a:1", "b:1", "c:1"], Strategy::RoundRobin));
+        assert_eq!(picks(&mut upstreams, None, 4), vec!["a:1", "b:1", "c:1", "a:1"]);
+        // Les autres serveurs suivent en cas d'échec de connexion
+        let order: Vec<String> = upstreams.candidates("backend", "", None).into_iter().map(|l| l.addr).collect();
+        assert_eq!(order, vec!["b:1", "c:1", "a:1"]);
+        // Un nom qui n'est pas un groupe désigne l'hôte lui-même
+        let direct = upstreams.candidates("127.0.0.1:3000", "127.0.0.1:3000", None);
+        assert_eq!(direct, vec![Lease { group: None, index: 0, addr: String::from("127.0.0.1:3000") }]);
+    }
+
+    #[test]
+    fn test_least_connections_and_ip_hash() {
+        let mut upstreams = setup_upstreams(group(&["a:1", "b:1", "c:1"], Strategy::LeastConnections));
+        let pool = upstreams.pools.get_mut("backend").unwrap();
+        pool.peers[0].active = 2;
+        pool.peers[1].active = 1;
+        pool.peers[2].active = 1;
+        assert_eq!(picks(&mut upstreams, None, 2), vec!["b:1", "c:1"]);
+
+        let mut upstreams = setup_upstreams(group(&["a:1", "b:1", "c:1"], Strategy::IpHash));
+        let client: IpAddr = "192.168.1.20".parse().unwrap();
+        let sticky = picks(&mut upstreams, Some(client), 3);
+        assert!(sticky.iter().all(|addr| *addr == sticky[0]));
+        // Le serveur attitré écarté, le suivant prend le relais
+        let index = ["a:1", "b:1", "c:1"].iter().position(|addr| *addr == sticky[0]).unwrap();
+        let pool = upstreams.pools.get_mut("backend").unwrap();
+        pool.peers[index].healthy = false;
+        let other = picks(&mut upstreams, Some(client), 1);
+        assert_ne!(other[0], sticky[0]);
+        // Sans adresse (socket Unix), tourniquet
+        assert_eq!(picks(&mut upstreams, None, 1).len(), 1);
+    }
+
+    // Connexion refusée, constatée comme le fait un échange relayé
+    fn refused(upstreams: &mut Upstreams, lease: &Lease) {
+        let (connection, reused) = upstreams.checkout(lease).unwrap();
+        assert!(!reused);
+        thread::sleep(Duration::from_millis(50));
+        assert!(connection.take_error().unwrap().is_some());
+        upstreams.release(lease, None, false);
+    }
+
+    #[test]
+    fn test_passive_failures() {
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let live = TcpListener::bind("127.0.0.1:0").unwrap();
+        let live_addr = live.local_addr().unwrap().to_string();
+        let mut config = group(&[&closed, &live_addr], Strategy::RoundRobin);
+        config.max_fails = 2;
+        let mut upstreams = setup_upstreams(config);
+        let timeout = Duration::from_secs(1);
+
+        let lease = upstreams.candidates("backend", "", None).remove(0);
+        assert_eq!(lease.addr, closed);
+        refused(&mut upstreams, &lease);
+        refused(&mut upstreams, &lease);
+        // Écarté après max_fails échecs: seul le serveur vivant reste proposé
+        let leases = upstreams.candidates("backend", "", None);
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases[0].addr, live_addr);
+        let (connection, reused) = upstreams.checkout(&leases[0]).unwrap();
+        assert!(!reused);
+        upstreams.release(&leases[0], Some(connection), true);
+        // Tous écartés: aucun serveur
+        let pool = upstreams.pools.get_mut("backend").unwrap();
+        pool.peers[1].down_until = Some(Instant::now() + timeout);
+        assert!(upstreams.candidates("backend", "", None).is_empty());
+
+        // Un serveur seul n'est jamais écarté
+        let mut single = setup_upstreams(group(&[&closed], Strategy::RoundRobin));
+        let lease = single.candidates("backend", "", None).remove(0);
+        for _ in 0..3 {
+            refused(&mut single, &lease);
+        }
+        assert_eq!(single.candidates("backend", "", None).len(), 1);
+    }
+
+    #[test]
+    fn test_keepalive_pool() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut upstreams = setup_upstreams(group(&[&addr], Strategy::RoundRobin));
+        let lease = upstreams.candidates("backend", "", None).remove(0);
+
+        let (connection, reused) = upstreams.checkout(&lease).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        assert!(!reused);
+        upstreams.release(&lease, Some(connection), true);
+        let (connection, reused) = upstreams.checkout(&lease).unwrap();
+        assert!(reused);
+        upstreams.release(&lease, Some(connection), true);
+
+        // Fermée par le serveur amont, la connexion inactive n'est pas reprise
+        drop(accepted);
+        thread::sleep(Duration::from_millis(50));
+        let (_, reused) = upstreams.checkout(&lease).unwrap();
+        assert!(!reused);
+    }
+
+    // Fait tourner l'epoll comme la boucle du serveur jusqu'à la fin des sondes
+    fn run_probes(upstreams: &mut Upstreams, epoll_fd: RawFd) {
+        upstreams.tick();
+        let mut events = vec![epoll_event { events: 0, u64: 0 }; 10];
+        while !upstreams.probes.is_empty() {
+            let nfds = unsafe { epoll_wait(epoll_fd, events.as_mut_ptr(), events.len() as i32, 100) };
+            for event in events.iter().take(nfds.max(0) as usize) {
+                upstreams.on_event(event.u64);
+            }
+            upstreams.tick();
+        }
+    }
+
+    #[test]
+    fn test_health_checks() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in ["503 Service Unavailable", "200 OK"] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut line = String::new();
+                BufReader::new(&stream).read_line(&mut line).unwrap();
+                requests.push(line);
+                write!(&stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            }
+            requests
+        });
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let mut config = group(&[&addr, &closed], Strategy::RoundRobin);
+        config.health_check = Some(HealthCheck {
+            path: String::from("/health"),
+            interval: 60,
+            ..HealthCheck::default()
+        });
+        let mut upstreams = setup_upstreams(config);
+        let epoll_fd = unsafe { epoll_create1(EPOLL_CLOEXEC) };
+        upstreams.attach(epoll_fd);
+
+        run_probes(&mut upstreams, epoll_fd);
+        let peers = &upstreams.pools["backend"].peers;
+        assert!(!peers[0].healthy && !peers[1].healthy);
+        assert!(upstreams.candidates("backend", "", None).is_empty());
+
+        // Sondes suivantes sans attendre l'intervalle
+        for peer in &mut upstreams.pools.get_mut("backend").unwrap().peers {
+            peer.next_check = Instant::now();
+        }
+        run_probes(&mut upstreams, epoll_fd);
+        let leases = upstreams.candidates("backend", "", None);
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases[0].addr, addr);
+        assert_eq!(server.join().unwrap(), vec!["GET /health HTTP/1.1\r\n"; 2]);
+        unsafe {
+            libc::close(epoll_fd);
+        }
+    }
+
+    #[test]
+    fn test_update_keeps_unchanged_groups() {
+        let config = group(&["a:1", "b:1"], Strategy::RoundRobin);
+        let mut upstreams = setup_upstreams(config.clone());
+        upstreams.pools.get_mut("backend").unwrap().peers[0].healthy = false;
+        upstreams.update(&HashMap::from([(String::from("backend"), config.clone())]));
+        assert!(!upstreams.pools["backend"].peers[0].healthy);
+        let mut changed = config;
+        changed.strategy = Strategy::LeastConnections;
+        upstreams.update(&HashMap::from([(String::from("backend"), changed)]));
+        assert!(upstreams.pools["backend"].peers[0].healthy);
+        upstreams.update(&HashMap::new());
+        assert!(upstreams.pools.is_empty());
+    }
+}
@@ -0,0 +1,1049 @@
+// validate.rs
+use crate::config::{
+    ok_count_redirect, ok_same_port, Config, ConfigError, ConfigServer, ListenAddr, Route,
+};
+use crate::handler::SERVICES;
+use crate::loader::{ConfigSource, ServerSpan};
+use crate::matcher::param_name;
+use crate::proxy::Upstream;
+use crate::upstream::UpstreamGroup;
+use crate::redirect::{redirect_loop, status_code};
+use crate::websocket::WebSocketMode;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+const KNOWN_METHODS: [&str; 7] = ["GET", "POST", "DELETE", "PUT", "PATCH", "HEAD", "OPTIONS"];
+const SAME_SITE: [&str; 3] = ["Strict", "Lax", "None"];
+
+// Vérifie la cohérence de toute la configuration et retourne l'ensemble des
+// problèmes, chacun rattaché à son serveur et à sa route. Les erreurs sont
+// positionnées dans le fichier (principal ou inclus) qui déclare le serveur.
+pub fn validate_config(sources: &[ConfigSource], config: &Config) -> Vec<ConfigError> {
+    let mut errors = Vec::new();
+    let mut listeners: Vec<(ListenAddr, String, bool)> = Vec::new();
+    // Les serveurs des fichiers inclus sont ajoutés dans l'ordre de lecture
+    // des fichiers: le n-ième serveur est le n-ième déclaré
+    let declared: Vec<(&ConfigSource, ServerSpan)> = sources
+        .iter()
+        .flat_map(|file| file.server_spans().into_iter().map(move |span| (file, span)))
+        .collect();
+
+    for (index, server) in config.servers.iter().enumerate() {
+        let name = server.host_name.trim();
+        let (path, source, span) = match declared.get(index) {
+            Some((file, span)) => (file.path.as_str(), file.text.as_str(), Some(span)),
+            None => (sources.first().map_or("", |file| file.path.as_str()), "", None),
+        };
+        let at = |key: &str| span.map(|span| span.table.offset(key));
+        let mut error = |message: String, offset: Option<usize>| {
+            let error = ConfigError::new(path, format!("server `{}`: {}", name, message));
+            errors.push(match offset {
+                Some(offset) => error.at_offset(source, offset),
+                None => error,
+            });
+        };
+
+        // Serveur
+        let root = Path::new(&server.root);
+        if !root.is_dir() {
+            error(format!("root `{}` is not a directory", server.root), at("root"));
+        }
+        if !ok_same_port(server) {
+            error(String::from("a port is listed more than once"), at("ports"));
+        }
+        let addrs = match server.listen_addrs() {
+            Ok(addrs) => addrs,
+            Err(problem) => {
+                let key = if problem.starts_with("host") { "host" } else { "listen" };
+                error(problem, at(key));
+                Vec::new()
+            }
+        };
+        if addrs.is_empty() && server.ports.is_empty() && server.listen.is_empty() {
+            error(String::from("no address to listen on, set ports or listen"), span.map(|span| span.table.start));
+        }
+        if server.backlog <= 0 {
+            error(format!("backlog must be positive, got {}", server.backlog), at("backlog"));
+        }
+        if server.socket_mode.is_some_and(|mode| mode > 0o777) {
+            error(String::from("socket_mode must be a permission like 0o660"), at("socket_mode"));
+        }
+        let mut seen: Vec<ListenAddr> = Vec::new();
+        for addr in addrs {
+            if let ListenAddr::Unix(path) = &addr {
+                let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty());
+                if parent.is_some_and(|parent| !parent.is_dir()) {
+                    error(format!("directory of socket `{}` does not exist", path.display()), at("listen"));
+                }
+            }
+            let key = if server.listen.is_empty() { "ports" } else { "listen" };
+            if seen.contains(&addr) {
+                // Les ports répétés sont déjà signalés ci-dessus
+                if ok_same_port(server) {
+                    error(format!("{} is listed more than once", addr), at(key));
+                }
+                continue;
+            }
+            seen.push(addr.clone());
+            // Des serveurs de noms différents peuvent partager une adresse:
+            // l'en-tête Host (ou SNI en TLS) les départage. La socket est alors
+            // commune et doit être TLS pour tous ou pour aucun.
+            let shared = |other: &ListenAddr, tls: bool| tls == server.tls.is_some() && *other == addr;
+            let clash = listeners.iter().find(|(other, other_name, tls)| {
+                clashes(other, &addr) && (!shared(other, *tls) || other_name.eq_ignore_ascii_case(name))
+            });
+            if let Some((_, other, _)) = clash {
+                error(format!("{} is already used by server `{}`", addr, other), at(key));
+            }
+            listeners.push((addr, name.to_string(), server.tls.is_some()));
+        }
+        if let Some(Err(problem)) = server.tls.as_ref().map(|tls| tls.load()) {
+            error(format!("tls {}", problem), at("tls"));
+        }
+        for (code, page) in sorted(&server.error_pages) {
+            if code.parse::<u16>().map_or(true, |code| !(400..600).contains(&code)) {
+                error(format!("error page key `{}` is not an HTTP error code", code), at("error_pages"));
+            }
+            if root.is_dir() && !resolve(root, page).is_file() {
+                error(format!("error page `{}` for {} not found", page, code), at("error_pages"));
+            }
+        }
+        for (extension, script) in sorted(&server.cgi_extensions) {
+            if root.is_dir() {
+                if let Err(problem) = check_script(root, script) {
+                    error(
+                        format!("CGI script `{}` for .{} {}", script, extension, problem),
+                        at("cgi_extensions"),
+                    );
+                }
+            }
+        }
+
+        // Redirections et réécritures, suivies à travers les règles et les
+        // routes. Une boucle n'est signalée qu'une fois, par la première règle
+        // ou route qui y mène.
+        let mut loops: HashSet<Vec<String>> = HashSet::new();
+        let mut is_new_loop = |cycle: &[String]| {
+            let mut members = cycle.to_vec();
+            members.sort();
+            members.dedup();
+            loops.insert(members)
+        };
+        for (index, rule) in server.redirects.iter().enumerate() {
+            let offset = span.and_then(|span| span.redirects.get(index)).map(|rule| rule.offset("from"));
+            let mut rule_error = |message: String| {
+                error(format!("redirect `{}`: {}", rule.from, message), offset);
+            };
+            if let Err(e) = Regex::new(&rule.from) {
+                rule_error(format!("invalid regex: {}", e));
+            }
+            if rule.rewrite && !rule.to.starts_with('/') {
+                rule_error(format!("rewrite target `{}` must be a local path", rule.to));
+            }
+            if !rule.rewrite && status_code(rule.status).is_none() {
+                rule_error(format!("status must be 301, 302, 303, 307 or 308, got {}", rule.status));
+            }
+            if rule.to.starts_with('/') {
+                let cycle = probe_targets(&rule.to).iter().find_map(|target| redirect_loop(server, target));
+                if let Some(cycle) = cycle.filter(|c| is_new_loop(c)) {
+                    rule_error(format!("redirect loop {}", cycle.join(" -> ")));
+                }
+            }
+        }
+
+        // Routes
+        let mut aliases: HashMap<&str, usize> = HashMap::new();
+        for route in &server.routes {
+            *aliases.entry(route.alias.as_str()).or_default() += 1;
+        }
+        for (index, route) in server.routes.iter().enumerate() {
+            let offset = span.and_then(|span| span.routes.get(index)).map(|route| route.offset("alias"));
+            let mut route_error = |message: String| {
+                error(format!("route `{}`: {}", route.alias, message), offset);
+            };
+            if let Err(problem) = check_alias(&route.alias) {
+                route_error(problem);
+            }
+            if aliases.get(route.alias.as_str()) > Some(&1) {
+                route_error(String::from("alias is defined more than once"));
+            }
+            for method in &route.methods {
+                if !KNOWN_METHODS.contains(&method.as_str()) {
+                    route_error(format!("unknown method `{}`", method));
+                }
+            }
+            // La page par défaut est lue dans root, qu'elle figure ou non dans pages
+            if !route.default_page.is_empty() && root.is_dir() && !resolve(root, &route.default_page).is_file() {
+                route_error(format!("default_page `{}` not found", route.default_page));
+            }
+            if !ok_count_redirect(std::slice::from_ref(route)) {
+                route_error(String::from("redirect must contain exactly one entry"));
+            } else if let Some(problem) = check_redirect(server, route) {
+                route_error(problem);
+            } else if let Some(cycle) = sample_path(&route.alias).and_then(|path| redirect_loop(server, &path)) {
+                if is_new_loop(&cycle) {
+                    route_error(format!("redirect loop {}", cycle.join(" -> ")));
+                }
+            }
+            if let (Some(script), true) = (&route.cgi, root.is_dir()) {
+                if let Err(problem) = check_script(root, script) {
+                    route_error(format!("CGI script `{}` {}", script, problem));
+                }
+            }
+            match route.csrf.as_ref().and_then(|policy| policy.same_site.as_deref()) {
+                Some("None") if server.tls.is_none() => {
+                    route_error(String::from("csrf same_site `None` requires a TLS server (Secure cookie)"))
+                }
+                Some(value) if !SAME_SITE.contains(&value) => {
+                    route_error(format!("csrf same_site must be Strict, Lax or None, got `{}`", value))
+                }
+                _ => {}
+            }
+            for problem in route.auth.iter().flat_map(|auth| auth.check()) {
+                route_error(problem);
+            }
+            if let Some(service) = route.service.as_deref().filter(|service| !SERVICES.contains(service)) {
+                route_error(format!("unknown service `{}`", service));
+            }
+            if let Some(Err(problem)) = route.proxy_pass.as_deref().map(Upstream::parse) {
+                route_error(problem);
+            }
+            if route.proxy.is_some() && route.proxy_pass.is_none() {
+                route_error(String::from("proxy options require proxy_pass"));
+            }
+            if let Some(policy) = &route.websocket {
+                match (policy.mode, route.proxy_pass.is_some()) {
+                    (WebSocketMode::Proxy, false) => {
+                        route_error(String::from("websocket mode `proxy` requires proxy_pass"))
+                    }
+                    (WebSocketMode::Echo | WebSocketMode::Broadcast, true) => {
+                        route_error(String::from("websocket mode `echo` or `broadcast` cannot be used with proxy_pass"))
+                    }
+                    _ => {}
+                }
+                if policy.max_message_size == 0 {
+                    route_error(String::from("websocket max_message_size must be positive"));
+                }
+            }
+            if let Some(source) = &route.sse {
+                match (&source.script, &source.channel) {
+                    (Some(_), Some(_)) => route_error(String::from("sse takes either script or channel, not both")),
+                    (None, None) => route_error(String::from("sse requires a script or a channel")),
+                    (Some(script), None) if root.is_dir() => {
+                        if let Err(problem) = check_script(root, script) {
+                            route_error(format!("sse script `{}` {}", script, problem));
+                        }
+                    }
+                    (None, Some(channel)) if channel.trim().is_empty() => {
+                        route_error(String::from("sse channel must not be empty"))
+                    }
+                    _ => {}
+                }
+                if route.websocket.is_some() || route.proxy_pass.is_some() {
+                    route_error(String::from("sse cannot be combined with websocket or proxy_pass"));
+                }
+            }
+        }
+    }
+
+    // Groupes de serveurs amont, déclarés dans le fichier principal
+    let (path, source) = sources
+        .first()
+        .map_or(("", ""), |file| (file.path.as_str(), file.text.as_str()));
+    let mut names: Vec<&String> = config.upstreams.keys().collect();
+    names.sort();
+    for name in names {
+        let offset = sources.first().and_then(|file| file.upstream_span(name));
+        for problem in check_upstream(&config.upstreams[name]) {
+            let error = ConfigError::new(path, format!("upstream `{}`: {}", name, problem));
+            errors.push(match offset {
+                Some(offset) => error.at_offset(source, offset),
+                None => error,
+            });
+        }
+    }
+    errors
+}
+
+fn check_upstream(group: &UpstreamGroup) -> Vec<String> {
+    let mut problems = Vec::new();
+    if group.servers.is_empty() {
+        problems.push(String::from("servers must not be empty"));
+    }
+    for server in &group.servers {
+        let valid = server
+            .rsplit_once(':')
+            .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+        if !valid {
+            problems.push(format!("server `{}` must be host:port", server));
+        }
+    }
+    if let Some(check) = &group.health_check {
+        if !check.path.starts_with('/') {
+            problems.push(format!("health_check path `{}` must start with /", check.path));
+        }
+        if check.interval == 0 || check.timeout == 0 {
+            problems.push(String::from("health_check interval and timeout must be positive"));
+        }
+    }
+    problems
+}
+
+// Deux sockets ne peuvent pas écouter la même adresse; une adresse générique
+// (0.0.0.0, [::]) entre en conflit avec celles du même port et de la même
+// famille, [::] étant limité à IPv6 lorsque le port est aussi écouté en IPv4
+fn clashes(a: &ListenAddr, b: &ListenAddr) -> bool {
+    match (a, b) {
+        (ListenAddr::Tcp(a), ListenAddr::Tcp(b)) => {
+            a.port() == b.port()
+                && a.is_ipv4() == b.is_ipv4()
+                && (a.ip() == b.ip() || a.ip().is_unspecified() || b.ip().is_unspecified())
+        }
+        (ListenAddr::Unix(a), ListenAddr::Unix(b)) => a == b,
+        _ => false,
+    }
+}
+
+// Un alias est "/", un chemin "/a/b/" dont les segments peuvent être {param}
+// ou *, ou une expression régulière précédée de ~
+fn check_alias(alias: &str) -> Result<(), String> {
+    if let Some(pattern) = alias.strip_prefix('~') {
+        return Regex::new(pattern)
+            .map(|_| ())
+            .map_err(|e| format!("invalid regex: {}", e));
+    }
+    if alias == "/" {
+        return Ok(());
+    }
+    let segments = alias.strip_prefix('/').and_then(|rest| rest.strip_suffix('/'));
+    let well_formed = segments.is_some_and(|segments| {
+        segments.split('/').all(|segment| match param_name(segment) {
+            Some(name) => !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_'),
+            None => {
+                !segment.is_empty()
+                    && !segment.contains(['{', '}'])
+                    && !segment.chars().any(char::is_whitespace)
+            }
+        })
+    });
+    if well_formed {
+        Ok(())
+    } else {
+        Err(String::from("alias must be \"/\", \"/name/\", \"/a/{param}/*/\" or \"~regex\""))
+    }
+}
+
+// La cible d'une redirection doit être une route du même serveur et, si un
+// fichier est indiqué, une page de cette route
+fn check_redirect(server: &ConfigServer, route: &Route) -> Option<String> {
+    let (target_alias, target_page) = route.redirect.as_ref()?.iter().next()?;
+    let target = match server.routes.iter().find(|r| &r.alias == target_alias) {
+        Some(target) => target,
+        None => return Some(format!("redirect target `{}` is not a route", target_alias)),
+    };
+    let page = target_page.trim_start_matches('/');
+    if !page.is_empty() && !target.pages.iter().any(|p| p == page) {
+        return Some(format!(
+            "redirect target `{}{}` is not a page of route `{}`",
+            target_alias, target_page, target_alias
+        ));
+    }
+    None
+}
+
+// Valeurs essayées à la place des captures ($1, $nom) d'une cible: une
+// boucle dont la cible dépend de la requête est cherchée à partir de chemins
+// représentatifs
+const PROBES: [&str; 2] = ["probe", "1"];
+
+fn probe_targets(to: &str) -> Vec<String> {
+    let target = to.split('?').next().unwrap_or_default();
+    if !target.contains('$') {
+        return vec![target.to_string()];
+    }
+    let placeholder = Regex::new(r"\$(\{[^}]*\}|[0-9A-Za-z_]+)").expect("valid placeholder regex");
+    PROBES
+        .iter()
+        .map(|value| placeholder.replace_all(target, *value).into_owned())
+        .collect()
+}
+
+// Chemin servi par un alias: "/users/{id}/*/" donne "/users/probe/probe/"
+fn sample_path(alias: &str) -> Option<String> {
+    if alias.starts_with('~') {
+        return None;
+    }
+    let segments: Vec<&str> = alias
+        .split('/')
+        .map(|segment| if segment == "*" || param_name(segment).is_some() { PROBES[0] } else { segment })
+        .collect();
+    Some(segments.join("/"))
+}
+
+fn resolve(root: &Path, file: &str) -> PathBuf {
+    root.join(file.trim_start_matches('/'))
+}
+
+// Les scripts Python sont lancés avec python3, les autres directement
+fn check_script(root: &Path, script: &str) -> Result<(), &'static str> {
+    let script_path = resolve(root, script);
+    if !script_path.is_file() {
+        return Err("not found");
+    }
+    if script.ends_with(".py") {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = script_path.metadata().map(|m| m.permissions().mode()).unwrap_or(0);
+        if mode & 0o111 == 0 {
+            return Err("is not executable");
+        }
+    }
+    Ok(())
+}
+
+// Ordre stable pour des diagnostics reproductibles
+fn sorted(map: &HashMap<String, String>) -> Vec<(&String, &String)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort();
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use crate::auth::RouteAuth;
+    use crate::csrf::CsrfPolicy;
+    use crate::redirect::RedirectRule;
+    use crate::proxy::ProxyOptions;
+    use crate::sse::EventSource;
+    use crate::tls::TlsConfig;
+    use crate::websocket::WebSocketPolicy;
+    use crate::upstream::{HealthCheck, Strategy};
+    use std::fs;
+
+    fn setup_config(root: &Path) -> ConfigServer {
+        fs::write(root.join("index.html"), "").unwrap();
+        ConfigServer {
+            root: root.to_string_lossy().into_owned(),
+            ports: vec![8080],
+            client_body_limit: 1024,
+            routes: vec![Route {
+                alias: "/test/".to_string(),
+                pages: vec!["index.html".to_string()],
+                default_page: "/index.html".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn main_source(text: &str) -> Vec<ConfigSource> {
+        vec![ConfigSource {
+            path: String::from("config.toml"),
+            text: text.to_string(),
+        }]
+    }
+
+    fn messages(errors: &[ConfigError]) -> Vec<String> {
+        errors.iter().map(|e| e.message.clone()).collect()
+    }
+
+    #[test]
+    fn test_valid_config() {
+        let root = temp_dir("validate_ok");
+        fs::write(root.join("404.html"), "").unwrap();
+        let mut server = setup_config(&root);
+        server.error_pages.insert("404".to_string(), "404.html".to_string());
+        let config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
+            servers: vec![server],
+        };
+        assert!(validate_config(&main_source(""), &config).is_empty());
+    }
+
+    #[test]
+    fn test_reports_all_errors() {
+        let root = temp_dir("validate_errors");
+        fs::write(root.join("script.sh"), "#!/bin/sh\n").unwrap();
+        let mut server = setup_config(&root);
+        server.ports = vec![8080, 8080];
+        server.error_pages.insert("404".to_string(), "missing.html".to_string());
+        server.cgi_extensions.insert("sh".to_string(), "script.sh".to_string());
+        let route = &mut server.routes[0];
+        route.methods.push("BREW".to_string());
+        route.default_page = "/home.html".to_string();
+        route.redirect = Some(HashMap::from([("/nowhere/".to_string(), String::new())]));
+        route.cgi = Some("missing.py".to_string());
+        route.service = Some("billing".to_string());
+        let mut duplicate = server.routes[0].clone();
+        duplicate.alias = "no-slash".to_string();
+        duplicate.redirect = None;
+        duplicate.cgi = None;
+        duplicate.service = None;
+        server.routes.push(duplicate.clone());
+        server.routes.push(duplicate);
+
+        let config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
+            servers: vec![server],
+        };
+        let errors = messages(&validate_config(&main_source(""), &config));
+        let expected = [
+            "server `localhost`: a port is listed more than once",
+            "server `localhost`: error page `missing.html` for 404 not found",
+            "server `localhost`: CGI script `script.sh` for .sh is not executable",
+            "server `localhost`: route `/test/`: unknown method `BREW`",
+            "server `localhost`: route `/test/`: default_page `/home.html` not found",
+            "server `localhost`: route `/test/`: redirect target `/nowhere/` is not a route",
+            "server `localhost`: route `/test/`: CGI script `missing.py` not found",
+            "server `localhost`: route `/test/`: unknown service `billing`",
+            "server `localhost`: route `no-slash`: alias must be \"/\", \"/name/\", \"/a/{param}/*/\" or \"~regex\"",
+            "server `localhost`: route `no-slash`: alias is defined more than once",
+        ];
+        for message in expected {
+            assert!(errors.iter().any(|e| e == message), "missing: {}\n{:?}", message, errors);
+        }
+    }
+
+    #[test]
+    fn test_port_clash_across_servers() {
+        let root = temp_dir("validate_ports");
+        let first = setup_config(&root);
+        let mut second = setup_config(&root);
+        second.host_name = "other".to_string();
+        second.host = "0.0.0.0".to_string();
+        let config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
+            servers: vec![first, second],
+        };
+        let source = "[[servers]]\nhost_name = \"localhost\"\n\n[[servers]]\nhost_name = \"other\"\nports = [8080]\n";
+        let errors = validate_config(&main_source(source), &config);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "server `other`: 0.0.0.0:8080 is already used by server `localhost`"
+        );
+        assert_eq!((errors[0].line, errors[0].column), (Some(6), Some(9)));
+    }
+
+    #[test]
+    fn test_virtual_hosts_share_address() {
+        let root = temp_dir("validate_virtual_hosts");
+        let first = setup_config(&root);
+        let mut second = setup_config(&root);
+        second.host_name = "other".to_string();
+        let mut third = setup_config(&root);
+        third.host_name = "LOCALHOST".to_string();
+        let config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
+            servers: vec![first, second, third],
+        };
+        assert_eq!(
+            messages(&validate_config(&main_source(""), &config)),
+            ["server `LOCALHOST`: 127.0.0.1:8080 is already used by server `localhost`"]
+        );
+    }
+
+    #[test]
+    fn test_errors_located_by_span() {
+        let root = temp_dir("validate_spans");
+        let first = setup_config(&root);
+        let mut second = setup_config(&root);
+        second.ports = vec![8081];
+        second.backlog = 0;
+        second.routes.push(second.routes[0].clone());
+        let config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
+            servers: vec![first, second],
+        };
+        // Les deux serveurs ont le même host_name et le second répète sa route
+        let source = "[[servers]]\nhost_name = \"localhost\"\n\n[[servers]]\nhost_name = \"localhost\"\nbacklog = 0\n\n\
+                      [[servers.routes]]\nalias = \"/test/\"\n\n[[servers.routes]]\n  alias = \"/test/\"\n";
+        let errors = validate_config(&main_source(source), &config);
+        let located: Vec<(&str, Option<usize>, Option<usize>)> = errors
+            .iter()
+            .map(|e| (e.message.as_str(), e.line, e.column))
+            .collect();
+        assert_eq!(
+            located,
+            vec![
+                ("server `localhost`: backlog must be positive, got 0", Some(6), Some(11)),
+                ("server `localhost`: route `/test/`: alias is defined more than once", Some(9), Some(9)),
+                ("server `localhost`: route `/test/`: alias is defined more than once", Some(12), Some(11)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tls_servers() {
+        let root = temp_dir("validate_tls");
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        fs::write(root.join("cert.pem"), generated.cert.pem()).unwrap();
+        fs::write(root.join("key.pem"), generated.key_pair.serialize_pem()).unwrap();
+        let tls = TlsConfig {
+            cert: root.join("cert.pem").to_string_lossy().into_owned(),
+            key: root.join("key.pem").to_string_lossy().into_owned(),
+        };
+        let server = |name: &str, tls: Option<TlsConfig>| ConfigServer {
+            host_name: name.to_string(),
+            tls,
+            ..setup_config(&root)
+        };
+        let validate = |servers: Vec<ConfigServer>| {
+            let config = Config {
+                drain_timeout: 30,
+                upstreams: HashMap::new(),
+                servers,
+            };
+            messages(&validate_config(&main_source(""), &config))
+        };
+
+        // Des serveurs TLS partagent l'adresse, départagés par SNI
+        assert!(validate(vec![server("localhost", Some(tls.clone())), server("other", Some(tls.clone()))]).is_empty());
+        assert_eq!(
+            validate(vec![server("localhost", Some(tls.clone())), server("other", None)]),
+            ["server `other`: 127.0.0.1:8080 is already used by server `localhost`"]
+        );
+        assert_eq!(
+            validate(vec![server("localhost", Some(tls.clone())), server("localhost", Some(tls.clone()))]),
+            ["server `localhost`: 127.0.0.1:8080 is already used by server `localhost`"]
+        );
+
+        let missing = TlsConfig {
+            key: root.join("missing.pem").to_string_lossy().into_owned(),
+            ..tls.clone()
+        };
+        let errors = validate(vec![server("localhost", Some(missing))]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("server `localhost`: tls cannot read"), "{:?}", errors);
+        let swapped = TlsConfig {
+            cert: tls.key.clone(),
+            key: tls.cert.clone(),
+        };
+        let errors = validate(vec![server("localhost", Some(swapped))]);
+        assert!(errors[0].starts_with("server `localhost`: tls no certificate found"), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_listen_addresses() {
+        let root = temp_dir("validate_listen");
+        let mut first = setup_config(&root);
+        first.ports = Vec::new();
+        first.listen = vec!["[::]:8443".to_string(), "127.0.0.1:9000".to_string()];
+        let mut second = setup_config(&root);
+        second.host_name = "other".to_string();
+        second.ports = Vec::new();
+        second.listen = vec![
+            "[::1]:8443".to_string(),
+            "127.0.0.2:9000".to_string(),
+            "localhost:80".to_string(),
+        ];
+        second.backlog = 0;
+        let mut third = setup_config(&root);
+        third.host_name = "third".to_string();
+        third.ports = Vec::new();
+        third.listen = vec![
+            "[::ffff:127.0.0.3]:9000".to_string(),
+            "[::1]:8443".to_string(),
+            format!("unix:{}/web.sock", root.display()),
+        ];
+        third.socket_mode = Some(0o1777);
+        // Même nom que `third` sur la même socket: aucun en-tête Host ne les départage
+        let mut unix = setup_config(&root);
+        unix.host_name = "third".to_string();
+        unix.ports = Vec::new();
+        unix.listen = vec![
+            format!("unix:{}/web.sock", root.display()),
+            "unix:/nonexistent/web.sock".to_string(),
+        ];
+        let mut fourth = setup_config(&root);
+        fourth.host_name = "fourth".to_string();
+        fourth.ports = Vec::new();
+        let config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
+            servers: vec![first, second, third, unix, fourth],
+        };
+        let errors = messages(&validate_config(&main_source(""), &config));
+        assert_eq!(
+            errors,
+            vec![
+                "server `other`: listen address `localhost:80` is not of the form ip:port, [ipv6]:port or unix:path",
+                "server `other`: backlog must be positive, got 0",
+                "server `third`: socket_mode must be a permission like 0o660",
+                "server `third`: [::1]:8443 is already used by server `localhost`",
+                &format!("server `third`: unix:{}/web.sock is already used by server `third`", root.display()),
+                "server `third`: directory of socket `/nonexistent/web.sock` does not exist",
+                "server `fourth`: no address to listen on, set ports or listen",
+            ]
+        );
+
+        let mut server = setup_config(&root);
+        server.listen = vec![
+            "127.0.0.1:8080".to_string(),
+            "[::1]:8080".to_string(),
+            "0.0.0.0:9000".to_string(),
+            "[::]:9000".to_string(),
+        ];
+        server.host = "::1".to_string();
+        let config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
+            servers: vec![server],
+        };
+        let errors = messages(&validate_config(&main_source(""), &config));
+        assert_eq!(errors, vec!["server `localhost`: [::1]:8080 is listed more than once"]);
+    }
+
+    #[test]
+    fn test_redirect_target_page() {
+        let root = temp_dir("validate_redirect");
+        let mut server = setup_config(&root);
+        let mut login = server.routes[0].clone();
+        login.alias = "/login/".to_string();
+        server.routes.push(login);
+        server.routes[0].redirect = Some(HashMap::from([(
+            "/login/".to_string(),
+            "index.html".to_string(),
+        )]));
+        assert!(check_redirect(&server, &server.routes[0]).is_none());
+        server.routes[0].redirect = Some(HashMap::from([(
+            "/login/".to_string(),
+            "other.html".to_string(),
+        )]));
+        assert!(check_redirect(&server, &server.routes[0]).is_some());
+    }
+
+    #[test]
+    fn test_route_patterns() {
+        for alias in ["/", "/docs/api/v1/", "/users/{id}/", "/files/*/{name}/", "~^/a/(?P<y>[0-9]+)/"] {
+            assert_eq!(check_alias(alias), Ok(()), "{}", alias);
+        }
+        for alias in ["/docs", "/a//b/", "/users/{}/", "/users/{id/", "/a b/"] {
+            assert!(check_alias(alias).is_err(), "{}", alias);
+        }
+        assert!(check_alias("~^/a/(").unwrap_err().starts_with("invalid regex"));
+    }
+
+    #[test]
+    fn test_redirect_rules() {
+        let root = temp_dir("validate_redirect_rules");
+        let mut server = setup_config(&root);
+        let rule = |from: &str, to: &str, status: u16, rewrite: bool| RedirectRule {
+            from: from.to_string(),
+            to: to.to_string(),
+            status,
+            rewrite,
+            ..Default::default()
+        };
+        server.redirects = vec![
+            rule("^/old/(.*)$", "https://example.com/$1", 308, false),
+            rule("^/latest/", "/v2/", 0, true),
+            rule("^/(", "/", 302, false),
+            rule("^/tmp/", "/", 200, false),
+            rule("^/ext/", "https://example.com/", 302, true),
+        ];
+        let config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
+            servers: vec![server],
+        };
+        let errors = messages(&validate_config(&main_source(""), &config));
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors[0].starts_with("server `localhost`: redirect `^/(`: invalid regex"));
+        assert_eq!(
+            errors[1],
+            "server `localhost`: redirect `^/tmp/`: status must be 301, 302, 303, 307 or 308, got 200"
+        );
+        assert_eq!(
+            errors[2],
+            "server `localhost`: redirect `^/ext/`: rewrite target `https://example.com/` must be a local path"
+        );
+    }
+
+    #[test]
+    fn test_redirect_loops() {
+        let root = temp_dir("validate_redirect_loops");
+        let mut server = setup_config(&root);
+        let template = server.routes[0].clone();
+        server.routes = [("/a/", "/b/"), ("/b/", "/c/"), ("/c/", "/a/"), ("/d/", "/a/"), ("/e/", "/f/")]
+            .iter()
+            .map(|(alias, target)| {
+                let mut route = template.clone();
+                route.alias = alias.to_string();
+                route.redirect = Some(HashMap::from([(target.to_string(), String::new())]));
+                route
+            })
+            .collect();
+        let mut plain = template.clone();
+        plain.alias = String::from("/f/");
+        server.routes.push(plain);
+        let rule = |from: &str, to: &str, rewrite: bool| RedirectRule {
+            from: from.to_string(),
+            to: to.to_string(),
+            rewrite,
+            ..Default::default()
+        };
+        server.redirects = vec![
+            rule("^/x$", "/y?from=x", false),
+            rule("^/y$", "/x", true),
+            rule("^/old/(.*)$", "/new/$1", false),
+            // Boucles dont la cible dépend des captures, entre règles puis
+            // entre une règle et une route
+            rule("^/p/(.*)$", "/q/$1", false),
+            rule("^/q/(.*)$", "/p/$1", false),
+            rule("^/f/(.*)$", "/e/$1", false),
+        ];
+        server.prepare();
+        let config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
+            servers: vec![server],
+        };
+        let errors = messages(&validate_config(&main_source(""), &config));
+        assert_eq!(
+            errors,
+            vec![
+                "server `localhost`: redirect `^/x$`: redirect loop /y -> /x -> /y",
+                "server `localhost`: redirect `^/p/(.*)$`: redirect loop /q/probe -> /p/probe -> /q/probe",
+                "server `localhost`: redirect `^/f/(.*)$`: redirect loop /f/ -> /e/ -> /f/",
+                "server `localhost`: route `/a/`: redirect loop /a/ -> /b/ -> /c/ -> /a/",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_proxy_routes() {
+        let root = temp_dir("validate_proxy_routes");
+        let mut server = setup_config(&root);
+        let mut route = server.routes[0].clone();
+        route.alias = String::from("/api/");
+        route.proxy_pass = Some(String::from("http://127.0.0.1:3000/v1"));
+        let mut https = route.clone();
+        https.alias = String::from("/secure/");
+        https.proxy_pass = Some(String::from("https://127.0.0.1:3443"));
+        let mut options = route.clone();
+        options.alias = String::from("/options/");
+        options.proxy_pass = None;
+        options.proxy = Some(ProxyOptions::default());
+        server.routes.extend([route, https, options]);
+        let config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
+            servers: vec![server],
+        };
+        let errors = messages(&validate_config(&main_source(""), &config));
+        assert_eq!(
+            errors,
+            vec![
+                "server `localhost`: route `/secure/`: proxy_pass `https://127.0.0.1:3443` must start with http://",
+                "server `localhost`: route `/options/`: proxy options require proxy_pass",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_websocket_routes() {
+        let root = temp_dir("validate_websocket_routes");
+        let mut server = setup_config(&root);
+        let mut echo = server.routes[0].clone();
+        echo.alias = String::from("/ws/");
+        echo.websocket = Some(WebSocketPolicy::default());
+        let mut relay = echo.clone();
+        relay.alias = String::from("/relay/");
+        relay.websocket = Some(WebSocketPolicy {
+            mode: WebSocketMode::Proxy,
+            max_message_size: 0,
+            idle_timeout: 0,
+        });
+        let mut mixed = echo.clone();
+        mixed.alias = String::from("/mixed/");
+        mixed.proxy_pass = Some(String::from("http://127.0.0.1:3000"));
+        server.routes.extend([echo, relay, mixed]);
+        let config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
+            servers: vec![server],
+        };
+        let errors = messages(&validate_config(&main_source(""), &config));
+        assert_eq!(
+            errors,
+            vec![
+                "server `localhost`: route `/relay/`: websocket mode `proxy` requires proxy_pass",
+                "server `localhost`: route `/relay/`: websocket max_message_size must be positive",
+                "server `localhost`: route `/mixed/`: websocket mode `echo` or `broadcast` cannot be used with proxy_pass",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sse_routes() {
+        let root = temp_dir("validate_sse_routes");
+        fs::write(root.join("ticker.py"), "print('tick')").unwrap();
+        let mut server = setup_config(&root);
+        let mut ticker = server.routes[0].clone();
+        ticker.alias = String::from("/ticker/");
+        ticker.sse = Some(EventSource {
+            script: Some(String::from("ticker.py")),
+            ..EventSource::default()
+        });
+        let mut missing = ticker.clone();
+        missing.alias = String::from("/missing/");
+        missing.sse = Some(EventSource {
+            script: Some(String::from("missing.py")),
+            ..EventSource::default()
+        });
+        let mut both = ticker.clone();
+        both.alias = String::from("/both/");
+        both.sse = Some(EventSource {
+            script: Some(String::from("ticker.py")),
+            channel: Some(String::from("news")),
+            ..EventSource::default()
+        });
+        let mut empty = ticker.clone();
+        empty.alias = String::from("/empty/");
+        empty.sse = Some(EventSource::default());
+        server.routes.extend([ticker, missing, both, empty]);
+        let config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
+            servers: vec![server],
+        };
+        let errors = messages(&validate_config(&main_source(""), &config));
+        assert_eq!(
+            errors,
+            vec![
+                "server `localhost`: route `/missing/`: sse script `missing.py` not found",
+                "server `localhost`: route `/both/`: sse takes either script or channel, not both",
+                "server `localhost`: route `/empty/`: sse requires a script or a channel",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_csrf_same_site() {
+        let root = temp_dir("validate_csrf_same_site");
+        let mut server = setup_config(&root);
+        let policy = |same_site: &str| CsrfPolicy {
+            token: true,
+            check_origin: false,
+            trusted_origins: vec![],
+            same_site: Some(same_site.to_string()),
+        };
+        for (alias, same_site) in [("/strict/", "Strict"), ("/none/", "None"), ("/header/", "Lax; Domain=evil.example")] {
+            let mut route = server.routes[0].clone();
+            route.alias = alias.to_string();
+            route.csrf = Some(policy(same_site));
+            server.routes.push(route);
+        }
+        let mut config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
+            servers: vec![server],
+        };
+        assert_eq!(
+            messages(&validate_config(&main_source(""), &config)),
+            vec![
+                "server `localhost`: route `/none/`: csrf same_site `None` requires a TLS server (Secure cookie)",
+                "server `localhost`: route `/header/`: csrf same_site must be Strict, Lax or None, got `Lax; Domain=evil.example`",
+            ]
+        );
+
+        // Servi en TLS, le cookie porte Secure et SameSite=None est accepté
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        fs::write(root.join("cert.pem"), generated.cert.pem()).unwrap();
+        fs::write(root.join("key.pem"), generated.key_pair.serialize_pem()).unwrap();
+        config.servers[0].tls = Some(TlsConfig {
+            cert: root.join("cert.pem").to_string_lossy().into_owned(),
+            key: root.join("key.pem").to_string_lossy().into_owned(),
+        });
+        config.servers[0].routes.pop();
+        assert!(validate_config(&main_source(""), &config).is_empty());
+    }
+
+    #[test]
+    fn test_auth_files() {
+        let root = temp_dir("validate_auth_files");
+        let htpasswd = root.join("htpasswd");
+        let tokens = root.join("tokens.txt");
+        fs::write(&htpasswd, "# admins\nalice:{SHA}secret\nbob\n\n:nouser\n").unwrap();
+        fs::write(&tokens, "ci-token\nbad token\n").unwrap();
+        let mut server = setup_config(&root);
+        let mut protected = server.routes[0].clone();
+        protected.alias = String::from("/admin/");
+        protected.auth = Some(RouteAuth {
+            basic: Some(htpasswd.to_string_lossy().into_owned()),
+            bearer: Some(tokens.to_string_lossy().into_owned()),
+            ..RouteAuth::default()
+        });
+        let mut missing = server.routes[0].clone();
+        missing.alias = String::from("/missing/");
+        missing.auth = Some(RouteAuth {
+            basic: Some(String::from("/nonexistent/htpasswd")),
+            ..RouteAuth::default()
+        });
+        server.routes.extend([protected, missing]);
+        let config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::new(),
+            servers: vec![server],
+        };
+        let (htpasswd, tokens) = (htpasswd.display(), tokens.display());
+        let errors = messages(&validate_config(&main_source(""), &config));
+        assert_eq!(
+            errors,
+            vec![
+                format!("server `localhost`: route `/admin/`: auth basic file `{}` line 3: expected user:hash", htpasswd),
+                format!("server `localhost`: route `/admin/`: auth basic file `{}` line 5: expected user:hash", htpasswd),
+                format!("server `localhost`: route `/admin/`: auth bearer file `{}` line 2: expected a single token", tokens),
+                String::from("server `localhost`: route `/missing/`: auth basic file `/nonexistent/htpasswd` not found"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_upstream_groups() {
+        let root = temp_dir("validate_upstream_groups");
+        let group = UpstreamGroup {
+            servers: vec![String::from("127.0.0.1:3001"), String::from("backend")],
+            strategy: Strategy::IpHash,
+            max_fails: 1,
+            fail_timeout: 10,
+            keepalive: 8,
+            keepalive_timeout: 60,
+            health_check: Some(HealthCheck {
+                path: String::from("health"),
+                ..HealthCheck::default()
+            }),
+        };
+        let empty = UpstreamGroup {
+            servers: Vec::new(),
+            health_check: None,
+            ..group.clone()
+        };
+        let config = Config {
+            drain_timeout: 30,
+            upstreams: HashMap::from([(String::from("api"), group), (String::from("empty"), empty)]),
+            servers: vec![setup_config(&root)],
+        };
+        let errors = messages(&validate_config(&main_source(""), &config));
+        assert_eq!(
+            errors,
+            vec![
+                "upstream `api`: server `backend` must be host:port",
+                "upstream `api`: health_check path `health` must start with /",
+                "upstream `empty`: servers must not be empty",
+            ]
+        );
+    }
+}
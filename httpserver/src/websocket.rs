@@ -0,0 +1,812 @@
+// websocket.rs
+use crate::auth::authorize;
+use crate::config::{ConfigServer, Route};
+use crate::handler::PageErrorHandler;
+use crate::listener::Stream;
+use crate::proxy::{self, Relays};
+use crate::upstream::{watch, Upstreams};
+use http::httprequest::{HttpRequest, Method};
+use http::websocket::{
+    handshake, handshake_response, is_upgrade, Assembler, Frame, HandshakeError, Message, Opcode,
+    WsError, CLOSE_GOING_AWAY, CLOSE_POLICY,
+};
+use libc::{EPOLLIN, EPOLLOUT, EPOLLRDHUP, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, prelude::*};
+use std::net::Shutdown;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+// Octets lus par événement epoll: la lecture reprend à l'événement suivant
+const READ_CHUNK: usize = 16 * 1024;
+
+// Données en attente d'envoi au-delà desquelles la lecture de l'autre côté
+// est suspendue; un destinataire de diffusion aussi lent est déconnecté
+const MAX_PENDING: usize = 4 * 1024 * 1024;
+
+// Section `websocket` d'une route
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct WebSocketPolicy {
+    pub mode: WebSocketMode,
+    pub max_message_size: usize, // octets d'un message reconstitué ou d'une trame relayée
+    pub idle_timeout: u64,       // secondes sans trame avant la fermeture, 0 pour aucune limite
+}
+
+impl Default for WebSocketPolicy {
+    fn default() -> Self {
+        WebSocketPolicy {
+            mode: WebSocketMode::Echo,
+            max_message_size: 1024 * 1024,
+            idle_timeout: 300,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WebSocketMode {
+    #[default]
+    Echo,      // chaque message est renvoyé à son expéditeur
+    Broadcast, // chaque message est envoyé à tous les clients de la route
+    Proxy,     // trames relayées vers le serveur amont de `proxy_pass`
+}
+
+// Passage en WebSocket d'une requête sur une route `websocket`. En cas de
+// succès la connexion est confiée à `websockets` et servie ensuite par la
+// boucle epoll; sinon une réponse d'erreur est envoyée.
+#[allow(clippy::too_many_arguments)]
+pub fn upgrade(
+    req: &HttpRequest,
+    stream: Stream,
+    leftover: Vec<u8>,
+    config: &ConfigServer,
+    route: &Route,
+    prefix: &str,
+    upstreams: &mut Upstreams,
+    relays: &mut Relays,
+    websockets: &mut WebSockets,
+) {
+    let policy = route.websocket.clone().unwrap_or_default();
+    let mut client = &stream;
+    if req.method != Method::Get || !route.methods.iter().any(|method| method == "GET") {
+        let _ = PageErrorHandler::error_response(config, "405").send_response(&mut client);
+        return;
+    }
+    if let Err(resp) = authorize(req, route, config) {
+        let _ = resp.send_response(&mut client);
+        return;
+    }
+    let accept = match handshake(&req.headers) {
+        Ok(accept) => accept,
+        // Requête HTTP ordinaire ou version du protocole non prise en charge
+        Err(HandshakeError::UnsupportedVersion) => {
+            let _ = upgrade_required(config, "Sec-WebSocket-Version", "13").send_response(&mut client);
+            return;
+        }
+        Err(HandshakeError::Invalid(_)) if !is_upgrade(&req.headers) => {
+            let _ = upgrade_required(config, "Upgrade", "websocket").send_response(&mut client);
+            return;
+        }
+        Err(HandshakeError::Invalid(reason)) => {
+            eprintln!("Invalid WebSocket handshake: {}", reason);
+            let _ = PageErrorHandler::error_response(config, "400").send_response(&mut client);
+            return;
+        }
+    };
+    // La session relayée n'est ouverte qu'une fois la poignée de main
+    // acceptée par l'amont, dans la boucle epoll
+    if policy.mode == WebSocketMode::Proxy {
+        proxy::open_websocket(req, stream, leftover, config, route, prefix, upstreams, relays);
+        return;
+    }
+    if client.write_all(handshake_response(&accept).as_bytes()).is_ok() {
+        websockets.open(stream, leftover, policy, channel(config, route), None);
+    }
+}
+
+// Canal de diffusion des sessions d'une route
+pub fn channel(config: &ConfigServer, route: &Route) -> String {
+    format!("{}{}", config.host_name, route.alias)
+}
+
+fn upgrade_required<'a>(config: &ConfigServer, name: &'a str, value: &'a str) -> http::httpresponse::HttpResponse<'a> {
+    let mut resp = PageErrorHandler::error_response(config, "426");
+    if let Some(headers) = resp.headers.as_mut() {
+        headers.insert(name, value);
+    }
+    resp
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Client,
+    Upstream,
+}
+
+// Une extrémité d'une connexion: octets reçus en attente de décodage et
+// octets restant à envoyer
+struct Endpoint {
+    stream: Stream,
+    inbound: Vec<u8>,
+    outbound: Vec<u8>,
+    messages: Assembler,
+    masked: bool, // trames reçues masquées (client) ou non (serveur amont)
+}
+
+impl Endpoint {
+    fn new(stream: Stream, inbound: Vec<u8>, masked: bool, max_message: usize) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        Ok(Endpoint {
+            stream,
+            inbound,
+            outbound: Vec::new(),
+            messages: Assembler::new(max_message),
+            masked,
+        })
+    }
+
+    // Trame vers cette extrémité, masquée lorsque le serveur joue le rôle de
+    // client auprès de l'amont
+    fn queue(&mut self, frame: &Frame) {
+        let mask = (!self.masked).then(rand::random::<[u8; 4]>);
+        self.outbound.extend_from_slice(&frame.encode(mask));
+    }
+
+    // Envoie ce qui peut l'être sans bloquer
+    fn send(&mut self) -> io::Result<()> {
+        while !self.outbound.is_empty() {
+            match (&self.stream).write(&self.outbound) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.outbound.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Session {
+    client: Endpoint,
+    upstream: Option<Endpoint>,
+    policy: WebSocketPolicy,
+    channel: String, // hôte et alias de la route, partagés par la diffusion
+    last_seen: Instant,
+    closing: bool, // fermeture envoyée: la connexion se termine une fois les données transmises
+}
+
+impl Session {
+    fn endpoint(&mut self, side: Side) -> Option<&mut Endpoint> {
+        match side {
+            Side::Client => Some(&mut self.client),
+            Side::Upstream => self.upstream.as_mut(),
+        }
+    }
+
+    // Les trames reçues d'un côté sont envoyées de l'autre (au client lui-même
+    // hors relais): sa lecture attend que l'envoi progresse
+    fn events(&self, side: Side) -> u32 {
+        let (own, target) = match (side, &self.upstream) {
+            (Side::Client, Some(upstream)) => (&self.client, upstream),
+            (Side::Upstream, Some(upstream)) => (upstream, &self.client),
+            _ => (&self.client, &self.client),
+        };
+        let mut events = EPOLLRDHUP as u32;
+        if target.outbound.len() < MAX_PENDING {
+            events |= EPOLLIN as u32;
+        }
+        if !own.outbound.is_empty() {
+            events |= EPOLLOUT as u32;
+        }
+        events
+    }
+}
+
+// Connexions WebSocket ouvertes, servies par la boucle epoll du serveur
+#[derive(Default)]
+pub struct WebSockets {
+    sessions: HashMap<RawFd, Session>,   // par descripteur du client
+    upstream_fds: HashMap<RawFd, RawFd>, // descripteur amont -> descripteur du client
+    epoll_fd: Option<RawFd>,
+}
+
+impl WebSockets {
+    pub fn attach(&mut self, epoll_fd: RawFd) {
+        self.epoll_fd = Some(epoll_fd);
+    }
+
+    pub fn open(
+        &mut self,
+        stream: Stream,
+        leftover: Vec<u8>,
+        policy: WebSocketPolicy,
+        channel: String,
+        upstream: Option<(Stream, Vec<u8>)>,
+    ) {
+        let max = policy.max_message_size;
+        let client = match Endpoint::new(stream, leftover, true, max) {
+            Ok(client) => client,
+            Err(e) => return eprintln!("WebSocket setup failed: {}", e),
+        };
+        let upstream = match upstream.map(|(stream, leftover)| Endpoint::new(stream, leftover, false, max)) {
+            Some(Err(e)) => return eprintln!("WebSocket setup failed: {}", e),
+            upstream => upstream.and_then(Result::ok),
+        };
+        let fd = client.stream.as_raw_fd();
+        watch(self.epoll_fd, EPOLL_CTL_ADD, fd, (EPOLLIN | EPOLLRDHUP) as u32);
+        if let Some(upstream) = &upstream {
+            let upstream_fd = upstream.stream.as_raw_fd();
+            watch(self.epoll_fd, EPOLL_CTL_ADD, upstream_fd, (EPOLLIN | EPOLLRDHUP) as u32);
+            self.upstream_fds.insert(upstream_fd, fd);
+        }
+        let session = Session {
+            client,
+            upstream,
+            policy,
+            channel,
+            last_seen: Instant::now(),
+            closing: false,
+        };
+        self.sessions.insert(fd, session);
+        // Trames arrivées avec la poignée de main
+        self.process(fd, Side::Client);
+        self.process(fd, Side::Upstream);
+        self.flush(fd);
+    }
+
+    // Événement epoll: false si le descripteur n'est pas une connexion WebSocket
+    pub fn on_event(&mut self, token: u64) -> bool {
+        let fd = token as RawFd;
+        let (client_fd, side) = if self.sessions.contains_key(&fd) {
+            (fd, Side::Client)
+        } else if let Some(client_fd) = self.upstream_fds.get(&fd) {
+            (*client_fd, Side::Upstream)
+        } else {
+            return false;
+        };
+        let Some(endpoint) = self.sessions.get_mut(&client_fd).and_then(|s| s.endpoint(side)) else {
+            return true;
+        };
+        let mut chunk = vec![0; READ_CHUNK];
+        match (&endpoint.stream).read(&mut chunk) {
+            Ok(0) => return self.hang_up(client_fd, side),
+            Ok(n) => {
+                endpoint.inbound.extend_from_slice(&chunk[..n]);
+                self.process(client_fd, side);
+            }
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => {}
+            Err(_) => return self.hang_up(client_fd, side),
+        }
+        self.flush(client_fd);
+        true
+    }
+
+    // Ferme les connexions inactives depuis plus de `idle_timeout`
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        let idle: Vec<RawFd> = self
+            .sessions
+            .iter()
+            .filter(|(_, s)| s.policy.idle_timeout > 0 && !s.closing)
+            .filter(|(_, s)| now.duration_since(s.last_seen) >= Duration::from_secs(s.policy.idle_timeout))
+            .map(|(fd, _)| *fd)
+            .collect();
+        for fd in idle {
+            self.close(fd, &Frame::close(CLOSE_GOING_AWAY, "idle timeout"));
+        }
+    }
+
+    // Début de l'arrêt: chaque client reçoit une trame de fermeture, la
+    // session se termine une fois la trame transmise
+    pub fn shut_down(&mut self) {
+        let fds: Vec<RawFd> = self.sessions.iter().filter(|(_, s)| !s.closing).map(|(fd, _)| *fd).collect();
+        for fd in fds {
+            self.close(fd, &Frame::close(CLOSE_GOING_AWAY, "server shutting down"));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    // Fin du délai d'arrêt: les sessions restantes sont fermées
+    pub fn close_all(&mut self) {
+        let fds: Vec<RawFd> = self.sessions.keys().cloned().collect();
+        for fd in fds {
+            self.close(fd, &Frame::close(CLOSE_GOING_AWAY, "server shutting down"));
+            self.teardown(fd);
+        }
+    }
+
+    // Décode les trames complètes reçues d'un côté
+    fn process(&mut self, fd: RawFd, side: Side) {
+        loop {
+            let Some(session) = self.sessions.get_mut(&fd) else {
+                return;
+            };
+            let closing = session.closing;
+            let max = session.policy.max_message_size;
+            let Some(endpoint) = session.endpoint(side) else {
+                return;
+            };
+            // Après la fermeture, les trames reçues sont ignorées
+            if closing {
+                endpoint.inbound.clear();
+                return;
+            }
+            let frame = match Frame::parse(&endpoint.inbound, endpoint.masked, max) {
+                Ok(Some((frame, used))) => {
+                    endpoint.inbound.drain(..used);
+                    frame
+                }
+                Ok(None) => return,
+                Err(e) => return self.fail(fd, side, e),
+            };
+            session.last_seen = Instant::now();
+            if let Err(e) = self.dispatch(fd, side, frame) {
+                return self.fail(fd, side, e);
+            }
+        }
+    }
+
+    fn dispatch(&mut self, fd: RawFd, side: Side, frame: Frame) -> Result<(), WsError> {
+        let Some(session) = self.sessions.get_mut(&fd) else {
+            return Ok(());
+        };
+        if session.policy.mode == WebSocketMode::Proxy {
+            // Les fragments sont relayés un à un, en vérifiant leur enchaînement
+            // et la taille des messages
+            if let Some(endpoint) = session.endpoint(side) {
+                endpoint.messages.check(&frame)?;
+            }
+            let target = match side {
+                Side::Client => session.upstream.as_mut(),
+                Side::Upstream => Some(&mut session.client),
+            };
+            if let Some(target) = target {
+                target.queue(&frame);
+            }
+            return Ok(());
+        }
+        let Some(message) = session.client.messages.push(frame)? else {
+            return Ok(());
+        };
+        match message {
+            Message::Text(_) | Message::Binary(_) if session.policy.mode == WebSocketMode::Broadcast => {
+                let channel = session.channel.clone();
+                self.broadcast(&channel, &message.into_frame());
+            }
+            Message::Text(_) | Message::Binary(_) => session.client.queue(&message.into_frame()),
+            Message::Ping(data) => session.client.queue(&Frame::new(Opcode::Pong, data)),
+            Message::Pong(_) => {}
+            // Le client reçoit la même trame de fermeture, puis la connexion se termine
+            Message::Close(reason) => {
+                session.client.queue(&Message::Close(reason).into_frame());
+                session.closing = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn broadcast(&mut self, channel: &str, frame: &Frame) {
+        let bytes = frame.encode(None);
+        let mut recipients = Vec::new();
+        let mut slow = Vec::new();
+        for (fd, session) in self.sessions.iter_mut() {
+            if session.channel != channel || session.closing {
+                continue;
+            }
+            if session.client.outbound.len() >= MAX_PENDING {
+                slow.push(*fd);
+                continue;
+            }
+            session.client.outbound.extend_from_slice(&bytes);
+            recipients.push(*fd);
+        }
+        for fd in recipients {
+            self.flush(fd);
+        }
+        for fd in slow {
+            eprintln!("WebSocket client too slow, closing");
+            self.close(fd, &Frame::close(CLOSE_POLICY, "client too slow"));
+        }
+    }
+
+    // Erreur de protocole d'un côté: il reçoit le code d'erreur, l'autre côté
+    // d'un relais est prévenu du départ
+    fn fail(&mut self, fd: RawFd, side: Side, e: WsError) {
+        eprintln!("WebSocket protocol error: {}", e.reason);
+        let Some(session) = self.sessions.get_mut(&fd) else {
+            return;
+        };
+        let error = Frame::close(e.code, e.reason);
+        let going_away = Frame::close(CLOSE_GOING_AWAY, "");
+        let (client_frame, upstream_frame) = match side {
+            Side::Client => (&error, &going_away),
+            Side::Upstream => (&going_away, &error),
+        };
+        session.client.queue(client_frame);
+        if let Some(upstream) = session.upstream.as_mut() {
+            upstream.queue(upstream_frame);
+        }
+        session.closing = true;
+        self.flush(fd);
+    }
+
+    // Envoie une trame de fermeture à chaque extrémité
+    fn close(&mut self, fd: RawFd, frame: &Frame) {
+        let Some(session) = self.sessions.get_mut(&fd) else {
+            return;
+        };
+        session.client.queue(frame);
+        if let Some(upstream) = session.upstream.as_mut() {
+            upstream.queue(frame);
+        }
+        session.closing = true;
+        self.flush(fd);
+    }
+
+    // Fin de connexion d'un côté: le client reçoit encore les données en
+    // attente lorsque c'est l'amont qui ferme
+    fn hang_up(&mut self, fd: RawFd, side: Side) -> bool {
+        match side {
+            Side::Client => self.teardown(fd),
+            Side::Upstream => {
+                if let Some(upstream) = self.sessions.get_mut(&fd).and_then(|s| s.upstream.take()) {
+                    let upstream_fd = upstream.stream.as_raw_fd();
+                    watch(self.epoll_fd, EPOLL_CTL_DEL, upstream_fd, 0);
+                    self.upstream_fds.remove(&upstream_fd);
+                }
+                if let Some(session) = self.sessions.get_mut(&fd) {
+                    session.closing = true;
+                }
+                self.flush(fd);
+            }
+        }
+        true
+    }
+
+    // Envoie ce qui peut l'être puis ajuste les événements surveillés
+    fn flush(&mut self, fd: RawFd) {
+        let Some(session) = self.sessions.get_mut(&fd) else {
+            return;
+        };
+        let mut failed = session.client.send().is_err();
+        if let Some(upstream) = session.upstream.as_mut() {
+            failed |= upstream.send().is_err();
+        }
+        if failed || (session.closing && session.client.outbound.is_empty()) {
+            return self.teardown(fd);
+        }
+        watch(self.epoll_fd, EPOLL_CTL_MOD, fd, session.events(Side::Client));
+        if let Some(upstream) = &session.upstream {
+            let upstream_fd = upstream.stream.as_raw_fd();
+            watch(self.epoll_fd, EPOLL_CTL_MOD, upstream_fd, session.events(Side::Upstream));
+        }
+    }
+
+    fn teardown(&mut self, fd: RawFd) {
+        let Some(session) = self.sessions.remove(&fd) else {
+            return;
+        };
+        watch(self.epoll_fd, EPOLL_CTL_DEL, fd, 0);
+        let _ = session.client.stream.shutdown(Shutdown::Both);
+        if let Some(upstream) = session.upstream {
+            let upstream_fd = upstream.stream.as_raw_fd();
+            watch(self.epoll_fd, EPOLL_CTL_DEL, upstream_fd, 0);
+            self.upstream_fds.remove(&upstream_fd);
+            let _ = upstream.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::httprequest::{Resource, Version};
+    use http::websocket::{accept_key, close_reason, CLOSE_PROTOCOL_ERROR, CLOSE_TOO_BIG};
+    use libc::{epoll_create1, epoll_event, epoll_wait, EPOLL_CLOEXEC};
+    use std::io::BufReader;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+    fn setup_config() -> ConfigServer {
+        ConfigServer {
+            root: String::from("/public"),
+            client_body_limit: 1024,
+            ..Default::default()
+        }
+    }
+
+    fn ws_route(policy: WebSocketPolicy, proxy_pass: Option<&str>) -> Route {
+        Route {
+            alias: "/ws/".to_string(),
+            proxy_pass: proxy_pass.map(str::to_string),
+            websocket: Some(policy),
+            ..Default::default()
+        }
+    }
+
+    fn request(method: Method, headers: &[(&str, &str)]) -> HttpRequest {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), format!(" {}\r\n", value)))
+            .collect();
+        HttpRequest::new(method, Version::V1_1, Resource::Path("/ws/".to_string()), headers, String::new())
+    }
+
+    fn handshake_headers(version: &'static str) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("Host", "localhost"),
+            ("Upgrade", "websocket"),
+            ("Connection", "Upgrade"),
+            ("Sec-WebSocket-Key", KEY),
+            ("Sec-WebSocket-Version", version),
+            ("Sec-WebSocket-Extensions", "permessage-deflate"),
+        ]
+    }
+
+    // Connexion d'un client passée par `upgrade`; retourne la socket du client
+    // et l'en-tête de la réponse
+    fn open(req: &HttpRequest, route: &Route, websockets: &mut WebSockets) -> (TcpStream, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut upstreams = Upstreams::default();
+        upgrade(req, stream.into(), Vec::new(), &setup_config(), route, "/ws/", &mut upstreams, &mut Relays::default(), websockets);
+        let head = read_head(&client);
+        (client, head)
+    }
+
+    fn read_head(client: &TcpStream) -> String {
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut reader = BufReader::new(client);
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            if reader.read_line(&mut head).unwrap() == 0 {
+                break;
+            }
+        }
+        head
+    }
+
+    // Traite les événements jusqu'à ce qu'il n'y en ait plus
+    fn pump(websockets: &mut WebSockets, epoll_fd: RawFd) {
+        let mut events = vec![epoll_event { events: 0, u64: 0 }; 16];
+        loop {
+            let n = unsafe { epoll_wait(epoll_fd, events.as_mut_ptr(), events.len() as i32, 100) };
+            if n <= 0 {
+                return;
+            }
+            for event in &events[..n as usize] {
+                assert!(websockets.on_event(event.u64));
+            }
+        }
+    }
+
+    fn send(client: &mut TcpStream, frame: &Frame) {
+        client.write_all(&frame.encode(Some([1, 2, 3, 4]))).unwrap();
+    }
+
+    // Lecture octet par octet pour laisser les trames suivantes sur la socket
+    fn receive(client: &mut TcpStream) -> Frame {
+        let mut buf = Vec::new();
+        let mut byte = [0; 1];
+        loop {
+            if let Some((frame, _)) = Frame::parse(&buf, false, 1 << 20).unwrap() {
+                return frame;
+            }
+            client.read_exact(&mut byte).unwrap();
+            buf.push(byte[0]);
+        }
+    }
+
+    fn is_closed(client: &mut TcpStream) -> bool {
+        matches!(client.read(&mut [0; 16]), Ok(0))
+    }
+
+    fn attached() -> (WebSockets, RawFd) {
+        let epoll_fd = unsafe { epoll_create1(EPOLL_CLOEXEC) };
+        let mut websockets = WebSockets::default();
+        websockets.attach(epoll_fd);
+        (websockets, epoll_fd)
+    }
+
+    #[test]
+    fn test_echo_session() {
+        let (mut websockets, epoll_fd) = attached();
+        let route = ws_route(WebSocketPolicy::default(), None);
+        let (mut client, head) = open(&request(Method::Get, &handshake_headers("13")), &route, &mut websockets);
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains(&format!("Sec-WebSocket-Accept: {}\r\n", accept_key(KEY))));
+        assert!(!head.contains("Extensions"));
+
+        // Message fragmenté avec un ping intercalé
+        send(&mut client, &Frame { fin: false, opcode: Opcode::Text, payload: b"Hel".to_vec() });
+        send(&mut client, &Frame::new(Opcode::Ping, b"p".to_vec()));
+        send(&mut client, &Frame { fin: true, opcode: Opcode::Continuation, payload: b"lo".to_vec() });
+        pump(&mut websockets, epoll_fd);
+        assert_eq!(receive(&mut client), Frame::new(Opcode::Pong, b"p".to_vec()));
+        assert_eq!(receive(&mut client), Frame::new(Opcode::Text, b"Hello".to_vec()));
+
+        send(&mut client, &Frame::close(1000, "bye"));
+        pump(&mut websockets, epoll_fd);
+        let close = receive(&mut client);
+        assert_eq!(close_reason(&close.payload), Ok(Some((1000, "bye".to_string()))));
+        assert!(is_closed(&mut client));
+        assert!(websockets.sessions.is_empty());
+        unsafe {
+            libc::close(epoll_fd);
+        }
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let (mut websockets, epoll_fd) = attached();
+        let policy = WebSocketPolicy {
+            mode: WebSocketMode::Broadcast,
+            ..WebSocketPolicy::default()
+        };
+        let route = ws_route(policy, None);
+        let req = request(Method::Get, &handshake_headers("13"));
+        let (mut first, _) = open(&req, &route, &mut websockets);
+        let (mut second, _) = open(&req, &route, &mut websockets);
+        send(&mut second, &Frame::new(Opcode::Binary, vec![1, 2, 3]));
+        pump(&mut websockets, epoll_fd);
+        for client in [&mut first, &mut second] {
+            assert_eq!(receive(client), Frame::new(Opcode::Binary, vec![1, 2, 3]));
+        }
+        // Un client qui part ne gêne pas les autres
+        drop(second);
+        pump(&mut websockets, epoll_fd);
+        assert_eq!(websockets.sessions.len(), 1);
+        websockets.close_all();
+        assert_eq!(close_reason(&receive(&mut first).payload).unwrap().unwrap().0, CLOSE_GOING_AWAY);
+        unsafe {
+            libc::close(epoll_fd);
+        }
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        let (mut websockets, epoll_fd) = attached();
+        let policy = WebSocketPolicy {
+            max_message_size: 8,
+            ..WebSocketPolicy::default()
+        };
+        let route = ws_route(policy, None);
+        let req = request(Method::Get, &handshake_headers("13"));
+
+        // Trame non masquée
+        let (mut client, _) = open(&req, &route, &mut websockets);
+        client.write_all(&Frame::new(Opcode::Text, b"hi".to_vec()).encode(None)).unwrap();
+        pump(&mut websockets, epoll_fd);
+        assert_eq!(close_reason(&receive(&mut client).payload).unwrap().unwrap().0, CLOSE_PROTOCOL_ERROR);
+        assert!(is_closed(&mut client));
+
+        // Message trop grand, même fragmenté
+        let (mut client, _) = open(&req, &route, &mut websockets);
+        send(&mut client, &Frame { fin: false, opcode: Opcode::Text, payload: b"12345".to_vec() });
+        send(&mut client, &Frame { fin: true, opcode: Opcode::Continuation, payload: b"6789".to_vec() });
+        pump(&mut websockets, epoll_fd);
+        assert_eq!(close_reason(&receive(&mut client).payload).unwrap().unwrap().0, CLOSE_TOO_BIG);
+        assert!(websockets.sessions.is_empty());
+        unsafe {
+            libc::close(epoll_fd);
+        }
+    }
+
+    #[test]
+    fn test_handshake_errors() {
+        let mut websockets = WebSockets::default();
+        let route = ws_route(WebSocketPolicy::default(), None);
+        let (_, head) = open(&request(Method::Get, &handshake_headers("8")), &route, &mut websockets);
+        assert!(head.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert!(head.contains("Sec-WebSocket-Version:13\r\n"));
+
+        let (_, head) = open(&request(Method::Get, &[("Host", "localhost")]), &route, &mut websockets);
+        assert!(head.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert!(head.contains("Upgrade:websocket\r\n"));
+
+        let mut headers = handshake_headers("13");
+        headers[3] = ("Sec-WebSocket-Key", "c2hvcnQ=");
+        let (_, head) = open(&request(Method::Get, &headers), &route, &mut websockets);
+        assert!(head.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        let (_, head) = open(&request(Method::Post, &handshake_headers("13")), &route, &mut websockets);
+        assert!(head.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(websockets.sessions.is_empty());
+    }
+
+    #[test]
+    fn test_proxy_session() {
+        // Serveur amont: accepte la poignée de main, renvoie un message en
+        // majuscules puis ferme la connexion
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let upstream = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") {
+                reader.read_line(&mut head).unwrap();
+            }
+            write!(
+                stream,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(KEY)
+            )
+            .unwrap();
+            let mut buf = reader.buffer().to_vec();
+            let mut chunk = [0; 1024];
+            let frame = loop {
+                if let Some((frame, _)) = Frame::parse(&buf, true, 1024).unwrap() {
+                    break frame;
+                }
+                let n = stream.read(&mut chunk).unwrap();
+                buf.extend_from_slice(&chunk[..n]);
+            };
+            let reply = Frame::new(Opcode::Text, frame.payload.to_ascii_uppercase());
+            stream.write_all(&reply.encode(None)).unwrap();
+            head
+        });
+
+        let (mut websockets, epoll_fd) = attached();
+        let policy = WebSocketPolicy {
+            mode: WebSocketMode::Proxy,
+            ..WebSocketPolicy::default()
+        };
+        let route = ws_route(policy, Some(&format!("http://{}", addr)));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut upstreams = Upstreams::default();
+        let mut relays = Relays::default();
+        relays.attach(epoll_fd);
+        let req = request(Method::Get, &handshake_headers("13"));
+        upgrade(&req, stream.into(), Vec::new(), &setup_config(), &route, "/ws/", &mut upstreams, &mut relays, &mut websockets);
+        // La poignée de main avec l'amont se fait dans la boucle epoll, puis
+        // la session relayée est ouverte
+        let mut events = vec![epoll_event { events: 0, u64: 0 }; 16];
+        while !relays.is_empty() {
+            let n = unsafe { epoll_wait(epoll_fd, events.as_mut_ptr(), events.len() as i32, 2000) };
+            assert!(n > 0);
+            for event in &events[..n as usize] {
+                assert!(relays.on_event(event.u64, &mut upstreams));
+            }
+        }
+        for handoff in relays.take_handoffs() {
+            let proxy::Handoff::WebSocket { client, leftover, upstream, upstream_leftover, policy, channel } = handoff else {
+                panic!("unexpected handoff");
+            };
+            websockets.open(client, leftover, policy, channel, Some((upstream.into(), upstream_leftover)));
+        }
+        let head = read_head(&client);
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains(&format!("Sec-WebSocket-Accept: {}\r\n", accept_key(KEY))));
+
+        send(&mut client, &Frame::new(Opcode::Text, b"hello".to_vec()));
+        pump(&mut websockets, epoll_fd);
+        assert_eq!(receive(&mut client), Frame::new(Opcode::Text, b"HELLO".to_vec()));
+        let request_head = upstream.join().unwrap();
+        assert!(request_head.starts_with("GET /ws/ HTTP/1.1\r\n"));
+        assert!(request_head.contains("Upgrade: websocket\r\nConnection: Upgrade\r\n"));
+        assert!(request_head.contains(&format!("Sec-WebSocket-Key: {}\r\n", KEY)));
+        assert!(!request_head.contains("Sec-WebSocket-Extensions"));
+
+        // L'amont a fermé: le client aussi est déconnecté
+        pump(&mut websockets, epoll_fd);
+        assert!(is_closed(&mut client));
+        assert!(websockets.sessions.is_empty() && websockets.upstream_fds.is_empty());
+        unsafe {
+            libc::close(epoll_fd);
+        }
+    }
+}